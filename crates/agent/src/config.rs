@@ -19,4 +19,9 @@ impl AgentConfig {
         let cfg: Self = toml::from_str(&data)?;
         Ok(cfg)
     }
+
+    /// Where the agent keeps per-container state, next to its credentials.
+    pub fn state_dir(&self) -> PathBuf {
+        self.cert_path.parent().map(PathBuf::from).unwrap_or_default()
+    }
 }
@@ -1,9 +1,12 @@
-use proto::agent::{agent_service_client::AgentServiceClient, NodeStatus};
+use proto::agent::{agent_service_client::AgentServiceClient, NodeId, NodeStatus};
 use sysinfo::System;
-use std::time::Duration;
+use std::{fs, time::Duration};
 use tonic::transport::{Channel, ClientTlsConfig, Certificate as TlsCertificate, Identity};
+use tracing::{info, warn};
 
-pub async fn run_heartbeat(mut client: AgentServiceClient<Channel>, node_id: String) {
+use crate::config::AgentConfig;
+
+pub async fn run_heartbeat(mut client: AgentServiceClient<Channel>, node_id: String, cfg: AgentConfig) {
     let mut sys = System::new_all();
     loop {
         sys.refresh_all();
@@ -15,11 +18,33 @@ pub async fn run_heartbeat(mut client: AgentServiceClient<Channel>, node_id: Str
         metadata.insert("mem_used".to_string(), mem);
         metadata.insert("mem_total".to_string(), total);
         let status = NodeStatus { node_id: node_id.clone(), status: "healthy".into(), metadata };
-        let _ = client.heartbeat(status).await;
+        if let Ok(ack) = client.heartbeat(status).await {
+            if ack.into_inner().reissue_certificate {
+                match renew_credentials(&mut client, &node_id, &cfg).await {
+                    Ok(renewed) => client = renewed,
+                    Err(e) => warn!(error = %e, "Certificate renewal failed; will retry on next heartbeat"),
+                }
+            }
+        }
         tokio::time::sleep(Duration::from_secs(30)).await;
     }
 }
 
+/// Fetch a certificate issued by the active CA, persist it together with the
+/// updated CA bundle, and reconnect using the new identity.
+async fn renew_credentials(client: &mut AgentServiceClient<Channel>, node_id: &str, cfg: &AgentConfig) -> anyhow::Result<AgentServiceClient<Channel>> {
+    let creds = client.renew_certificate(NodeId { id: node_id.to_string() }).await?.into_inner();
+    fs::write(&cfg.cert_path, &creds.cert)?;
+    fs::write(&cfg.key_path, &creds.key)?;
+    let ca_pem = match &cfg.ca_cert_path {
+        Some(path) if !creds.ca.is_empty() => { fs::write(path, &creds.ca)?; Some(creds.ca.clone()) }
+        Some(path) => fs::read(path).ok(),
+        None => None,
+    };
+    info!(%node_id, "Renewed node certificate under the active CA");
+    make_client_with_identity(&cfg.control_plane_url, ca_pem, Some(creds.cert), Some(creds.key)).await
+}
+
 pub async fn make_client_with_identity(cp_url: &str, ca_pem: Option<Vec<u8>>, cert_pem: Option<Vec<u8>>, key_pem: Option<Vec<u8>>) -> anyhow::Result<AgentServiceClient<Channel>> {
     let mut tls = ClientTlsConfig::new();
     if let Some(ca) = ca_pem { tls = tls.ca_certificate(TlsCertificate::from_pem(ca)); }
@@ -0,0 +1,5 @@
+pub mod config;
+pub mod heartbeat;
+pub mod logs;
+pub mod reconcile;
+pub mod runtime;
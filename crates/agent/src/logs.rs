@@ -0,0 +1,247 @@
+use prost_types::Timestamp;
+use proto::agent::{agent_service_client::AgentServiceClient, LogBatch, LogLine};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tonic::transport::Channel;
+use tracing::{info, warn};
+
+use crate::{config::AgentConfig, reconcile::reconnect, runtime};
+
+/// Pause after a failed shipment, or after the runtime stopped following a
+/// container, before trying again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Most lines, and bytes of them, sent in one batch.
+const BATCH_LINES: usize = 500;
+const BATCH_BYTES: usize = 1 << 20;
+/// Lines read but not shipped yet. Output is not read further while the
+/// buffer is full, so it waits in the runtime rather than in memory.
+const MAX_BUFFERED_LINES: usize = 10_000;
+/// Longer lines are cut, the rest dropped.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+/// `2024-05-01T12:00:00.123456789Z `, as the runtime prefixes each line.
+const TIMESTAMP_BYTES: usize = 31;
+const STREAMS: [&str; 2] = ["stdout", "stderr"];
+
+/// Ship what managed containers write to the control plane, which
+/// publishes it on their apps' log subjects.
+///
+/// Each container's output is followed through the container runtime,
+/// which stamps every line with the time the container wrote it. The
+/// timestamp of the last line shipped per container and stream is kept in
+/// `<state dir>/logs/positions.json`, so a restarted agent asks the runtime
+/// for output from there on and skips what it already shipped.
+pub async fn run_log_shipper(mut client: AgentServiceClient<Channel>, cfg: AgentConfig, mut managed: watch::Receiver<HashSet<String>>) {
+    let mut shipper = Shipper::load(cfg.state_dir().join("logs"));
+    let (sender, mut lines) = mpsc::channel(MAX_BUFFERED_LINES);
+    // Until the first reconcile pass no container looks managed, and their
+    // positions would be forgotten
+    if managed.changed().await.is_err() { return; }
+    let mut batch = Vec::new();
+    loop {
+        let containers = managed.borrow_and_update().clone();
+        shipper.follow(&containers, &sender);
+        tokio::select! {
+            changed = managed.changed() => {
+                if changed.is_err() { return; }
+                continue;
+            }
+            _ = lines.recv_many(&mut batch, BATCH_LINES) => {}
+        }
+        while let Err(e) = shipper.ship(&mut client, &mut batch).await {
+            warn!(error = %e, buffered = batch.len() + lines.len(), "failed to ship container logs; will retry");
+            tokio::time::sleep(RETRY_INTERVAL).await;
+            if let Ok(renewed) = reconnect(&cfg).await { client = renewed; }
+        }
+    }
+}
+
+struct Shipper {
+    dir: PathBuf,
+    /// Timestamp of the last line the control plane took, keyed by
+    /// `<container id>/<stream>`.
+    shipped: HashMap<String, Timestamp>,
+    followers: HashMap<String, JoinHandle<()>>,
+}
+
+impl Shipper {
+    fn load(dir: PathBuf) -> Self {
+        let saved: HashMap<String, String> = fs::read(dir.join("positions.json"))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let shipped: HashMap<String, Timestamp> = saved.into_iter().filter_map(|(key, at)| Some((key, at.parse().ok()?))).collect();
+        if !shipped.is_empty() {
+            info!(streams = shipped.len(), "resuming container log shipping");
+        }
+        Self { dir, shipped, followers: HashMap::new() }
+    }
+
+    /// Follow the output of `containers`, and stop following and forget
+    /// the positions of the rest.
+    fn follow(&mut self, containers: &HashSet<String>, lines: &mpsc::Sender<LogLine>) {
+        self.followers.retain(|id, follower| {
+            let keep = containers.contains(id);
+            if !keep { follower.abort(); }
+            keep
+        });
+        for id in containers {
+            if self.followers.contains_key(id) { continue; }
+            let since = STREAMS.map(|stream| self.shipped.get(&format!("{id}/{stream}")).cloned());
+            self.followers.insert(id.clone(), tokio::spawn(follow(id.clone(), since, lines.clone())));
+        }
+        let before = self.shipped.len();
+        self.shipped.retain(|key, _| key.split_once('/').is_some_and(|(id, _)| containers.contains(id)));
+        if self.shipped.len() != before {
+            if let Err(e) = save_positions(&self.dir, &self.shipped) {
+                warn!(error = %e, "failed to save container log positions");
+            }
+        }
+    }
+
+    /// Send `batch` in batches the control plane accepts, recording how far
+    /// each stream got after every one it takes. What is left in `batch`
+    /// on error was not shipped.
+    async fn ship(&mut self, client: &mut AgentServiceClient<Channel>, batch: &mut Vec<LogLine>) -> Result<(), tonic::Status> {
+        while !batch.is_empty() {
+            let mut bytes = 0;
+            let count = batch.iter().take_while(|l| { bytes += l.line.len(); bytes <= BATCH_BYTES }).count().max(1);
+            let ack = client.ship_logs(LogBatch { lines: batch[..count].to_vec() }).await?.into_inner();
+            if ack.rejected > 0 {
+                warn!(rejected = ack.rejected, "control plane dropped log lines of containers it did not schedule here");
+            }
+            for line in batch.drain(..count) {
+                if let Some(timestamp) = line.timestamp {
+                    self.shipped.insert(format!("{}/{}", line.container_id, line.stream), timestamp);
+                }
+            }
+            if let Err(e) = save_positions(&self.dir, &self.shipped) {
+                warn!(error = %e, "failed to save container log positions");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Follow the output of container `id` until aborted, sending each line of
+/// a stream written after its entry in `since`. The runtime stops
+/// following when the container stops, or fails to before it exists; it
+/// is then asked again from the last line read.
+async fn follow(id: String, mut since: [Option<Timestamp>; 2], lines: mpsc::Sender<LogLine>) {
+    loop {
+        // Streams without a position are read from the start
+        let from = match &since {
+            [Some(stdout), Some(stderr)] => Some(if order(stdout) <= order(stderr) { stdout } else { stderr }.to_string()),
+            _ => None,
+        };
+        match runtime::logs(&id, from.as_deref()) {
+            Ok(mut child) => {
+                let [stdout_since, stderr_since] = &mut since;
+                let (stdout, stderr) = tokio::join!(
+                    read_stream(&id, "stdout", child.stdout.take(), stdout_since, &lines),
+                    read_stream(&id, "stderr", child.stderr.take(), stderr_since, &lines),
+                );
+                // Nothing ships lines any more
+                if stdout.is_err() || stderr.is_err() { return; }
+                let _ = child.wait().await;
+            }
+            Err(e) => warn!(container = %id, error = %e, "failed to follow container output"),
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Send the lines the runtime wrote on one of a container's output streams
+/// after `since`, moving it along. Fails once nothing receives lines.
+async fn read_stream<R: AsyncRead + Unpin>(id: &str, stream: &str, output: Option<R>, since: &mut Option<Timestamp>, lines: &mpsc::Sender<LogLine>) -> Result<(), mpsc::error::SendError<LogLine>> {
+    let Some(output) = output else { return Ok(()) };
+    let mut reader = BufReader::new(output);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match (&mut reader).take((TIMESTAMP_BYTES + MAX_LINE_BYTES + 1) as u64).read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => return Ok(()),
+            Ok(_) => {}
+        }
+        // The rest of a cut line has no timestamp and is dropped here
+        let Some((timestamp, line)) = parse_line(&buf) else { continue };
+        if since.as_ref().is_some_and(|since| order(&timestamp) <= order(since)) { continue; }
+        *since = Some(timestamp.clone());
+        lines.send(LogLine { container_id: id.to_string(), stream: stream.to_string(), timestamp: Some(timestamp), line }).await?;
+    }
+}
+
+/// A line of `logs --timestamps` output: the runtime's timestamp and what
+/// the container wrote, cut to `MAX_LINE_BYTES`.
+fn parse_line(data: &[u8]) -> Option<(Timestamp, String)> {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    let data = data.strip_suffix(b"\r").unwrap_or(data);
+    let space = data.iter().position(|b| *b == b' ').unwrap_or(data.len());
+    let timestamp = std::str::from_utf8(&data[..space]).ok()?.parse().ok()?;
+    let line = data.get(space + 1..).unwrap_or_default();
+    Some((timestamp, String::from_utf8_lossy(&line[..line.len().min(MAX_LINE_BYTES)]).into_owned()))
+}
+
+fn order(timestamp: &Timestamp) -> (i64, i32) {
+    (timestamp.seconds, timestamp.nanos)
+}
+
+fn save_positions(dir: &Path, positions: &HashMap<String, Timestamp>) -> anyhow::Result<()> {
+    fs::create_dir_all(dir)?;
+    let positions: HashMap<&String, String> = positions.iter().map(|(key, at)| (key, at.to_string())).collect();
+    let tmp = dir.join("positions.json.tmp");
+    fs::write(&tmp, serde_json::to_vec(&positions)?)?;
+    fs::rename(tmp, dir.join("positions.json"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_runtime_timestamps_and_cuts_long_lines() {
+        let (timestamp, line) = parse_line(b"2026-05-01T12:00:00.123456789Z GET / 200\r\n").unwrap();
+        assert_eq!((timestamp.seconds, timestamp.nanos), (1_777_636_800, 123_456_789));
+        assert_eq!(line, "GET / 200");
+        assert_eq!(parse_line(b"2026-05-01T12:00:00Z \n").unwrap().1, "");
+        assert!(parse_line(b"xxxxxxxx the rest of a cut line\n").is_none());
+
+        let long = [b"2026-05-01T12:00:00Z ".as_slice(), &vec![b'x'; MAX_LINE_BYTES + 10]].concat();
+        assert_eq!(parse_line(&long).unwrap().1.len(), MAX_LINE_BYTES);
+    }
+
+    #[tokio::test]
+    async fn sends_lines_written_after_the_last_shipped_one() {
+        let output = b"2026-05-01T12:00:00Z one\n2026-05-01T12:00:01Z two\n2026-05-01T12:00:02Z three\n".as_slice();
+        let (sender, mut lines) = mpsc::channel(10);
+        let mut since = Some("2026-05-01T12:00:00Z".parse().unwrap());
+        read_stream("c1", "stderr", Some(output), &mut since, &sender).await.unwrap();
+        drop(sender);
+        let mut read = Vec::new();
+        while let Some(line) = lines.recv().await {
+            read.push((line.stream, line.line));
+        }
+        assert_eq!(read, [("stderr".to_string(), "two".to_string()), ("stderr".to_string(), "three".to_string())]);
+        assert_eq!(since.unwrap().to_string(), "2026-05-01T12:00:02Z");
+    }
+
+    #[test]
+    fn resumes_from_saved_positions() {
+        let dir = std::env::temp_dir().join(format!("span-agent-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let at: Timestamp = "2026-05-01T12:00:00.5Z".parse().unwrap();
+        save_positions(&dir, &HashMap::from([("c1/stdout".to_string(), at.clone())])).unwrap();
+        let shipper = Shipper::load(dir.clone());
+        assert_eq!(shipper.shipped, HashMap::from([("c1/stdout".to_string(), at)]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,4 @@
-mod config;
-mod heartbeat;
-
-use config::AgentConfig;
+use agent::{config::AgentConfig, heartbeat, logs, reconcile};
 use dirs::home_dir;
 use proto::agent::{agent_service_client::AgentServiceClient, NodeInfo};
 use std::{fs, path::PathBuf};
@@ -20,7 +17,7 @@ async fn main() -> anyhow::Result<()> {
     // Ensure certs exist; if missing, register
     let have_creds = cfg.cert_path.exists() && cfg.key_path.exists();
 
-    let ca_pem = cfg.ca_cert_path.as_ref().and_then(|p| fs::read(p).ok());
+    let mut ca_pem = cfg.ca_cert_path.as_ref().and_then(|p| fs::read(p).ok());
 
     if !have_creds {
         let mut client = heartbeat::make_client_with_identity(&cfg.control_plane_url, ca_pem.clone(), None, None).await?;
@@ -29,6 +26,10 @@ async fn main() -> anyhow::Result<()> {
         fs::create_dir_all(cfg.cert_path.parent().unwrap()).ok();
         fs::write(&cfg.cert_path, &resp.cert)?;
         fs::write(&cfg.key_path, &resp.key)?;
+        if !resp.ca.is_empty() {
+            if let Some(path) = &cfg.ca_cert_path { fs::write(path, &resp.ca)?; }
+            ca_pem = Some(resp.ca.clone());
+        }
         fs::write(cfg.cert_path.parent().unwrap().join("node_id"), resp.node_id.as_bytes()).ok();
         info!(node_id = %resp.node_id, "Registered and saved mTLS credentials");
     }
@@ -40,7 +41,10 @@ async fn main() -> anyhow::Result<()> {
 
     let node_id_path = cfg.cert_path.parent().unwrap().join("node_id");
     let node_id = fs::read_to_string(node_id_path).unwrap_or_else(|_| "unknown".into());
-    heartbeat::run_heartbeat(client, node_id).await;
+    let (managed, managed_rx) = tokio::sync::watch::channel(Default::default());
+    tokio::spawn(reconcile::run_reconcile(client.clone(), node_id.clone(), cfg.clone(), managed));
+    tokio::spawn(logs::run_log_shipper(client.clone(), cfg.clone(), managed_rx));
+    heartbeat::run_heartbeat(client, node_id, cfg).await;
     Ok(())
 }
 
@@ -0,0 +1,122 @@
+use proto::agent::{agent_service_client::AgentServiceClient, Container, NodeId};
+use std::{collections::{HashMap, HashSet}, fs, path::{Component, Path}, time::Duration};
+use tokio::sync::watch;
+use tonic::transport::Channel;
+use tracing::{info, warn};
+
+use crate::{config::AgentConfig, heartbeat::make_client_with_identity, runtime};
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Poll the desired state and act on containers whose revision changed.
+///
+/// Secret files are staged under `<state dir>/containers/<id>/` with the modes
+/// requested in the app spec and mounted into the container. A new revision
+/// means the container is replaced to pick up new config; containers that
+/// failed to start are retried on the next pass. The ids of the containers
+/// applied are sent on `managed` after each pass.
+pub async fn run_reconcile(mut client: AgentServiceClient<Channel>, node_id: String, cfg: AgentConfig, managed: watch::Sender<HashSet<String>>) {
+    let root = cfg.state_dir().join("containers");
+    let mut applied: HashMap<String, String> = HashMap::new();
+    loop {
+        match client.get_desired_state(NodeId { id: node_id.clone() }).await {
+            Ok(resp) => {
+                let containers = resp.into_inner().containers;
+                for container in changed(&applied, &containers) {
+                    if let Err(e) = stage_files(&root, container) {
+                        warn!(container = %container.id, error = %e, "failed to stage secret files");
+                        continue;
+                    }
+                    let action = if applied.contains_key(&container.id) { "restarting" } else { "starting" };
+                    info!(container = %container.id, revision = %container.revision, image = %container.image, "{action} container");
+                    match runtime::start(container, &root.join(&container.id)).await {
+                        Ok(()) => { applied.insert(container.id.clone(), container.revision.clone()); }
+                        Err(e) => warn!(container = %container.id, error = %e, "failed to start container"),
+                    }
+                }
+                for id in removed(&applied, &containers) {
+                    info!(container = %id, "stopping container no longer scheduled here");
+                    if let Err(e) = runtime::remove(&id).await {
+                        warn!(container = %id, error = %e, "failed to remove container; will retry");
+                        continue;
+                    }
+                    fs::remove_dir_all(root.join(&id)).ok();
+                    applied.remove(&id);
+                }
+                managed.send_replace(applied.keys().cloned().collect());
+            }
+            Err(e) => {
+                warn!(error = %e, "failed to fetch desired state; reconnecting");
+                // The heartbeat may have renewed our certificate since we connected
+                if let Ok(renewed) = reconnect(&cfg).await { client = renewed; }
+            }
+        }
+        tokio::time::sleep(RECONCILE_INTERVAL).await;
+    }
+}
+
+pub(crate) async fn reconnect(cfg: &AgentConfig) -> anyhow::Result<AgentServiceClient<Channel>> {
+    let cert = fs::read(&cfg.cert_path)?;
+    let key = fs::read(&cfg.key_path)?;
+    let ca = cfg.ca_cert_path.as_ref().and_then(|p| fs::read(p).ok());
+    make_client_with_identity(&cfg.control_plane_url, ca, Some(cert), Some(key)).await
+}
+
+/// Containers that are new or whose revision differs from the applied one.
+fn changed<'a>(applied: &HashMap<String, String>, desired: &'a [Container]) -> Vec<&'a Container> {
+    desired.iter().filter(|c| applied.get(&c.id) != Some(&c.revision)).collect()
+}
+
+/// Applied containers that are no longer desired.
+fn removed(applied: &HashMap<String, String>, desired: &[Container]) -> Vec<String> {
+    applied.keys().filter(|id| !desired.iter().any(|c| &c.id == *id)).cloned().collect()
+}
+
+fn stage_files(root: &Path, container: &Container) -> anyhow::Result<()> {
+    let dir = root.join(&container.id);
+    if dir.exists() { fs::remove_dir_all(&dir)?; }
+    fs::create_dir_all(&dir)?;
+    for file in &container.files {
+        let relative = Path::new(file.path.trim_start_matches('/'));
+        if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+            anyhow::bail!("refusing to stage secret file outside the container directory: {}", file.path);
+        }
+        let path = dir.join(relative);
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+        write_with_mode(&path, &file.content, file.mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_with_mode(path: &Path, content: &[u8], mode: u32) -> anyhow::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    let mut f = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(mode).open(path)?;
+    f.write_all(content)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_with_mode(path: &Path, content: &[u8], _mode: u32) -> anyhow::Result<()> {
+    fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(id: &str, revision: &str) -> Container {
+        Container { id: id.into(), revision: revision.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn detects_new_changed_and_removed_containers() {
+        let applied = HashMap::from([("a".to_string(), "r1".to_string()), ("b".to_string(), "r1".to_string())]);
+        let desired = vec![container("a", "r1"), container("b", "r2"), container("c", "r1")];
+        let ids: Vec<&str> = changed(&applied, &desired).iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "c"]);
+        assert!(removed(&applied, &desired).is_empty());
+        assert_eq!(removed(&applied, &desired[..1]), vec!["b".to_string()]);
+    }
+}
@@ -0,0 +1,63 @@
+use proto::agent::Container;
+use std::{path::Path, process::Stdio};
+use tokio::process::{Child, Command};
+
+/// The container runtime CLI managed containers run under.
+const RUNTIME: &str = "docker";
+/// Label carrying the id the control plane gave a container.
+const ID_LABEL: &str = "io.span.container";
+
+/// The runtime's name for managed container `id`.
+pub fn name(id: &str) -> String {
+    format!("span-{id}")
+}
+
+/// Replace the runtime's container for `container` with a fresh one. Its
+/// secret files, staged under `files`, are mounted read-only where the app
+/// spec asked for them. Env values are handed to the runtime CLI through
+/// its own environment, so they never show up in its arguments.
+pub async fn start(container: &Container, files: &Path) -> anyhow::Result<()> {
+    remove(&container.id).await?;
+    let mut cmd = Command::new(RUNTIME);
+    cmd.args(["run", "--detach", "--restart", "unless-stopped", "--name", &name(&container.id), "--label"]).arg(format!("{ID_LABEL}={}", container.id));
+    for (key, value) in &container.env {
+        cmd.arg("--env").arg(key).env(key, value);
+    }
+    for file in &container.files {
+        let source = files.join(file.path.trim_start_matches('/'));
+        cmd.arg("--volume").arg(format!("{}:{}:ro", source.display(), file.path));
+    }
+    cmd.arg(&container.image);
+    run(cmd).await
+}
+
+/// Remove the runtime's container for `id`, running or not. Containers
+/// that do not exist are fine.
+pub async fn remove(id: &str) -> anyhow::Result<()> {
+    let mut cmd = Command::new(RUNTIME);
+    cmd.args(["rm", "--force", &name(id)]);
+    match run(cmd).await {
+        Err(e) if e.to_string().contains("No such container") => Ok(()),
+        result => result,
+    }
+}
+
+/// Follow the output of container `id` as the runtime recorded it, from
+/// `since` (RFC 3339) on. Each line is prefixed with the runtime's
+/// timestamp for it; stdout and stderr come out on the child's own.
+pub fn logs(id: &str, since: Option<&str>) -> std::io::Result<Child> {
+    let mut cmd = Command::new(RUNTIME);
+    cmd.args(["logs", "--follow", "--timestamps"]);
+    if let Some(since) = since {
+        cmd.args(["--since", since]);
+    }
+    cmd.arg(name(id)).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true).spawn()
+}
+
+async fn run(mut cmd: Command) -> anyhow::Result<()> {
+    let output = cmd.stdin(Stdio::null()).output().await?;
+    if !output.status.success() {
+        anyhow::bail!("{RUNTIME} failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
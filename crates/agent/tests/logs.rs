@@ -0,0 +1,101 @@
+//! A workload the agent starts through the container runtime, followed to
+//! the log envelope the control plane publishes for its output. Needs
+//! Docker.
+
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+use agent::{config::AgentConfig, logs, reconcile, runtime};
+use common::events::{LogEnvelope, LogStream};
+use proto::agent::{
+    agent_service_client::AgentServiceClient,
+    agent_service_server::{AgentService, AgentServiceServer},
+    Container, DesiredState, HeartbeatAck, LogBatch, LogBatchAck, LogLine, NodeCredentials, NodeId, NodeInfo, NodeStatus,
+};
+use tokio::sync::{mpsc, watch};
+use tonic::{Request, Response, Status};
+
+/// A control plane scheduling one container here and recording the lines
+/// shipped for it.
+struct ControlPlane {
+    container: Container,
+    shipped: mpsc::UnboundedSender<LogLine>,
+}
+
+#[tonic::async_trait]
+impl AgentService for ControlPlane {
+    async fn register_node(&self, _: Request<NodeInfo>) -> Result<Response<NodeCredentials>, Status> {
+        Err(Status::unimplemented("register_node"))
+    }
+
+    async fn heartbeat(&self, _: Request<NodeStatus>) -> Result<Response<HeartbeatAck>, Status> {
+        Err(Status::unimplemented("heartbeat"))
+    }
+
+    async fn get_desired_state(&self, _: Request<NodeId>) -> Result<Response<DesiredState>, Status> {
+        Ok(Response::new(DesiredState { containers: vec![self.container.clone()] }))
+    }
+
+    async fn renew_certificate(&self, _: Request<NodeId>) -> Result<Response<NodeCredentials>, Status> {
+        Err(Status::unimplemented("renew_certificate"))
+    }
+
+    async fn ship_logs(&self, request: Request<LogBatch>) -> Result<Response<LogBatchAck>, Status> {
+        for line in request.into_inner().lines {
+            let _ = self.shipped.send(line);
+        }
+        Ok(Response::new(LogBatchAck { rejected: 0 }))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn ships_workload_output_with_runtime_timestamps() {
+    let id = format!("hello-{}", std::process::id());
+    let dir = std::env::temp_dir().join(format!("span-agent-{id}"));
+    runtime::remove(&id).await.expect("the container runtime is not available");
+    let container = Container { id: id.clone(), image: "hello-world".into(), revision: "r1".into(), ..Default::default() };
+    let (shipped, mut lines) = mpsc::unbounded_channel();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let service = AgentServiceServer::new(ControlPlane { container, shipped });
+    tokio::spawn(tonic::transport::Server::builder().add_service(service).serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)));
+
+    let cfg = AgentConfig {
+        control_plane_url: format!("http://{addr}"),
+        node_name: "node-1".into(),
+        region: None,
+        labels: Default::default(),
+        cert_path: dir.join("node.crt"),
+        key_path: dir.join("node.key"),
+        ca_cert_path: None,
+    };
+    let client = AgentServiceClient::connect(cfg.control_plane_url.clone()).await.unwrap();
+    let (managed, managed_rx) = watch::channel(HashSet::new());
+    tokio::spawn(reconcile::run_reconcile(client.clone(), "node-1".into(), cfg.clone(), managed));
+    tokio::spawn(logs::run_log_shipper(client, cfg, managed_rx));
+
+    let line = tokio::time::timeout(Duration::from_secs(120), async {
+        loop {
+            let line = lines.recv().await.unwrap();
+            if line.line == "Hello from Docker!" { return line; }
+        }
+    })
+    .await
+    .expect("the workload's output was never shipped");
+    assert_eq!((line.container_id.as_str(), line.stream.as_str()), (id.as_str(), "stdout"));
+
+    // Stamped as the runtime recorded the line, not when the agent read it
+    let output = tokio::process::Command::new("docker").args(["logs", "--timestamps", &runtime::name(&id)]).output().await.unwrap();
+    let recorded = String::from_utf8(output.stdout).unwrap().lines().find_map(|l| l.strip_suffix(" Hello from Docker!").map(str::to_string)).unwrap();
+    let timestamp = line.timestamp.clone().unwrap();
+    assert_eq!(timestamp, recorded.parse::<prost_types::Timestamp>().unwrap());
+
+    // What the control plane publishes for the line
+    let at = chrono::DateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32).unwrap();
+    let envelope = LogEnvelope::new("hello", "node-1", line.container_id, LogStream::Stdout, at, line.line);
+    assert_eq!(envelope.timestamp, chrono::DateTime::parse_from_rfc3339(&recorded).unwrap());
+    let suffix: String = std::process::id().to_string().chars().take(5).collect();
+    assert_eq!((envelope.replica, envelope.line), (format!("hello-{suffix}"), "Hello from Docker!".to_string()));
+
+    runtime::remove(&id).await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
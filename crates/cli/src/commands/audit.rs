@@ -0,0 +1,63 @@
+use anyhow::Result;
+use clap::Args;
+
+/// Filters passed straight through as query parameters of `GET /api/v1/audit`.
+#[derive(Args, Debug)]
+pub struct AuditArgs {
+    /// Only events by this actor
+    #[arg(long)]
+    pub actor: Option<String>,
+    /// Action prefix, e.g. `nodes` or `apps.delete`
+    #[arg(long)]
+    pub action: Option<String>,
+    /// Resource prefix, e.g. `namespaces/default`
+    #[arg(long)]
+    pub resource: Option<String>,
+    /// success, denied or failure
+    #[arg(long)]
+    pub outcome: Option<String>,
+    /// RFC 3339 timestamp
+    #[arg(long)]
+    pub since: Option<String>,
+    /// RFC 3339 timestamp
+    #[arg(long)]
+    pub until: Option<String>,
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+    /// Print raw JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+pub async fn list(args: &AuditArgs, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let mut query: Vec<(&str, String)> = vec![("limit", args.limit.to_string())];
+    let filters = [("actor", &args.actor), ("action", &args.action), ("resource", &args.resource), ("outcome", &args.outcome), ("since", &args.since), ("until", &args.until)];
+    query.extend(filters.into_iter().filter_map(|(k, v)| v.clone().map(|v| (k, v))));
+
+    let client = reqwest::Client::new();
+    let mut req = client.get(format!("{}/api/v1/audit", cp_url.trim_end_matches('/'))).query(&query);
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        eprintln!("Error: {}", resp.status());
+        return Ok(());
+    }
+    let events: Vec<serde_json::Value> = resp.json().await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&events)?);
+        return Ok(());
+    }
+    println!("{:<32} {:<20} {:<28} {:<40} {:<8} {:<36}", "TIME", "ACTOR", "ACTION", "RESOURCE", "OUTCOME", "REQUEST");
+    for e in events {
+        println!(
+            "{:<32} {:<20} {:<28} {:<40} {:<8} {:<36}",
+            e["occurred_at"].as_str().unwrap_or("?"),
+            e["actor"].as_str().unwrap_or("?"),
+            e["action"].as_str().unwrap_or("?"),
+            e["resource"].as_str().unwrap_or("?"),
+            e["outcome"].as_str().unwrap_or("?"),
+            e["request_id"].as_str().unwrap_or("?")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use anyhow::Result;
+
+pub async fn status(cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.get(format!("{}/api/v1/pki/ca", cp_url.trim_end_matches('/')));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    if !resp.status().is_success() { eprintln!("Error: {}", resp.status()); return Ok(()); }
+    let status: serde_json::Value = resp.json().await?;
+    print_status(&status);
+    Ok(())
+}
+
+pub async fn rotate(cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.post(format!("{}/api/v1/pki/ca/rotate", cp_url.trim_end_matches('/')));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    if resp.status().is_success() {
+        let status: serde_json::Value = resp.json().await?;
+        println!("✓ New CA introduced; both CAs are trusted until the previous one is retired");
+        print_status(&status);
+        println!("Nodes renew on their next heartbeat. Once none are pending, run: span ca retire");
+    } else {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        eprintln!("Error: {status} - {text}");
+    }
+    Ok(())
+}
+
+pub async fn retire(force: bool, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let mut url = format!("{}/api/v1/pki/ca/retire", cp_url.trim_end_matches('/'));
+    if force { url.push_str("?force=true"); }
+    let client = reqwest::Client::new();
+    let mut req = client.post(url);
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    let status = resp.status();
+    let body: serde_json::Value = resp.json().await.unwrap_or_default();
+    if status.is_success() {
+        println!("✓ Previous CA retired");
+        print_status(&body);
+    } else {
+        eprintln!("Error: {} - {}", status, body["error"].as_str().unwrap_or("unknown error"));
+        print_pending(&body);
+        if body["pending_nodes"].as_array().map(|a| !a.is_empty()).unwrap_or(false) {
+            eprintln!("Wait for these nodes to renew, or pass --force to cut them off");
+        }
+    }
+    Ok(())
+}
+
+fn print_status(status: &serde_json::Value) {
+    println!("Active CA serial:   {}", status["serial"].as_str().unwrap_or("?"));
+    if let Some(prev) = status["previous_serial"].as_str() {
+        println!("Retiring CA serial: {prev}");
+    }
+    print_pending(status);
+}
+
+fn print_pending(status: &serde_json::Value) {
+    let pending = status["pending_nodes"].as_array().cloned().unwrap_or_default();
+    if pending.is_empty() { return; }
+    println!("Nodes pending re-issue ({}):", pending.len());
+    println!("  {:<38} {:<16}", "ID", "NAME");
+    for n in pending {
+        println!("  {:<38} {:<16}", n["id"].as_str().unwrap_or("?"), n["name"].as_str().unwrap_or("?"));
+    }
+}
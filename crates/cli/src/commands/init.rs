@@ -15,7 +15,7 @@ pub struct InitArgs {
     pub install_dir: Option<PathBuf>,
 }
 
-pub async fn run(args: InitArgs) -> anyhow::Result<()> {
+pub async fn run(args: InitArgs, token: Option<&str>) -> anyhow::Result<()> {
     common::telemetry::init_tracing();
 
     let install_dir = args
@@ -27,7 +27,7 @@ pub async fn run(args: InitArgs) -> anyhow::Result<()> {
 
     if let Some(peer) = args.join {
         println!("Joining cluster via {}", peer);
-        join_cluster(&peer, &mut cfg).await?;
+        join_cluster(&peer, token, &mut cfg).await?;
     } else {
         println!("Bootstrapping new cluster");
         bootstrap_cluster(&mut cfg).await?;
@@ -56,13 +56,18 @@ struct ClusterInfo {
     cluster_id: String,
     node_count: u32,
     peers: Vec<String>,
-    jwt_secret: String,
-    master_key: String,
+    jwt_secret: Option<String>,
+    master_key: Option<String>,
 }
 
-async fn join_cluster(peer: &str, cfg: &mut HashMap<String, String>) -> anyhow::Result<()> {
+async fn join_cluster(peer: &str, token: Option<&str>, cfg: &mut HashMap<String, String>) -> anyhow::Result<()> {
     let url = format!("http://{}:8080/api/v1/cluster/info", peer);
-    let info: ClusterInfo = reqwest::get(url).await?.json().await?;
+    let mut req = reqwest::Client::new().get(url);
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let info: ClusterInfo = req.send().await?.error_for_status()?.json().await?;
+    let (Some(jwt_secret), Some(master_key)) = (info.jwt_secret, info.master_key) else {
+        anyhow::bail!("joining a cluster needs a cluster admin token (--token)");
+    };
     println!("✓ Connected to cluster: {} ({} nodes)", info.cluster_id, info.node_count);
 
     cfg.insert("CLUSTER_MODE".into(), "cluster".into());
@@ -83,8 +88,8 @@ async fn join_cluster(peer: &str, cfg: &mut HashMap<String, String>) -> anyhow::
     cfg.insert("MINIO_DISTRIBUTED_MODE_ENABLED".into(), "yes".into());
     cfg.insert("MINIO_DISTRIBUTED_NODES".into(), info.peers.join(","));
 
-    cfg.insert("JWT_SECRET".into(), info.jwt_secret);
-    cfg.insert("SPAN_MASTER_KEY".into(), info.master_key);
+    cfg.insert("JWT_SECRET".into(), jwt_secret);
+    cfg.insert("SPAN_MASTER_KEY".into(), master_key);
 
     println!("✓ Configured to join cluster");
     Ok(())
@@ -0,0 +1,159 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// A control plane session saved by `span login`.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedSession {
+    cp_url: String,
+    subject: String,
+    token: String,
+    expires_at: u64,
+}
+
+fn session_path() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("span/session.json")
+}
+
+/// Token from the last `span login` against `cp_url`, unless it has expired.
+pub fn saved_token(cp_url: &str) -> Option<String> {
+    let text = std::fs::read_to_string(session_path()).ok()?;
+    let session: SavedSession = serde_json::from_str(&text).ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (session.cp_url.trim_end_matches('/') == cp_url.trim_end_matches('/') && session.expires_at > now).then_some(session.token)
+}
+
+fn save(session: &SavedSession) -> Result<PathBuf> {
+    let path = session_path();
+    if let Some(parent) = path.parent() { std::fs::create_dir_all(parent)?; }
+    let text = serde_json::to_vec_pretty(session)?;
+    #[cfg(unix)]
+    {
+        use std::{io::Write, os::unix::fs::OpenOptionsExt};
+        let mut f = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&path)?;
+        f.write_all(&text)?;
+    }
+    #[cfg(not(unix))]
+    std::fs::write(&path, text)?;
+    Ok(path)
+}
+
+#[derive(Deserialize)]
+struct OidcSettings {
+    issuer: String,
+    client_id: String,
+    scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    token_endpoint: String,
+    device_authorization_endpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    #[serde(default)]
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_interval() -> u64 { 5 }
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Sign in through the cluster's identity provider with the OAuth device flow
+/// and store the resulting control plane session.
+pub async fn login(cp_url: &str) -> Result<()> {
+    let http = reqwest::Client::new();
+    let cp = cp_url.trim_end_matches('/');
+    let resp = http.get(format!("{cp}/api/v1/auth/oidc")).send().await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        bail!("single sign-on is not configured on {cp}");
+    }
+    let settings: OidcSettings = resp.error_for_status()?.json().await?;
+    let discovery: Discovery = http
+        .get(format!("{}/.well-known/openid-configuration", settings.issuer.trim_end_matches('/')))
+        .send().await?.error_for_status()?.json().await
+        .context("reading the issuer's discovery document")?;
+    let device_endpoint = discovery.device_authorization_endpoint.context("the identity provider does not support the device flow")?;
+
+    let scope = settings.scopes.join(" ");
+    let device: DeviceAuthorization = http
+        .post(device_endpoint)
+        .form(&[("client_id", settings.client_id.as_str()), ("scope", scope.as_str())])
+        .send().await?.error_for_status()?.json().await?;
+
+    println!("To sign in, open {}", device.verification_uri_complete.as_deref().unwrap_or(&device.verification_uri));
+    println!("and enter the code: {}", device.user_code);
+
+    let id_token = poll_for_id_token(&http, &discovery.token_endpoint, &settings.client_id, &device).await?;
+
+    let resp = http.post(format!("{cp}/api/v1/auth/oidc/session")).json(&serde_json::json!({ "id_token": id_token })).send().await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        bail!("control plane rejected the login: {} {}", status, body["error"].as_str().unwrap_or(""));
+    }
+    let session: serde_json::Value = resp.json().await?;
+    let saved = SavedSession {
+        cp_url: cp.to_string(),
+        subject: session["subject"].as_str().unwrap_or_default().to_string(),
+        token: session["token"].as_str().context("no session token in response")?.to_string(),
+        expires_at: session["expires_at"].as_u64().unwrap_or_default(),
+    };
+    save(&saved)?;
+
+    println!("✓ Logged in as {}", saved.subject);
+    if let Some(roles) = session["roles"].as_object() {
+        for (namespace, role) in roles {
+            println!("  {namespace}: {}", role.as_str().unwrap_or("?"));
+        }
+    }
+    Ok(())
+}
+
+async fn poll_for_id_token(http: &reqwest::Client, token_endpoint: &str, client_id: &str, device: &DeviceAuthorization) -> Result<String> {
+    let deadline = Instant::now() + Duration::from_secs(device.expires_in);
+    let mut interval = Duration::from_secs(device.interval.max(1));
+    loop {
+        tokio::time::sleep(interval).await;
+        if Instant::now() > deadline {
+            bail!("the login code expired; run `span login` again");
+        }
+        let resp: TokenResponse = http
+            .post(token_endpoint)
+            .form(&[("grant_type", DEVICE_CODE_GRANT), ("device_code", device.device_code.as_str()), ("client_id", client_id)])
+            .send().await?.json().await?;
+        match resp.error.as_deref() {
+            None => return resp.id_token.context("the identity provider returned no ID token; is the openid scope allowed?"),
+            Some("authorization_pending") => {}
+            // RFC 8628 3.5: back off by 5 seconds
+            Some("slow_down") => interval += Duration::from_secs(5),
+            Some("access_denied") => bail!("the login was denied"),
+            Some("expired_token") => bail!("the login code expired; run `span login` again"),
+            Some(other) => bail!("login failed: {other} {}", resp.error_description.unwrap_or_default()),
+        }
+    }
+}
+
+pub fn logout() -> Result<()> {
+    match std::fs::remove_file(session_path()) {
+        Ok(()) => println!("✓ Logged out"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => println!("Not logged in"),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
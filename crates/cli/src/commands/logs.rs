@@ -1,32 +1,79 @@
 use anyhow::Result;
-use futures_util::{StreamExt, SinkExt};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use clap::Args;
+use common::events::{LogEnvelope, LogStream};
+use futures_util::StreamExt;
+use tokio_tungstenite::{connect_async, tungstenite::{client::IntoClientRequest, protocol::frame::coding::CloseCode, Message}};
 
-pub async fn stream_logs(app: &str, follow: bool, _tail: Option<usize>, cp_url: &str, token: Option<&str>) -> Result<()> {
-    let parts: Vec<_> = app.split('/').collect();
+#[derive(Args, Debug)]
+pub struct LogsArgs {
+    /// App namespace/name
+    pub app: String,
+    /// Follow logs
+    #[arg(short, long)]
+    pub follow: bool,
+    /// Only the last N lines
+    #[arg(short, long)]
+    pub tail: Option<usize>,
+    /// Only lines since a time (RFC 3339) or a duration ago (15m, 2h, 7d)
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Only lines matching this regular expression, filtered by the server
+    #[arg(long)]
+    pub grep: Option<String>,
+    /// Only lines written to stderr
+    #[arg(long)]
+    pub stderr: bool,
+    /// Prefix lines with when they were written
+    #[arg(long)]
+    pub timestamps: bool,
+}
+
+pub async fn stream_logs(args: &LogsArgs, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let parts: Vec<_> = args.app.split('/').collect();
     if parts.len() != 2 { return Err(anyhow::anyhow!("Invalid app format. Use: namespace/name")); }
     let (namespace, name) = (parts[0], parts[1]);
 
     let ws_base = if cp_url.starts_with("https://") { cp_url.replacen("https", "wss", 1) } else { cp_url.replacen("http", "ws", 1) };
-    let mut ws_url = format!("{}/api/v1/apps/{}/{}/logs", ws_base.trim_end_matches('/'), namespace, name);
-    if follow { ws_url.push_str("?follow=true"); }
-
-    let (mut ws_stream, _) = connect_async(&ws_url).await?;
+    let ws_url = format!("{}/api/v1/apps/{}/{}/logs", ws_base.trim_end_matches('/'), namespace, name);
+    let mut params = vec![("follow", args.follow.to_string())];
+    if let Some(tail) = args.tail { params.push(("tail", tail.to_string())); }
+    if let Some(since) = &args.since { params.push(("since", since.clone())); }
+    if let Some(grep) = &args.grep { params.push(("grep", grep.clone())); }
+    if args.stderr { params.push(("stream", "stderr".into())); }
+    let ws_url = reqwest::Url::parse_with_params(&ws_url, &params)?;
 
-    // If token exists, try to send as first message (if server expects subprotocol/headers this may not work; using query param would be better if supported)
-    if let Some(t) = token { let _ = ws_stream.send(Message::Text(format!("AUTH {}", t))).await; }
+    let mut request = ws_url.as_str().into_client_request()?;
+    if let Some(t) = token { request.headers_mut().insert("Authorization", format!("Bearer {t}").parse()?); }
+    let (ws_stream, _) = connect_async(request).await?;
 
-    println!("Connected to logs for {}/{}...", namespace, name);
+    if args.follow { eprintln!("Connected to logs for {namespace}/{name}..."); }
 
+    // The server closes the connection once the history is sent, unless following
     let (_, mut read) = ws_stream.split();
     while let Some(msg) = read.next().await {
         match msg? {
-            Message::Text(t) => println!("{}", t),
-            Message::Binary(b) => println!("{}", String::from_utf8_lossy(&b)),
-            Message::Close(_) => { println!("Connection closed"); break; },
+            Message::Text(t) => print_line(&t, args.timestamps),
+            Message::Binary(b) => print_line(&String::from_utf8_lossy(&b), args.timestamps),
+            Message::Close(Some(frame)) if frame.code != CloseCode::Normal => {
+                return Err(anyhow::anyhow!("Log stream closed: {}", frame.reason));
+            }
+            Message::Close(_) => break,
             _ => {}
         }
-        if !follow { break; }
     }
     Ok(())
 }
+
+/// Print a container's line to the output it was written to; anything else
+/// as it came.
+fn print_line(text: &str, timestamps: bool) {
+    let Ok(log) = serde_json::from_str::<LogEnvelope>(text) else {
+        println!("{text}");
+        return;
+    };
+    let line = if timestamps { format!("{} {}", log.timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ"), log.line) } else { log.line };
+    match log.stream {
+        LogStream::Stdout => println!("{line}"),
+        LogStream::Stderr => eprintln!("{line}"),
+    }
+}
@@ -6,3 +6,7 @@ pub mod bucket;
 pub mod secret;
 pub mod route;
 pub mod function;
+pub mod ca;
+pub mod token;
+pub mod audit;
+pub mod login;
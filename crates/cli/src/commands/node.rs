@@ -72,6 +72,7 @@ pub async fn join(join_token: Option<&str>, _cp_url: &str, _token: Option<&str>)
     fs::write(base.join("node.crt"), &creds.cert)?;
     fs::write(base.join("node.key"), &creds.key)?;
     fs::write(base.join("node_id"), creds.node_id.as_bytes())?;
+    if !creds.ca.is_empty() { fs::write(&ca_path, &creds.ca)?; }
 
     println!("Node registered successfully!");
     println!("Run: span-agent --config {}", base.join("config.toml").display());
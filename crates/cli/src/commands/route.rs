@@ -1,4 +1,6 @@
 use anyhow::Result;
+use futures_util::StreamExt;
+use tokio_tungstenite::{connect_async, tungstenite::{client::IntoClientRequest, Message}};
 
 pub async fn list(namespace: Option<&str>, cp_url: &str, token: Option<&str>) -> Result<()> {
     let url = match namespace {
@@ -9,12 +11,18 @@ pub async fn list(namespace: Option<&str>, cp_url: &str, token: Option<&str>) ->
     let mut req = client.get(url);
     if let Some(t) = token { req = req.header("Authorization", format!("Bearer {}", t)); }
     let routes: Vec<serde_json::Value> = req.send().await?.json().await.unwrap_or_default();
-    println!("{:<24} {:<16} {:<30}", "NAME", "NAMESPACE", "HOST");
+    println!("{:<24} {:<16} {:<30} {:<12} {:<10}", "NAME", "NAMESPACE", "HOST", "TLS", "PROTOCOL");
     for r in routes {
         let name = r["name"].as_str().unwrap_or("?");
         let ns = r["namespace"].as_str().unwrap_or("default");
         let host = r["host"].as_str().unwrap_or("-");
-        println!("{:<24} {:<16} {:<30}", name, ns, host);
+        let tls = if r["tls"].is_object() { r["tls"]["challenge"].as_str().unwrap_or("http-01") } else { "-" };
+        let l4 = &r["spec"]["l4"];
+        let protocol = match l4["listenPort"].as_u64() {
+            Some(port) => format!("{}/{}", l4["protocol"].as_str().unwrap_or("tcp"), port),
+            None => r["spec"]["protocol"].as_str().unwrap_or("http1").to_string(),
+        };
+        println!("{name:<24} {ns:<16} {host:<30} {tls:<12} {protocol:<10}");
     }
     Ok(())
 }
@@ -29,9 +37,8 @@ pub async fn apply(file: &str, cp_url: &str, token: Option<&str>) -> Result<()>
     let client = reqwest::Client::new();
     let mut req = client.post(format!("{}/api/v1/namespaces/{}/routes", cp_url.trim_end_matches('/'), namespace)).json(&doc);
     if let Some(t) = token { req = req.header("Authorization", format!("Bearer {}", t)); }
-    let resp = req.send().await?;
-    if resp.status().is_success() { println!("✓ Route applied"); } else { eprintln!("Error: {}", resp.status()); }
-    Ok(())
+    // Rules and other policies are validated by the control plane
+    report(req.send().await?, "Route applied").await
 }
 
 pub async fn delete(namespace: &str, name: &str, cp_url: &str, token: Option<&str>) -> Result<()> {
@@ -42,3 +49,98 @@ pub async fn delete(namespace: &str, name: &str, cp_url: &str, token: Option<&st
     if resp.status().is_success() { println!("✓ Route {}/{} deleted", namespace, name); } else { eprintln!("Error: {}", resp.status()); }
     Ok(())
 }
+
+pub async fn releases(namespace: &str, name: &str, window_secs: u64, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.get(format!("{}/api/v1/namespaces/{}/routes/{}/releases?window_secs={}", cp_url.trim_end_matches('/'), namespace, name, window_secs));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        return Err(anyhow::anyhow!("{}", body["error"].as_str().unwrap_or("failed to load releases")));
+    }
+    let body: serde_json::Value = resp.json().await?;
+    println!("{:<16} {:<8} {:<10} {:<8} {:<10}", "RELEASE", "WEIGHT", "REQUESTS", "ERRORS", "ERROR RATE");
+    for r in body["releases"].as_array().into_iter().flatten() {
+        let rate = r["error_rate"].as_f64().map(|rate| format!("{:.2}%", rate * 100.0)).unwrap_or_else(|| "-".into());
+        println!("{:<16} {:<8} {:<10} {:<8} {:<10}", r["release"].as_str().unwrap_or("?"), r["weight"], r["requests"], r["errors"], rate);
+    }
+    let shift = &body["shift"];
+    if shift.is_object() {
+        println!("\nShifting {} to {}% by {} every {}s (next step at {})", shift["release"].as_str().unwrap_or("?"), shift["target_weight"], shift["step"], shift["interval_secs"], shift["next_step_at"].as_str().unwrap_or("-"));
+    }
+    Ok(())
+}
+
+pub async fn weights(namespace: &str, name: &str, weights: &[String], cp_url: &str, token: Option<&str>) -> Result<()> {
+    let releases = weights
+        .iter()
+        .map(|w| {
+            let (release, weight) = w.split_once('=').ok_or_else(|| anyhow::anyhow!("expected RELEASE=WEIGHT, got {w:?}"))?;
+            Ok(serde_json::json!({ "release": release, "weight": weight.parse::<u32>()? }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let client = reqwest::Client::new();
+    let mut req = client.put(format!("{}/api/v1/namespaces/{}/routes/{}/weights", cp_url.trim_end_matches('/'), namespace, name)).json(&serde_json::json!({ "releases": releases }));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    report(req.send().await?, &format!("Weights of {namespace}/{name} set")).await
+}
+
+pub async fn shift(namespace: &str, name: &str, shift: &serde_json::Value, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.post(format!("{}/api/v1/namespaces/{}/routes/{}/weights/shift", cp_url.trim_end_matches('/'), namespace, name)).json(shift);
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    report(req.send().await?, &format!("Shifting {} to {}%", shift["release"].as_str().unwrap_or("?"), shift["target_weight"])).await
+}
+
+/// Promote or abort a release.
+pub async fn set_release(namespace: &str, name: &str, release: &str, action: &str, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.post(format!("{}/api/v1/namespaces/{}/routes/{}/weights/{}", cp_url.trim_end_matches('/'), namespace, name, action)).json(&serde_json::json!({ "release": release }));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let done = if action == "promote" { "now receives all traffic" } else { "no longer receives traffic" };
+    report(req.send().await?, &format!("Release {release} {done}")).await
+}
+
+async fn report(resp: reqwest::Response, success: &str) -> Result<()> {
+    if resp.status().is_success() {
+        println!("✓ {success}");
+        return Ok(());
+    }
+    let status = resp.status();
+    let body: serde_json::Value = resp.json().await.unwrap_or_default();
+    Err(anyhow::anyhow!("{}", body["error"].as_str().map(str::to_string).unwrap_or_else(|| status.to_string())))
+}
+
+/// Print access log records as they arrive, one line each unless `json`.
+pub async fn access(namespace: &str, name: &str, json: bool, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let ws_base = if cp_url.starts_with("https://") { cp_url.replacen("https", "wss", 1) } else { cp_url.replacen("http", "ws", 1) };
+    let mut request = format!("{}/api/v1/routes/{}/{}/access", ws_base.trim_end_matches('/'), namespace, name).into_client_request()?;
+    if let Some(t) = token { request.headers_mut().insert("Authorization", format!("Bearer {t}").parse()?); }
+    let (mut ws_stream, _) = connect_async(request).await?;
+
+    while let Some(msg) = ws_stream.next().await {
+        let line = match msg? {
+            Message::Text(t) => t,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let record: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(record) if !json => record,
+            _ => { println!("{line}"); continue; }
+        };
+        let latency = record["latency_ms"].as_f64().unwrap_or_default();
+        println!(
+            "{} {} {} {} {} {:.1}ms {}B upstream={}",
+            record["timestamp"].as_str().unwrap_or("-"),
+            record["client_ip"].as_str().unwrap_or("-"),
+            record["method"].as_str().unwrap_or("-"),
+            record["path"].as_str().unwrap_or("-"),
+            record["status"],
+            latency,
+            record["bytes_sent"],
+            record["upstream"].as_str().unwrap_or("-"),
+        );
+    }
+    Ok(())
+}
@@ -1,22 +1,147 @@
 use anyhow::Result;
 use std::io::{self, Write};
 
-pub async fn set(namespace: &str, name: &str, cp_url: &str, token: Option<&str>) -> Result<()> {
-    print!("Enter secret value: ");
-    io::stdout().flush()?;
-    let value = rpassword::read_password()?;
+/// Where `secret set` reads the value from.
+pub enum Source<'a> {
+    Prompt,
+    File(&'a str),
+    /// `KEY=VALUE` lines, one secret per key; `name` is ignored.
+    EnvFile(&'a str),
+}
+
+pub async fn set(namespace: &str, name: Option<&str>, source: Source<'_>, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let entries = match source {
+        Source::Prompt => {
+            let name = name.ok_or_else(|| anyhow::anyhow!("a secret name is required"))?;
+            print!("Enter secret value: ");
+            io::stdout().flush()?;
+            vec![(name.to_string(), rpassword::read_password()?)]
+        }
+        Source::File(path) => {
+            let name = name.ok_or_else(|| anyhow::anyhow!("a secret name is required with --from-file"))?;
+            vec![(name.to_string(), std::fs::read_to_string(path)?)]
+        }
+        Source::EnvFile(path) => parse_env_file(&std::fs::read_to_string(path)?)?,
+    };
 
+    let client = reqwest::Client::new();
+    for (name, value) in entries {
+        let mut req = client
+            .post(format!("{}/api/v1/namespaces/{namespace}/secrets", cp_url.trim_end_matches('/')))
+            .json(&serde_json::json!({ "name": name, "value": value }));
+        if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+        let resp = req.send().await?;
+        if resp.status().is_success() {
+            let body: serde_json::Value = resp.json().await.unwrap_or_default();
+            println!("✓ Secret {name} set (version {})", body["version"].as_i64().unwrap_or(0));
+        } else {
+            eprintln!("Error: {name} {}", resp.status());
+        }
+    }
+    Ok(())
+}
+
+/// Parse `KEY=VALUE` lines, skipping blanks and `#` comments. Values may be
+/// wrapped in single or double quotes.
+fn parse_env_file(content: &str) -> Result<Vec<(String, String)>> {
+    let mut entries = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').ok_or_else(|| anyhow::anyhow!("line {}: expected KEY=VALUE", i + 1))?;
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+            .unwrap_or(value);
+        entries.push((key.trim().to_string(), value.to_string()));
+    }
+    Ok(entries)
+}
+
+pub async fn get(namespace: &str, name: &str, version: Option<i32>, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.get(format!("{}/api/v1/namespaces/{namespace}/secrets/{name}", cp_url.trim_end_matches('/')));
+    if let Some(v) = version { req = req.query(&[("version", v)]); }
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    match resp.status() {
+        s if s.is_success() => {
+            let body: serde_json::Value = resp.json().await?;
+            // Raw value only, so the output can be piped
+            print!("{}", body["value"].as_str().unwrap_or_default());
+            io::stdout().flush()?;
+        }
+        reqwest::StatusCode::UNAUTHORIZED => eprintln!("Error: reading secret values requires a token (--token / SPAN_TOKEN)"),
+        reqwest::StatusCode::FORBIDDEN => eprintln!("Error: reading secret values requires the admin role in {namespace}"),
+        s => eprintln!("Error: {s}"),
+    }
+    Ok(())
+}
+
+pub async fn versions(namespace: &str, name: &str, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.get(format!("{}/api/v1/namespaces/{namespace}/secrets/{name}/versions", cp_url.trim_end_matches('/')));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        eprintln!("Error: {}", resp.status());
+        return Ok(());
+    }
+    let versions: Vec<serde_json::Value> = resp.json().await.unwrap_or_default();
+    println!("{:<8} {:<28} {:<28}", "VERSION", "CREATED", "DELETED");
+    for v in versions {
+        println!(
+            "{:<8} {:<28} {:<28}",
+            v["version"].as_i64().unwrap_or(0),
+            v["created_at"].as_str().unwrap_or("-"),
+            v["deleted_at"].as_str().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+pub async fn delete(namespace: &str, name: &str, version: Option<i32>, force: bool, cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.delete(format!("{}/api/v1/namespaces/{namespace}/secrets/{name}", cp_url.trim_end_matches('/')));
+    if let Some(v) = version { req = req.query(&[("version", v)]); }
+    if force { req = req.query(&[("force", true)]); }
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    let status = resp.status();
+    if status.is_success() {
+        let what = version.map(|v| format!(" version {v}")).unwrap_or_default();
+        println!("✓ Secret {name}{what} deleted");
+    } else if status == reqwest::StatusCode::CONFLICT {
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        let apps: Vec<&str> = body["apps"].as_array().map(|a| a.iter().filter_map(|v| v.as_str()).collect()).unwrap_or_default();
+        eprintln!("Error: secret {name} is still used by: {}", apps.join(", "));
+        eprintln!("Re-run with --force to delete it anyway");
+    } else {
+        eprintln!("Error: {status}");
+    }
+    Ok(())
+}
+
+/// Pin `app`'s references to the secret at `version`, or unpin with `None`.
+pub async fn pin(namespace: &str, name: &str, app: &str, version: Option<i32>, cp_url: &str, token: Option<&str>) -> Result<()> {
     let client = reqwest::Client::new();
     let mut req = client
-        .post(format!(
-            "{}/api/v1/namespaces/{}/secrets",
-            cp_url.trim_end_matches('/'),
-            namespace
-        ))
-        .json(&serde_json::json!({ "name": name, "value": value }));
-    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {}", t)); }
+        .put(format!("{}/api/v1/namespaces/{namespace}/apps/{app}/secrets/{name}", cp_url.trim_end_matches('/')))
+        .json(&serde_json::json!({ "version": version }));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
     let resp = req.send().await?;
-    if resp.status().is_success() { println!("✓ Secret {} created", name); } else { eprintln!("Error: {}", resp.status()); }
+    if resp.status().is_success() {
+        match version {
+            Some(v) => println!("✓ App {app} pinned to {name} version {v}"),
+            None => println!("✓ App {app} follows the latest version of {name}"),
+        }
+    } else {
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        eprintln!("Error: {} {}", status, body["error"].as_str().unwrap_or(""));
+    }
     Ok(())
 }
 
@@ -38,3 +163,39 @@ pub async fn list(namespace: &str, cp_url: &str, token: Option<&str>) -> Result<
     }
     Ok(())
 }
+
+pub async fn rotate_master(cp_url: &str, token: Option<&str>) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut req = client.post(format!("{}/api/v1/secrets/master-key/rotate", cp_url.trim_end_matches('/')));
+    if let Some(t) = token { req = req.header("Authorization", format!("Bearer {t}")); }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        eprintln!("Error: {} {}", status, body["error"].as_str().unwrap_or(""));
+        return Ok(());
+    }
+    let body: serde_json::Value = resp.json().await?;
+    println!(
+        "✓ Master key rotated to v{} ({} data keys re-wrapped)",
+        body["version"].as_i64().unwrap_or(0),
+        body["rewrapped"].as_u64().unwrap_or(0)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_env_files() {
+        let entries = parse_env_file("# db\nDB_USER=app\nexport DB_PASSWORD=\"p=ss word\"\n\nTOKEN='abc'\n").unwrap();
+        assert_eq!(entries, vec![
+            ("DB_USER".to_string(), "app".to_string()),
+            ("DB_PASSWORD".to_string(), "p=ss word".to_string()),
+            ("TOKEN".to_string(), "abc".to_string()),
+        ]);
+        assert!(parse_env_file("NOT_AN_ASSIGNMENT").is_err());
+    }
+}
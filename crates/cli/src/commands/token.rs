@@ -0,0 +1,11 @@
+use anyhow::Result;
+use common::auth::{issue_token, Claims, Role};
+use std::time::Duration;
+
+/// Mint a bearer token signed with the control plane's `JWT_SECRET`.
+pub fn create(subject: &str, role: &str, namespace: &str, ttl_hours: u64, jwt_secret: &str) -> Result<()> {
+    let role: Role = role.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+    let claims = Claims::new(subject, Duration::from_secs(ttl_hours * 60 * 60)).with_role(namespace, role);
+    println!("{}", issue_token(&claims, jwt_secret)?);
+    Ok(())
+}
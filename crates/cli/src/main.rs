@@ -13,7 +13,7 @@ struct Cli {
     #[arg(long, env = "SPAN_CP_URL", default_value = "http://localhost:8080")]
     cp_url: String,
 
-    /// Auth token
+    /// Auth token; defaults to the session from `span login`
     #[arg(long, env = "SPAN_TOKEN")]
     token: Option<String>,
 }
@@ -47,17 +47,25 @@ enum Commands {
     #[command(subcommand)]
     Route(RouteCommands),
 
+    /// Cluster certificate authority management
+    #[command(subcommand)]
+    Ca(CaCommands),
+
+    /// API token management
+    #[command(subcommand)]
+    Token(TokenCommands),
+
+    /// Sign in through the cluster's identity provider
+    Login,
+
+    /// Forget the session saved by `span login`
+    Logout,
+
+    /// Show who changed what (requires the cluster-wide admin role)
+    Audit(commands::audit::AuditArgs),
+
     /// View logs for an app
-    Logs {
-        /// App namespace/name
-        app: String,
-        /// Follow logs
-        #[arg(short, long)]
-        follow: bool,
-        /// Tail last N lines (optional, server-dependent)
-        #[arg(short, long)]
-        tail: Option<usize>,
-    },
+    Logs(commands::logs::LogsArgs),
 }
 
 #[derive(Subcommand, Debug)]
@@ -130,10 +138,31 @@ enum BucketCommands {
 
 #[derive(Subcommand, Debug)]
 enum SecretCommands {
-    /// Set a secret (prompts for value)
-    Set { namespace: String, name: String },
+    /// Set a secret (prompts for value unless a file is given)
+    Set {
+        namespace: String,
+        name: Option<String>,
+        /// Read the value from a file
+        #[arg(long, conflicts_with = "from_env_file")]
+        from_file: Option<String>,
+        /// Set one secret per KEY=VALUE line
+        #[arg(long)]
+        from_env_file: Option<String>,
+    },
+    /// Print a secret value (requires the admin role)
+    Get { namespace: String, name: String, #[arg(long)] version: Option<i32> },
     /// List secrets in a namespace
     List { namespace: String },
+    /// List the versions of a secret
+    Versions { namespace: String, name: String },
+    /// Delete a secret, or a single version of it
+    Delete { namespace: String, name: String, #[arg(long)] version: Option<i32>, #[arg(long)] force: bool },
+    /// Pin an app to a specific version of a secret
+    Pin { namespace: String, name: String, version: i32, #[arg(long)] app: String },
+    /// Let an app follow the latest version of a secret again
+    Unpin { namespace: String, name: String, #[arg(long)] app: String },
+    /// Move every secret to a new master key version
+    RotateMaster,
 }
 
 #[derive(Subcommand, Debug)]
@@ -144,62 +173,149 @@ enum RouteCommands {
     Apply { #[arg(short, long)] file: String },
     /// Delete a route
     Delete { namespace: String, name: String },
+    /// Show a route's release weights and per-release error rates
+    Releases { namespace: String, name: String, #[arg(long, default_value_t = 300)] window_secs: u64 },
+    /// Set release weights, e.g. `v12=90 v13=10`
+    Weights { namespace: String, name: String, #[arg(required = true)] weights: Vec<String> },
+    /// Move a release gradually to a target weight
+    Shift {
+        namespace: String,
+        name: String,
+        release: String,
+        #[arg(long, default_value_t = 100)] to: u32,
+        #[arg(long, default_value_t = 10)] step: u32,
+        #[arg(long, default_value_t = 60)] interval_secs: u32,
+        /// Drop the release to weight 0 when its error rate goes over this (0-1)
+        #[arg(long)] max_error_rate: Option<f64>,
+        /// Requests a release must serve before each step is judged
+        #[arg(long, default_value_t = 0)] min_requests: u64,
+    },
+    /// Send all of a route's traffic to a release
+    Promote { namespace: String, name: String, release: String },
+    /// Take all of a route's traffic off a release
+    Abort { namespace: String, name: String, release: String },
+    /// Stream a route's access log from every gateway
+    Access { namespace: String, name: String, #[arg(long)] json: bool },
+}
+
+#[derive(Subcommand, Debug)]
+enum CaCommands {
+    /// Show the active CA and nodes still awaiting re-issue
+    Status,
+    /// Introduce a new CA; the current one stays trusted until retired
+    Rotate,
+    /// Stop trusting the previous CA once every node has been re-issued
+    Retire { #[arg(long)] force: bool },
+}
+
+#[derive(Subcommand, Debug)]
+enum TokenCommands {
+    /// Sign a token granting a role in a namespace ("*" for all)
+    Create {
+        #[arg(long)]
+        subject: String,
+        #[arg(long, default_value = "viewer")]
+        role: String,
+        #[arg(long, default_value = "*")]
+        namespace: String,
+        #[arg(long, default_value_t = 24)]
+        ttl_hours: u64,
+        /// The control plane's JWT secret
+        #[arg(long, env = "JWT_SECRET", hide_env_values = true)]
+        jwt_secret: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let token = cli.token.clone().or_else(|| commands::login::saved_token(&cli.cp_url));
 
     match cli.command {
-        Commands::Init(args) => commands::init::run(args).await?,
+        Commands::Init(args) => commands::init::run(args, token.as_deref()).await?,
 
         Commands::Node(cmd) => match cmd {
-            NodeCommands::List => commands::node::list(&cli.cp_url, cli.token.as_deref()).await?,
-            NodeCommands::Get { id } => commands::node::get(&id, &cli.cp_url, cli.token.as_deref()).await?,
-            NodeCommands::Join { token } => commands::node::join(token.as_deref(), &cli.cp_url, cli.token.as_deref()).await?,
-            NodeCommands::Cordon { id } => commands::node::cordon(&id, &cli.cp_url, cli.token.as_deref()).await?,
-            NodeCommands::Uncordon { id } => commands::node::uncordon(&id, &cli.cp_url, cli.token.as_deref()).await?,
-            NodeCommands::Drain { id } => commands::node::drain(&id, &cli.cp_url, cli.token.as_deref()).await?,
-            NodeCommands::Remove { id } => commands::node::remove(&id, &cli.cp_url, cli.token.as_deref()).await?,
+            NodeCommands::List => commands::node::list(&cli.cp_url, token.as_deref()).await?,
+            NodeCommands::Get { id } => commands::node::get(&id, &cli.cp_url, token.as_deref()).await?,
+            NodeCommands::Join { token: join_token } => commands::node::join(join_token.as_deref(), &cli.cp_url, token.as_deref()).await?,
+            NodeCommands::Cordon { id } => commands::node::cordon(&id, &cli.cp_url, token.as_deref()).await?,
+            NodeCommands::Uncordon { id } => commands::node::uncordon(&id, &cli.cp_url, token.as_deref()).await?,
+            NodeCommands::Drain { id } => commands::node::drain(&id, &cli.cp_url, token.as_deref()).await?,
+            NodeCommands::Remove { id } => commands::node::remove(&id, &cli.cp_url, token.as_deref()).await?,
 
         },
 
         Commands::App(cmd) => match cmd {
-            AppCommands::List { namespace } => commands::app::list(namespace.as_deref(), &cli.cp_url, cli.token.as_deref()).await?,
-            AppCommands::Get { namespace, name } => commands::app::get(&namespace, &name, &cli.cp_url, cli.token.as_deref()).await?,
-            AppCommands::Apply { file } => commands::app::apply(&file, &cli.cp_url, cli.token.as_deref()).await?,
-            AppCommands::Delete { namespace, name } => commands::app::delete(&namespace, &name, &cli.cp_url, cli.token.as_deref()).await?,
-            AppCommands::Deploy { namespace, name, version } => commands::app::deploy(&namespace, &name, version, &cli.cp_url, cli.token.as_deref()).await?,
-            AppCommands::Rollback { namespace, name } => commands::app::rollback(&namespace, &name, &cli.cp_url, cli.token.as_deref()).await?,
-            AppCommands::Scale { namespace, name, replicas } => commands::app::scale(&namespace, &name, replicas, &cli.cp_url, cli.token.as_deref()).await?,
+            AppCommands::List { namespace } => commands::app::list(namespace.as_deref(), &cli.cp_url, token.as_deref()).await?,
+            AppCommands::Get { namespace, name } => commands::app::get(&namespace, &name, &cli.cp_url, token.as_deref()).await?,
+            AppCommands::Apply { file } => commands::app::apply(&file, &cli.cp_url, token.as_deref()).await?,
+            AppCommands::Delete { namespace, name } => commands::app::delete(&namespace, &name, &cli.cp_url, token.as_deref()).await?,
+            AppCommands::Deploy { namespace, name, version } => commands::app::deploy(&namespace, &name, version, &cli.cp_url, token.as_deref()).await?,
+            AppCommands::Rollback { namespace, name } => commands::app::rollback(&namespace, &name, &cli.cp_url, token.as_deref()).await?,
+            AppCommands::Scale { namespace, name, replicas } => commands::app::scale(&namespace, &name, replicas, &cli.cp_url, token.as_deref()).await?,
         },
 
         Commands::Function(cmd) => match cmd {
-            FunctionCommands::List { namespace } => commands::function::list(namespace.as_deref(), &cli.cp_url, cli.token.as_deref()).await?,
-            FunctionCommands::Invoke { namespace, name, data, file, path } => commands::function::invoke(&namespace, &name, data.as_deref(), file.as_deref(), path.as_deref(), &cli.cp_url, cli.token.as_deref()).await?,
+            FunctionCommands::List { namespace } => commands::function::list(namespace.as_deref(), &cli.cp_url, token.as_deref()).await?,
+            FunctionCommands::Invoke { namespace, name, data, file, path } => commands::function::invoke(&namespace, &name, data.as_deref(), file.as_deref(), path.as_deref(), &cli.cp_url, token.as_deref()).await?,
         },
 
         Commands::Bucket(cmd) => match cmd {
-            BucketCommands::Create { namespace, name, public } => commands::bucket::create(&namespace, &name, public, &cli.cp_url, cli.token.as_deref()).await?,
-            BucketCommands::Upload { bucket, key, file } => commands::bucket::upload(&bucket, &key, &file, &cli.cp_url, cli.token.as_deref()).await?,
-            BucketCommands::Download { bucket, key, file } => commands::bucket::download(&bucket, &key, &file, &cli.cp_url, cli.token.as_deref()).await?,
-            BucketCommands::ListObjects { bucket, prefix } => commands::bucket::list_objects(&bucket, prefix.as_deref(), &cli.cp_url, cli.token.as_deref()).await?,
+            BucketCommands::Create { namespace, name, public } => commands::bucket::create(&namespace, &name, public, &cli.cp_url, token.as_deref()).await?,
+            BucketCommands::Upload { bucket, key, file } => commands::bucket::upload(&bucket, &key, &file, &cli.cp_url, token.as_deref()).await?,
+            BucketCommands::Download { bucket, key, file } => commands::bucket::download(&bucket, &key, &file, &cli.cp_url, token.as_deref()).await?,
+            BucketCommands::ListObjects { bucket, prefix } => commands::bucket::list_objects(&bucket, prefix.as_deref(), &cli.cp_url, token.as_deref()).await?,
         },
 
         Commands::Secret(cmd) => match cmd {
-            SecretCommands::Set { namespace, name } => commands::secret::set(&namespace, &name, &cli.cp_url, cli.token.as_deref()).await?,
-            SecretCommands::List { namespace } => commands::secret::list(&namespace, &cli.cp_url, cli.token.as_deref()).await?,
+            SecretCommands::Set { namespace, name, from_file, from_env_file } => {
+                let source = match (&from_file, &from_env_file) {
+                    (Some(path), _) => commands::secret::Source::File(path),
+                    (_, Some(path)) => commands::secret::Source::EnvFile(path),
+                    _ => commands::secret::Source::Prompt,
+                };
+                commands::secret::set(&namespace, name.as_deref(), source, &cli.cp_url, token.as_deref()).await?
+            }
+            SecretCommands::Get { namespace, name, version } => commands::secret::get(&namespace, &name, version, &cli.cp_url, token.as_deref()).await?,
+            SecretCommands::List { namespace } => commands::secret::list(&namespace, &cli.cp_url, token.as_deref()).await?,
+            SecretCommands::Versions { namespace, name } => commands::secret::versions(&namespace, &name, &cli.cp_url, token.as_deref()).await?,
+            SecretCommands::Delete { namespace, name, version, force } => commands::secret::delete(&namespace, &name, version, force, &cli.cp_url, token.as_deref()).await?,
+            SecretCommands::Pin { namespace, name, version, app } => commands::secret::pin(&namespace, &name, &app, Some(version), &cli.cp_url, token.as_deref()).await?,
+            SecretCommands::Unpin { namespace, name, app } => commands::secret::pin(&namespace, &name, &app, None, &cli.cp_url, token.as_deref()).await?,
+            SecretCommands::RotateMaster => commands::secret::rotate_master(&cli.cp_url, token.as_deref()).await?,
         },
 
         Commands::Route(cmd) => match cmd {
-            RouteCommands::List { namespace } => commands::route::list(namespace.as_deref(), &cli.cp_url, cli.token.as_deref()).await?,
-            RouteCommands::Apply { file } => commands::route::apply(&file, &cli.cp_url, cli.token.as_deref()).await?,
-            RouteCommands::Delete { namespace, name } => commands::route::delete(&namespace, &name, &cli.cp_url, cli.token.as_deref()).await?,
+            RouteCommands::List { namespace } => commands::route::list(namespace.as_deref(), &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Apply { file } => commands::route::apply(&file, &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Delete { namespace, name } => commands::route::delete(&namespace, &name, &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Releases { namespace, name, window_secs } => commands::route::releases(&namespace, &name, window_secs, &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Weights { namespace, name, weights } => commands::route::weights(&namespace, &name, &weights, &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Shift { namespace, name, release, to, step, interval_secs, max_error_rate, min_requests } => {
+                let shift = serde_json::json!({ "release": release, "target_weight": to, "step": step, "interval_secs": interval_secs, "max_error_rate": max_error_rate, "min_requests": min_requests });
+                commands::route::shift(&namespace, &name, &shift, &cli.cp_url, token.as_deref()).await?
+            }
+            RouteCommands::Promote { namespace, name, release } => commands::route::set_release(&namespace, &name, &release, "promote", &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Abort { namespace, name, release } => commands::route::set_release(&namespace, &name, &release, "abort", &cli.cp_url, token.as_deref()).await?,
+            RouteCommands::Access { namespace, name, json } => commands::route::access(&namespace, &name, json, &cli.cp_url, token.as_deref()).await?,
+        },
+
+        Commands::Ca(cmd) => match cmd {
+            CaCommands::Status => commands::ca::status(&cli.cp_url, token.as_deref()).await?,
+            CaCommands::Rotate => commands::ca::rotate(&cli.cp_url, token.as_deref()).await?,
+            CaCommands::Retire { force } => commands::ca::retire(force, &cli.cp_url, token.as_deref()).await?,
         },
 
-        Commands::Logs { app, follow, tail } => {
-            commands::logs::stream_logs(&app, follow, tail, &cli.cp_url, cli.token.as_deref()).await?
-        }
+        Commands::Token(cmd) => match cmd {
+            TokenCommands::Create { subject, role, namespace, ttl_hours, jwt_secret } => commands::token::create(&subject, &role, &namespace, ttl_hours, &jwt_secret)?,
+        },
+
+        Commands::Login => commands::login::login(&cli.cp_url).await?,
+        Commands::Logout => commands::login::logout()?,
+
+        Commands::Audit(args) => commands::audit::list(&args, &cli.cp_url, token.as_deref()).await?,
+
+        Commands::Logs(args) => commands::logs::stream_logs(&args, &cli.cp_url, token.as_deref()).await?,
     }
 
     Ok(())
@@ -0,0 +1,102 @@
+use std::{collections::HashMap, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+/// Namespace key granting a role in every namespace.
+pub const ALL_NAMESPACES: &str = "*";
+
+/// What a caller may do within a namespace. Roles are ordered, so a higher
+/// role includes everything a lower one allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read metadata: apps, routes, secret names and versions.
+    Viewer,
+    /// Deploy apps and write secrets.
+    Developer,
+    /// Everything, including reading secret values.
+    Admin,
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "developer" => Ok(Role::Developer),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("unknown role {other:?}; expected viewer, developer or admin")),
+        }
+    }
+}
+
+/// Claims carried by a control plane bearer token (HS256, signed with `JWT_SECRET`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    /// Role per namespace; [`ALL_NAMESPACES`] applies everywhere.
+    #[serde(default)]
+    pub roles: HashMap<String, Role>,
+}
+
+impl Claims {
+    pub fn new(sub: impl Into<String>, ttl: Duration) -> Self {
+        let exp = (SystemTime::now() + ttl).duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        Claims { sub: sub.into(), exp, roles: HashMap::new() }
+    }
+
+    pub fn with_role(mut self, namespace: impl Into<String>, role: Role) -> Self {
+        self.roles.insert(namespace.into(), role);
+        self
+    }
+
+    /// Highest role held in `namespace`, counting cluster-wide grants.
+    pub fn role_in(&self, namespace: &str) -> Option<Role> {
+        let scoped = self.roles.get(namespace).copied();
+        let global = self.roles.get(ALL_NAMESPACES).copied();
+        scoped.max(global)
+    }
+
+    pub fn allows(&self, namespace: &str, required: Role) -> bool {
+        self.role_in(namespace).is_some_and(|role| role >= required)
+    }
+}
+
+pub fn issue_token(claims: &Claims, secret: &str) -> Result<String, jsonwebtoken::errors::Error> {
+    encode(&Header::default(), claims, &EncodingKey::from_secret(secret.as_bytes()))
+}
+
+/// Verify signature and expiry, returning the token's claims.
+pub fn verify_token(token: &str, secret: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default()).map(|data| data.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_are_scoped_to_namespaces() {
+        let claims = Claims::new("alice", Duration::from_secs(60)).with_role("prod", Role::Viewer).with_role("dev", Role::Admin);
+        assert!(claims.allows("dev", Role::Admin));
+        assert!(claims.allows("prod", Role::Viewer));
+        assert!(!claims.allows("prod", Role::Admin));
+        assert!(!claims.allows("other", Role::Viewer));
+
+        let global = Claims::new("ops", Duration::from_secs(60)).with_role(ALL_NAMESPACES, Role::Admin);
+        assert!(global.allows("anything", Role::Admin));
+    }
+
+    #[test]
+    fn tokens_round_trip_and_reject_wrong_secret() {
+        let claims = Claims::new("alice", Duration::from_secs(60)).with_role("dev", Role::Developer);
+        let token = issue_token(&claims, "s3cret").unwrap();
+        let verified = verify_token(&token, "s3cret").unwrap();
+        assert_eq!(verified.sub, "alice");
+        assert_eq!(verified.role_in("dev"), Some(Role::Developer));
+        assert!(verify_token(&token, "other").is_err());
+    }
+}
@@ -7,7 +7,131 @@ pub enum SpanEvent {
     BuildLog { build_id: String, line: String },
     BuildCompleted { build_id: String, status: String },
     DeploymentStarted { app_id: String, release_id: String },
-    ContainerLog { container_id: String, line: String },
+    /// A line container `log.container_id` of app `namespace/app` wrote.
+    ContainerLog { namespace: String, app: String, log: LogEnvelope },
+    CertificatesRevoked { node_id: String, serials: Vec<String> },
+    AuditRecorded { actor: String, action: String, resource: String, request_id: String, outcome: String, status: i32 },
+    /// A route was applied or deleted; `version` is the new gateway config version.
+    RouteChanged { version: i64, namespace: String, name: String },
+    /// The endpoints of app `backend` changed.
+    EndpointsChanged { version: i64, namespace: String, backend: String },
+    /// A TLS certificate for `host` was issued or renewed.
+    CertificateIssued { version: i64, host: String },
+}
+
+/// Subjects gateways subscribe to for routing table changes.
+pub const GATEWAY_SUBJECTS: &str = "span.gateway.>";
+
+/// Subject gateways exchange rate limit hits on, for routes whose limits
+/// are shared. Kept outside [`GATEWAY_SUBJECTS`] so hits do not trigger
+/// table reloads.
+pub const GATEWAY_RATE_LIMIT_SUBJECT: &str = "span.ratelimit.gateway";
+
+/// Access logs of every route, as published by gateways.
+pub const ACCESS_LOG_SUBJECTS: &str = "span.routes.*.*.access";
+
+/// Subject the access log of route `namespace/name` is published on.
+pub fn access_log_subject(namespace: &str, name: &str) -> String {
+    format!("span.routes.{namespace}.{name}.access")
+}
+
+/// Subject the logs of app `namespace/name` are published on.
+pub fn app_log_subject(namespace: &str, name: &str) -> String {
+    format!("span.apps.{namespace}.{name}.logs")
+}
+
+/// The output a container log line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// Severity of a log line, ordered from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// A level name as apps commonly write it: `WARN`, `warning`, `E`...
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "trace" | "trc" => Self::Trace,
+            "debug" | "dbg" => Self::Debug,
+            "info" | "inf" | "notice" => Self::Info,
+            "warn" | "warning" | "wrn" => Self::Warn,
+            "error" | "err" | "fatal" | "critical" | "crit" | "panic" => Self::Error,
+            _ => return None,
+        })
+    }
+
+    /// The level a line declares, if it declares one: a `level` field of
+    /// JSON or logfmt lines, or a level name among its first words
+    /// (`2024-05-01T12:00:00Z WARN ...`, `[error] ...`).
+    pub fn parse(line: &str) -> Option<Self> {
+        if let Some(level) = line.trim_start().starts_with('{').then(|| serde_json::from_str::<serde_json::Value>(line).ok()).flatten() {
+            return ["level", "severity", "lvl"].iter().find_map(|k| level[k].as_str()).and_then(Self::from_name);
+        }
+        if let Some((_, rest)) = line.split_once("level=") {
+            return Self::from_name(rest.trim_start_matches('"').split(|c: char| !c.is_ascii_alphabetic()).next()?);
+        }
+        line.split_whitespace()
+            .take(4)
+            .find_map(|word| Self::from_name(word.trim_matches(|c| matches!(c, '[' | ']' | '(' | ')' | ':' | '<' | '>'))))
+    }
+}
+
+/// One line a container wrote, as published on its app's log subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEnvelope {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub stream: LogStream,
+    pub container_id: String,
+    pub replica: String,
+    pub node: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    pub line: String,
+}
+
+impl LogEnvelope {
+    /// `line` as written by container `container_id` of `app` on `node`.
+    /// The replica is named after the app and the start of the random part
+    /// of the container id (`web-3f2a1`).
+    pub fn new(app: &str, node: &str, container_id: String, stream: LogStream, timestamp: chrono::DateTime<chrono::Utc>, line: String) -> Self {
+        let suffix: String = container_id.rsplit('-').next().unwrap_or(&container_id).chars().take(5).collect();
+        Self { timestamp, stream, replica: format!("{app}-{suffix}"), container_id, node: node.to_string(), level: LogLevel::parse(&line), line }
+    }
+}
+
+/// One request a gateway routed, recorded once its response was sent.
+/// Requests the gateway answered itself have no upstream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessLog {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub namespace: String,
+    pub route: String,
+    pub host: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+    /// From the request arriving to the last byte of the response.
+    pub latency_ms: f64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
 }
 
 pub struct EventPublisher {
@@ -18,9 +142,12 @@ impl EventPublisher {
     pub async fn publish(&self, event: SpanEvent) -> anyhow::Result<()> {
         let subject = Self::subject_for_event(&event);
         match event {
-            SpanEvent::BuildLog { line, .. } | SpanEvent::ContainerLog { line, .. } => {
+            SpanEvent::BuildLog { line, .. } => {
                 self.client.publish(subject, line.into()).await?;
             }
+            SpanEvent::ContainerLog { log, .. } => {
+                self.client.publish(subject, serde_json::to_vec(&log)?.into()).await?;
+            }
             other => {
                 let payload = serde_json::to_vec(&other)?;
                 self.client.publish(subject, payload.into()).await?;
@@ -36,8 +163,13 @@ impl EventPublisher {
             SpanEvent::BuildLog { build_id, .. } => format!("span.builds.{build_id}.logs"),
             SpanEvent::BuildCompleted { build_id, .. } => format!("span.builds.{build_id}.status"),
             SpanEvent::DeploymentStarted { release_id, .. } => format!("span.deploys.{release_id}.status"),
-            // Using container_id directly for container logs; app-based logs use span.apps.{ns}.{name}.logs
-            SpanEvent::ContainerLog { container_id, .. } => format!("span.containers.{container_id}.logs"),
+            SpanEvent::ContainerLog { namespace, app, .. } => app_log_subject(namespace, app),
+            // Consumers (other control planes, gateways) refetch the CRL when this fires
+            SpanEvent::CertificatesRevoked { .. } => "span.pki.revocations".to_string(),
+            SpanEvent::AuditRecorded { action, .. } => format!("span.audit.{action}"),
+            SpanEvent::RouteChanged { namespace, name, .. } => format!("span.gateway.routes.{namespace}.{name}"),
+            SpanEvent::EndpointsChanged { namespace, backend, .. } => format!("span.gateway.endpoints.{namespace}.{backend}"),
+            SpanEvent::CertificateIssued { host, .. } => format!("span.gateway.certificates.{host}"),
         }
     }
 }
@@ -63,7 +195,53 @@ mod tests {
         let e = SpanEvent::DeploymentStarted { app_id: "app".into(), release_id: "rel".into() };
         assert_eq!(EventPublisher::subject_for_event(&e), "span.deploys.rel.status");
 
-        let e = SpanEvent::ContainerLog { container_id: "cid".into(), line: "line".into() };
-        assert_eq!(EventPublisher::subject_for_event(&e), "span.containers.cid.logs");
+        let log = LogEnvelope {
+            timestamp: chrono::Utc::now(),
+            stream: LogStream::Stdout,
+            container_id: "cid".into(),
+            replica: "web-0".into(),
+            node: "node1".into(),
+            level: None,
+            line: "line".into(),
+        };
+        let e = SpanEvent::ContainerLog { namespace: "default".into(), app: "web".into(), log };
+        assert_eq!(EventPublisher::subject_for_event(&e), "span.apps.default.web.logs");
+
+        let e = SpanEvent::CertificatesRevoked { node_id: "n1".into(), serials: vec!["ab".into()] };
+        assert_eq!(EventPublisher::subject_for_event(&e), "span.pki.revocations");
+
+        let e = SpanEvent::AuditRecorded { actor: "alice".into(), action: "nodes.drain".into(), resource: "nodes/n1/drain".into(), request_id: "r".into(), outcome: "success".into(), status: 202 };
+        assert_eq!(EventPublisher::subject_for_event(&e), "span.audit.nodes.drain");
+
+        assert_eq!(access_log_subject("default", "web"), "span.routes.default.web.access");
+
+        let e = SpanEvent::RouteChanged { version: 7, namespace: "default".into(), name: "web".into() };
+        assert_eq!(EventPublisher::subject_for_event(&e), "span.gateway.routes.default.web");
+
+        let e = SpanEvent::EndpointsChanged { version: 8, namespace: "default".into(), backend: "api".into() };
+        assert_eq!(EventPublisher::subject_for_event(&e), "span.gateway.endpoints.default.api");
+
+        let e = SpanEvent::CertificateIssued { version: 9, host: "app.example.com".into() };
+        assert_eq!(EventPublisher::subject_for_event(&e), "span.gateway.certificates.app.example.com");
+    }
+
+    #[test]
+    fn parses_log_levels() {
+        let cases = [
+            (r#"{"level":"WARN","msg":"disk almost full"}"#, Some(LogLevel::Warn)),
+            (r#"{"severity":"error","msg":"x"}"#, Some(LogLevel::Error)),
+            (r#"{"msg":"no level"}"#, None),
+            ("time=2024-05-01T12:00:00Z level=debug msg=\"cache miss\"", Some(LogLevel::Debug)),
+            (r#"level="info" msg=started"#, Some(LogLevel::Info)),
+            ("2024-05-01T12:00:00.123Z  INFO app::server: listening on 0.0.0.0:8080", Some(LogLevel::Info)),
+            ("[error] connect() failed (111: Connection refused)", Some(LogLevel::Error)),
+            ("E0501 12:00:00.000000 1 main.go:42] FATAL: out of memory", None),
+            ("GET /info 200", None),
+            ("listening on port 8080", None),
+        ];
+        for (line, level) in cases {
+            assert_eq!(LogLevel::parse(line), level, "{line}");
+        }
+        assert!(LogLevel::Warn > LogLevel::Info);
     }
 }
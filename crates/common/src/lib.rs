@@ -1,3 +1,4 @@
 pub mod telemetry;
 pub mod error;
 pub mod events;
+pub mod auth;
@@ -0,0 +1,241 @@
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use crypto::acme::AccountKey;
+use models::route::AcmeChallenge;
+use reqwest::{header, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+const REPLAY_NONCE: &str = "replay-nonce";
+const JOSE_JSON: &str = "application/jose+json";
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+/// How long to wait between polls of a pending authorization or order, and
+/// how many polls to make before giving up.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Order {
+    #[serde(skip)]
+    pub url: String,
+    pub status: String,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+    pub error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<ChallengeObject>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChallengeObject {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: Option<String>,
+    error: Option<Value>,
+}
+
+/// A challenge the ACME server is waiting for us to answer.
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    pub kind: AcmeChallenge,
+    pub url: String,
+    pub authorization: String,
+    pub token: String,
+    pub key_authorization: String,
+}
+
+/// Minimal RFC 8555 client: enough to register an account and take a
+/// single-host order through HTTP-01 or TLS-ALPN-01 to a certificate.
+pub struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    key: AccountKey,
+    account_url: Option<String>,
+    nonce: Mutex<Option<String>>,
+}
+
+fn problem(body: &Value) -> String {
+    let kind = body["type"].as_str().unwrap_or("unknown error");
+    match body["detail"].as_str() {
+        Some(detail) => format!("{kind}: {detail}"),
+        None => kind.to_string(),
+    }
+}
+
+impl AcmeClient {
+    pub async fn connect(http: reqwest::Client, directory_url: &str, key: AccountKey) -> Result<Self> {
+        let directory = http
+            .get(directory_url)
+            .send()
+            .await
+            .with_context(|| format!("fetching ACME directory {directory_url}"))?
+            .error_for_status()?
+            .json()
+            .await
+            .context("parsing ACME directory")?;
+        Ok(Self { http, directory, key, account_url: None, nonce: Mutex::new(None) })
+    }
+
+    pub fn key(&self) -> &AccountKey {
+        &self.key
+    }
+
+    /// Sign requests as an account registered earlier.
+    pub fn with_account(mut self, account_url: String) -> Self {
+        self.account_url = Some(account_url);
+        self
+    }
+
+    /// Create the account, or look it up if the key already has one, and
+    /// return its URL.
+    pub async fn register(&mut self, contact: &[String]) -> Result<String> {
+        let payload = json!({ "termsOfServiceAgreed": true, "contact": contact });
+        let response = self.post(&self.directory.new_account, Some(&payload)).await?;
+        let url = location(&response).ok_or_else(|| anyhow!("ACME server returned no account URL"))?;
+        self.account_url = Some(url.clone());
+        Ok(url)
+    }
+
+    pub async fn new_order(&self, host: &str) -> Result<Order> {
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": host }] });
+        let response = self.post(&self.directory.new_order, Some(&payload)).await?;
+        let url = location(&response).ok_or_else(|| anyhow!("ACME server returned no order URL"))?;
+        let mut order: Order = response.json().await.context("parsing ACME order")?;
+        order.url = url;
+        Ok(order)
+    }
+
+    /// Challenges of `kind` still to be answered for `order`. Authorizations
+    /// the account already holds need none.
+    pub async fn challenges(&self, order: &Order, kind: AcmeChallenge) -> Result<Vec<Challenge>> {
+        let mut pending = Vec::new();
+        for url in &order.authorizations {
+            let authz: Authorization = self.fetch(url).await?;
+            if authz.status == "valid" {
+                continue;
+            }
+            let offered = authz
+                .challenges
+                .into_iter()
+                .find(|c| c.kind == kind.as_str())
+                .ok_or_else(|| anyhow!("ACME server offers no {} challenge", kind.as_str()))?;
+            let token = offered.token.ok_or_else(|| anyhow!("{} challenge has no token", kind.as_str()))?;
+            pending.push(Challenge {
+                kind,
+                url: offered.url,
+                authorization: url.clone(),
+                key_authorization: self.key.key_authorization(&token),
+                token,
+            });
+        }
+        Ok(pending)
+    }
+
+    /// Tell the server the challenge is ready and wait for the outcome.
+    pub async fn validate(&self, challenge: &Challenge) -> Result<()> {
+        self.post(&challenge.url, Some(&json!({}))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            let authz: Authorization = self.fetch(&challenge.authorization).await?;
+            match authz.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" | "processing" => tokio::time::sleep(POLL_INTERVAL).await,
+                status => {
+                    let reason = authz
+                        .challenges
+                        .iter()
+                        .find(|c| c.url == challenge.url)
+                        .and_then(|c| c.error.as_ref())
+                        .map(problem)
+                        .unwrap_or_else(|| format!("authorization is {status}"));
+                    bail!("{} validation failed: {reason}", challenge.kind.as_str());
+                }
+            }
+        }
+        bail!("timed out waiting for {} validation", challenge.kind.as_str())
+    }
+
+    /// Submit the CSR (base64url DER) and download the issued chain as PEM.
+    pub async fn finalize(&self, order: &Order, csr: &str) -> Result<String> {
+        self.post(&order.finalize, Some(&json!({ "csr": csr }))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            let current: Order = self.fetch(&order.url).await?;
+            match (current.status.as_str(), &current.certificate) {
+                ("valid", Some(certificate)) => {
+                    let response = self.post(certificate, None).await?;
+                    return Ok(response.text().await?);
+                }
+                ("invalid", _) => bail!("order failed: {}", current.error.as_ref().map(problem).unwrap_or_default()),
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        bail!("timed out waiting for the certificate to be issued")
+    }
+
+    /// POST-as-GET `url` and parse the JSON response.
+    async fn fetch<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let response = self.post(url, None).await?;
+        response.json().await.with_context(|| format!("parsing {url}"))
+    }
+
+    async fn fresh_nonce(&self) -> Result<String> {
+        if let Some(nonce) = self.nonce.lock().map_err(|_| anyhow!("nonce lock poisoned"))?.take() {
+            return Ok(nonce);
+        }
+        let response = self.http.head(&self.directory.new_nonce).send().await?;
+        replay_nonce(&response).ok_or_else(|| anyhow!("ACME server returned no nonce"))
+    }
+
+    /// Signed POST, retrying once when the server rejects the nonce.
+    async fn post(&self, url: &str, payload: Option<&Value>) -> Result<Response> {
+        let mut retried = false;
+        loop {
+            let nonce = self.fresh_nonce().await?;
+            let body = self.key.sign(url, &nonce, self.account_url.as_deref(), payload)?;
+            let response = self
+                .http
+                .post(url)
+                .header(header::CONTENT_TYPE, JOSE_JSON)
+                .body(body.to_string())
+                .send()
+                .await
+                .with_context(|| format!("POST {url}"))?;
+            if let Some(nonce) = replay_nonce(&response) {
+                *self.nonce.lock().map_err(|_| anyhow!("nonce lock poisoned"))? = Some(nonce);
+            }
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status();
+            let body: Value = response.json().await.unwrap_or_default();
+            if status == StatusCode::BAD_REQUEST && body["type"] == BAD_NONCE && !retried {
+                retried = true;
+                continue;
+            }
+            bail!("ACME request to {url} failed ({status}): {}", problem(&body));
+        }
+    }
+}
+
+fn replay_nonce(response: &Response) -> Option<String> {
+    response.headers().get(REPLAY_NONCE).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+fn location(response: &Response) -> Option<String> {
+    response.headers().get(header::LOCATION).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
@@ -0,0 +1,167 @@
+//! Certificates for TLS routes, obtained and renewed via ACME.
+//!
+//! Any control plane may run the issuance loop; a claim on the certificate
+//! row keeps them from ordering the same host twice. Challenges are stored
+//! in the database and answered by whichever gateway the CA reaches, and
+//! issued certificates are handed to every gateway on its next sync.
+
+pub mod client;
+pub mod store;
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use crypto::acme::AccountKey;
+use models::route::AcmeChallenge;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use self::client::AcmeClient;
+use crate::{events::gateway::{self, Change}, state::SharedState};
+
+/// `[acme]` section of the control plane config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AcmeConfig {
+    #[serde(default = "default_directory_url")]
+    pub directory_url: String,
+    /// Contact URLs for the account, e.g. `mailto:ops@example.com`.
+    #[serde(default)]
+    pub contact: Vec<String>,
+    #[serde(default = "default_renew_before_days")]
+    pub renew_before_days: u64,
+    /// How often hosts are checked for missing or expiring certificates.
+    #[serde(default = "default_check_interval_secs")]
+    pub check_interval_secs: u64,
+    /// Wait after a failed attempt before ordering for the same host again.
+    #[serde(default = "default_retry_after_secs")]
+    pub retry_after_secs: u64,
+    /// Extra root certificate (PEM) to trust for the directory, such as a
+    /// local Pebble instance's.
+    #[serde(default)]
+    pub ca_cert_path: Option<PathBuf>,
+    /// Skip verifying the directory's certificate. Only for local testing.
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+fn default_directory_url() -> String { "https://acme-v02.api.letsencrypt.org/directory".into() }
+fn default_renew_before_days() -> u64 { 30 }
+fn default_check_interval_secs() -> u64 { 60 }
+fn default_retry_after_secs() -> u64 { 60 * 60 }
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            directory_url: default_directory_url(),
+            contact: Vec::new(),
+            renew_before_days: default_renew_before_days(),
+            check_interval_secs: default_check_interval_secs(),
+            retry_after_secs: default_retry_after_secs(),
+            ca_cert_path: None,
+            insecure: false,
+        }
+    }
+}
+
+impl AcmeConfig {
+    /// HTTP client for talking to the directory.
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+        if let Some(path) = &self.ca_cert_path {
+            let pem = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        if self.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Issue certificates for new TLS routes and renew expiring ones, forever.
+/// Without an `[acme]` section this never returns and does nothing.
+pub async fn run(state: SharedState, cfg: Option<AcmeConfig>) {
+    let Some(cfg) = cfg else { return std::future::pending().await };
+    if !state.keyring.is_configured() {
+        warn!("ACME is configured but SPAN_MASTER_KEY is not set; certificate keys cannot be stored");
+        return std::future::pending().await;
+    }
+    info!(directory = %cfg.directory_url, "ACME certificate management enabled");
+    let mut client = None;
+    loop {
+        if let Err(e) = renew_due(&state, &cfg, &mut client).await {
+            warn!(error = %e, "ACME certificate check failed");
+            // Start over with a fresh directory and nonce next time
+            client = None;
+        }
+        tokio::time::sleep(Duration::from_secs(cfg.check_interval_secs)).await;
+    }
+}
+
+async fn renew_due(state: &SharedState, cfg: &AcmeConfig, client: &mut Option<AcmeClient>) -> Result<()> {
+    let retry_after = Duration::from_secs(cfg.retry_after_secs);
+    let due = store::due_hosts(Duration::from_secs(cfg.renew_before_days * 24 * 60 * 60), retry_after, &state.db).await?;
+    if due.is_empty() {
+        return Ok(());
+    }
+    let client = match client {
+        Some(c) => c,
+        None => client.insert(account(state, cfg).await?),
+    };
+    for (host, kind) in due {
+        if !store::claim(&host, retry_after, &state.db).await? {
+            continue;
+        }
+        info!(%host, challenge = kind.as_str(), "requesting certificate");
+        match issue(client, &host, kind, &state.db).await {
+            Ok((chain, key_pem)) => {
+                let not_after = crypto::acme::not_after(&chain)?;
+                let not_after = chrono::DateTime::from_timestamp(not_after, 0).unwrap_or_default();
+                store::save_certificate(&host, &chain, &key_pem, not_after, &state.keyring, &state.db).await?;
+                info!(%host, %not_after, "certificate issued");
+                gateway::changed(state, Change::Certificate { host: &host }).await;
+            }
+            Err(e) => {
+                warn!(%host, error = %e, "certificate issuance failed");
+                store::record_failure(&host, &format!("{e:#}"), &state.db).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A client for the stored account, registering one on first use.
+async fn account(state: &SharedState, cfg: &AcmeConfig) -> Result<AcmeClient> {
+    let http = cfg.http_client()?;
+    if let Some((key, url)) = store::load_account(&cfg.directory_url, &state.keyring, &state.db).await? {
+        return Ok(AcmeClient::connect(http, &cfg.directory_url, key).await?.with_account(url));
+    }
+    let mut client = AcmeClient::connect(http.clone(), &cfg.directory_url, AccountKey::generate()?).await?;
+    let url = client.register(&cfg.contact).await?;
+    if store::save_account(&cfg.directory_url, client.key(), &url, &state.keyring, &state.db).await? {
+        info!(account = %url, "registered ACME account");
+        return Ok(client);
+    }
+    // Another control plane registered at the same time; use its account
+    let (key, url) = store::load_account(&cfg.directory_url, &state.keyring, &state.db).await?.context("ACME account vanished")?;
+    Ok(AcmeClient::connect(http, &cfg.directory_url, key).await?.with_account(url))
+}
+
+/// Take an order for `host` through validation to a certificate chain and
+/// its private key PEM.
+pub async fn issue(client: &AcmeClient, host: &str, kind: AcmeChallenge, db: &models::PgPool) -> Result<(String, String)> {
+    let order = client.new_order(host).await?;
+    for challenge in client.challenges(&order, kind).await? {
+        let alpn_cert = match kind {
+            AcmeChallenge::TlsAlpn01 => Some(crypto::acme::tls_alpn_challenge_cert(host, &challenge.key_authorization)?),
+            AcmeChallenge::Http01 => None,
+        };
+        store::put_challenge(host, &challenge, alpn_cert.as_ref(), db).await?;
+        let validated = client.validate(&challenge).await;
+        store::remove_challenge(host, db).await?;
+        validated?;
+    }
+    let request = crypto::acme::certificate_request(host)?;
+    let chain = client.finalize(&order, &request.csr).await?;
+    Ok((chain, request.key_pem))
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use crypto::{acme::AccountKey, envelope};
+use models::{route::{AcmeChallenge, GatewayCertificate, TlsPolicy}, PgPool};
+use sqlx::Row;
+
+use super::client::Challenge;
+use crate::secrets::keyring::MasterKeyring;
+
+fn sealed(row: &sqlx::postgres::PgRow, ciphertext: &str) -> envelope::SealedSecret {
+    envelope::SealedSecret {
+        ciphertext: row.get::<Option<Vec<u8>>, _>(ciphertext).unwrap_or_default(),
+        wrapped_key: row.get::<Option<Vec<u8>>, _>("wrapped_key").unwrap_or_default(),
+        master_version: row.get::<Option<i32>, _>("master_key_version").unwrap_or_default(),
+    }
+}
+
+/// The account key and URL registered with `directory_url`, if any.
+pub async fn load_account(directory_url: &str, keyring: &MasterKeyring, db: &PgPool) -> Result<Option<(AccountKey, String)>> {
+    let row = sqlx::query("SELECT account_url, encrypted_key, wrapped_key, master_key_version FROM acme_accounts WHERE directory_url = $1")
+        .bind(directory_url)
+        .fetch_optional(db)
+        .await?;
+    let Some(row) = row else { return Ok(None) };
+    let sealed = sealed(&row, "encrypted_key");
+    let pkcs8 = envelope::open(&keyring.key(sealed.master_version, db).await?, &sealed)?;
+    Ok(Some((AccountKey::from_pkcs8(&pkcs8)?, row.get("account_url"))))
+}
+
+/// Record a newly registered account. Returns false if another control
+/// plane registered one first; its account should be used instead.
+pub async fn save_account(directory_url: &str, key: &AccountKey, account_url: &str, keyring: &MasterKeyring, db: &PgPool) -> Result<bool> {
+    let sealed = envelope::seal(&keyring.active()?, key.pkcs8())?;
+    let inserted = sqlx::query("INSERT INTO acme_accounts (directory_url, account_url, encrypted_key, wrapped_key, master_key_version) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (directory_url) DO NOTHING")
+        .bind(directory_url)
+        .bind(account_url)
+        .bind(sealed.ciphertext)
+        .bind(sealed.wrapped_key)
+        .bind(sealed.master_version)
+        .execute(db)
+        .await?;
+    Ok(inserted.rows_affected() == 1)
+}
+
+/// Hosts of TLS routes without a certificate or whose certificate expires
+/// within `renew_before`, skipping hosts attempted in the last `retry_after`.
+pub async fn due_hosts(renew_before: Duration, retry_after: Duration, db: &PgPool) -> Result<Vec<(String, AcmeChallenge)>> {
+    let rows = sqlx::query("SELECT DISTINCT ON (r.host) r.host, r.tls_policy FROM routes r LEFT JOIN tls_certificates c ON c.host = r.host WHERE r.tls_policy IS NOT NULL AND (c.not_after IS NULL OR c.not_after < NOW() + make_interval(secs => $1)) AND (c.attempted_at IS NULL OR c.attempted_at < NOW() - make_interval(secs => $2)) ORDER BY r.host ASC, r.created_at ASC")
+        .bind(renew_before.as_secs_f64())
+        .bind(retry_after.as_secs_f64())
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let policy: serde_json::Value = row.get("tls_policy");
+            let policy: TlsPolicy = serde_json::from_value(policy).unwrap_or_default();
+            (row.get("host"), policy.challenge)
+        })
+        .collect())
+}
+
+/// Claim `host` for an issuance attempt. Only one control plane wins the
+/// claim; the rest see it as attempted until `retry_after` passes.
+pub async fn claim(host: &str, retry_after: Duration, db: &PgPool) -> Result<bool> {
+    let claimed = sqlx::query("INSERT INTO tls_certificates (host, attempted_at) VALUES ($1, NOW()) ON CONFLICT (host) DO UPDATE SET attempted_at = NOW() WHERE tls_certificates.attempted_at IS NULL OR tls_certificates.attempted_at < NOW() - make_interval(secs => $2)")
+        .bind(host)
+        .bind(retry_after.as_secs_f64())
+        .execute(db)
+        .await?;
+    Ok(claimed.rows_affected() == 1)
+}
+
+pub async fn save_certificate(host: &str, chain_pem: &str, key_pem: &str, not_after: chrono::DateTime<chrono::Utc>, keyring: &MasterKeyring, db: &PgPool) -> Result<()> {
+    let sealed = envelope::seal(&keyring.active()?, key_pem.as_bytes())?;
+    sqlx::query("UPDATE tls_certificates SET cert_pem = $2, encrypted_key = $3, wrapped_key = $4, master_key_version = $5, not_after = $6, issued_at = NOW(), last_error = NULL WHERE host = $1")
+        .bind(host)
+        .bind(chain_pem)
+        .bind(sealed.ciphertext)
+        .bind(sealed.wrapped_key)
+        .bind(sealed.master_version)
+        .bind(not_after)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn record_failure(host: &str, error: &str, db: &PgPool) -> Result<()> {
+    sqlx::query("UPDATE tls_certificates SET last_error = $2 WHERE host = $1")
+        .bind(host)
+        .bind(error)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Issued certificates with their decrypted keys, for hosts that still have
+/// a TLS route.
+pub async fn gateway_certificates(keyring: &MasterKeyring, db: &PgPool) -> Result<Vec<GatewayCertificate>> {
+    let rows = sqlx::query("SELECT c.host, c.cert_pem, c.encrypted_key, c.wrapped_key, c.master_key_version, c.not_after FROM tls_certificates c WHERE c.cert_pem IS NOT NULL AND EXISTS (SELECT 1 FROM routes r WHERE r.host = c.host AND r.tls_policy IS NOT NULL) ORDER BY c.host ASC")
+        .fetch_all(db)
+        .await?;
+    let mut certificates = Vec::with_capacity(rows.len());
+    for row in rows {
+        let sealed = sealed(&row, "encrypted_key");
+        let key_pem = envelope::open(&keyring.key(sealed.master_version, db).await?, &sealed)?;
+        certificates.push(GatewayCertificate {
+            host: row.get("host"),
+            cert_pem: row.get("cert_pem"),
+            key_pem: String::from_utf8(key_pem)?,
+            not_after: row.get("not_after"),
+        });
+    }
+    Ok(certificates)
+}
+
+/// Make `challenge` answerable by the gateways until it is removed.
+/// TLS-ALPN-01 challenges carry the certificate to present.
+pub async fn put_challenge(host: &str, challenge: &Challenge, alpn_cert: Option<&(String, String)>, db: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO acme_challenges (host, kind, token, key_authorization, cert_pem, key_pem) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (host) DO UPDATE SET kind = EXCLUDED.kind, token = EXCLUDED.token, key_authorization = EXCLUDED.key_authorization, cert_pem = EXCLUDED.cert_pem, key_pem = EXCLUDED.key_pem, created_at = NOW()")
+        .bind(host)
+        .bind(challenge.kind.as_str())
+        .bind(&challenge.token)
+        .bind(&challenge.key_authorization)
+        .bind(alpn_cert.map(|(cert, _)| cert))
+        .bind(alpn_cert.map(|(_, key)| key))
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn remove_challenge(host: &str, db: &PgPool) -> Result<()> {
+    sqlx::query("DELETE FROM acme_challenges WHERE host = $1").bind(host).execute(db).await?;
+    Ok(())
+}
+
+/// Key authorization for an HTTP-01 `token`.
+pub async fn http01_key_authorization(token: &str, db: &PgPool) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar("SELECT key_authorization FROM acme_challenges WHERE token = $1 AND kind = 'http-01'")
+        .bind(token)
+        .fetch_optional(db)
+        .await?)
+}
+
+/// Certificate and key PEM to present for a TLS-ALPN-01 validation of `host`.
+pub async fn tls_alpn01_cert(host: &str, db: &PgPool) -> Result<Option<(String, String)>> {
+    Ok(sqlx::query_as("SELECT cert_pem, key_pem FROM acme_challenges WHERE host = $1 AND kind = 'tls-alpn-01' AND cert_pem IS NOT NULL AND key_pem IS NOT NULL")
+        .bind(host)
+        .fetch_optional(db)
+        .await?)
+}
+
+pub struct CertificateStatus {
+    pub host: String,
+    pub not_after: Option<chrono::DateTime<chrono::Utc>>,
+    pub issued_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub attempted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+}
+
+pub async fn list_certificates(db: &PgPool) -> Result<Vec<CertificateStatus>> {
+    let rows = sqlx::query("SELECT host, not_after, issued_at, attempted_at, last_error FROM tls_certificates ORDER BY host ASC")
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|row| CertificateStatus {
+        host: row.get("host"),
+        not_after: row.get("not_after"),
+        issued_at: row.get("issued_at"),
+        attempted_at: row.get("attempted_at"),
+        last_error: row.get("last_error"),
+    }).collect())
+}
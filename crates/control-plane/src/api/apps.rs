@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use common::auth::Role;
+use models::app::AppSpec;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Row;
+use uuid::Uuid;
+
+use super::auth::Caller;
+use crate::{scheduler::{rollout, schedule_app}, secrets::store, state::AppState};
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(json!({ "error": message.into() })))
+}
+
+fn internal<E>(_: E) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+}
+
+#[derive(Deserialize)]
+pub struct ApplyApp {
+    pub name: String,
+    pub spec: serde_json::Value,
+}
+
+fn app_json(row: &sqlx::postgres::PgRow) -> serde_json::Value {
+    let id: Uuid = row.get("id");
+    let namespace: String = row.get("namespace");
+    let name: String = row.get("name");
+    let spec: serde_json::Value = row.get("spec");
+    let created_at: chrono::DateTime<chrono::Utc> = row.get("created_at");
+    json!({
+        "id": id,
+        "namespace": namespace,
+        "name": name,
+        "spec": spec,
+        "created_at": created_at,
+    })
+}
+
+/// Create or update an app. Secret references must point at existing secrets
+/// in the app's namespace; their values are only resolved for nodes. Needs
+/// the developer role in the namespace.
+pub async fn apply_app(Path(namespace): Path<String>, caller: Caller, State(state): State<Arc<AppState>>, Json(req): Json<ApplyApp>) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "applying apps requires the developer role"))?;
+    if !store::valid_name(&req.name) {
+        return Err(error(StatusCode::BAD_REQUEST, "app names may only contain letters, digits, '-', '_' and '.'"));
+    }
+    let spec: AppSpec = serde_json::from_value(req.spec.clone()).map_err(|e| error(StatusCode::BAD_REQUEST, format!("invalid spec: {e}")))?;
+    spec.validate().map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+    for r in spec.secret_refs() {
+        let exists = store::secret_exists(&namespace, &r.name, r.version, &state.db).await.map_err(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "internal error"))?;
+        if !exists {
+            let version = r.version.map(|v| format!(" version {v}")).unwrap_or_default();
+            return Err(error(StatusCode::UNPROCESSABLE_ENTITY, format!("secret {}{version} not found in namespace {namespace}", r.name)));
+        }
+    }
+
+    let ns_id = models::namespace::ensure_namespace(&namespace, &state.db).await.map_err(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "internal error"))?;
+    let row = sqlx::query("INSERT INTO apps (namespace_id, name, spec) VALUES ($1, $2, $3) ON CONFLICT (namespace_id, name) DO UPDATE SET spec = EXCLUDED.spec RETURNING id, (xmax = 0) AS created")
+        .bind(ns_id)
+        .bind(&req.name)
+        .bind(&req.spec)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "internal error"))?;
+    let app_id: Uuid = row.get("id");
+    let created: bool = row.get("created");
+
+    if created {
+        for _ in 0..spec.replicas {
+            if let Err(e) = schedule_app(app_id, state.db.clone()).await {
+                tracing::warn!(%app_id, error = %e, "could not schedule replica");
+                break;
+            }
+        }
+    }
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(json!({ "id": app_id, "namespace": namespace, "name": req.name, "created": created }))))
+}
+
+pub async fn list_apps(State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query("SELECT a.id, n.name AS namespace, a.name, a.spec, a.created_at FROM apps a JOIN namespaces n ON n.id = a.namespace_id ORDER BY n.name ASC, a.name ASC")
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!(rows.iter().map(app_json).collect::<Vec<_>>())))
+}
+
+pub async fn list_namespace_apps(Path(namespace): Path<String>, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query("SELECT a.id, n.name AS namespace, a.name, a.spec, a.created_at FROM apps a JOIN namespaces n ON n.id = a.namespace_id WHERE n.name = $1 ORDER BY a.name ASC")
+        .bind(&namespace)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!(rows.iter().map(app_json).collect::<Vec<_>>())))
+}
+
+pub async fn get_app(Path((namespace, name)): Path<(String, String)>, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let row = sqlx::query("SELECT a.id, n.name AS namespace, a.name, a.spec, a.created_at FROM apps a JOIN namespaces n ON n.id = a.namespace_id WHERE n.name = $1 AND a.name = $2")
+        .bind(&namespace)
+        .bind(&name)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(app_json(&row)))
+}
+
+/// Delete an app and its deployments. Needs the developer role in the
+/// namespace.
+pub async fn delete_app(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<StatusCode, StatusCode> {
+    caller.require(&namespace, Role::Developer)?;
+    let app_id: Option<Uuid> = sqlx::query_scalar("DELETE FROM apps a USING namespaces n WHERE n.id = a.namespace_id AND n.name = $1 AND a.name = $2 RETURNING a.id")
+        .bind(&namespace)
+        .bind(&name)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let app_id = app_id.ok_or(StatusCode::NOT_FOUND)?;
+    // container_deployments.app_id has no foreign key, so clean up explicitly
+    sqlx::query("DELETE FROM container_deployments WHERE app_id = $1")
+        .bind(app_id)
+        .execute(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct PinSecret {
+    pub version: Option<i32>,
+}
+
+/// Pin the app's references to `secret` at a version, or follow the latest
+/// version again when `version` is null.
+pub async fn pin_secret(Path((namespace, name, secret)): Path<(String, String, String)>, caller: Caller, State(state): State<Arc<AppState>>, Json(req): Json<PinSecret>) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "pinning secret versions requires the developer role"))?;
+    if !store::secret_exists(&namespace, &secret, req.version, &state.db).await.map_err(internal)? {
+        let version = req.version.map(|v| format!(" version {v}")).unwrap_or_default();
+        return Err(error(StatusCode::UNPROCESSABLE_ENTITY, format!("secret {secret}{version} not found in namespace {namespace}")));
+    }
+    let row = sqlx::query("SELECT a.id, a.spec FROM apps a JOIN namespaces n ON n.id = a.namespace_id WHERE n.name = $1 AND a.name = $2")
+        .bind(&namespace)
+        .bind(&name)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "app not found"))?;
+    let app_id: Uuid = row.get("id");
+    let mut spec: serde_json::Value = row.get("spec");
+    let changed = models::app::pin_secret_refs(&mut spec, &secret, req.version);
+    if changed == 0 {
+        return Err(error(StatusCode::NOT_FOUND, format!("app {name} does not reference secret {secret}")));
+    }
+    sqlx::query("UPDATE apps SET spec = $1 WHERE id = $2")
+        .bind(&spec)
+        .bind(app_id)
+        .execute(&state.db)
+        .await
+        .map_err(internal)?;
+    tracing::info!(%namespace, app = %name, %secret, version = ?req.version, "secret reference pinned");
+
+    if req.version.is_none() {
+        // Replicas may still hold an old pin; move them to the latest version gradually
+        let db = state.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = rollout::roll_app(app_id, &db).await {
+                tracing::warn!(%app_id, error = %e, "rolling restart failed");
+            }
+        });
+    }
+    Ok(Json(json!({ "name": name, "secret": secret, "version": req.version, "references": changed })))
+}
@@ -0,0 +1,14 @@
+use std::sync::Arc;
+use axum::{extract::{Query, State}, http::StatusCode, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use serde_json::json;
+
+use super::auth::Caller;
+use crate::{audit::{self, AuditFilter}, state::AppState};
+
+/// Audit events across the cluster, newest first. Requires the cluster-wide admin role.
+pub async fn list_audit(Query(filter): Query<AuditFilter>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    caller.require(ALL_NAMESPACES, Role::Admin)?;
+    let events = audit::query(&filter, &state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!(events)))
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+use axum::{async_trait, extract::FromRequestParts, http::{header, request::Parts, StatusCode}};
+use common::auth::{verify_token, Claims, Role};
+
+use crate::state::AppState;
+
+/// The verified bearer token of a request, if it carried one.
+///
+/// A missing token is not an error here; handlers that need a role call
+/// [`Caller::require`]. A token that fails verification is rejected outright.
+pub struct Caller(pub Option<Claims>);
+
+impl Caller {
+    /// 401 without a token, 403 when the token lacks `role` in `namespace`.
+    pub fn require(&self, namespace: &str, role: Role) -> Result<&Claims, StatusCode> {
+        let claims = self.0.as_ref().ok_or(StatusCode::UNAUTHORIZED)?;
+        if claims.allows(namespace, role) { Ok(claims) } else { Err(StatusCode::FORBIDDEN) }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Caller {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(header::AUTHORIZATION) else { return Ok(Caller(None)) };
+        let token = value.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")).ok_or(StatusCode::UNAUTHORIZED)?;
+        let claims = verify_token(token, &state.jwt_secret).map_err(|_| StatusCode::UNAUTHORIZED)?;
+        Ok(Caller(Some(claims)))
+    }
+}
@@ -0,0 +1,142 @@
+use std::{sync::Arc, time::Duration};
+use axum::{extract::{Path, Query, State}, http::StatusCode, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use models::route::{ReleaseStats, ReleaseWeight, TrafficSplit};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::auth::Caller;
+use crate::{canary::{self, LockedRoute, RouteReleases, SplitError, WeightShift}, events::gateway::{self, Change}, state::AppState};
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(json!({ "error": message.into() })))
+}
+
+impl From<SplitError> for ApiError {
+    fn from(e: SplitError) -> Self {
+        let status = match &e {
+            SplitError::NotFound => StatusCode::NOT_FOUND,
+            SplitError::NoSplit => StatusCode::CONFLICT,
+            SplitError::Invalid(_) => StatusCode::BAD_REQUEST,
+            SplitError::Db(e) => {
+                tracing::error!(error = %e, "failed to read or update route weights");
+                return error(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+            }
+        };
+        error(status, e.to_string())
+    }
+}
+
+/// Per-release request and error counts since the last report, as sent by
+/// `span-gateway`. Requires the cluster-wide admin role.
+pub async fn report_stats(caller: Caller, State(state): State<Arc<AppState>>, Json(stats): Json<Vec<ReleaseStats>>) -> Result<StatusCode, StatusCode> {
+    caller.require(ALL_NAMESPACES, Role::Admin)?;
+    canary::record_stats(&stats, &state.db).await.map_err(|e| {
+        tracing::error!(error = %e, "failed to record release stats");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct ReleasesQuery {
+    /// How far back error rates look.
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_window_secs() -> u64 { 300 }
+
+/// A route's release weights with each release's traffic and error rate
+/// over the window, and the weight shift in progress. Requires the viewer
+/// role in the route's namespace.
+pub async fn get_releases(Path((namespace, name)): Path<(String, String)>, caller: Caller, Query(query): Query<ReleasesQuery>, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(&namespace, Role::Viewer).map_err(|s| error(s, "reading release weights requires the viewer role"))?;
+    let route = RouteReleases::load(&namespace, &name, &state.db).await?;
+    let mut totals = canary::release_totals(route.namespace_id, &route.backend, Duration::from_secs(query.window_secs), &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "failed to load release totals");
+            error(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+        })?;
+    let releases: Vec<serde_json::Value> = route.split.releases.iter().map(|r| {
+        let t = totals.remove(&r.release).unwrap_or_default();
+        json!({
+            "release": r.release,
+            "weight": r.weight,
+            "requests": t.requests,
+            "errors": t.errors,
+            "error_rate": t.error_rate(),
+        })
+    }).collect();
+    Ok(Json(json!({
+        "namespace": namespace,
+        "name": name,
+        "backend": route.backend,
+        "window_secs": query.window_secs,
+        "releases": releases,
+        "shift": route.shift,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct SetWeights {
+    pub releases: Vec<ReleaseWeight>,
+}
+
+/// Replace a route's release weights, keeping its stickiness. Stops any
+/// shift in progress. Requires the developer role in the namespace.
+pub async fn set_weights(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>, Json(req): Json<SetWeights>) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "changing route weights requires the developer role"))?;
+    let mut route = LockedRoute::load(&namespace, &name, &state.db).await?;
+    let split = route.split()?;
+    let updated = TrafficSplit { releases: req.releases, sticky_on: split.sticky_on.clone() };
+    updated.validate().map_err(SplitError::Invalid)?;
+    *split = updated;
+    save(route, &state, &namespace, &name).await
+}
+
+/// Start moving a release gradually to a target weight, replacing any
+/// shift in progress. Requires the developer role in the namespace.
+pub async fn shift_weights(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>, Json(shift): Json<WeightShift>) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "changing route weights requires the developer role"))?;
+    let mut route = LockedRoute::load(&namespace, &name, &state.db).await?;
+    shift.validate(route.split()?).map_err(SplitError::Invalid)?;
+    route.start_shift(&shift).await?;
+    route.save().await?;
+    tracing::info!(%namespace, %name, release = %shift.release, target = shift.target_weight, step = shift.step, "weight shift started");
+    Ok((StatusCode::ACCEPTED, Json(json!({ "namespace": namespace, "name": name, "shift": shift }))))
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseRef {
+    pub release: String,
+}
+
+/// Send all traffic to a release. Requires the developer role.
+pub async fn promote_release(path: Path<(String, String)>, caller: Caller, state: State<Arc<AppState>>, Json(req): Json<ReleaseRef>) -> Result<Json<serde_json::Value>, ApiError> {
+    move_release(path, caller, state, &req.release, TrafficSplit::TOTAL_WEIGHT).await
+}
+
+/// Take all traffic off a release. Requires the developer role.
+pub async fn abort_release(path: Path<(String, String)>, caller: Caller, state: State<Arc<AppState>>, Json(req): Json<ReleaseRef>) -> Result<Json<serde_json::Value>, ApiError> {
+    move_release(path, caller, state, &req.release, 0).await
+}
+
+async fn move_release(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>, release: &str, weight: u32) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "changing route weights requires the developer role"))?;
+    let mut route = LockedRoute::load(&namespace, &name, &state.db).await?;
+    route.split()?.set_weight(release, weight).map_err(SplitError::Invalid)?;
+    tracing::info!(%namespace, %name, %release, weight, "release weight set");
+    save(route, &state, &namespace, &name).await
+}
+
+async fn save(mut route: LockedRoute, state: &AppState, namespace: &str, name: &str) -> Result<Json<serde_json::Value>, ApiError> {
+    route.cancel_shift().await?;
+    let split = route.split()?.clone();
+    route.save().await?;
+    gateway::changed(state, Change::Route { namespace, name }).await;
+    Ok(Json(json!({ "namespace": namespace, "name": name, "releases": split.releases })))
+}
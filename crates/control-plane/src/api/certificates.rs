@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use models::route::GatewayCertificate;
+use serde_json::json;
+
+use super::auth::Caller;
+use crate::{acme::store, state::AppState};
+
+/// Issuance status of every TLS certificate.
+pub async fn list_certificates(State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let certificates = store::list_certificates(&state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!(certificates.iter().map(|c| json!({
+        "host": c.host,
+        "not_after": c.not_after,
+        "issued_at": c.issued_at,
+        "attempted_at": c.attempted_at,
+        "last_error": c.last_error,
+    })).collect::<Vec<_>>())))
+}
+
+/// Certificates with their private keys, as loaded by `span-gateway`.
+/// Requires the cluster-wide admin role.
+pub async fn gateway_certificates(caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<Vec<GatewayCertificate>>, StatusCode> {
+    caller.require(ALL_NAMESPACES, Role::Admin)?;
+    if !state.keyring.is_configured() {
+        return Ok(Json(Vec::new()));
+    }
+    let certificates = store::gateway_certificates(&state.keyring, &state.db).await.map_err(|e| {
+        tracing::error!(error = %e, "failed to load gateway certificates");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(certificates))
+}
+
+/// Key authorization for a pending HTTP-01 challenge. Public: the CA
+/// fetches it through any gateway, and it reveals nothing secret.
+pub async fn http01_challenge(Path(token): Path<String>, State(state): State<Arc<AppState>>) -> Result<String, StatusCode> {
+    store::http01_key_authorization(&token, &state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Certificate a gateway presents for a TLS-ALPN-01 validation of `host`.
+/// Requires the cluster-wide admin role.
+pub async fn tls_alpn01_challenge(Path(host): Path<String>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    caller.require(ALL_NAMESPACES, Role::Admin)?;
+    let (cert_pem, key_pem) = store::tls_alpn01_cert(&host.to_ascii_lowercase(), &state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(json!({ "cert_pem": cert_pem, "key_pem": key_pem })))
+}
@@ -1,6 +1,8 @@
 use std::sync::Arc;
 use axum::{extract::State, Json};
+use common::auth::{Role, ALL_NAMESPACES};
 use serde::{Deserialize, Serialize};
+use super::auth::Caller;
 use crate::state::AppState;
 
 #[derive(Serialize)]
@@ -8,11 +10,14 @@ pub struct ClusterInfo {
     pub cluster_id: String,
     pub node_count: u32,
     pub peers: Vec<String>,
-    pub jwt_secret: String,
-    pub master_key: String,
+    /// Shared secrets a joining control plane needs; only cluster admins get them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwt_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub master_key: Option<String>,
 }
 
-pub async fn cluster_info(State(state): State<Arc<AppState>>) -> Json<ClusterInfo> {
+pub async fn cluster_info(caller: Caller, State(state): State<Arc<AppState>>) -> Json<ClusterInfo> {
     let nodes = sqlx::query("SELECT id, name, status FROM nodes WHERE status = 'healthy'")
         .fetch_all(&state.db)
         .await
@@ -20,12 +25,13 @@ pub async fn cluster_info(State(state): State<Arc<AppState>>) -> Json<ClusterInf
 
     let peers: Vec<String> = nodes.iter().map(|_n| String::new()).collect();
 
+    let admin = caller.require(ALL_NAMESPACES, Role::Admin).is_ok();
     Json(ClusterInfo {
         cluster_id: state.cluster_id.clone(),
         node_count: nodes.len() as u32,
         peers,
-        jwt_secret: state.jwt_secret.clone(),
-        master_key: std::env::var("SPAN_MASTER_KEY").unwrap_or_default(),
+        jwt_secret: admin.then(|| state.jwt_secret.clone()),
+        master_key: admin.then(|| std::env::var("SPAN_MASTER_KEY").unwrap_or_default()),
     })
 }
 
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use models::{bucket::BucketObject, route::{bucket_backend, Endpoint, GatewaySnapshot, RouteAuth, RouteSpec}};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Row;
+use uuid::Uuid;
+
+use super::auth::Caller;
+use crate::{events::gateway::{self, Change}, secrets::store, state::AppState};
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(json!({ "error": message.into() })))
+}
+
+fn internal<E>(_: E) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+}
+
+fn route_json(row: &sqlx::postgres::PgRow) -> serde_json::Value {
+    let id: Uuid = row.get("id");
+    let namespace: String = row.get("namespace");
+    let name: String = row.get("name");
+    let host: String = row.get("host");
+    let path_prefix: String = row.get("path_prefix");
+    let backend_ref: String = row.get("backend_ref");
+    let tls: Option<serde_json::Value> = row.get("tls_policy");
+    let spec: Option<serde_json::Value> = row.get("spec");
+    let created_at: chrono::DateTime<chrono::Utc> = row.get("created_at");
+    json!({
+        "id": id,
+        "namespace": namespace,
+        "name": name,
+        "host": host,
+        "path_prefix": path_prefix,
+        "backend_ref": backend_ref,
+        "tls": tls,
+        "spec": spec,
+        "created_at": created_at,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct RouteMetadata {
+    pub name: String,
+    pub namespace: Option<String>,
+}
+
+/// A Route manifest as sent by `span route apply`.
+#[derive(Deserialize)]
+pub struct ApplyRoute {
+    pub metadata: RouteMetadata,
+    pub spec: RouteSpec,
+}
+
+/// Create or update a route. The backend app does not have to exist yet;
+/// the gateway answers 503 until it has endpoints. Requires the developer
+/// role, and serving a bucket requires it in the bucket's namespace too.
+/// A host routed by another namespace is refused.
+pub async fn apply_route(Path(namespace): Path<String>, caller: Caller, State(state): State<Arc<AppState>>, Json(req): Json<ApplyRoute>) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "applying routes requires the developer role"))?;
+    let name = req.metadata.name;
+    if !store::valid_name(&name) {
+        return Err(error(StatusCode::BAD_REQUEST, "route names may only contain letters, digits, '-', '_' and '.'"));
+    }
+    if req.metadata.namespace.as_deref().is_some_and(|ns| ns != namespace) {
+        return Err(error(StatusCode::BAD_REQUEST, format!("metadata.namespace does not match namespace {namespace}")));
+    }
+    req.spec.validate().map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+    if let Some((bucket_namespace, _)) = bucket_backend(&req.spec.backend_ref) {
+        caller.require(bucket_namespace, Role::Developer).map_err(|s| error(s, format!("serving a bucket of namespace {bucket_namespace} requires the developer role there")))?;
+    }
+    if let Some(l4) = &req.spec.l4 {
+        state.l4_ports.check(l4).map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+    }
+    let spec = req.spec.normalized();
+
+    let ns_id = models::namespace::ensure_namespace(&namespace, &state.db).await.map_err(internal)?;
+    let mut tx = state.db.begin().await.map_err(internal)?;
+    // One route write at a time, so two routes cannot both find a host or
+    // port free
+    sqlx::query("LOCK TABLE routes IN SHARE ROW EXCLUSIVE MODE").execute(&mut *tx).await.map_err(internal)?;
+    match &spec.l4 {
+        Some(l4) => {
+            if let Some(other) = models::route::conflicting_l4_route(&namespace, &name, &spec.host, l4, &mut tx).await.map_err(internal)? {
+                return Err(error(StatusCode::CONFLICT, format!("route {other} already listens on {} port {}", l4.protocol.as_str(), l4.listen_port)));
+            }
+        }
+        None => {
+            if let Some(owner) = models::route::host_owner(&namespace, &spec.host, &mut tx).await.map_err(internal)? {
+                return Err(error(StatusCode::CONFLICT, format!("host {} is routed by namespace {owner}", spec.host)));
+            }
+        }
+    }
+    let row = sqlx::query("INSERT INTO routes (namespace_id, name, host, path_prefix, backend_ref, tls_policy, spec) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (namespace_id, name) DO UPDATE SET host = EXCLUDED.host, path_prefix = EXCLUDED.path_prefix, backend_ref = EXCLUDED.backend_ref, tls_policy = EXCLUDED.tls_policy, spec = EXCLUDED.spec, updated_at = NOW() RETURNING id, (xmax = 0) AS created")
+        .bind(ns_id)
+        .bind(&name)
+        .bind(&spec.host)
+        .bind(&spec.path_prefix)
+        .bind(&spec.backend_ref)
+        .bind(spec.tls.as_ref().map(|tls| json!(tls)))
+        .bind(json!(spec))
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(internal)?;
+    tx.commit().await.map_err(internal)?;
+    let id: Uuid = row.get("id");
+    let created: bool = row.get("created");
+    tracing::info!(%namespace, %name, host = %spec.host, path_prefix = %spec.path_prefix, backend = %spec.backend_ref, "route applied");
+    gateway::changed(&state, Change::Route { namespace: &namespace, name: &name }).await;
+
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(json!({ "id": id, "namespace": namespace, "name": name, "created": created }))))
+}
+
+pub async fn list_routes(State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query("SELECT r.id, n.name AS namespace, r.name, r.host, r.path_prefix, r.backend_ref, r.tls_policy, r.spec, r.created_at FROM routes r JOIN namespaces n ON n.id = r.namespace_id ORDER BY n.name ASC, r.name ASC")
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!(rows.iter().map(route_json).collect::<Vec<_>>())))
+}
+
+pub async fn list_namespace_routes(Path(namespace): Path<String>, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query("SELECT r.id, n.name AS namespace, r.name, r.host, r.path_prefix, r.backend_ref, r.tls_policy, r.spec, r.created_at FROM routes r JOIN namespaces n ON n.id = r.namespace_id WHERE n.name = $1 ORDER BY r.name ASC")
+        .bind(&namespace)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(json!(rows.iter().map(route_json).collect::<Vec<_>>())))
+}
+
+pub async fn delete_route(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<StatusCode, StatusCode> {
+    caller.require(&namespace, Role::Developer)?;
+    let deleted = sqlx::query("DELETE FROM routes r USING namespaces n WHERE n.id = r.namespace_id AND n.name = $1 AND r.name = $2")
+        .bind(&namespace)
+        .bind(&name)
+        .execute(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if deleted.rows_affected() == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    tracing::info!(%namespace, %name, "route deleted");
+    gateway::changed(&state, Change::Route { namespace: &namespace, name: &name }).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_endpoints(Path((namespace, name)): Path<(String, String)>, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let rows = sqlx::query("SELECT e.address, e.healthy, e.release, e.node_id, e.updated_at FROM service_endpoints e JOIN namespaces n ON n.id = e.namespace_id WHERE n.name = $1 AND e.backend = $2 ORDER BY e.address ASC")
+        .bind(&namespace)
+        .bind(&name)
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let endpoints: Vec<serde_json::Value> = rows.iter().map(|row| {
+        let node_id: Option<Uuid> = row.get("node_id");
+        let updated_at: chrono::DateTime<chrono::Utc> = row.get("updated_at");
+        json!({
+            "address": row.get::<String, _>("address"),
+            "healthy": row.get::<bool, _>("healthy"),
+            "release": row.get::<Option<String>, _>("release"),
+            "node_id": node_id,
+            "updated_at": updated_at,
+        })
+    }).collect();
+    Ok(Json(json!(endpoints)))
+}
+
+#[derive(Deserialize)]
+pub struct PutEndpoints {
+    /// Node the endpoints run on; its endpoints go away when it is removed.
+    pub node_id: Option<Uuid>,
+    pub endpoints: Vec<Endpoint>,
+}
+
+/// Replace an app's endpoints, or only those on `node_id` when given.
+/// Requires the developer role in the namespace.
+pub async fn put_endpoints(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>, Json(req): Json<PutEndpoints>) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "registering endpoints requires the developer role"))?;
+    if let Some(bad) = req.endpoints.iter().find(|e| !e.address.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())) {
+        return Err(error(StatusCode::BAD_REQUEST, format!("endpoint address {:?} must be host:port", bad.address)));
+    }
+    if let Some(bad) = req.endpoints.iter().filter_map(|e| e.release.as_deref()).find(|r| !Endpoint::valid_release(r)) {
+        return Err(error(StatusCode::BAD_REQUEST, format!("invalid release {bad:?}")));
+    }
+    let ns_id = models::namespace::ensure_namespace(&namespace, &state.db).await.map_err(internal)?;
+    let mut tx = state.db.begin().await.map_err(internal)?;
+    sqlx::query("DELETE FROM service_endpoints WHERE namespace_id = $1 AND backend = $2 AND ($3::UUID IS NULL OR node_id = $3)")
+        .bind(ns_id)
+        .bind(&name)
+        .bind(req.node_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal)?;
+    for endpoint in &req.endpoints {
+        sqlx::query("INSERT INTO service_endpoints (namespace_id, backend, node_id, address, healthy, release) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(ns_id)
+            .bind(&name)
+            .bind(req.node_id)
+            .bind(&endpoint.address)
+            .bind(endpoint.healthy)
+            .bind(&endpoint.release)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db) if db.is_foreign_key_violation() => error(StatusCode::UNPROCESSABLE_ENTITY, "node not found"),
+                e => internal(e),
+            })?;
+    }
+    tx.commit().await.map_err(internal)?;
+    tracing::info!(%namespace, app = %name, count = req.endpoints.len(), "endpoints updated");
+    gateway::changed(&state, Change::Endpoints { namespace: &namespace, backend: &name }).await;
+    Ok(Json(json!({ "namespace": namespace, "name": name, "endpoints": req.endpoints.len() })))
+}
+
+/// Routes with their backends' endpoints and the config version they
+/// reflect, as loaded by `span-gateway`. Basic auth users are only handed
+/// out to cluster-wide admins; other gateways refuse those routes' clients.
+pub async fn gateway_routes(caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<GatewaySnapshot>, StatusCode> {
+    let mut snapshot = models::route::gateway_snapshot(&state.db).await.map_err(|e| {
+        tracing::error!(error = %e, "failed to load gateway routes");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if caller.require(ALL_NAMESPACES, Role::Admin).is_ok() && state.keyring.is_configured() {
+        for route in &mut snapshot.routes {
+            let Some(RouteAuth::Basic(basic)) = &route.auth else { continue };
+            match store::basic_auth_users(&route.namespace, &basic.secret_ref, &state.keyring, &state.db).await {
+                Ok(Some(users)) => route.basic_auth_users = users,
+                Ok(None) => tracing::warn!(namespace = %route.namespace, route = %route.name, secret = %basic.secret_ref, "basic auth secret not found"),
+                Err(e) => {
+                    tracing::error!(error = %e, namespace = %route.namespace, route = %route.name, "failed to read basic auth secret");
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+    }
+    Ok(Json(snapshot))
+}
+
+/// Metadata of an object served by a bucket route, as looked up by
+/// `span-gateway`. Requires the cluster-wide admin role, since the bucket
+/// itself may be private.
+pub async fn gateway_object(Path((namespace, bucket, key)): Path<(String, String, String)>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<BucketObject>, StatusCode> {
+    caller.require(ALL_NAMESPACES, Role::Admin)?;
+    models::bucket::find_object(&namespace, &bucket, &key, &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, %namespace, %bucket, "failed to look up object");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
@@ -1,4 +1,14 @@
+pub mod apps;
+pub mod audit;
+pub mod auth;
+pub mod canary;
+pub mod certificates;
 pub mod health;
+pub mod ingress;
 pub mod cluster;
 pub mod routes;
 pub mod nodes;
+pub mod oidc;
+pub mod secrets;
+#[cfg(feature = "grpc")]
+pub mod pki;
@@ -3,8 +3,9 @@ use axum::{extract::{Path, State}, http::StatusCode, Json};
 use serde_json::json;
 use uuid::Uuid;
 use sqlx::Row;
+use common::events::{EventPublisher, SpanEvent};
 
-use crate::{state::AppState, nodes::{drain::drain_node, remove::remove_node}};
+use crate::{events::gateway::{self, Change}, state::AppState, nodes::{drain::drain_node, remove::remove_node}};
 
 pub async fn list_nodes(State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
     let rows = sqlx::query("SELECT id, name, status, COALESCE(cordoned, FALSE) as cordoned FROM nodes ORDER BY created_at ASC")
@@ -56,7 +57,7 @@ pub async fn cordon_node(Path(node_id): Path<Uuid>, State(state): State<Arc<AppS
         .execute(&state.db)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    println!("Node {} cordoned", node_id);
+    tracing::info!(%node_id, "node cordoned");
     Ok(StatusCode::OK)
 }
 
@@ -66,19 +67,31 @@ pub async fn uncordon_node(Path(node_id): Path<Uuid>, State(state): State<Arc<Ap
         .execute(&state.db)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    println!("Node {} uncordoned", node_id);
+    tracing::info!(%node_id, "node uncordoned");
     Ok(StatusCode::OK)
 }
 
 pub async fn drain_node_handler(Path(node_id): Path<Uuid>, State(state): State<Arc<AppState>>) -> Result<StatusCode, StatusCode> {
     let db = state.db.clone();
     tokio::spawn(async move {
-        let _ = drain_node(node_id, db).await;
+        if let Err(e) = drain_node(node_id, db).await {
+            tracing::warn!(%node_id, error = %e, "drain failed");
+        }
     });
     Ok(StatusCode::ACCEPTED)
 }
 
 pub async fn remove_node_handler(Path(node_id): Path<Uuid>, State(state): State<Arc<AppState>>) -> Result<StatusCode, StatusCode> {
-    remove_node(node_id, state.db.clone()).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+    // Endpoints on the node go with it; gateways must stop routing to them
+    let backends = models::route::node_backends(node_id, &state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let revoked = remove_node(node_id, state.db.clone()).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+    state.revocations.extend(revoked.iter().cloned());
+    if let Some(nc) = &state.nats {
+        let publisher = EventPublisher { client: nc.clone() };
+        let _ = publisher.publish(SpanEvent::CertificatesRevoked { node_id: node_id.to_string(), serials: revoked }).await;
+    }
+    for (namespace, backend) in &backends {
+        gateway::changed(&state, Change::Endpoints { namespace, backend }).await;
+    }
     Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::auth::Caller;
+use crate::{oidc::SessionError, state::AppState};
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(json!({ "error": message.into() })))
+}
+
+/// What `span login` needs to run the device flow against our issuer.
+pub async fn oidc_config(State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, ApiError> {
+    let oidc = state.oidc.as_ref().ok_or_else(|| error(StatusCode::NOT_FOUND, "single sign-on is not configured"))?;
+    Ok(Json(json!({
+        "issuer": oidc.config.issuer,
+        "client_id": oidc.config.client_id,
+        "scopes": oidc.config.scopes,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct SessionRequest {
+    pub id_token: String,
+}
+
+/// Exchange an ID token from the issuer for a control plane session token.
+pub async fn create_session(State(state): State<Arc<AppState>>, Json(req): Json<SessionRequest>) -> Result<Json<serde_json::Value>, ApiError> {
+    let oidc = state.oidc.as_ref().ok_or_else(|| error(StatusCode::NOT_FOUND, "single sign-on is not configured"))?;
+    let session = oidc.session(&req.id_token, &state.jwt_secret).await.map_err(|e| {
+        tracing::warn!(error = %e, "rejected single sign-on session");
+        let status = match e {
+            SessionError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            SessionError::NoRoles => StatusCode::FORBIDDEN,
+            SessionError::Issuer(_) => StatusCode::BAD_GATEWAY,
+        };
+        error(status, e.to_string())
+    })?;
+    tracing::info!(subject = %session.subject, "single sign-on session issued");
+    Ok(Json(json!(session)))
+}
+
+/// The caller's identity and roles as carried by their token.
+pub async fn whoami(caller: Caller) -> Result<Json<serde_json::Value>, StatusCode> {
+    let claims = caller.0.ok_or(StatusCode::UNAUTHORIZED)?;
+    Ok(Json(json!({ "subject": claims.sub, "expires_at": claims.exp, "roles": claims.roles })))
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use axum::{extract::{Query, State}, http::{header, StatusCode}, response::IntoResponse, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use serde::Deserialize;
+use serde_json::json;
+use super::auth::Caller;
+use crate::{nodes::certs::{list_revoked, nodes_pending_reissue}, state::SharedState};
+
+/// How long a published CRL stays valid; consumers should refetch well before this.
+const CRL_VALIDITY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Serve the current CRLs (PEM) for components that verify node certificates,
+/// such as the gateway. Each trusted CA signs a CRL of the certificates it
+/// issued, so certificates from a retiring CA can still be checked against
+/// their issuer. Refetch when `span.pki.revocations` fires.
+pub async fn get_crl(State(state): State<SharedState>) -> Result<impl IntoResponse, StatusCode> {
+    let revoked = list_revoked(&state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Seconds since epoch keep the CRL number monotonic across control planes
+    let crl_number = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let ca = state.ca.read().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut issuers = vec![(&ca.ca_cert_pem, &ca.ca)];
+    if let Some(prev) = &ca.previous { issuers.push((&prev.ca_cert_pem, &prev.ca)); }
+
+    let mut pem = String::new();
+    for (cert_pem, signer) in issuers {
+        let serial = crypto::ca_serial(cert_pem).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        // Certificates recorded without an issuer predate rotation; list them under every CA
+        let revoked: Vec<crypto::RevokedSerial> = revoked
+            .iter()
+            .filter(|r| r.ca_serial.as_ref().is_none_or(|s| *s == serial))
+            .map(|r| crypto::RevokedSerial { serial: r.serial.clone(), revoked_at: r.revoked_at.into() })
+            .collect();
+        pem.push_str(&crypto::build_crl(signer, &revoked, crl_number, CRL_VALIDITY).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
+    }
+    Ok(([(header::CONTENT_TYPE, "application/pkix-crl")], pem))
+}
+
+async fn ca_status(state: &SharedState) -> Result<serde_json::Value, StatusCode> {
+    let (serial, previous_serial) = {
+        let ca = state.ca.read().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let serial = ca.serial().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let previous_serial = match &ca.previous {
+            Some(prev) => Some(crypto::ca_serial(&prev.ca_cert_pem).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?),
+            None => None,
+        };
+        (serial, previous_serial)
+    };
+    let pending = nodes_pending_reissue(&serial, &state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let pending: Vec<serde_json::Value> = pending.into_iter().map(|(id, name)| json!({ "id": id, "name": name })).collect();
+    Ok(json!({
+        "serial": serial,
+        "previous_serial": previous_serial,
+        "rotation_in_progress": previous_serial.is_some(),
+        "pending_nodes": pending,
+    }))
+}
+
+pub async fn get_ca(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, StatusCode> {
+    Ok(Json(ca_status(&state).await?))
+}
+
+/// Introduce a new CA. The old one stays trusted until `retire_ca`, and nodes
+/// are asked to renew on their next heartbeat.
+pub async fn rotate_ca(caller: Caller, State(state): State<SharedState>) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    caller.require(ALL_NAMESPACES, Role::Admin).map_err(|s| (s, String::new()))?;
+    let rotated = crypto::rotate_ca(None).map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+    *state.ca.write().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "CA lock poisoned".to_string()))? = rotated;
+    state.ca_reload.notify_one();
+    tracing::info!("CA rotation started");
+    let status = ca_status(&state).await.map_err(|s| (s, String::new()))?;
+    Ok(Json(status))
+}
+
+#[derive(Deserialize)]
+pub struct RetireQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Drop the retiring CA from trust. Refuses while nodes still lack a
+/// certificate from the active CA, unless `force` is set.
+pub async fn retire_ca(caller: Caller, State(state): State<SharedState>, Query(q): Query<RetireQuery>) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    caller.require(ALL_NAMESPACES, Role::Admin).map_err(|s| (s, Json(json!({ "error": "admin role required" }))))?;
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "internal error" })));
+    let status = ca_status(&state).await.map_err(internal)?;
+    if !status["rotation_in_progress"].as_bool().unwrap_or(false) {
+        return Err((StatusCode::CONFLICT, Json(json!({ "error": "no CA rotation in progress" }))));
+    }
+    let pending = status["pending_nodes"].as_array().map(|a| a.len()).unwrap_or(0);
+    if pending > 0 && !q.force {
+        return Err((StatusCode::CONFLICT, Json(json!({ "error": "nodes have not been re-issued under the new CA", "pending_nodes": status["pending_nodes"] }))));
+    }
+
+    let retired = crypto::retire_previous_ca(None).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "failed to retire CA" }))))?;
+    *state.ca.write().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "CA lock poisoned" }))))? = retired;
+    state.ca_reload.notify_one();
+    tracing::info!(pending, "Previous CA retired");
+    Ok(Json(ca_status(&state).await.map_err(internal)?))
+}
@@ -1,22 +1,60 @@
-use axum::{routing::{get, post, delete}, Router, Json};
+use axum::{middleware, routing::{delete, get, post, put}, Router};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use serde_json::json;
 use crate::state::SharedState;
 
 pub fn router(state: SharedState) -> Router {
-    Router::new()
+    let api = Router::new()
         .route("/health", get(super::health::get_health))
+        .route("/metrics", get(crate::metrics::get_metrics))
         .route("/api/v1/nodes", get(super::nodes::list_nodes))
         .route("/api/v1/nodes/:id", get(super::nodes::get_node).delete(super::nodes::remove_node_handler))
         .route("/api/v1/nodes/:id/cordon", post(super::nodes::cordon_node))
         .route("/api/v1/nodes/:id/uncordon", post(super::nodes::uncordon_node))
         .route("/api/v1/nodes/:id/drain", post(super::nodes::drain_node_handler))
-        .route("/api/v1/apps", get(|| async { Json(json!([])) }))
+        .route("/api/v1/apps", get(super::apps::list_apps))
+        .route("/api/v1/namespaces/:namespace/apps", get(super::apps::list_namespace_apps).post(super::apps::apply_app))
+        .route("/api/v1/namespaces/:namespace/apps/:name", get(super::apps::get_app).delete(super::apps::delete_app))
+        .route("/api/v1/namespaces/:namespace/apps/:name/endpoints", get(super::ingress::list_endpoints).put(super::ingress::put_endpoints))
+        .route("/api/v1/routes", get(super::ingress::list_routes))
+        .route("/api/v1/namespaces/:namespace/routes", get(super::ingress::list_namespace_routes).post(super::ingress::apply_route))
+        .route("/api/v1/namespaces/:namespace/routes/:name", delete(super::ingress::delete_route))
+        .route("/api/v1/namespaces/:namespace/routes/:name/releases", get(super::canary::get_releases))
+        .route("/api/v1/namespaces/:namespace/routes/:name/weights", put(super::canary::set_weights))
+        .route("/api/v1/namespaces/:namespace/routes/:name/weights/shift", post(super::canary::shift_weights))
+        .route("/api/v1/namespaces/:namespace/routes/:name/weights/promote", post(super::canary::promote_release))
+        .route("/api/v1/namespaces/:namespace/routes/:name/weights/abort", post(super::canary::abort_release))
+        .route("/api/v1/gateway/routes", get(super::ingress::gateway_routes))
+        .route("/api/v1/gateway/stats", post(super::canary::report_stats))
+        .route("/api/v1/gateway/buckets/:namespace/:bucket/objects/*key", get(super::ingress::gateway_object))
+        .route("/api/v1/gateway/certificates", get(super::certificates::gateway_certificates))
+        .route("/api/v1/gateway/acme/http-01/:token", get(super::certificates::http01_challenge))
+        .route("/api/v1/gateway/acme/tls-alpn-01/:host", get(super::certificates::tls_alpn01_challenge))
+        .route("/api/v1/certificates", get(super::certificates::list_certificates))
         .route("/api/v1/cluster/info", get(super::cluster::cluster_info))
         .route("/api/v1/cluster/join", post(super::cluster::join_cluster))
+        .route("/api/v1/namespaces/:namespace/apps/:name/secrets/:secret", put(super::apps::pin_secret))
+        .route("/api/v1/namespaces/:namespace/secrets", get(super::secrets::list_secrets).post(super::secrets::create_secret))
+        .route("/api/v1/namespaces/:namespace/secrets/:name", get(super::secrets::get_secret).delete(super::secrets::delete_secret))
+        .route("/api/v1/namespaces/:namespace/secrets/:name/versions", get(super::secrets::list_versions))
+        .route("/api/v1/secrets/master-key/rotate", post(super::secrets::rotate_master_key))
+        .route("/api/v1/audit", get(super::audit::list_audit))
+        .route("/api/v1/auth/oidc", get(super::oidc::oidc_config))
+        .route("/api/v1/auth/oidc/session", post(super::oidc::create_session))
+        .route("/api/v1/auth/whoami", get(super::oidc::whoami))
         // Log streaming (WebSocket)
         .route("/api/v1/apps/:namespace/:name/logs", get(crate::events::logs::ws_app_logs))
         .route("/api/v1/builds/:id/logs", get(crate::events::logs::ws_build_logs))
+        .route("/api/v1/routes/:namespace/:name/access", get(crate::events::logs::ws_route_access));
+    // The CRL is signed by the cluster CA, which is only loaded with gRPC enabled
+    #[cfg(feature = "grpc")]
+    let api = api
+        .route("/api/v1/pki/crl", get(super::pki::get_crl))
+        .route("/api/v1/pki/ca", get(super::pki::get_ca))
+        .route("/api/v1/pki/ca/rotate", post(super::pki::rotate_ca))
+        .route("/api/v1/pki/ca/retire", post(super::pki::retire_ca));
+    api
+        .route_layer(middleware::from_fn_with_state(state.clone(), crate::audit::audit_http))
+        .layer(middleware::from_fn_with_state(state.clone(), crate::ratelimit::limit_http))
         .with_state(state)
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
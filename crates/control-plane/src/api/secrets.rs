@@ -0,0 +1,148 @@
+use std::sync::Arc;
+use axum::{extract::{Path, Query, State}, http::StatusCode, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::auth::Caller;
+use crate::{events::gateway::{self, Change}, scheduler::rollout, secrets::store, state::AppState};
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(json!({ "error": message.into() })))
+}
+
+fn require_keyring(state: &AppState) -> Result<(), ApiError> {
+    if state.keyring.is_configured() { Ok(()) } else { Err(error(StatusCode::SERVICE_UNAVAILABLE, "SPAN_MASTER_KEY is not configured")) }
+}
+
+/// Apps following the latest version of a secret pick up changes through a rolling restart.
+fn spawn_rollout(state: &AppState, namespace: &str, name: &str) {
+    let db = state.db.clone();
+    let (ns, name) = (namespace.to_string(), name.to_string());
+    tokio::spawn(async move {
+        if let Err(e) = rollout::roll_secret(&ns, &name, &db).await {
+            tracing::warn!(namespace = %ns, %name, error = %e, "failed to roll apps using secret");
+        }
+    });
+}
+
+/// Routes taking their basic auth users from a secret serve its latest version.
+async fn reload_routes(state: &AppState, namespace: &str, name: &str) {
+    match models::route::routes_using_secret(namespace, name, &state.db).await {
+        Ok(routes) => {
+            for route in routes {
+                gateway::changed(state, Change::Route { namespace, name: &route }).await;
+            }
+        }
+        Err(e) => tracing::warn!(%namespace, %name, error = %e, "failed to look up routes using secret"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateSecret {
+    pub name: String,
+    pub value: String,
+}
+
+pub async fn create_secret(Path(namespace): Path<String>, caller: Caller, State(state): State<Arc<AppState>>, Json(req): Json<CreateSecret>) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "writing secrets requires the developer role"))?;
+    require_keyring(&state)?;
+    if !store::valid_name(&req.name) {
+        return Err(error(StatusCode::BAD_REQUEST, "secret names may only contain letters, digits, '-', '_' and '.'"));
+    }
+    let version = store::put_secret(&namespace, &req.name, req.value.as_bytes(), &state.keyring, &state.db)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, %namespace, name = %req.name, "failed to store secret");
+            error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store secret")
+        })?;
+    tracing::info!(%namespace, name = %req.name, version, "secret stored");
+
+    spawn_rollout(&state, &namespace, &req.name);
+    reload_routes(&state, &namespace, &req.name).await;
+    Ok((StatusCode::CREATED, Json(json!({ "namespace": namespace, "name": req.name, "version": version }))))
+}
+
+pub async fn list_secrets(Path(namespace): Path<String>, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let secrets = store::list_secrets(&namespace, &state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let secrets: Vec<serde_json::Value> = secrets.into_iter().map(|s| json!({
+        "name": s.name,
+        "version": s.version,
+        "created_at": s.created_at,
+    })).collect();
+    Ok(Json(json!(secrets)))
+}
+
+#[derive(Deserialize)]
+pub struct VersionQuery {
+    pub version: Option<i32>,
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Reveal a secret value. Requires the admin role in the namespace.
+pub async fn get_secret(Path((namespace, name)): Path<(String, String)>, Query(q): Query<VersionQuery>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, ApiError> {
+    let claims = caller.require(&namespace, Role::Admin).map_err(|s| error(s, "reading secret values requires the admin role"))?;
+    require_keyring(&state)?;
+    let internal = |_| error(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+    let version = match q.version {
+        Some(v) => v,
+        None => store::latest_version(&namespace, &name, &state.db).await.map_err(internal)?.ok_or_else(|| error(StatusCode::NOT_FOUND, "secret not found"))?,
+    };
+    let value = store::read_secret(&namespace, &name, version, &state.keyring, &state.db)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "secret version not found"))?;
+    tracing::info!(%namespace, %name, version, subject = %claims.sub, "secret value read");
+    Ok(Json(json!({ "name": name, "version": version, "value": String::from_utf8_lossy(&value) })))
+}
+
+pub async fn list_versions(Path((namespace, name)): Path<(String, String)>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, StatusCode> {
+    caller.require(&namespace, Role::Viewer)?;
+    let versions = store::list_versions(&namespace, &name, &state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if versions.is_empty() { return Err(StatusCode::NOT_FOUND); }
+    let versions: Vec<serde_json::Value> = versions.into_iter().map(|v| json!({
+        "version": v.version,
+        "created_at": v.created_at,
+        "deleted_at": v.deleted_at,
+    })).collect();
+    Ok(Json(json!(versions)))
+}
+
+/// Soft-delete a secret, or one version with `?version=`. Deleted versions are
+/// purged after the retention period. Refuses while apps depend on what would
+/// be deleted, unless `?force=true`.
+pub async fn delete_secret(Path((namespace, name)): Path<(String, String)>, Query(q): Query<VersionQuery>, caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(&namespace, Role::Developer).map_err(|s| error(s, "deleting secrets requires the developer role"))?;
+    let internal = |_| error(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+    if !q.force {
+        let apps = store::apps_using(&namespace, &name, q.version, &state.db).await.map_err(internal)?;
+        if !apps.is_empty() {
+            return Err((StatusCode::CONFLICT, Json(json!({ "error": "secret is in use", "apps": apps }))));
+        }
+    }
+    let deleted = store::soft_delete(&namespace, &name, q.version, &state.db).await.map_err(internal)?;
+    if deleted == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "secret not found"));
+    }
+    tracing::info!(%namespace, %name, version = ?q.version, deleted, "secret soft-deleted");
+    // Replicas resolving a deleted latest version move back to the newest remaining one
+    if q.version.is_some() { spawn_rollout(&state, &namespace, &name); }
+    reload_routes(&state, &namespace, &name).await;
+    Ok(Json(json!({ "name": name, "deleted_versions": deleted })))
+}
+
+/// Move every data key to a new master key version. Reads and writes keep
+/// working while the re-wrap runs.
+pub async fn rotate_master_key(caller: Caller, State(state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, ApiError> {
+    caller.require(ALL_NAMESPACES, Role::Admin).map_err(|s| error(s, "rotating the master key requires the cluster admin role"))?;
+    require_keyring(&state)?;
+    let report = state.keyring.rotate(&state.db).await.map_err(|e| {
+        tracing::error!(error = %e, "master key rotation failed");
+        error(StatusCode::CONFLICT, e.to_string())
+    })?;
+    tracing::info!(version = report.version, rewrapped = report.rewrapped, "master key rotated");
+    Ok(Json(json!({ "version": report.version, "rewrapped": report.rewrapped })))
+}
@@ -0,0 +1,246 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use common::{auth::verify_token, events::{EventPublisher, SpanEvent}};
+use models::PgPool;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::state::SharedState;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const ANONYMOUS: &str = "anonymous";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    /// Rejected for missing or insufficient credentials.
+    Denied,
+    Failure,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Denied => "denied",
+            Outcome::Failure => "failure",
+        }
+    }
+
+    pub fn from_http(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Outcome::Denied,
+            s if s.is_client_error() || s.is_server_error() => Outcome::Failure,
+            _ => Outcome::Success,
+        }
+    }
+
+    pub fn from_grpc(code: tonic::Code) -> Self {
+        match code {
+            tonic::Code::Ok => Outcome::Success,
+            tonic::Code::Unauthenticated | tonic::Code::PermissionDenied => Outcome::Denied,
+            _ => Outcome::Failure,
+        }
+    }
+}
+
+/// A mutating call about to be written to `audit_events`.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub actor: String,
+    pub action: String,
+    pub resource: String,
+    pub request_id: String,
+    pub outcome: Outcome,
+    /// HTTP status, or the gRPC status code for `source = "grpc"`.
+    pub status: i32,
+    pub source: &'static str,
+}
+
+/// Persists audit entries and, when configured, mirrors them to NATS on
+/// `span.audit.<action>`.
+pub struct AuditLog {
+    mirror: Option<async_nats::Client>,
+}
+
+impl AuditLog {
+    pub fn new(mirror: Option<async_nats::Client>) -> Self {
+        Self { mirror }
+    }
+
+    /// Record an entry. Failures are logged rather than returned so that a
+    /// broken audit table never fails the call being audited.
+    pub async fn record(&self, entry: AuditEntry, db: &PgPool) {
+        let inserted = sqlx::query("INSERT INTO audit_events (actor, action, resource, request_id, outcome, status, source) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(&entry.actor)
+            .bind(&entry.action)
+            .bind(&entry.resource)
+            .bind(&entry.request_id)
+            .bind(entry.outcome.as_str())
+            .bind(entry.status)
+            .bind(entry.source)
+            .execute(db)
+            .await;
+        if let Err(e) = inserted {
+            tracing::error!(error = %e, action = %entry.action, request_id = %entry.request_id, "failed to write audit event");
+        }
+        if let Some(client) = &self.mirror {
+            let publisher = EventPublisher { client: client.clone() };
+            let event = SpanEvent::AuditRecorded {
+                actor: entry.actor,
+                action: entry.action,
+                resource: entry.resource,
+                request_id: entry.request_id,
+                outcome: entry.outcome.as_str().to_string(),
+                status: entry.status,
+            };
+            if let Err(e) = publisher.publish(event).await {
+                tracing::warn!(error = %e, "failed to mirror audit event to NATS");
+            }
+        }
+    }
+}
+
+/// The caller's `x-request-id` if usable, or a fresh one.
+pub fn request_id(supplied: Option<&str>) -> String {
+    supplied
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Audit every non-GET request that matched a route. Installed with
+/// `route_layer` so the matched route template is available to name the action.
+pub async fn audit_http(State(state): State<SharedState>, matched: Option<MatchedPath>, request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    if matches!(method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+    let request_id = request_id(request.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+    let actor = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(|token| verify_token(token, &state.jwt_secret).ok())
+        .map(|claims| claims.sub)
+        .unwrap_or_else(|| ANONYMOUS.to_string());
+    let path = request.uri().path().to_string();
+    let template = matched.as_ref().map(|m| m.as_str().to_string()).unwrap_or_else(|| path.clone());
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let entry = AuditEntry {
+        actor,
+        action: action_name(&method, &template),
+        resource: path.trim_start_matches("/api/v1/").to_string(),
+        request_id,
+        outcome: Outcome::from_http(response.status()),
+        status: response.status().as_u16() as i32,
+        source: "http",
+    };
+    state.audit.record(entry, &state.db).await;
+    response
+}
+
+/// Name an action after its route: `POST /api/v1/nodes/:id/cordon` is
+/// `nodes.cordon`, `DELETE /api/v1/namespaces/:namespace/apps/:name` is
+/// `apps.delete`. Routes ending in a parameter or naming a bare collection
+/// get a verb from the method.
+pub fn action_name(method: &Method, template: &str) -> String {
+    let segments: Vec<&str> = template.trim_start_matches("/api/v1/").split('/').filter(|s| !s.is_empty()).collect();
+    let mut parts: Vec<&str> = segments.iter().copied().filter(|s| !s.starts_with(':') && *s != "namespaces").collect();
+    let ends_in_param = segments.last().is_some_and(|s| s.starts_with(':'));
+    if ends_in_param || parts.len() == 1 {
+        parts.push(match *method {
+            Method::POST => "create",
+            Method::PUT | Method::PATCH => "update",
+            Method::DELETE => "delete",
+            _ => "call",
+        });
+    }
+    parts.join(".")
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    /// Prefix match, so `nodes` covers `nodes.cordon`, `nodes.drain`, ...
+    pub action: Option<String>,
+    /// Prefix match on the resource path.
+    pub resource: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub resource: String,
+    pub request_id: String,
+    pub outcome: String,
+    pub status: i32,
+    pub source: String,
+}
+
+/// Newest matching events first, at most `limit` (default 100, capped at 1000).
+pub async fn query(filter: &AuditFilter, db: &PgPool) -> anyhow::Result<Vec<AuditRecord>> {
+    let limit = filter.limit.unwrap_or(100).clamp(1, 1000);
+    let rows = sqlx::query("SELECT id, occurred_at, actor, action, resource, request_id, outcome, status, source FROM audit_events WHERE ($1::TEXT IS NULL OR actor = $1) AND ($2::TEXT IS NULL OR starts_with(action, $2)) AND ($3::TEXT IS NULL OR starts_with(resource, $3)) AND ($4::TEXT IS NULL OR outcome = $4) AND ($5::TIMESTAMPTZ IS NULL OR occurred_at >= $5) AND ($6::TIMESTAMPTZ IS NULL OR occurred_at < $6) ORDER BY id DESC LIMIT $7")
+        .bind(&filter.actor)
+        .bind(&filter.action)
+        .bind(&filter.resource)
+        .bind(&filter.outcome)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(limit)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|row| AuditRecord {
+        id: row.get("id"),
+        occurred_at: row.get("occurred_at"),
+        actor: row.get("actor"),
+        action: row.get("action"),
+        resource: row.get("resource"),
+        request_id: row.get("request_id"),
+        outcome: row.get("outcome"),
+        status: row.get("status"),
+        source: row.get("source"),
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_actions_after_routes() {
+        assert_eq!(action_name(&Method::POST, "/api/v1/nodes/:id/cordon"), "nodes.cordon");
+        assert_eq!(action_name(&Method::DELETE, "/api/v1/nodes/:id"), "nodes.delete");
+        assert_eq!(action_name(&Method::POST, "/api/v1/namespaces/:namespace/apps"), "apps.create");
+        assert_eq!(action_name(&Method::DELETE, "/api/v1/namespaces/:namespace/apps/:name"), "apps.delete");
+        assert_eq!(action_name(&Method::PUT, "/api/v1/namespaces/:namespace/apps/:name/secrets/:secret"), "apps.secrets.update");
+        assert_eq!(action_name(&Method::POST, "/api/v1/secrets/master-key/rotate"), "secrets.master-key.rotate");
+        assert_eq!(action_name(&Method::POST, "/api/v1/cluster/join"), "cluster.join");
+    }
+
+    #[test]
+    fn maps_statuses_to_outcomes() {
+        assert_eq!(Outcome::from_http(StatusCode::ACCEPTED), Outcome::Success);
+        assert_eq!(Outcome::from_http(StatusCode::FORBIDDEN), Outcome::Denied);
+        assert_eq!(Outcome::from_http(StatusCode::CONFLICT), Outcome::Failure);
+        assert_eq!(Outcome::from_grpc(tonic::Code::Unauthenticated), Outcome::Denied);
+    }
+}
@@ -0,0 +1,371 @@
+//! Canary releases: routes splitting traffic between releases of their
+//! backend by weight.
+//!
+//! Gateways report requests and server errors per release, which are kept
+//! in one-minute buckets. Weights are changed by hand through the API or
+//! shifted gradually by [`run`], which steps a release toward its target
+//! weight and aborts the shift when the release's error rate climbs past
+//! the shift's threshold.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use models::{route::{ReleaseStats, RouteSpec, TrafficSplit}, PgPool};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, Row, Transaction};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{events::gateway::{self, Change}, state::SharedState};
+
+/// How long per-release stats are kept.
+const STATS_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SplitError {
+    #[error("route not found")]
+    NotFound,
+    #[error("route does not split traffic between releases")]
+    NoSplit,
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+/// Requests and server errors of one release over a window.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ReleaseTotals {
+    pub requests: u64,
+    pub errors: u64,
+}
+
+impl ReleaseTotals {
+    pub fn error_rate(&self) -> Option<f64> {
+        (self.requests > 0).then(|| self.errors as f64 / self.requests as f64)
+    }
+}
+
+/// A gradual move of `release` to `target_weight`, `step` points every
+/// `interval_secs`. With `max_error_rate`, a step only happens once the
+/// release served `min_requests` since the last one, and the release is
+/// dropped to weight 0 when its error rate exceeds the threshold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightShift {
+    pub release: String,
+    #[serde(default = "default_target_weight")]
+    pub target_weight: u32,
+    #[serde(default = "default_step")]
+    pub step: u32,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u32,
+    #[serde(default)]
+    pub max_error_rate: Option<f64>,
+    #[serde(default)]
+    pub min_requests: u64,
+    #[serde(default, skip_deserializing)]
+    pub next_step_at: Option<DateTime<Utc>>,
+}
+
+fn default_target_weight() -> u32 { TrafficSplit::TOTAL_WEIGHT }
+fn default_step() -> u32 { 10 }
+fn default_interval_secs() -> u32 { 60 }
+
+impl WeightShift {
+    pub fn validate(&self, split: &TrafficSplit) -> Result<(), String> {
+        if !split.releases.iter().any(|r| r.release == self.release) {
+            return Err(format!("release {:?} is not part of the split", self.release));
+        }
+        if self.target_weight > TrafficSplit::TOTAL_WEIGHT {
+            return Err(format!("target_weight must be at most {}", TrafficSplit::TOTAL_WEIGHT));
+        }
+        if self.step == 0 || self.step > TrafficSplit::TOTAL_WEIGHT {
+            return Err(format!("step must be between 1 and {}", TrafficSplit::TOTAL_WEIGHT));
+        }
+        if self.interval_secs == 0 || i32::try_from(self.interval_secs).is_err() {
+            return Err(format!("interval_secs must be between 1 and {}", i32::MAX));
+        }
+        if self.max_error_rate.is_some_and(|r| !(0.0..=1.0).contains(&r)) {
+            return Err("max_error_rate must be between 0 and 1".into());
+        }
+        Ok(())
+    }
+
+    /// Weight after the next step from `current`.
+    pub fn next_weight(&self, current: u32) -> u32 {
+        if current < self.target_weight {
+            (current + self.step).min(self.target_weight)
+        } else {
+            current.saturating_sub(self.step).max(self.target_weight)
+        }
+    }
+
+    /// Whether `totals` should abort the shift.
+    pub fn exceeded(&self, totals: ReleaseTotals) -> bool {
+        let Some(max) = self.max_error_rate else { return false };
+        totals.requests >= self.min_requests.max(1) && totals.error_rate().is_some_and(|rate| rate > max)
+    }
+}
+
+/// Add counts reported by a gateway to the current minute's buckets.
+/// Stats for namespaces that do not exist are dropped.
+pub async fn record_stats(stats: &[ReleaseStats], db: &PgPool) -> Result<()> {
+    for s in stats {
+        sqlx::query("INSERT INTO release_stats (namespace_id, backend, release, bucket, requests, errors) SELECT n.id, $2, $3, date_trunc('minute', NOW()), $4, $5 FROM namespaces n WHERE n.name = $1 ON CONFLICT (namespace_id, backend, release, bucket) DO UPDATE SET requests = release_stats.requests + EXCLUDED.requests, errors = release_stats.errors + EXCLUDED.errors")
+            .bind(&s.namespace)
+            .bind(&s.backend)
+            .bind(&s.release)
+            .bind(i64::try_from(s.requests).unwrap_or(i64::MAX))
+            .bind(i64::try_from(s.errors).unwrap_or(i64::MAX))
+            .execute(db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Totals per release of `backend` over the last `window`, rounded out to
+/// whole minutes.
+pub async fn release_totals(namespace_id: Uuid, backend: &str, window: Duration, db: &PgPool) -> Result<HashMap<String, ReleaseTotals>> {
+    let rows = sqlx::query("SELECT release, SUM(requests)::BIGINT AS requests, SUM(errors)::BIGINT AS errors FROM release_stats WHERE namespace_id = $1 AND backend = $2 AND bucket >= date_trunc('minute', NOW() - make_interval(secs => $3)) GROUP BY release")
+        .bind(namespace_id)
+        .bind(backend)
+        .bind(window.as_secs_f64())
+        .fetch_all(db)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let totals = ReleaseTotals { requests: row.get::<i64, _>("requests").max(0) as u64, errors: row.get::<i64, _>("errors").max(0) as u64 };
+            (row.get("release"), totals)
+        })
+        .collect())
+}
+
+/// A route's traffic split and the shift in progress, read without locking
+/// the route.
+pub struct RouteReleases {
+    pub namespace_id: Uuid,
+    pub backend: String,
+    pub split: TrafficSplit,
+    pub shift: Option<WeightShift>,
+}
+
+impl RouteReleases {
+    pub async fn load(namespace: &str, name: &str, db: &PgPool) -> Result<Self, SplitError> {
+        let row = sqlx::query("SELECT r.id, r.namespace_id, r.spec FROM routes r JOIN namespaces n ON n.id = r.namespace_id WHERE n.name = $1 AND r.name = $2")
+            .bind(namespace)
+            .bind(name)
+            .fetch_optional(db)
+            .await?
+            .ok_or(SplitError::NotFound)?;
+        let spec: Option<serde_json::Value> = row.get("spec");
+        let spec: RouteSpec = spec.and_then(|s| serde_json::from_value(s).ok()).ok_or(SplitError::NoSplit)?;
+        let split = spec.split.ok_or(SplitError::NoSplit)?;
+        let shift = sqlx::query("SELECT release, target_weight, step, interval_secs, max_error_rate, min_requests, next_step_at FROM route_weight_shifts WHERE route_id = $1")
+            .bind(row.get::<Uuid, _>("id"))
+            .fetch_optional(db)
+            .await?;
+        Ok(Self { namespace_id: row.get("namespace_id"), backend: spec.backend_ref, split, shift: shift.map(|row| shift_from_row(&row)) })
+    }
+}
+
+/// A route whose spec is being changed, locked until `save` commits.
+pub struct LockedRoute {
+    pub id: Uuid,
+    pub namespace_id: Uuid,
+    pub spec: RouteSpec,
+    tx: Transaction<'static, Postgres>,
+}
+
+impl LockedRoute {
+    pub async fn load(namespace: &str, name: &str, db: &PgPool) -> Result<Self, SplitError> {
+        let mut tx = db.begin().await?;
+        let row = sqlx::query("SELECT r.id, r.namespace_id, r.spec FROM routes r JOIN namespaces n ON n.id = r.namespace_id WHERE n.name = $1 AND r.name = $2 FOR UPDATE OF r")
+            .bind(namespace)
+            .bind(name)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(SplitError::NotFound)?;
+        let spec: Option<serde_json::Value> = row.get("spec");
+        let spec = spec.and_then(|s| serde_json::from_value(s).ok()).ok_or(SplitError::NoSplit)?;
+        Ok(Self { id: row.get("id"), namespace_id: row.get("namespace_id"), spec, tx })
+    }
+
+    pub fn split(&mut self) -> Result<&mut TrafficSplit, SplitError> {
+        self.spec.split.as_mut().ok_or(SplitError::NoSplit)
+    }
+
+    /// The shift in progress on this route, if any.
+    pub async fn shift(&mut self) -> Result<Option<WeightShift>, SplitError> {
+        let row = sqlx::query("SELECT release, target_weight, step, interval_secs, max_error_rate, min_requests, next_step_at FROM route_weight_shifts WHERE route_id = $1")
+            .bind(self.id)
+            .fetch_optional(&mut *self.tx)
+            .await?;
+        Ok(row.map(|row| shift_from_row(&row)))
+    }
+
+    /// Replace any shift in progress with `shift`, taking its first step
+    /// after one interval.
+    pub async fn start_shift(&mut self, shift: &WeightShift) -> Result<(), SplitError> {
+        let column = |value: u32, field: &str| i32::try_from(value).map_err(|_| SplitError::Invalid(format!("{field} must be at most {}", i32::MAX)));
+        let (target_weight, step, interval_secs) = (column(shift.target_weight, "target_weight")?, column(shift.step, "step")?, column(shift.interval_secs, "interval_secs")?);
+        sqlx::query("INSERT INTO route_weight_shifts (route_id, release, target_weight, step, interval_secs, max_error_rate, min_requests, next_step_at) VALUES ($1, $2, $3, $4, $5, $6, $7, NOW() + make_interval(secs => $5)) ON CONFLICT (route_id) DO UPDATE SET release = EXCLUDED.release, target_weight = EXCLUDED.target_weight, step = EXCLUDED.step, interval_secs = EXCLUDED.interval_secs, max_error_rate = EXCLUDED.max_error_rate, min_requests = EXCLUDED.min_requests, next_step_at = EXCLUDED.next_step_at, created_at = NOW()")
+            .bind(self.id)
+            .bind(&shift.release)
+            .bind(target_weight)
+            .bind(step)
+            .bind(interval_secs)
+            .bind(shift.max_error_rate)
+            .bind(i64::try_from(shift.min_requests).unwrap_or(i64::MAX))
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+
+    /// Stop the shift in progress, if any. Returns whether there was one.
+    pub async fn cancel_shift(&mut self) -> Result<bool, SplitError> {
+        let deleted = sqlx::query("DELETE FROM route_weight_shifts WHERE route_id = $1").bind(self.id).execute(&mut *self.tx).await?;
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    /// Store the spec and commit.
+    pub async fn save(mut self) -> Result<(), SplitError> {
+        sqlx::query("UPDATE routes SET spec = $2, updated_at = NOW() WHERE id = $1")
+            .bind(self.id)
+            .bind(serde_json::json!(self.spec))
+            .execute(&mut *self.tx)
+            .await?;
+        self.tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn shift_from_row(row: &sqlx::postgres::PgRow) -> WeightShift {
+    WeightShift {
+        release: row.get("release"),
+        target_weight: row.get::<i32, _>("target_weight").max(0) as u32,
+        step: row.get::<i32, _>("step").max(0) as u32,
+        interval_secs: row.get::<i32, _>("interval_secs").max(0) as u32,
+        max_error_rate: row.get("max_error_rate"),
+        min_requests: row.get::<i64, _>("min_requests").max(0) as u64,
+        next_step_at: Some(row.get("next_step_at")),
+    }
+}
+
+/// Step weight shifts as they come due and prune old stats. Any control
+/// plane may run this; each due shift is taken by one of them.
+pub async fn run(state: SharedState) {
+    let mut pruned_at = None::<tokio::time::Instant>;
+    loop {
+        loop {
+            match step_due_shift(&state).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    warn!(error = %e, "Failed to step weight shift");
+                    break;
+                }
+            }
+        }
+        if pruned_at.is_none_or(|at| at.elapsed() >= Duration::from_secs(60 * 60)) {
+            match sqlx::query("DELETE FROM release_stats WHERE bucket < NOW() - make_interval(secs => $1)").bind(STATS_RETENTION.as_secs_f64()).execute(&state.db).await {
+                Ok(_) => pruned_at = Some(tokio::time::Instant::now()),
+                Err(e) => warn!(error = %e, "Failed to prune release stats"),
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Take one step of a due shift. Returns false when none was due.
+async fn step_due_shift(state: &SharedState) -> Result<bool> {
+    let mut tx = state.db.begin().await?;
+    let row = sqlx::query("SELECT s.route_id, s.release, s.target_weight, s.step, s.interval_secs, s.max_error_rate, s.min_requests, s.next_step_at, r.namespace_id, n.name AS namespace, r.name, r.spec FROM route_weight_shifts s JOIN routes r ON r.id = s.route_id JOIN namespaces n ON n.id = r.namespace_id WHERE s.next_step_at <= NOW() ORDER BY s.next_step_at ASC LIMIT 1 FOR UPDATE OF s, r SKIP LOCKED")
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(row) = row else { return Ok(false) };
+    let route_id: Uuid = row.get("route_id");
+    let namespace: String = row.get("namespace");
+    let name: String = row.get("name");
+    let shift = shift_from_row(&row);
+    let spec: Option<RouteSpec> = row.get::<Option<serde_json::Value>, _>("spec").and_then(|s| serde_json::from_value(s).ok());
+
+    let Some(mut spec) = spec.filter(|s| s.split.as_ref().is_some_and(|split| shift.validate(split).is_ok())) else {
+        // The route was reapplied without the split or the release
+        info!(%namespace, route = %name, release = %shift.release, "Weight shift dropped; release no longer in the split");
+        sqlx::query("DELETE FROM route_weight_shifts WHERE route_id = $1").bind(route_id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        return Ok(true);
+    };
+    let split = spec.split.as_mut().expect("checked above");
+    let current = split.weight(&shift.release).unwrap_or_default();
+
+    let window = Duration::from_secs(u64::from(shift.interval_secs));
+    let totals = release_totals(row.get("namespace_id"), &spec.backend_ref, window, &state.db).await?.remove(&shift.release).unwrap_or_default();
+    let (weight, done) = if shift.exceeded(totals) {
+        warn!(%namespace, route = %name, release = %shift.release, requests = totals.requests, errors = totals.errors, "Error rate over threshold; aborting weight shift");
+        (0, true)
+    } else if shift.max_error_rate.is_some() && totals.requests < shift.min_requests {
+        // Not enough traffic to judge the release yet
+        (current, false)
+    } else {
+        let weight = shift.next_weight(current);
+        (weight, weight == shift.target_weight)
+    };
+
+    if weight != current {
+        split.set_weight(&shift.release, weight).map_err(anyhow::Error::msg)?;
+        sqlx::query("UPDATE routes SET spec = $2, updated_at = NOW() WHERE id = $1").bind(route_id).bind(serde_json::json!(spec)).execute(&mut *tx).await?;
+    }
+    if done {
+        sqlx::query("DELETE FROM route_weight_shifts WHERE route_id = $1").bind(route_id).execute(&mut *tx).await?;
+    } else {
+        sqlx::query("UPDATE route_weight_shifts SET next_step_at = NOW() + make_interval(secs => interval_secs) WHERE route_id = $1").bind(route_id).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+
+    if weight != current {
+        info!(%namespace, route = %name, release = %shift.release, from = current, to = weight, "Shifted release weight");
+        gateway::changed(state, Change::Route { namespace: &namespace, name: &name }).await;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::route::ReleaseWeight;
+
+    fn shift(target_weight: u32, step: u32) -> WeightShift {
+        WeightShift { release: "v13".into(), target_weight, step, interval_secs: 60, max_error_rate: Some(0.05), min_requests: 100, next_step_at: None }
+    }
+
+    #[test]
+    fn steps_toward_the_target_without_overshooting() {
+        assert_eq!(shift(100, 30).next_weight(10), 40);
+        assert_eq!(shift(100, 30).next_weight(80), 100);
+        assert_eq!(shift(0, 30).next_weight(20), 0);
+        assert_eq!(shift(50, 10).next_weight(50), 50);
+    }
+
+    #[test]
+    fn validates_shifts() {
+        let split = TrafficSplit { releases: vec![ReleaseWeight { release: "v12".into(), weight: 90 }, ReleaseWeight { release: "v13".into(), weight: 10 }], sticky_on: None };
+        assert!(shift(100, 10).validate(&split).is_ok());
+        assert!(shift(101, 10).validate(&split).is_err());
+        assert!(shift(100, 0).validate(&split).is_err());
+        assert!(WeightShift { interval_secs: 0, ..shift(100, 10) }.validate(&split).is_err());
+        assert!(WeightShift { interval_secs: u32::MAX, ..shift(100, 10) }.validate(&split).is_err());
+        assert!(WeightShift { release: "v14".into(), ..shift(100, 10) }.validate(&split).is_err());
+    }
+
+    #[test]
+    fn aborts_only_on_enough_failing_traffic() {
+        let shift = shift(100, 10);
+        assert!(!shift.exceeded(ReleaseTotals { requests: 50, errors: 50 }));
+        assert!(!shift.exceeded(ReleaseTotals { requests: 200, errors: 10 }));
+        assert!(shift.exceeded(ReleaseTotals { requests: 200, errors: 11 }));
+        assert!(!WeightShift { max_error_rate: None, ..shift }.exceeded(ReleaseTotals { requests: 200, errors: 200 }));
+    }
+}
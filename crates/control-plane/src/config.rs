@@ -1,6 +1,9 @@
+use models::route::L4PortRange;
 use serde::Deserialize;
 use std::{env, fs, path::PathBuf};
 
+use crate::{acme::AcmeConfig, oidc::OidcConfig, ratelimit::RateLimitConfig};
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub database_url: String,
@@ -9,10 +12,36 @@ pub struct Config {
     #[serde(default = "default_grpc_bind")] 
     pub grpc_bind: String,
     pub nats_url: Option<String>,
+    /// Days a soft-deleted secret version is kept before it is purged.
+    #[serde(default = "default_secret_retention_days")]
+    pub secret_retention_days: u64,
+    /// Days app and build logs are kept in JetStream.
+    #[serde(default = "default_log_retention_days")]
+    pub log_retention_days: u64,
+    /// Lines kept per app or build log, oldest dropped first.
+    #[serde(default = "default_log_lines_per_subject")]
+    pub log_lines_per_subject: i64,
+    /// Mirror audit events to NATS on `span.audit.<action>`.
+    #[serde(default)]
+    pub audit_nats: bool,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Single sign-on for `span login`; disabled when absent.
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    /// Certificates for TLS routes; disabled when absent.
+    #[serde(default)]
+    pub acme: Option<AcmeConfig>,
+    /// Gateway ports L4 routes may listen on, as `start-end`.
+    #[serde(default)]
+    pub l4_ports: L4PortRange,
 }
 
 fn default_http_bind() -> String { "0.0.0.0:8080".into() }
 fn default_grpc_bind() -> String { "0.0.0.0:50051".into() }
+fn default_secret_retention_days() -> u64 { 30 }
+fn default_log_retention_days() -> u64 { 7 }
+fn default_log_lines_per_subject() -> i64 { 10_000 }
 
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
@@ -22,6 +51,14 @@ impl Config {
             http_bind: default_http_bind(),
             grpc_bind: default_grpc_bind(),
             nats_url: None,
+            secret_retention_days: default_secret_retention_days(),
+            log_retention_days: default_log_retention_days(),
+            log_lines_per_subject: default_log_lines_per_subject(),
+            audit_nats: false,
+            rate_limit: RateLimitConfig::default(),
+            oidc: None,
+            acme: None,
+            l4_ports: L4PortRange::default(),
         };
 
         // Load from file in priority order
@@ -48,6 +85,29 @@ impl Config {
         if let Ok(v) = env::var("SPAN_GRPC_BIND") { cfg.grpc_bind = v; }
         if let Ok(v) = env::var("SPAN_NATS_URL") { cfg.nats_url = Some(v); }
         if let Ok(v) = env::var("SPAN_DATABASE_URL") { cfg.database_url = v; }
+        if let Ok(v) = env::var("SPAN_SECRET_RETENTION_DAYS") { cfg.secret_retention_days = v.parse()?; }
+        if let Ok(v) = env::var("SPAN_LOG_RETENTION_DAYS") { cfg.log_retention_days = v.parse()?; }
+        if let Ok(v) = env::var("SPAN_LOG_LINES_PER_SUBJECT") { cfg.log_lines_per_subject = v.parse()?; }
+        if let Ok(v) = env::var("SPAN_RATE_LIMIT_ENABLED") { cfg.rate_limit.enabled = !matches!(v.as_str(), "0" | "false" | "no"); }
+        if let Ok(v) = env::var("SPAN_RATE_LIMIT_PER_IP_RPS") { cfg.rate_limit.per_ip_rps = v.parse()?; }
+        if let Ok(v) = env::var("SPAN_RATE_LIMIT_PER_TOKEN_RPS") { cfg.rate_limit.per_token_rps = v.parse()?; }
+        if let (Ok(issuer), Ok(client_id)) = (env::var("SPAN_OIDC_ISSUER"), env::var("SPAN_OIDC_CLIENT_ID")) {
+            // Only the issuer changes; scopes, claims and role mappings stay as configured
+            cfg.oidc = Some(match cfg.oidc.take() {
+                Some(oidc) => OidcConfig { issuer, client_id, ..oidc },
+                None => OidcConfig::new(issuer, client_id),
+            });
+        }
+        // Empty values (unset compose variables) leave ACME as configured
+        if let Some(v) = env::var("SPAN_ACME_DIRECTORY_URL").ok().filter(|v| !v.is_empty()) {
+            cfg.acme = Some(AcmeConfig { directory_url: v, ..cfg.acme.take().unwrap_or_default() });
+        }
+        if let (Some(acme), Some(v)) = (cfg.acme.as_mut(), env::var("SPAN_ACME_CONTACT").ok().filter(|v| !v.is_empty())) {
+            acme.contact = v.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect();
+        }
+        if let (Some(acme), Some(v)) = (cfg.acme.as_mut(), env::var("SPAN_ACME_CA_CERT").ok().filter(|v| !v.is_empty())) { acme.ca_cert_path = Some(v.into()); }
+        if let Ok(v) = env::var("SPAN_L4_PORTS") { cfg.l4_ports = v.parse().map_err(anyhow::Error::msg)?; }
+        if let Ok(v) = env::var("SPAN_AUDIT_NATS") { cfg.audit_nats = matches!(v.as_str(), "1" | "true" | "yes"); }
 
         if cfg.database_url.is_empty() {
             anyhow::bail!("DATABASE_URL or SPAN_DATABASE_URL must be set or provided in config");
@@ -62,5 +122,13 @@ fn merge(mut base: Config, other: Config) -> Config {
     if other.http_bind != default_http_bind() { base.http_bind = other.http_bind; }
     if other.grpc_bind != default_grpc_bind() { base.grpc_bind = other.grpc_bind; }
     if other.nats_url.is_some() { base.nats_url = other.nats_url; }
+    if other.secret_retention_days != default_secret_retention_days() { base.secret_retention_days = other.secret_retention_days; }
+    if other.log_retention_days != default_log_retention_days() { base.log_retention_days = other.log_retention_days; }
+    if other.log_lines_per_subject != default_log_lines_per_subject() { base.log_lines_per_subject = other.log_lines_per_subject; }
+    if other.audit_nats { base.audit_nats = true; }
+    if other.oidc.is_some() { base.oidc = other.oidc; }
+    if other.acme.is_some() { base.acme = other.acme; }
+    if other.rate_limit != RateLimitConfig::default() { base.rate_limit = other.rate_limit; }
+    if other.l4_ports != L4PortRange::default() { base.l4_ports = other.l4_ports; }
     base
 }
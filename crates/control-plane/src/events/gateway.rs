@@ -0,0 +1,32 @@
+use common::events::{EventPublisher, SpanEvent};
+
+use crate::state::AppState;
+
+/// What changed in the gateway config.
+pub enum Change<'a> {
+    Route { namespace: &'a str, name: &'a str },
+    Endpoints { namespace: &'a str, backend: &'a str },
+    Certificate { host: &'a str },
+}
+
+/// Bump the gateway config version and tell gateways to reload. Failures are
+/// logged: gateways still pick the change up on their next full sync.
+pub async fn changed(state: &AppState, change: Change<'_>) {
+    let version = match models::route::bump_version(&state.db).await {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to bump gateway config version");
+            return;
+        }
+    };
+    let Some(client) = &state.nats else { return };
+    let event = match change {
+        Change::Route { namespace, name } => SpanEvent::RouteChanged { version, namespace: namespace.into(), name: name.into() },
+        Change::Endpoints { namespace, backend } => SpanEvent::EndpointsChanged { version, namespace: namespace.into(), backend: backend.into() },
+        Change::Certificate { host } => SpanEvent::CertificateIssued { version, host: host.into() },
+    };
+    let publisher = EventPublisher { client: client.clone() };
+    if let Err(e) = publisher.publish(event).await {
+        tracing::warn!(error = %e, version, "failed to publish gateway config change");
+    }
+}
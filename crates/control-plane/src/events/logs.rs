@@ -1,22 +1,165 @@
-use std::{collections::{HashMap, VecDeque}, sync::Arc};
+use std::{collections::{HashMap, VecDeque}, pin::Pin, sync::Arc, time::Duration};
 use tokio::sync::{RwLock, broadcast};
-use async_nats::Client;
+use async_nats::{Client, jetstream::{self, consumer::{pull, DeliverPolicy}, stream::{self, StorageType}}};
+use chrono::{DateTime, Utc};
 use tracing::{info, warn};
 use futures_util::StreamExt;
+use common::events::{access_log_subject, app_log_subject, AccessLog, LogEnvelope, LogLevel, LogStream, ACCESS_LOG_SUBJECTS};
+use regex::{Regex, RegexBuilder};
+use crate::metrics;
 
 const LOG_BUFFER_CAP: usize = 1000;
 
+/// JetStream streams keeping app and build logs, and the subjects each holds.
+const LOG_STREAMS: [(&str, &str); 2] = [("SPAN_APP_LOGS", "span.apps.*.*.logs"), ("SPAN_BUILD_LOGS", "span.builds.*.logs")];
+
+/// How much of each app and build log JetStream keeps.
+#[derive(Debug, Clone)]
+pub struct LogRetention {
+    pub max_age: Duration,
+    pub max_lines_per_subject: i64,
+}
+
+/// Which lines of a log to send, and whether to keep sending new ones.
+#[derive(Debug, Clone)]
+pub struct LogQuery {
+    /// Only the last this many lines of the history.
+    pub tail: Option<usize>,
+    /// Only lines logged at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    pub follow: bool,
+    pub filter: LogFilter,
+}
+
+impl Default for LogQuery {
+    fn default() -> Self { Self { tail: None, since: None, follow: true, filter: LogFilter::default() } }
+}
+
+/// Filters on the fields of container log envelopes. Lines that are not
+/// envelopes, such as build logs, only pass a filter on their text.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub stream: Option<LogStream>,
+    pub replica: Option<String>,
+    /// The least severe level to send; lines without one are left out.
+    pub level: Option<LogLevel>,
+    pub grep: Option<Regex>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, line: &str) -> bool {
+        if self.stream.is_none() && self.replica.is_none() && self.level.is_none() && self.grep.is_none() { return true; }
+        match serde_json::from_str::<LogEnvelope>(line) {
+            Ok(log) => {
+                self.stream.is_none_or(|stream| stream == log.stream)
+                    && self.replica.as_ref().is_none_or(|replica| *replica == log.replica)
+                    && self.level.is_none_or(|level| log.level.is_some_and(|l| l >= level))
+                    && self.grep.as_ref().is_none_or(|grep| grep.is_match(&log.line))
+            }
+            Err(_) => {
+                self.stream.is_none() && self.replica.is_none() && self.level.is_none()
+                    && self.grep.as_ref().is_none_or(|grep| grep.is_match(line))
+            }
+        }
+    }
+}
+
+/// The lines of a log matching a query, then new lines as they come.
+pub struct LogReader {
+    pub history: Vec<String>,
+    live: Live,
+    filter: LogFilter,
+}
+
+enum Live {
+    Done,
+    Buffer(broadcast::Receiver<String>),
+    Stream(Pin<Box<pull::Ordered<'static>>>),
+}
+
+impl LogReader {
+    /// The next line logged, or `None` when the query does not follow or
+    /// the log is gone.
+    pub async fn next(&mut self) -> Option<String> {
+        loop {
+            match &mut self.live {
+                Live::Done => return None,
+                Live::Buffer(rx) => match rx.recv().await {
+                    Ok(line) => if self.filter.matches(&line) { return Some(line) },
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => warn!(skipped, "log reader fell behind"),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                Live::Stream(messages) => match messages.next().await? {
+                    Ok(message) => match String::from_utf8(message.payload.to_vec()) {
+                        Ok(line) if self.filter.matches(&line) => return Some(line),
+                        _ => {}
+                    },
+                    Err(e) => { warn!(error = %e, "log stream read failed"); return None; }
+                },
+            }
+        }
+    }
+}
+
+/// Recent lines of a log, with when they came in.
+type Buffer = VecDeque<(DateTime<Utc>, String)>;
+
 #[derive(Clone, Default)]
 pub struct LogHub {
-    buffers: Arc<RwLock<HashMap<String, VecDeque<String>>>>,
+    buffers: Arc<RwLock<HashMap<String, Buffer>>>,
     channels: Arc<RwLock<HashMap<String, broadcast::Sender<String>>>>,
+    /// Set when app and build logs are kept in JetStream rather than in
+    /// the buffers.
+    jetstream: Option<jetstream::Context>,
 }
 
 impl LogHub {
     pub fn new() -> Self { Self::default() }
 
+    /// A hub reading app and build logs from JetStream, whose streams are
+    /// created, or updated to `retention`, first.
+    pub async fn durable(client: Client, retention: &LogRetention) -> anyhow::Result<Self> {
+        let jetstream = jetstream::new(client);
+        for (name, subject) in LOG_STREAMS {
+            let config = stream::Config {
+                name: name.into(),
+                subjects: vec![subject.into()],
+                max_age: retention.max_age,
+                max_messages_per_subject: retention.max_lines_per_subject,
+                storage: StorageType::File,
+                ..Default::default()
+            };
+            jetstream.get_or_create_stream(config.clone()).await?;
+            jetstream.update_stream(config).await?;
+            info!(stream = name, subject, "Keeping logs in JetStream");
+        }
+        Ok(Self { jetstream: Some(jetstream), ..Self::default() })
+    }
+
     pub async fn start_subscribers(self: Arc<Self>, client: Client) {
         // Establish subscriptions before returning to avoid race with early publishes
+        if self.jetstream.is_none() {
+            self.clone().subscribe_app_and_build_logs(&client).await;
+        }
+
+        let mut sub_access = match client.subscribe(ACCESS_LOG_SUBJECTS).await {
+            Ok(s) => { info!(subject = ACCESS_LOG_SUBJECTS, "Subscribed to gateway access logs"); s },
+            Err(e) => { warn!(error = %e, "Failed to subscribe to gateway access logs"); return; }
+        };
+        let access = self.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = sub_access.next().await {
+                let subject = msg.subject.clone();
+                let line = match String::from_utf8(msg.payload.to_vec()) { Ok(s) => s, Err(_) => continue };
+                // Route metrics are aggregated from the records every gateway publishes
+                if let Ok(record) = serde_json::from_str::<AccessLog>(&line) { metrics::observe_access(&record); }
+                access.append_and_broadcast(&subject, line).await;
+            }
+        });
+    }
+
+    /// Buffer app and build logs in memory, for when JetStream is not there.
+    async fn subscribe_app_and_build_logs(self: Arc<Self>, client: &Client) {
         let mut sub_apps = match client.subscribe("span.apps.*.*.logs").await {
             Ok(s) => { info!(subject = "span.apps.*.*.logs", "Subscribed to app logs"); s },
             Err(e) => { warn!(error = %e, "Failed to subscribe to app logs"); return; }
@@ -46,7 +189,27 @@ impl LogHub {
 
     pub async fn get_buffer(&self, subject: &str) -> Vec<String> {
         let map = self.buffers.read().await;
-        map.get(subject).map(|d| d.iter().cloned().collect()).unwrap_or_default()
+        map.get(subject).map(|d| d.iter().map(|(_, line)| line.clone()).collect()).unwrap_or_default()
+    }
+
+    /// Read `subject`'s log as `query` asks, from JetStream when it keeps
+    /// the log and from the buffers otherwise.
+    pub async fn open(&self, subject: &str, query: &LogQuery) -> anyhow::Result<LogReader> {
+        match (&self.jetstream, stream_for(subject)) {
+            (Some(jetstream), Some(stream)) => open_stream(jetstream, stream, subject, query).await,
+            _ => Ok(self.open_buffer(subject, query).await),
+        }
+    }
+
+    async fn open_buffer(&self, subject: &str, query: &LogQuery) -> LogReader {
+        let map = self.buffers.read().await;
+        // Lines are sent while the buffer is locked, so subscribing under
+        // the lock neither misses nor repeats any
+        let live = if query.follow { Live::Buffer(self.get_sender(subject).await.subscribe()) } else { Live::Done };
+        let lines = map.get(subject).into_iter().flatten()
+            .filter(|(at, line)| query.since.is_none_or(|since| *at >= since) && query.filter.matches(line))
+            .map(|(_, line)| line.clone());
+        LogReader { history: last(lines, query.tail), live, filter: query.filter.clone() }
     }
 
     pub async fn get_sender(&self, subject: &str) -> broadcast::Sender<String> {
@@ -63,54 +226,198 @@ impl LogHub {
     }
 
     async fn append_and_broadcast(&self, subject: &str, line: String) {
-        {
-            let mut map = self.buffers.write().await;
-            let buf = map.entry(subject.to_string()).or_insert_with(VecDeque::new);
-            if buf.len() >= LOG_BUFFER_CAP { buf.pop_front(); }
-            buf.push_back(line.clone());
-        }
+        let mut map = self.buffers.write().await;
+        let buf = map.entry(subject.to_string()).or_insert_with(VecDeque::new);
+        if buf.len() >= LOG_BUFFER_CAP { buf.pop_front(); }
+        buf.push_back((Utc::now(), line.clone()));
         let tx = self.get_sender(subject).await;
         let _ = tx.send(line);
     }
 }
 
-use axum::{extract::{Path, State, ws::{WebSocketUpgrade, Message, WebSocket}}, response::IntoResponse};
-use crate::state::SharedState;
+/// The JetStream stream keeping `subject`, if any does.
+fn stream_for(subject: &str) -> Option<&'static str> {
+    let matches = |pattern: &str| {
+        pattern.split('.').count() == subject.split('.').count()
+            && pattern.split('.').zip(subject.split('.')).all(|(p, s)| p == "*" || p == s)
+    };
+    LOG_STREAMS.iter().find(|(_, pattern)| matches(pattern)).map(|(name, _)| *name)
+}
+
+/// Read the history with an ordered consumer, which then follows the log
+/// from exactly where the history ends.
+async fn open_stream(jetstream: &jetstream::Context, stream: &str, subject: &str, query: &LogQuery) -> anyhow::Result<LogReader> {
+    let deliver_policy = match query.since {
+        Some(since) => DeliverPolicy::ByStartTime {
+            start_time: time::OffsetDateTime::from_unix_timestamp_nanos(since.timestamp_nanos_opt().unwrap_or_default().into())?,
+        },
+        None => DeliverPolicy::All,
+    };
+    let consumer = jetstream.get_stream(stream).await?
+        .create_consumer(pull::OrderedConfig { filter_subject: subject.into(), deliver_policy, ..Default::default() })
+        .await?;
+    let mut pending = consumer.cached_info().num_pending;
+    let mut messages = consumer.messages().await?;
+    // Only the tail is kept, but the whole range is read to find it; the
+    // stream's per-subject limit bounds that
+    let mut history = VecDeque::new();
+    while pending > 0 {
+        let Some(message) = messages.next().await else { break };
+        let message = message?;
+        pending = message.info().map_or(0, |info| info.pending);
+        let Ok(line) = String::from_utf8(message.payload.to_vec()) else { continue };
+        if !query.filter.matches(&line) { continue; }
+        history.push_back(line);
+        if query.tail.is_some_and(|tail| history.len() > tail) { history.pop_front(); }
+    }
+    let live = if query.follow { Live::Stream(Box::pin(messages)) } else { Live::Done };
+    Ok(LogReader { history: history.into(), live, filter: query.filter.clone() })
+}
+
+/// The last `tail` of `lines`, or all of them.
+fn last(lines: impl Iterator<Item = String>, tail: Option<usize>) -> Vec<String> {
+    let mut lines: Vec<String> = lines.collect();
+    if let Some(tail) = tail { lines.drain(..lines.len().saturating_sub(tail)); }
+    lines
+}
+
+/// A `since` parameter: an RFC 3339 time, or a duration before `now` such
+/// as `90s`, `15m`, `2h` or `7d`.
+fn parse_since(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) { return Some(at.with_timezone(&Utc)); }
+    let (amount, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit())?);
+    let seconds = match unit { "s" => 1, "m" => 60, "h" => 60 * 60, "d" => 24 * 60 * 60, _ => return None };
+    let ago = chrono::Duration::try_seconds(amount.parse::<i64>().ok()?.checked_mul(seconds)?)?;
+    now.checked_sub_signed(ago)
+}
+
+use axum::{extract::{Path, Query, State, ws::{close_code, CloseFrame, WebSocketUpgrade, Message, WebSocket}}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use common::auth::{Role, ALL_NAMESPACES};
+use serde::Deserialize;
+use crate::{api::auth::Caller, state::SharedState};
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+#[derive(Debug, Deserialize)]
+pub struct LogParams {
+    tail: Option<usize>,
+    since: Option<String>,
+    follow: Option<bool>,
+    stream: Option<LogStream>,
+    replica: Option<String>,
+    level: Option<String>,
+    grep: Option<String>,
+}
+
+/// Largest compiled `grep` pattern accepted.
+const GREP_SIZE_LIMIT: usize = 1 << 20;
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message.into() })))
+}
+
+impl LogParams {
+    fn query(self) -> Result<LogQuery, ApiError> {
+        let since = match self.since {
+            Some(since) => Some(parse_since(&since, Utc::now()).ok_or_else(|| error(StatusCode::BAD_REQUEST, "since must be an RFC 3339 time or a duration like 15m, 2h or 7d"))?),
+            None => None,
+        };
+        let level = match self.level {
+            Some(level) => Some(LogLevel::from_name(&level).ok_or_else(|| error(StatusCode::BAD_REQUEST, "level must be one of trace, debug, info, warn or error"))?),
+            None => None,
+        };
+        let grep = match self.grep {
+            Some(grep) => Some(RegexBuilder::new(&grep).size_limit(GREP_SIZE_LIMIT).build().map_err(|e| error(StatusCode::BAD_REQUEST, format!("invalid grep pattern: {e}")))?),
+            None => None,
+        };
+        let filter = LogFilter { stream: self.stream, replica: self.replica, level, grep };
+        Ok(LogQuery { tail: self.tail, since, follow: self.follow.unwrap_or(true), filter })
+    }
+}
+
+/// Whether `segment` fits in one token of a log subject. NATS wildcards
+/// and `.` would widen the subject to other namespaces' logs.
+fn subject_token(segment: &str) -> bool {
+    !segment.is_empty() && !segment.contains(['*', '>', '.'])
+}
 
-pub async fn ws_app_logs(Path((namespace, name)): Path<(String, String)>, State(state): State<SharedState>, ws: WebSocketUpgrade) -> impl IntoResponse {
-    let subject = format!("span.apps.{namespace}.{name}.logs");
-    ws.on_upgrade(move |socket| handle_ws(socket, state, subject))
+/// An app's logs. Needs the viewer role in the app's namespace.
+pub async fn ws_app_logs(Path((namespace, name)): Path<(String, String)>, caller: Caller, Query(params): Query<LogParams>, State(state): State<SharedState>, ws: WebSocketUpgrade) -> Result<Response, ApiError> {
+    caller.require(&namespace, Role::Viewer).map_err(|s| error(s, "reading app logs requires the viewer role"))?;
+    if !subject_token(&namespace) || !subject_token(&name) {
+        return Err(error(StatusCode::BAD_REQUEST, "namespace and app names in log streams may not contain '*', '>' or '.'"));
+    }
+    let subject = app_log_subject(&namespace, &name);
+    let query = params.query()?;
+    Ok(ws.on_upgrade(move |socket| handle_ws(socket, state, subject, query)).into_response())
 }
 
-pub async fn ws_build_logs(Path(build_id): Path<String>, State(state): State<SharedState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+/// A build's logs. Builds belong to no namespace, so this needs the viewer
+/// role across the cluster.
+pub async fn ws_build_logs(Path(build_id): Path<String>, caller: Caller, Query(params): Query<LogParams>, State(state): State<SharedState>, ws: WebSocketUpgrade) -> Result<Response, ApiError> {
+    caller.require(ALL_NAMESPACES, Role::Viewer).map_err(|s| error(s, "reading build logs requires the viewer role"))?;
+    if !subject_token(&build_id) {
+        return Err(error(StatusCode::BAD_REQUEST, "build ids in log streams may not contain '*', '>' or '.'"));
+    }
     let subject = format!("span.builds.{build_id}.logs");
-    ws.on_upgrade(move |socket| handle_ws(socket, state, subject))
+    let query = params.query()?;
+    Ok(ws.on_upgrade(move |socket| handle_ws(socket, state, subject, query)).into_response())
 }
 
-async fn handle_ws(mut socket: WebSocket, state: SharedState, subject: String) {
-    // Send buffered lines first
-    let buf = state.log_hub.get_buffer(&subject).await;
-    for line in buf { let _ = socket.send(Message::Text(line)).await; }
+/// A route's access log: one JSON record per request, from every gateway.
+/// Records carry client addresses, so this needs the viewer role in the
+/// route's namespace.
+pub async fn ws_route_access(Path((namespace, name)): Path<(String, String)>, caller: Caller, Query(params): Query<LogParams>, State(state): State<SharedState>, ws: WebSocketUpgrade) -> Result<Response, ApiError> {
+    caller.require(&namespace, Role::Viewer).map_err(|s| error(s, "reading access logs requires the viewer role"))?;
+    if !subject_token(&namespace) || !subject_token(&name) {
+        return Err(error(StatusCode::BAD_REQUEST, "namespace and route names in access logs may not contain '*', '>' or '.'"));
+    }
+    let subject = access_log_subject(&namespace, &name);
+    let query = params.query()?;
+    Ok(ws.on_upgrade(move |socket| handle_ws(socket, state, subject, query)).into_response())
+}
 
-    // Subscribe to live stream
-    let tx = state.log_hub.get_sender(&subject).await;
-    let mut rx = tx.subscribe();
+async fn handle_ws(mut socket: WebSocket, state: SharedState, subject: String, query: LogQuery) {
+    let mut reader = match state.log_hub.open(&subject, &query).await {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn!(subject=%subject, error=%e, "failed to read log history");
+            let _ = socket.send(close(close_code::ERROR, "log history unavailable")).await;
+            return;
+        }
+    };
+    for line in std::mem::take(&mut reader.history) {
+        if socket.send(Message::Text(line)).await.is_err() { return; }
+    }
+    if !query.follow {
+        let _ = socket.send(close(close_code::NORMAL, "")).await;
+        return;
+    }
 
     loop {
         tokio::select! {
             biased;
             // We only send server -> client; ignore client->server
-            msg = rx.recv() => {
+            line = reader.next() => {
+                match line {
+                    Some(line) => { if socket.send(Message::Text(line)).await.is_err() { break; } }
+                    None => { let _ = socket.send(close(close_code::AWAY, "log closed")).await; break; }
+                }
+            }
+            msg = socket.recv() => {
                 match msg {
-                    Ok(line) => { if socket.send(Message::Text(line)).await.is_err() { break; } }
-                    Err(e) => { warn!(subject=%subject, error=%e, "broadcast receive error"); break; }
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => {}
                 }
             }
-            _ = socket.recv() => { /* ignore client messages */ }
         }
     }
 }
 
+fn close(code: u16, reason: &'static str) -> Message {
+    Message::Close(Some(CloseFrame { code, reason: reason.into() }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -147,4 +454,99 @@ mod tests {
         let b = hub.get_sender("b").await;
         assert!(!a1.same_channel(&b));
     }
+
+    #[tokio::test]
+    async fn reads_the_tail_then_follows() {
+        let hub = LogHub::new();
+        let subject = "span.apps.shop.web.logs";
+        for i in 0..5 { hub.append_and_broadcast(subject, format!("l{i}")).await; }
+
+        let mut reader = hub.open(subject, &LogQuery { tail: Some(2), follow: false, ..Default::default() }).await.unwrap();
+        assert_eq!(reader.history, ["l3", "l4"]);
+        assert_eq!(reader.next().await, None, "one-shot reads end with the history");
+
+        let mut reader = hub.open(subject, &LogQuery { tail: Some(0), ..Default::default() }).await.unwrap();
+        assert!(reader.history.is_empty());
+        hub.append_and_broadcast(subject, "l5".into()).await;
+        assert_eq!(reader.next().await.as_deref(), Some("l5"));
+
+        let since = Some(Utc::now() + chrono::Duration::minutes(1));
+        let reader = hub.open(subject, &LogQuery { since, follow: false, ..Default::default() }).await.unwrap();
+        assert!(reader.history.is_empty());
+    }
+
+    fn envelope(stream: LogStream, replica: &str, line: &str) -> String {
+        serde_json::to_string(&LogEnvelope {
+            timestamp: Utc::now(),
+            stream,
+            container_id: format!("c-{replica}"),
+            replica: replica.into(),
+            node: "node-1".into(),
+            level: LogLevel::parse(line),
+            line: line.into(),
+        }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn filters_envelopes_on_the_server() {
+        let hub = LogHub::new();
+        let subject = "span.apps.shop.web.logs";
+        let lines = [
+            envelope(LogStream::Stdout, "web-0", "INFO listening on :8080"),
+            envelope(LogStream::Stderr, "web-1", "WARN pool exhausted"),
+            envelope(LogStream::Stderr, "web-0", "ERROR order 42 failed"),
+            envelope(LogStream::Stdout, "web-1", "GET /orders/42 200"),
+            "a line from before envelopes".to_string(),
+        ];
+        for line in &lines { hub.append_and_broadcast(subject, line.clone()).await; }
+        let read = |filter: LogFilter| {
+            let hub = hub.clone();
+            async move { hub.open(subject, &LogQuery { follow: false, filter, ..Default::default() }).await.unwrap().history }
+        };
+
+        assert_eq!(read(LogFilter::default()).await, lines);
+        assert_eq!(read(LogFilter { stream: Some(LogStream::Stderr), ..Default::default() }).await, [lines[1].clone(), lines[2].clone()]);
+        assert_eq!(read(LogFilter { replica: Some("web-1".into()), ..Default::default() }).await, [lines[1].clone(), lines[3].clone()]);
+        assert_eq!(read(LogFilter { level: Some(LogLevel::Warn), ..Default::default() }).await, [lines[1].clone(), lines[2].clone()]);
+        let grep = Some(Regex::new(r"\b42\b").unwrap());
+        assert_eq!(read(LogFilter { grep: grep.clone(), ..Default::default() }).await, [lines[2].clone(), lines[3].clone()]);
+        let before = Some(Regex::new("^a line").unwrap());
+        assert_eq!(read(LogFilter { grep: before, ..Default::default() }).await, [lines[4].clone()], "grep matches the text, not the envelope");
+
+        let mut reader = hub.open(subject, &LogQuery { tail: Some(0), filter: LogFilter { grep, ..Default::default() }, ..Default::default() }).await.unwrap();
+        hub.append_and_broadcast(subject, envelope(LogStream::Stdout, "web-0", "GET /health 200")).await;
+        let matching = envelope(LogStream::Stdout, "web-0", "GET /orders/42 304");
+        hub.append_and_broadcast(subject, matching.clone()).await;
+        assert_eq!(reader.next().await, Some(matching), "live lines are filtered too");
+    }
+
+    #[test]
+    fn parses_since_as_a_time_or_a_duration_ago() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let at = |s: &str| Some(DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc));
+        assert_eq!(parse_since("2024-04-30T08:30:00+02:00", now), at("2024-04-30T06:30:00Z"));
+        assert_eq!(parse_since("90s", now), at("2024-05-01T11:58:30Z"));
+        assert_eq!(parse_since("15m", now), at("2024-05-01T11:45:00Z"));
+        assert_eq!(parse_since("7d", now), at("2024-04-24T12:00:00Z"));
+        for bad in ["", "m", "10", "10w", "-5m", "99999999999999999999d"] {
+            assert_eq!(parse_since(bad, now), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn app_and_build_logs_have_streams() {
+        assert_eq!(stream_for("span.apps.shop.web.logs"), Some("SPAN_APP_LOGS"));
+        assert_eq!(stream_for("span.builds.b-1.logs"), Some("SPAN_BUILD_LOGS"));
+        assert_eq!(stream_for(&access_log_subject("shop", "web")), None);
+        assert_eq!(stream_for("span.apps.shop.logs"), None);
+    }
+
+    #[test]
+    fn subject_tokens_exclude_wildcards_and_dots() {
+        assert!(subject_token("shop"));
+        assert!(subject_token("b-1"));
+        for segment in ["*", ">", "shop.web", ""] {
+            assert!(!subject_token(segment), "{segment:?}");
+        }
+    }
 }
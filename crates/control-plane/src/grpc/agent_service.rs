@@ -1,13 +1,14 @@
 use tonic::{Request, Response, Status};
 use proto::agent::{
     agent_service_server::AgentService,
-    NodeInfo, NodeCredentials, NodeStatus, NodeId, DesiredState, Container,
+    NodeInfo, NodeCredentials, NodeStatus, NodeId, DesiredState, HeartbeatAck, LogBatch, LogBatchAck,
 };
-use crate::state::SharedState;
+use common::events::{EventPublisher, LogEnvelope, LogStream, SpanEvent};
+use sqlx::Row;
+use std::collections::HashMap;
+use crate::{audit::{self, AuditEntry, Outcome}, grpc::interceptor::{peer_node_id, peer_serial}, nodes::certs::{issuing_ca, record_issued}, scheduler::desired_state::desired_containers, state::SharedState};
 use sqlx::types::Json;
 use uuid::Uuid;
-#[cfg(feature = "grpc")]
-use tonic::transport::server::TlsConnectInfo;
 
 #[derive(Clone)]
 pub struct AgentSvc {
@@ -16,68 +17,168 @@ pub struct AgentSvc {
 
 impl AgentSvc {
     pub fn new(state: SharedState) -> Self { Self { state } }
-}
 
-#[tonic::async_trait]
-impl AgentService for AgentSvc {
-    async fn register_node(&self, request: Request<NodeInfo>) -> Result<Response<NodeCredentials>, Status> {
-        let info = request.into_inner();
+    /// Issue a certificate for `node_id` under the active CA and record it.
+    async fn issue_credentials(&self, node_id: Uuid) -> Result<NodeCredentials, Status> {
+        let (issued, ca_serial, bundle) = {
+            let ca = self.state.ca.read().map_err(|_| Status::internal("CA lock poisoned"))?;
+            let issued = crypto::issue_node_cert(&node_id.to_string(), &ca.ca)
+                .map_err(|e| Status::internal(format!("cert error: {e}")))?;
+            let ca_serial = ca.serial().map_err(|e| Status::internal(format!("cert error: {e}")))?;
+            (issued, ca_serial, ca.trust_bundle_pem())
+        };
+        record_issued(node_id, &issued.serial, &ca_serial, &self.state.db)
+            .await
+            .map_err(|e| Status::internal(format!("db error: {e}")))?;
+
+        Ok(NodeCredentials { node_id: node_id.to_string(), cert: issued.cert_pem.into_bytes(), key: issued.key_pem.into_bytes(), ca: bundle.into_bytes() })
+    }
+
+    async fn register(&self, info: &NodeInfo) -> Result<NodeCredentials, Status> {
         let node_id = Uuid::new_v4();
-        sqlx::query!(
-            "INSERT INTO nodes (id, name, region, labels, status) VALUES ($1, $2, $3, $4, 'registered')",
-            node_id, info.name, info.region, Json(info.labels)
-        )
+        sqlx::query("INSERT INTO nodes (id, name, region, labels, status) VALUES ($1, $2, $3, $4, 'registered')")
+        .bind(node_id)
+        .bind(&info.name)
+        .bind(&info.region)
+        .bind(Json(&info.labels))
         .execute(&self.state.db)
         .await
-        .map_err(|e| Status::internal(format!("db error: {}", e)))?;
+        .map_err(|e| Status::internal(format!("db error: {e}")))?;
+
+        self.issue_credentials(node_id).await
+    }
 
-        let (cert_pem, key_pem) = crypto::generate_node_cert(&node_id.to_string(), &self.state.ca)
-            .map_err(|e| Status::internal(format!("cert error: {}", e)))?;
+    async fn renew(&self, node_id: &str) -> Result<NodeCredentials, Status> {
+        let node_uuid = Uuid::parse_str(node_id).map_err(|_| Status::unauthenticated("invalid node id"))?;
+        let exists: Option<Uuid> = sqlx::query_scalar("SELECT id FROM nodes WHERE id = $1")
+            .bind(node_uuid)
+            .fetch_optional(&self.state.db)
+            .await
+            .map_err(|e| Status::internal(format!("db error: {e}")))?;
+        if exists.is_none() {
+            return Err(Status::permission_denied("node is not registered"));
+        }
 
-        Ok(Response::new(NodeCredentials { node_id: node_id.to_string(), cert: cert_pem.into_bytes(), key: key_pem.into_bytes() }))
+        tracing::info!(%node_id, "Re-issuing node certificate under active CA");
+        self.issue_credentials(node_uuid).await
     }
 
-    async fn heartbeat(&self, request: Request<NodeStatus>) -> Result<Response<prost_types::Empty>, Status> {
-        let body = request.get_ref();
-        let mut node_id = body.node_id.clone();
-        let status = &body.status;
+    /// Publish the lines of `batch` whose containers are scheduled on
+    /// `node_id`, each on its app's log subject. The agent only names the
+    /// container; which app it belongs to is looked up here.
+    async fn ship(&self, node_id: Uuid, batch: LogBatch) -> Result<LogBatchAck, Status> {
+        let nats = self.state.nats.as_ref().ok_or_else(|| Status::unavailable("event bus not configured"))?;
+        let ids: Vec<&str> = batch.lines.iter().map(|l| l.container_id.as_str()).collect();
+        let rows = sqlx::query("SELECT d.container_id, a.name AS app, n.name AS namespace, nd.name AS node FROM container_deployments d JOIN apps a ON a.id = d.app_id JOIN namespaces n ON n.id = a.namespace_id JOIN nodes nd ON nd.id = d.node_id WHERE d.node_id = $1 AND d.container_id = ANY($2)")
+            .bind(node_id)
+            .bind(&ids)
+            .fetch_all(&self.state.db)
+            .await
+            .map_err(|e| Status::internal(format!("db error: {e}")))?;
+        let owners: HashMap<String, (String, String, String)> = rows.iter()
+            .map(|r| (r.get("container_id"), (r.get("namespace"), r.get("app"), r.get("node"))))
+            .collect();
 
-        if let Some(info) = request.extensions().get::<TlsConnectInfo>() {
-            if let Some(first) = info.peer_certs().and_then(|v| v.first()).cloned() {
-                if let Ok((_rem, parsed)) = x509_parser::parse_x509_certificate(first.as_ref()) {
-                    if let Some(san) = parsed.subject_alternative_name() {
-                        for gn in san.value.general_names.iter() {
-                            if let x509_parser::extensions::GeneralName::DNSName(dns) = gn {
-                                node_id = dns.to_string();
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
+        let publisher = EventPublisher { client: nats.clone() };
+        let mut rejected = 0;
+        for line in batch.lines {
+            let Some((namespace, app, node)) = owners.get(&line.container_id) else {
+                rejected += 1;
+                continue;
+            };
+            let stream = match line.stream.as_str() {
+                "stderr" => LogStream::Stderr,
+                _ => LogStream::Stdout,
+            };
+            let timestamp = line.timestamp
+                .and_then(|t| chrono::DateTime::from_timestamp(t.seconds, t.nanos.try_into().unwrap_or(0)))
+                .unwrap_or_else(chrono::Utc::now);
+            let log = LogEnvelope::new(app, node, line.container_id, stream, timestamp, line.line);
+            publisher.publish(SpanEvent::ContainerLog { namespace: namespace.clone(), app: app.clone(), log })
+                .await
+                .map_err(|e| Status::unavailable(format!("event bus error: {e}")))?;
+        }
+        if rejected > 0 {
+            tracing::warn!(%node_id, rejected, "dropped log lines of containers not scheduled on the node");
         }
+        Ok(LogBatchAck { rejected })
+    }
+
+    async fn audit<T>(&self, actor: String, action: &str, resource: String, request_id: String, result: &Result<T, Status>) {
+        let code = result.as_ref().map(|_| tonic::Code::Ok).unwrap_or_else(|s| s.code());
+        let entry = AuditEntry { actor, action: action.to_string(), resource, request_id, outcome: Outcome::from_grpc(code), status: code as i32, source: "grpc" };
+        self.state.audit.record(entry, &self.state.db).await;
+    }
+}
+
+#[tonic::async_trait]
+impl AgentService for AgentSvc {
+    async fn register_node(&self, request: Request<NodeInfo>) -> Result<Response<NodeCredentials>, Status> {
+        let request_id = audit::request_id(request.metadata().get(audit::REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+        let info = request.into_inner();
+        let result = self.register(&info).await;
+        // The node has no identity until this call succeeds
+        let resource = match &result {
+            Ok(creds) => format!("nodes/{}", creds.node_id),
+            Err(_) => format!("nodes/{}", info.name),
+        };
+        self.audit("anonymous".into(), "nodes.register", resource, request_id, &result).await;
+        Ok(Response::new(result?))
+    }
+
+    async fn heartbeat(&self, request: Request<NodeStatus>) -> Result<Response<HeartbeatAck>, Status> {
+        let body = request.get_ref();
+        let node_id = peer_node_id(&request).unwrap_or_else(|| body.node_id.clone());
+        let status = &body.status;
 
         let node_uuid = Uuid::parse_str(&node_id).map_err(|_| Status::unauthenticated("invalid node id"))?;
 
-        sqlx::query!(
-            "UPDATE nodes SET status = $1, heartbeat_at = NOW() WHERE id = $2",
-            status,
-            node_uuid
-        )
+        sqlx::query("UPDATE nodes SET status = $1, heartbeat_at = NOW() WHERE id = $2")
+        .bind(status)
+        .bind(node_uuid)
         .execute(&self.state.db)
         .await
-        .map_err(|e| Status::internal(format!("db error: {}", e)))?;
+        .map_err(|e| Status::internal(format!("db error: {e}")))?;
 
         if let Some(nc) = &self.state.nats {
-            let subject = format!("span.nodes.{}.heartbeat", node_id);
+            let subject = format!("span.nodes.{node_id}.heartbeat");
             let _ = nc.publish(subject, Vec::new().into()).await;
         }
 
-        Ok(Response::new(prost_types::Empty{}))
+        // Ask nodes still holding a certificate from a retiring CA to renew
+        let mut reissue_certificate = false;
+        if let Some(serial) = peer_serial(&request) {
+            let active = self.state.ca.read().ok().and_then(|ca| ca.serial().ok());
+            let issuer = issuing_ca(&serial, &self.state.db)
+                .await
+                .map_err(|e| Status::internal(format!("db error: {e}")))?;
+            reissue_certificate = active.is_some() && issuer != active;
+        }
+
+        Ok(Response::new(HeartbeatAck { reissue_certificate }))
+    }
+
+    async fn get_desired_state(&self, request: Request<NodeId>) -> Result<Response<DesiredState>, Status> {
+        // Desired state carries decrypted secrets, so only hand it to the node itself
+        let node_id = peer_node_id(&request).ok_or_else(|| Status::unauthenticated("client certificate required"))?;
+        let node_uuid = Uuid::parse_str(&node_id).map_err(|_| Status::unauthenticated("invalid node id"))?;
+        let containers = desired_containers(node_uuid, &self.state.keyring, &self.state.db)
+            .await
+            .map_err(|e| Status::internal(format!("desired state error: {e}")))?;
+        Ok(Response::new(DesiredState { containers }))
+    }
+
+    async fn renew_certificate(&self, request: Request<NodeId>) -> Result<Response<NodeCredentials>, Status> {
+        let node_id = peer_node_id(&request).ok_or_else(|| Status::unauthenticated("client certificate required"))?;
+        let request_id = audit::request_id(request.metadata().get(audit::REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+        let result = self.renew(&node_id).await;
+        self.audit(format!("node:{node_id}"), "nodes.renew-certificate", format!("nodes/{node_id}"), request_id, &result).await;
+        Ok(Response::new(result?))
     }
 
-    async fn get_desired_state(&self, _request: Request<NodeId>) -> Result<Response<DesiredState>, Status> {
-        let state = DesiredState { containers: vec![ Container { id: "example".into(), image: "ghcr.io/example:latest".into(), env: Default::default() } ] };
-        Ok(Response::new(state))
+    async fn ship_logs(&self, request: Request<LogBatch>) -> Result<Response<LogBatchAck>, Status> {
+        let node_id = peer_node_id(&request).ok_or_else(|| Status::unauthenticated("client certificate required"))?;
+        let node_uuid = Uuid::parse_str(&node_id).map_err(|_| Status::unauthenticated("invalid node id"))?;
+        Ok(Response::new(self.ship(node_uuid, request.into_inner()).await?))
     }
 }
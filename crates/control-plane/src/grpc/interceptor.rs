@@ -0,0 +1,58 @@
+use std::{net::{IpAddr, Ipv4Addr}, sync::Arc};
+use tonic::{Request, Status};
+
+use crate::{nodes::certs::RevocationList, ratelimit::{self, RateLimiter}};
+
+/// Serial of the client certificate presented on this connection, if any.
+pub fn peer_serial<T>(request: &Request<T>) -> Option<String> {
+    let certs = request.peer_certs()?;
+    let first = certs.first()?;
+    crypto::serial_from_der(first.as_ref()).ok()
+}
+
+/// Node id carried in the SAN of the presented client certificate, if any.
+pub fn peer_node_id<T>(request: &Request<T>) -> Option<String> {
+    let certs = request.peer_certs()?;
+    let first = certs.first()?;
+    let (_rem, parsed) = x509_parser::parse_x509_certificate(first.as_ref()).ok()?;
+    let san = parsed.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|gn| match gn {
+        x509_parser::extensions::GeneralName::DNSName(dns) => Some(dns.to_string()),
+        _ => None,
+    })
+}
+
+/// Reject calls made with a revoked node certificate.
+///
+/// Anonymous calls (no client certificate) pass through so that `RegisterNode`
+/// keeps working for nodes that have not been issued credentials yet.
+pub fn revocation_check(revocations: Arc<RevocationList>) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |request: Request<()>| {
+        if let Some(serial) = peer_serial(&request) {
+            if revocations.is_revoked(&serial) {
+                tracing::warn!(%serial, "rejected gRPC call with revoked certificate");
+                return Err(Status::unauthenticated("certificate revoked"));
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Revocation check plus rate limiting for every gRPC call.
+///
+/// Calls are limited per peer IP and, when a client certificate is presented,
+/// per certificate serial. Anonymous calls (`RegisterNode`) only have the IP
+/// bucket. A revoked certificate counts as an authentication failure.
+pub fn guard(revocations: Arc<RevocationList>, limiter: Arc<RateLimiter>) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    let mut check_revoked = revocation_check(revocations);
+    move |request: Request<()>| {
+        let ip = request.remote_addr().map(|a| a.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let serial = peer_serial(&request);
+        if let Err(rejection) = limiter.check(ip, serial.as_deref()) {
+            ratelimit::rejected("grpc", ip, rejection);
+            let secs = rejection.retry_after().as_secs().max(1);
+            return Err(Status::resource_exhausted(format!("rate limited; retry in {secs}s")));
+        }
+        check_revoked(request).inspect_err(|_| ratelimit::auth_failed(&limiter, "grpc", ip))
+    }
+}
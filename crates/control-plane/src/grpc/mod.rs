@@ -1 +1,2 @@
 pub mod agent_service;
+pub mod interceptor;
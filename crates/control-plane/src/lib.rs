@@ -1,4 +1,7 @@
+pub mod acme;
 pub mod api;
+pub mod audit;
+pub mod canary;
 #[cfg(feature = "grpc")]
 pub mod grpc;
 pub mod scheduler;
@@ -6,8 +9,12 @@ pub mod nodes;
 pub mod state;
 pub mod config;
 pub mod events;
+pub mod metrics;
+pub mod oidc;
+pub mod ratelimit;
+pub mod secrets;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use axum::Router;
 use models::{create_pool, run_migrations};
 #[cfg(feature = "grpc")]
@@ -43,7 +50,18 @@ pub async fn start() -> anyhow::Result<()> {
         match async_nats::connect(url.clone()).await {
             Ok(client) => {
                 info!(%url, "Connected to NATS");
-                let hub = Arc::new(events::logs::LogHub::new());
+                let retention = events::logs::LogRetention {
+                    max_age: Duration::from_secs(cfg.log_retention_days * 24 * 60 * 60),
+                    max_lines_per_subject: cfg.log_lines_per_subject,
+                };
+                let hub = match events::logs::LogHub::durable(client.clone(), &retention).await {
+                    Ok(hub) => hub,
+                    Err(e) => {
+                        warn!(error=%e, "JetStream unavailable; keeping recent logs in memory only");
+                        events::logs::LogHub::new()
+                    }
+                };
+                let hub = Arc::new(hub);
                 hub.clone().start_subscribers(client.clone()).await;
                 (Some(client), hub)
             }
@@ -57,12 +75,31 @@ pub async fn start() -> anyhow::Result<()> {
         (None, Arc::new(events::logs::LogHub::new()))
     };
 
+    let revocations = Arc::new(nodes::certs::RevocationList::new());
+    if let Err(e) = revocations.refresh(&pool).await {
+        warn!(error=%e, "Failed to load certificate revocation list");
+    }
+
+    let keyring = Arc::new(secrets::keyring::MasterKeyring::from_env());
+    if keyring.is_configured() {
+        keyring.load(&pool).await?;
+    } else {
+        warn!("SPAN_MASTER_KEY not set; secrets API disabled");
+    }
+
+    let rate_limiter = Arc::new(ratelimit::RateLimiter::new(&cfg.rate_limit));
+    let oidc = cfg.oidc.clone().map(|c| {
+        info!(issuer = %c.issuer, "Single sign-on enabled");
+        Arc::new(oidc::OidcVerifier::new(c))
+    });
+    let audit = Arc::new(audit::AuditLog::new(if cfg.audit_nats { nats.clone() } else { None }));
+
     #[cfg(feature = "grpc")]
     let ca_material = crypto::load_or_init_ca(None)?;
     #[cfg(feature = "grpc")]
-    let state: SharedState = Arc::new(AppState { db: pool, version: VERSION, cluster_id, jwt_secret, nats, log_hub, ca_pem: ca_material.ca_cert_pem.clone(), ca: Arc::new(ca_material.ca) });
+    let state: SharedState = Arc::new(AppState { db: pool, version: VERSION, cluster_id, jwt_secret, nats, log_hub, revocations, keyring, audit, rate_limiter, oidc, l4_ports: cfg.l4_ports, ca: Arc::new(std::sync::RwLock::new(ca_material)), ca_reload: Arc::new(tokio::sync::Notify::new()) });
     #[cfg(not(feature = "grpc"))]
-    let state: SharedState = Arc::new(AppState { db: pool, version: VERSION, cluster_id, jwt_secret, nats, log_hub });
+    let state: SharedState = Arc::new(AppState { db: pool, version: VERSION, cluster_id, jwt_secret, nats, log_hub, revocations, keyring, audit, rate_limiter, oidc, l4_ports: cfg.l4_ports });
 
     let http_addr: SocketAddr = cfg.http_bind.parse()?;
     let grpc_addr: SocketAddr = cfg.grpc_bind.parse()?;
@@ -73,6 +110,11 @@ pub async fn start() -> anyhow::Result<()> {
     // Start node health monitor
     #[cfg(feature = "grpc")]
     let monitor = monitor_node_health(state.clone());
+    #[cfg(feature = "grpc")]
+    let revocation_sync = refresh_revocations(state.clone());
+    let secret_purge = purge_deleted_secrets(state.clone(), Duration::from_secs(cfg.secret_retention_days * 24 * 60 * 60));
+    let certificates = acme::run(state.clone(), cfg.acme.clone());
+    let weight_shifts = canary::run(state.clone());
     let shutdown = shutdown_signal();
 
     #[cfg(feature = "grpc")]
@@ -80,44 +122,76 @@ pub async fn start() -> anyhow::Result<()> {
         res = http => { res?; },
         res = grpc => { res?; },
         _ = monitor => { info!("Health monitor exited"); },
+        _ = revocation_sync => { info!("Revocation sync exited"); },
+        _ = secret_purge => { info!("Secret purge exited"); },
+        _ = certificates => { info!("Certificate manager exited"); },
+        _ = weight_shifts => { info!("Weight shifter exited"); },
         _ = shutdown => { info!("Shutdown signal received"); }
     }
 
     #[cfg(not(feature = "grpc"))]
     tokio::select! {
         res = http => { res?; },
+        _ = secret_purge => { info!("Secret purge exited"); },
+        _ = certificates => { info!("Certificate manager exited"); },
+        _ = weight_shifts => { info!("Weight shifter exited"); },
         _ = shutdown => { info!("Shutdown signal received"); }
     }
 
     Ok(())
 }
 
+/// Permanently remove secret versions soft-deleted longer than `retention` ago.
+async fn purge_deleted_secrets(state: SharedState, retention: Duration) {
+    loop {
+        match secrets::store::purge_deleted(retention, &state.db).await {
+            Ok(0) => {}
+            Ok(purged) => info!(purged, "Purged deleted secret versions past retention"),
+            Err(e) => warn!(error=%e, "Failed to purge deleted secrets"),
+        }
+        tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+    }
+}
+
 pub async fn run_http(addr: SocketAddr, state: SharedState) -> anyhow::Result<()> {
     let app: Router = router(state);
     let listener = TcpListener::bind(addr).await?;
     tracing::info!(%addr, "HTTP API listening");
-    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await?;
+    // Client addresses feed the per-IP rate limits
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).with_graceful_shutdown(shutdown_signal()).await?;
     Ok(())
 }
 
 #[cfg(feature = "grpc")]
 pub async fn run_grpc(addr: SocketAddr, state: SharedState) -> anyhow::Result<()> {
-    // Build server identity signed by CA for TLS
-    let (server_cert_pem, server_key_pem) = crypto::generate_node_cert("control-plane", &state.ca)?;
-    let identity = Identity::from_pem(server_cert_pem.clone(), server_key_pem.clone());
-    let _client_ca = TlsCertificate::from_pem(state.ca_pem.clone());
-
-    // Use optional client auth: allow both anonymous and authenticated clients.
-    let tls = ServerTlsConfig::new().identity(identity);
-
-    let svc = AgentSvc::new(state.clone());
-    tracing::info!(%addr, "gRPC API listening (TLS enabled)");
-    Server::builder()
-        .tls_config(tls)?
-        .add_service(AgentServiceServer::new(svc))
-        .serve_with_shutdown(addr, shutdown_signal())
-        .await?;
-    Ok(())
+    let mut shutdown = Box::pin(shutdown_signal());
+    loop {
+        // Build server identity signed by CA for TLS; rebuilt whenever the CA set changes
+        let (server_cert_pem, server_key_pem, trust_bundle) = {
+            let ca = state.ca.read().map_err(|_| anyhow::anyhow!("CA lock poisoned"))?;
+            let (cert, key) = crypto::generate_node_cert("control-plane", ca.server_signer())?;
+            (cert, key, ca.trust_bundle_pem())
+        };
+        let identity = Identity::from_pem(server_cert_pem, server_key_pem);
+        let client_ca = TlsCertificate::from_pem(trust_bundle);
+
+        // Use optional client auth: allow both anonymous and authenticated clients.
+        let tls = ServerTlsConfig::new().identity(identity).client_ca_root(client_ca).client_auth_optional(true);
+
+        let svc = AgentSvc::new(state.clone());
+        let check = grpc::interceptor::guard(state.revocations.clone(), state.rate_limiter.clone());
+        let reload = state.ca_reload.clone();
+        tracing::info!(%addr, "gRPC API listening (TLS enabled)");
+        let server = Server::builder()
+            .tls_config(tls)?
+            .add_service(AgentServiceServer::with_interceptor(svc, check))
+            .serve_with_shutdown(addr, async move { reload.notified().await });
+
+        tokio::select! {
+            res = server => { res?; info!("CA changed; restarting gRPC server with new TLS config"); }
+            _ = &mut shutdown => { return Ok(()); }
+        }
+    }
 }
 
 pub async fn shutdown_signal() {
@@ -142,11 +216,21 @@ pub async fn shutdown_signal() {
 pub async fn monitor_node_health(state: SharedState) {
     use std::time::Duration;
     loop {
-        let _ = sqlx::query!(
-            "UPDATE nodes SET status = 'unreachable' WHERE heartbeat_at < NOW() - INTERVAL '2 minutes' AND status != 'unreachable'"
-        )
+        let _ = sqlx::query("UPDATE nodes SET status = 'unreachable' WHERE heartbeat_at < NOW() - INTERVAL '2 minutes' AND status != 'unreachable'")
         .execute(&state.db)
         .await;
         tokio::time::sleep(Duration::from_secs(30)).await;
     }
 }
+
+/// Pick up revocations made by other control planes sharing the database.
+#[cfg(feature = "grpc")]
+pub async fn refresh_revocations(state: SharedState) {
+    use std::time::Duration;
+    loop {
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        if let Err(e) = state.revocations.refresh(&state.db).await {
+            warn!(error=%e, "Failed to refresh certificate revocation list");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::LazyLock;
+
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use common::events::AccessLog;
+use prometheus::{register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec, TextEncoder};
+
+pub static RATE_LIMIT_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("span_rate_limit_rejections_total", "Calls rejected by the rate limiter", &["surface", "reason"]).expect("register metric")
+});
+
+pub static AUTH_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("span_auth_failures_total", "Calls rejected for missing or invalid credentials", &["surface"]).expect("register metric")
+});
+
+pub static LOCKOUTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("span_auth_lockouts_total", "Client IPs locked out after repeated authentication failures", &["surface"]).expect("register metric")
+});
+
+pub static ROUTE_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("span_route_requests_total", "Requests gateways routed, by status class", &["namespace", "route", "class"]).expect("register metric")
+});
+
+pub static ROUTE_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("span_route_errors_total", "Routed requests answered with a server error", &["namespace", "route"]).expect("register metric")
+});
+
+pub static ROUTE_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!("span_route_request_duration_seconds", "Time from a request reaching a gateway to the end of its response", &["namespace", "route"]).expect("register metric")
+});
+
+pub static ROUTE_BYTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("span_route_bytes_total", "Body bytes gateways received and sent per route", &["namespace", "route", "direction"]).expect("register metric")
+});
+
+/// Count a request from a gateway access log into the route metrics.
+pub fn observe_access(record: &AccessLog) {
+    let labels = [record.namespace.as_str(), record.route.as_str()];
+    let class = match record.status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    };
+    ROUTE_REQUESTS.with_label_values(&[labels[0], labels[1], class]).inc();
+    if record.status >= 500 {
+        ROUTE_ERRORS.with_label_values(&labels).inc();
+    }
+    ROUTE_LATENCY.with_label_values(&labels).observe(record.latency_ms / 1000.0);
+    ROUTE_BYTES.with_label_values(&[labels[0], labels[1], "received"]).inc_by(record.bytes_received);
+    ROUTE_BYTES.with_label_values(&[labels[0], labels[1], "sent"]).inc_by(record.bytes_sent);
+}
+
+/// Prometheus text exposition of every registered metric.
+pub async fn get_metrics() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut body) {
+        tracing::warn!(error = %e, "failed to encode metrics");
+        return (StatusCode::INTERNAL_SERVER_ERROR, [(header::CONTENT_TYPE, "text/plain".to_string())], Vec::new());
+    }
+    (StatusCode::OK, [(header::CONTENT_TYPE, encoder.format_type().to_string())], body)
+}
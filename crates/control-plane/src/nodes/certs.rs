@@ -0,0 +1,116 @@
+use std::{collections::HashSet, sync::RwLock};
+
+use anyhow::Result;
+use models::PgPool;
+use sqlx::Row;
+use uuid::Uuid;
+
+/// In-memory set of revoked certificate serials.
+///
+/// gRPC interceptors are synchronous, so they cannot query the database on
+/// every call. The set is kept in sync by `refresh` and updated eagerly when
+/// this control plane revokes certificates itself.
+#[derive(Default)]
+pub struct RevocationList {
+    serials: RwLock<HashSet<String>>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn is_revoked(&self, serial: &str) -> bool {
+        self.serials.read().map(|s| s.contains(serial)).unwrap_or(false)
+    }
+
+    pub fn extend(&self, serials: impl IntoIterator<Item = String>) {
+        if let Ok(mut set) = self.serials.write() { set.extend(serials); }
+    }
+
+    pub fn len(&self) -> usize {
+        self.serials.read().map(|s| s.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Replace the set with every revoked serial recorded in the database.
+    pub async fn refresh(&self, db: &PgPool) -> Result<()> {
+        let serials: Vec<String> = sqlx::query_scalar("SELECT serial FROM node_certificates WHERE revoked_at IS NOT NULL")
+            .fetch_all(db)
+            .await?;
+        if let Ok(mut set) = self.serials.write() { *set = serials.into_iter().collect(); }
+        Ok(())
+    }
+}
+
+pub async fn record_issued(node_id: Uuid, serial: &str, ca_serial: &str, db: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO node_certificates (serial, node_id, ca_serial) VALUES ($1, $2, $3)")
+        .bind(serial)
+        .bind(node_id)
+        .bind(ca_serial)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Serial of the CA that issued the certificate with `serial`, if recorded.
+pub async fn issuing_ca(serial: &str, db: &PgPool) -> Result<Option<String>> {
+    let ca_serial: Option<Option<String>> = sqlx::query_scalar("SELECT ca_serial FROM node_certificates WHERE serial = $1")
+        .bind(serial)
+        .fetch_optional(db)
+        .await?;
+    Ok(ca_serial.flatten())
+}
+
+/// Nodes that do not yet hold a valid certificate issued by the CA with `ca_serial`.
+pub async fn nodes_pending_reissue(ca_serial: &str, db: &PgPool) -> Result<Vec<(Uuid, String)>> {
+    let rows = sqlx::query("SELECT n.id, n.name FROM nodes n WHERE NOT EXISTS (SELECT 1 FROM node_certificates c WHERE c.node_id = n.id AND c.revoked_at IS NULL AND c.ca_serial = $1) ORDER BY n.created_at ASC")
+        .bind(ca_serial)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|row| (row.get("id"), row.get("name"))).collect())
+}
+
+/// Revoke every still-valid certificate issued to `node_id`, returning the serials.
+pub async fn revoke_node_certs(node_id: Uuid, db: &mut sqlx::PgConnection) -> Result<Vec<String>> {
+    let serials = sqlx::query_scalar("UPDATE node_certificates SET revoked_at = NOW() WHERE node_id = $1 AND revoked_at IS NULL RETURNING serial")
+        .bind(node_id)
+        .fetch_all(db)
+        .await?;
+    Ok(serials)
+}
+
+pub struct RevokedCert {
+    pub serial: String,
+    pub node_id: Uuid,
+    /// Serial of the issuing CA; unset for certificates issued before CA rotation existed.
+    pub ca_serial: Option<String>,
+    pub revoked_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub async fn list_revoked(db: &PgPool) -> Result<Vec<RevokedCert>> {
+    let rows = sqlx::query("SELECT serial, node_id, ca_serial, revoked_at FROM node_certificates WHERE revoked_at IS NOT NULL ORDER BY revoked_at ASC")
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|row| RevokedCert {
+        serial: row.get("serial"),
+        node_id: row.get("node_id"),
+        ca_serial: row.get("ca_serial"),
+        revoked_at: row.get("revoked_at"),
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extend_marks_serials_revoked() {
+        let list = RevocationList::new();
+        assert!(list.is_empty());
+        list.extend(vec!["ab12".to_string(), "cd34".to_string()]);
+        assert!(list.is_revoked("ab12"));
+        assert!(list.is_revoked("cd34"));
+        assert!(!list.is_revoked("ef56"));
+        assert_eq!(list.len(), 2);
+    }
+}
@@ -10,20 +10,20 @@ pub async fn drain_node(node_id: Uuid, db: PgPool) -> Result<()> {
         .bind(node_id)
         .execute(&db)
         .await?;
-    println!("Node {} cordoned", node_id);
+    tracing::info!(%node_id, "node cordoned for drain");
 
     let deployments = sqlx::query("SELECT id, app_id, container_id FROM container_deployments WHERE node_id = $1")
         .bind(node_id)
         .fetch_all(&db)
         .await?;
-    println!("Found {} deployments to evict", deployments.len());
+    tracing::info!(%node_id, count = deployments.len(), "evicting deployments");
 
     for row in deployments {
         let deployment_id: Uuid = row.get("id");
         let app_id: Option<Uuid> = row.get("app_id");
         let container_id: String = row.get("container_id");
 
-        println!("Evicting container {} from node {}", container_id, node_id);
+        tracing::info!(%node_id, container = %container_id, "evicting container");
 
         sqlx::query("DELETE FROM container_deployments WHERE id = $1")
             .bind(deployment_id)
@@ -37,6 +37,6 @@ pub async fn drain_node(node_id: Uuid, db: PgPool) -> Result<()> {
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
     }
 
-    println!("Node {} drained successfully", node_id);
+    tracing::info!(%node_id, "node drained");
     Ok(())
 }
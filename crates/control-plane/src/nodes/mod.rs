@@ -1,2 +1,3 @@
 pub mod drain;
 pub mod remove;
+pub mod certs;
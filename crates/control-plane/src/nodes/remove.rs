@@ -3,7 +3,11 @@ use models::PgPool;
 use sqlx::Row;
 use uuid::Uuid;
 
-pub async fn remove_node(node_id: Uuid, db: PgPool) -> Result<()> {
+use super::certs::revoke_node_certs;
+
+/// Remove a drained node and revoke its certificates, returning the revoked serials.
+/// Both happen in one transaction, so a node is never gone with its certificates still valid.
+pub async fn remove_node(node_id: Uuid, db: PgPool) -> Result<Vec<String>> {
     let node_exists = sqlx::query("SELECT cordoned FROM nodes WHERE id = $1")
         .bind(node_id)
         .fetch_optional(&db)
@@ -23,16 +27,20 @@ pub async fn remove_node(node_id: Uuid, db: PgPool) -> Result<()> {
         return Err(anyhow!("Node still has active deployments. Drain first."));
     }
 
+    let mut tx = db.begin().await?;
+    let revoked = revoke_node_certs(node_id, &mut tx).await?;
+
     sqlx::query("DELETE FROM service_endpoints WHERE node_id = $1")
         .bind(node_id)
-        .execute(&db)
+        .execute(&mut *tx)
         .await?;
 
     sqlx::query("DELETE FROM nodes WHERE id = $1")
         .bind(node_id)
-        .execute(&db)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
 
-    println!("Node {} removed", node_id);
-    Ok(())
+    tracing::info!(%node_id, revoked = revoked.len(), "node removed");
+    Ok(revoked)
 }
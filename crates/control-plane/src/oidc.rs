@@ -0,0 +1,252 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+use anyhow::{anyhow, bail, Context, Result};
+use common::auth::{issue_token, Claims, Role};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+/// Tokens naming an unknown key refetch the JWKS at most this often, so
+/// forged tokens cannot hammer the issuer.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+
+/// `[oidc]` section of the control plane config.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OidcConfig {
+    /// Issuer URL; `/.well-known/openid-configuration` is fetched from it.
+    pub issuer: String,
+    /// Client id the CLI authenticates as; ID tokens must carry it as audience.
+    pub client_id: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// ID token claim listing the user's groups.
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    #[serde(default = "default_session_ttl_hours")]
+    pub session_ttl_hours: u64,
+    #[serde(default)]
+    pub group_roles: Vec<GroupRole>,
+}
+
+/// Grants `role` in `namespace` (`*` for all) to members of `group`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GroupRole {
+    pub group: String,
+    pub namespace: String,
+    pub role: Role,
+}
+
+fn default_scopes() -> Vec<String> { vec!["openid".into(), "profile".into(), "email".into()] }
+fn default_groups_claim() -> String { "groups".into() }
+fn default_session_ttl_hours() -> u64 { 12 }
+
+impl OidcConfig {
+    pub fn new(issuer: impl Into<String>, client_id: impl Into<String>) -> Self {
+        Self {
+            issuer: issuer.into(),
+            client_id: client_id.into(),
+            scopes: default_scopes(),
+            groups_claim: default_groups_claim(),
+            session_ttl_hours: default_session_ttl_hours(),
+            group_roles: Vec::new(),
+        }
+    }
+
+    /// Highest role per namespace granted by `groups`.
+    pub fn roles_for(&self, groups: &[String]) -> HashMap<String, Role> {
+        let mut roles: HashMap<String, Role> = HashMap::new();
+        for mapping in self.group_roles.iter().filter(|m| groups.contains(&m.group)) {
+            let role = roles.entry(mapping.namespace.clone()).or_insert(mapping.role);
+            *role = (*role).max(mapping.role);
+        }
+        roles
+    }
+}
+
+/// Endpoints published in the issuer's discovery document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub device_authorization_endpoint: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("invalid ID token: {0}")]
+    InvalidToken(String),
+    #[error("none of your groups grant a role on this cluster")]
+    NoRoles,
+    #[error("identity provider unavailable: {0}")]
+    Issuer(String),
+}
+
+/// A control plane session minted from a verified ID token.
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub token: String,
+    pub subject: String,
+    pub expires_at: u64,
+    pub roles: HashMap<String, Role>,
+}
+
+/// Verifies ID tokens from the configured issuer. Discovery and the JWKS are
+/// cached; the key set is refetched when a token names an unknown key id, at
+/// most every `JWKS_MIN_REFRESH`, so issuer key rotation needs no restart.
+pub struct OidcVerifier {
+    pub config: OidcConfig,
+    http: reqwest::Client,
+    discovery: RwLock<Option<Discovery>>,
+    jwks: RwLock<JwkSet>,
+    jwks_fetched: Mutex<Option<Instant>>,
+}
+
+impl OidcVerifier {
+    pub fn new(config: OidcConfig) -> Self {
+        let http = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().unwrap_or_default();
+        Self { config, http, discovery: RwLock::new(None), jwks: RwLock::new(JwkSet { keys: Vec::new() }), jwks_fetched: Mutex::new(None) }
+    }
+
+    pub async fn discovery(&self) -> Result<Discovery> {
+        if let Some(d) = self.discovery.read().await.clone() {
+            return Ok(d);
+        }
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+        let discovery: Discovery = self.http.get(&url).send().await?.error_for_status()?.json().await.with_context(|| format!("parsing {url}"))?;
+        if discovery.issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            bail!("discovery document is for issuer {}, expected {}", discovery.issuer, self.config.issuer);
+        }
+        *self.discovery.write().await = Some(discovery.clone());
+        Ok(discovery)
+    }
+
+    /// Refetch the key set, unless that was tried within `JWKS_MIN_REFRESH`.
+    async fn refresh_jwks(&self) -> Result<()> {
+        {
+            let mut fetched = self.jwks_fetched.lock().map_err(|_| anyhow!("JWKS lock poisoned"))?;
+            if fetched.is_some_and(|at| at.elapsed() < JWKS_MIN_REFRESH) {
+                return Ok(());
+            }
+            *fetched = Some(Instant::now());
+        }
+        let discovery = self.discovery().await?;
+        let jwks: JwkSet = self.http.get(&discovery.jwks_uri).send().await?.error_for_status()?.json().await?;
+        *self.jwks.write().await = jwks;
+        Ok(())
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>> {
+        let jwks = self.jwks.read().await;
+        let jwk = match kid {
+            Some(kid) => jwks.find(kid),
+            // Issuers with a single key may omit `kid`
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        };
+        jwk.map(|k| DecodingKey::from_jwk(k).map_err(|e| anyhow!("unusable JWK: {e}"))).transpose()
+    }
+
+    /// Verify signature, issuer, audience and expiry, returning the token's claims.
+    pub async fn verify(&self, id_token: &str) -> Result<serde_json::Map<String, serde_json::Value>, SessionError> {
+        let header = decode_header(id_token).map_err(|e| SessionError::InvalidToken(e.to_string()))?;
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(SessionError::InvalidToken("symmetric signatures are not accepted".into()));
+        }
+        let kid = header.kid.as_deref();
+        let key = match self.decoding_key(kid).await.map_err(|e| SessionError::InvalidToken(e.to_string()))? {
+            Some(key) => key,
+            None => {
+                self.refresh_jwks().await.map_err(|e| SessionError::Issuer(e.to_string()))?;
+                self.decoding_key(kid)
+                    .await
+                    .map_err(|e| SessionError::InvalidToken(e.to_string()))?
+                    .ok_or_else(|| SessionError::InvalidToken("signed with an unknown key".into()))?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[self.config.issuer.trim_end_matches('/'), self.config.issuer.as_str()]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        decode::<serde_json::Map<String, serde_json::Value>>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| SessionError::InvalidToken(e.to_string()))
+    }
+
+    /// Exchange an ID token for a control plane session token signed with `jwt_secret`.
+    pub async fn session(&self, id_token: &str, jwt_secret: &str) -> Result<Session, SessionError> {
+        let claims = self.verify(id_token).await?;
+        let groups = groups(&claims, &self.config.groups_claim);
+        let roles = self.config.roles_for(&groups);
+        if roles.is_empty() {
+            return Err(SessionError::NoRoles);
+        }
+        let subject = subject(&claims);
+
+        let mut session = Claims::new(&subject, Duration::from_secs(self.config.session_ttl_hours * 60 * 60));
+        session.roles = roles.clone();
+        let token = issue_token(&session, jwt_secret).map_err(|e| SessionError::Issuer(e.to_string()))?;
+        Ok(Session { token, subject, expires_at: session.exp, roles })
+    }
+}
+
+/// A human readable subject, as the audit log records it. The email only
+/// counts once the issuer verified it; anyone can put any address on an
+/// unverified account.
+fn subject(claims: &serde_json::Map<String, serde_json::Value>) -> String {
+    let verified = matches!(claims.get("email_verified"), Some(serde_json::Value::Bool(true))) || claims.get("email_verified").and_then(|v| v.as_str()) == Some("true");
+    let email = claims.get("email").filter(|_| verified);
+    [email, claims.get("preferred_username"), claims.get("sub")]
+        .into_iter()
+        .find_map(|v| v.and_then(|v| v.as_str()))
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Group names from `claim`, which issuers send as a list or a single string.
+fn groups(claims: &serde_json::Map<String, serde_json::Value>, claim: &str) -> Vec<String> {
+    match claims.get(claim) {
+        Some(serde_json::Value::Array(items)) => items.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+        Some(serde_json::Value::String(group)) => vec![group.clone()],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_groups_to_highest_role_per_namespace() {
+        let mut cfg = OidcConfig::new("https://idp.example.com", "span");
+        cfg.group_roles = vec![
+            GroupRole { group: "devs".into(), namespace: "staging".into(), role: Role::Developer },
+            GroupRole { group: "oncall".into(), namespace: "staging".into(), role: Role::Admin },
+            GroupRole { group: "devs".into(), namespace: "prod".into(), role: Role::Viewer },
+        ];
+        let roles = cfg.roles_for(&["devs".into(), "oncall".into()]);
+        assert_eq!(roles.get("staging"), Some(&Role::Admin));
+        assert_eq!(roles.get("prod"), Some(&Role::Viewer));
+        assert!(cfg.roles_for(&["other".into()]).is_empty());
+    }
+
+    #[test]
+    fn reads_groups_as_list_or_string() {
+        let claims = serde_json::json!({ "groups": ["a", "b"], "role": "c" });
+        let claims = claims.as_object().unwrap();
+        assert_eq!(groups(claims, "groups"), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(groups(claims, "role"), vec!["c".to_string()]);
+        assert!(groups(claims, "missing").is_empty());
+    }
+
+    #[test]
+    fn uses_the_email_as_subject_only_once_verified() {
+        let claims = |c: serde_json::Value| c.as_object().unwrap().clone();
+        assert_eq!(subject(&claims(serde_json::json!({ "sub": "u1", "email": "a@example.com", "email_verified": true }))), "a@example.com");
+        assert_eq!(subject(&claims(serde_json::json!({ "sub": "u1", "email": "a@example.com", "email_verified": "true" }))), "a@example.com");
+        assert_eq!(subject(&claims(serde_json::json!({ "sub": "u1", "email": "a@example.com", "preferred_username": "alice" }))), "alice");
+        assert_eq!(subject(&claims(serde_json::json!({ "sub": "u1", "email": "a@example.com", "email_verified": false }))), "u1");
+    }
+}
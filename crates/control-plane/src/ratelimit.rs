@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use common::auth::{verify_token, Role, ALL_NAMESPACES};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{metrics, state::SharedState};
+
+/// Buckets idle this long are forgotten once the table grows large.
+const IDLE_EVICTION: Duration = Duration::from_secs(10 * 60);
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// Paths that load balancers and scrapers poll; never limited.
+const EXEMPT_PATHS: &[&str] = &["/health", "/metrics"];
+/// The gateway API. A gateway looks up objects for all of its visitors,
+/// syncs routes and reports stats from one IP with one token, so cluster
+/// admins are not limited here.
+const GATEWAY_PREFIX: &str = "/api/v1/gateway/";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Sustained requests per second from one client IP.
+    pub per_ip_rps: f64,
+    pub per_ip_burst: u32,
+    /// Sustained requests per second for one bearer token or node certificate.
+    pub per_token_rps: f64,
+    pub per_token_burst: u32,
+    /// Authentication failures an IP may accumulate within
+    /// `auth_failure_window_secs` before it is locked out.
+    pub auth_failure_limit: u32,
+    pub auth_failure_window_secs: u64,
+    pub lockout_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            per_ip_rps: 20.0,
+            per_ip_burst: 40,
+            per_token_rps: 50.0,
+            per_token_burst: 100,
+            auth_failure_limit: 5,
+            auth_failure_window_secs: 60,
+            lockout_secs: 300,
+        }
+    }
+}
+
+/// A table of token buckets keyed by client identity.
+struct Buckets {
+    rate: f64,
+    burst: f64,
+    entries: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Buckets {
+    fn new(rate: f64, burst: u32) -> Self {
+        Self { rate, burst: f64::from(burst.max(1)), entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Take one token for `key`, or return how long until one is available.
+    fn take(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_TRACKED_KEYS {
+            entries.retain(|_, b| now.saturating_duration_since(b.updated) < IDLE_EVICTION);
+        }
+        let bucket = entries.entry(key.to_string()).or_insert(Bucket { tokens: self.burst, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The IP or token bucket is empty; retry after the given delay.
+    Limited(Duration),
+    /// Too many authentication failures from this IP.
+    LockedOut(Duration),
+}
+
+impl Rejection {
+    pub fn retry_after(self) -> Duration {
+        match self {
+            Rejection::Limited(d) | Rejection::LockedOut(d) => d,
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Rejection::Limited(_) => "rate_limited",
+            Rejection::LockedOut(_) => "locked_out",
+        }
+    }
+}
+
+/// Per-IP and per-token token buckets plus an authentication failure lockout,
+/// shared by the HTTP API and the gRPC server.
+///
+/// Each authentication failure takes a token from a much smaller per-IP bucket
+/// (`auth_failure_limit` tokens refilled over `auth_failure_window_secs`); an
+/// IP that empties it is locked out for `lockout_secs`.
+pub struct RateLimiter {
+    enabled: bool,
+    per_ip: Buckets,
+    per_token: Buckets,
+    failures: Buckets,
+    lockout: Duration,
+    locked: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(cfg: &RateLimitConfig) -> Self {
+        let failure_rate = f64::from(cfg.auth_failure_limit) / cfg.auth_failure_window_secs.max(1) as f64;
+        Self {
+            enabled: cfg.enabled,
+            per_ip: Buckets::new(cfg.per_ip_rps, cfg.per_ip_burst),
+            per_token: Buckets::new(cfg.per_token_rps, cfg.per_token_burst),
+            failures: Buckets::new(failure_rate, cfg.auth_failure_limit),
+            lockout: Duration::from_secs(cfg.lockout_secs),
+            locked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admit or reject a call from `ip`, carrying `token` if it presented one.
+    pub fn check(&self, ip: IpAddr, token: Option<&str>) -> Result<(), Rejection> {
+        self.check_at(ip, token, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, token: Option<&str>, now: Instant) -> Result<(), Rejection> {
+        if !self.enabled {
+            return Ok(());
+        }
+        {
+            let mut locked = self.locked.lock().unwrap_or_else(|e| e.into_inner());
+            match locked.get(&ip) {
+                Some(until) if *until > now => return Err(Rejection::LockedOut(*until - now)),
+                Some(_) => { locked.remove(&ip); }
+                None => {}
+            }
+        }
+        self.per_ip.take(&ip.to_string(), now).map_err(Rejection::Limited)?;
+        if let Some(token) = token {
+            self.per_token.take(&token_key(token), now).map_err(Rejection::Limited)?;
+        }
+        Ok(())
+    }
+
+    /// Count an authentication failure from `ip`. Returns true when this
+    /// failure locked the IP out.
+    pub fn auth_failed(&self, ip: IpAddr) -> bool {
+        self.auth_failed_at(ip, Instant::now())
+    }
+
+    fn auth_failed_at(&self, ip: IpAddr, now: Instant) -> bool {
+        if !self.enabled || self.failures.take(&ip.to_string(), now).is_ok() {
+            return false;
+        }
+        self.locked.lock().unwrap_or_else(|e| e.into_inner()).insert(ip, now + self.lockout);
+        tracing::warn!(%ip, lockout_secs = self.lockout.as_secs(), "too many authentication failures; locking out client");
+        true
+    }
+}
+
+/// Bucket key for a credential; the raw token is never kept in memory longer
+/// than the request.
+fn token_key(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    digest.iter().take(16).map(|b| format!("{b:02x}")).collect()
+}
+
+/// Record a rejection in metrics and log it.
+pub fn rejected(surface: &str, ip: IpAddr, rejection: Rejection) {
+    metrics::RATE_LIMIT_REJECTIONS.with_label_values(&[surface, rejection.reason()]).inc();
+    tracing::debug!(%ip, surface, reason = rejection.reason(), "request rejected by rate limiter");
+}
+
+/// Record an authentication failure, counting a lockout in metrics if it caused one.
+pub fn auth_failed(limiter: &RateLimiter, surface: &str, ip: IpAddr) {
+    metrics::AUTH_FAILURES.with_label_values(&[surface]).inc();
+    if limiter.auth_failed(ip) {
+        metrics::LOCKOUTS.with_label_values(&[surface]).inc();
+    }
+}
+
+/// Tower middleware limiting the HTTP API. The client IP comes from the
+/// connection (`into_make_service_with_connect_info`); 401 responses count as
+/// authentication failures.
+pub async fn limit_http(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    if EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if request.uri().path().starts_with(GATEWAY_PREFIX) && token.is_some_and(|t| is_cluster_admin(&state, t)) {
+        return next.run(request).await;
+    }
+    if let Err(rejection) = state.rate_limiter.check(ip, token) {
+        rejected("http", ip, rejection);
+        return too_many_requests(rejection);
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        auth_failed(&state.rate_limiter, "http", ip);
+    }
+    response
+}
+
+fn is_cluster_admin(state: &SharedState, token: &str) -> bool {
+    verify_token(token, &state.jwt_secret).is_ok_and(|claims| claims.allows(ALL_NAMESPACES, Role::Admin))
+}
+
+fn too_many_requests(rejection: Rejection) -> Response {
+    let secs = rejection.retry_after().as_secs_f64().ceil().max(1.0) as u64;
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, axum::Json(serde_json::json!({ "error": rejection.reason() }))).into_response();
+    if let Ok(value) = HeaderValue::from_str(&secs.to_string()) {
+        response.headers_mut().insert(header::RETRY_AFTER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_ip_burst: u32, auth_failure_limit: u32) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig { per_ip_rps: 1.0, per_ip_burst, per_token_rps: 1.0, per_token_burst: 1, auth_failure_limit, auth_failure_window_secs: 60, lockout_secs: 300, ..Default::default() })
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limiter = limiter(2, 5);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let t0 = Instant::now();
+        assert!(limiter.check_at(ip, None, t0).is_ok());
+        assert!(limiter.check_at(ip, None, t0).is_ok());
+        let Err(Rejection::Limited(wait)) = limiter.check_at(ip, None, t0) else { panic!("expected limit") };
+        assert!(wait <= Duration::from_secs(1));
+        assert!(limiter.check_at(ip, None, t0 + Duration::from_secs(1)).is_ok());
+        // Other clients have their own bucket
+        assert!(limiter.check_at("10.0.0.2".parse().unwrap(), None, t0).is_ok());
+    }
+
+    #[test]
+    fn tokens_are_limited_independently_of_ip() {
+        let limiter = limiter(10, 5);
+        let t0 = Instant::now();
+        assert!(limiter.check_at("10.0.0.1".parse().unwrap(), Some("tok"), t0).is_ok());
+        assert!(limiter.check_at("10.0.0.2".parse().unwrap(), Some("tok"), t0).is_err());
+        assert!(limiter.check_at("10.0.0.2".parse().unwrap(), Some("other"), t0).is_ok());
+    }
+
+    #[test]
+    fn repeated_auth_failures_lock_out() {
+        let limiter = limiter(100, 3);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let t0 = Instant::now();
+        assert!(!limiter.auth_failed_at(ip, t0));
+        assert!(!limiter.auth_failed_at(ip, t0));
+        assert!(!limiter.auth_failed_at(ip, t0));
+        assert!(limiter.auth_failed_at(ip, t0));
+        assert!(matches!(limiter.check_at(ip, None, t0), Err(Rejection::LockedOut(_))));
+        assert!(limiter.check_at(ip, None, t0 + Duration::from_secs(301)).is_ok());
+    }
+
+    #[test]
+    fn disabled_limiter_admits_everything() {
+        let limiter = RateLimiter::new(&RateLimitConfig { enabled: false, per_ip_burst: 1, ..Default::default() });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..10 {
+            assert!(limiter.check(ip, None).is_ok());
+        }
+        assert!(!limiter.auth_failed(ip));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::{anyhow, Result};
+use models::{app::{AppSpec, SecretRef}, PgPool};
+use proto::agent::{Container, SecretFile};
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::secrets::{keyring::MasterKeyring, store};
+
+/// Default mode for secret files that don't set one.
+const DEFAULT_FILE_MODE: u32 = 0o400;
+
+/// Secret versions a deployment resolves unpinned `secretRef`s to.
+pub type SecretVersions = BTreeMap<String, i32>;
+
+/// Fill in the latest version of every unpinned secret the spec follows that
+/// `pinned` does not cover yet. Existing pins are kept.
+pub async fn pin_missing(namespace: &str, spec: &AppSpec, pinned: &SecretVersions, db: &PgPool) -> Result<SecretVersions> {
+    let mut versions = pinned.clone();
+    for r in spec.secret_refs().filter(|r| r.version.is_none()) {
+        if versions.contains_key(&r.name) { continue; }
+        if let Some(latest) = store::latest_version(namespace, &r.name, db).await? {
+            versions.insert(r.name.clone(), latest);
+        }
+    }
+    Ok(versions)
+}
+
+/// Containers scheduled on `node_id`, with secret references resolved to
+/// plaintext. Only the node's own mTLS `GetDesiredState` call should see this.
+pub async fn desired_containers(node_id: Uuid, keyring: &MasterKeyring, db: &PgPool) -> Result<Vec<Container>> {
+    let rows = sqlx::query("SELECT d.id, d.container_id, d.secret_versions, a.spec, n.name AS namespace FROM container_deployments d JOIN apps a ON a.id = d.app_id JOIN namespaces n ON n.id = a.namespace_id WHERE d.node_id = $1 ORDER BY d.created_at ASC")
+        .bind(node_id)
+        .fetch_all(db)
+        .await?;
+
+    let mut containers = Vec::with_capacity(rows.len());
+    for row in rows {
+        let deployment_id: Uuid = row.get("id");
+        let container_id: String = row.get("container_id");
+        let namespace: String = row.get("namespace");
+        let spec: AppSpec = match serde_json::from_value(row.get("spec")) {
+            Ok(spec) => spec,
+            Err(e) => { tracing::warn!(%container_id, error = %e, "skipping container with invalid app spec"); continue; }
+        };
+        let pinned: SecretVersions = serde_json::from_value(row.get("secret_versions")).unwrap_or_default();
+
+        // First delivery pins the latest versions so later changes roll out gradually
+        let versions = pin_missing(&namespace, &spec, &pinned, db).await?;
+        if versions != pinned {
+            sqlx::query("UPDATE container_deployments SET secret_versions = $1 WHERE id = $2")
+                .bind(serde_json::to_value(&versions)?)
+                .bind(deployment_id)
+                .execute(db)
+                .await?;
+        }
+
+        match build_container(container_id.clone(), &namespace, &spec, &versions, keyring, db).await {
+            Ok(container) => containers.push(container),
+            Err(e) => tracing::warn!(%container_id, error = %e, "skipping container with unresolved secrets"),
+        }
+    }
+    Ok(containers)
+}
+
+async fn build_container(id: String, namespace: &str, spec: &AppSpec, versions: &SecretVersions, keyring: &MasterKeyring, db: &PgPool) -> Result<Container> {
+    let mut used = BTreeSet::new();
+    let mut env = HashMap::new();
+    for var in &spec.env {
+        let value = match (&var.value, &var.secret_ref) {
+            (Some(value), _) => value.clone(),
+            (None, Some(r)) => {
+                let bytes = resolve(r, namespace, versions, &mut used, keyring, db).await?;
+                String::from_utf8(bytes).map_err(|_| anyhow!("secret {} is not valid UTF-8", r.name))?
+            }
+            (None, None) => continue,
+        };
+        env.insert(var.name.clone(), value);
+    }
+    let mut files = Vec::with_capacity(spec.files.len());
+    for file in &spec.files {
+        let content = resolve(&file.secret_ref, namespace, versions, &mut used, keyring, db).await?;
+        files.push(SecretFile { path: file.path.clone(), content, mode: file.mode.unwrap_or(DEFAULT_FILE_MODE) });
+    }
+
+    let revision = revision(spec, &used)?;
+    Ok(Container { id, image: spec.image.clone(), env, files, revision })
+}
+
+/// Decrypt the version `r` points at, recording it in `used`.
+async fn resolve(r: &SecretRef, namespace: &str, versions: &SecretVersions, used: &mut BTreeSet<(String, i32)>, keyring: &MasterKeyring, db: &PgPool) -> Result<Vec<u8>> {
+    let version = r.version.or_else(|| versions.get(&r.name).copied())
+        .ok_or_else(|| anyhow!("secret {} not found", r.name))?;
+    used.insert((r.name.clone(), version));
+    store::read_secret(namespace, &r.name, version, keyring, db)
+        .await?
+        .ok_or_else(|| anyhow!("secret {} version {version} not found", r.name))
+}
+
+/// Fingerprint of everything that requires a restart when it changes: the spec
+/// itself and the secret versions it resolved to, never the secret values.
+fn revision(spec: &AppSpec, secrets: &BTreeSet<(String, i32)>) -> Result<String> {
+    let input = serde_json::to_vec(&serde_json::json!({ "spec": spec, "secrets": secrets }))?;
+    let digest = Sha256::digest(input);
+    Ok(digest[..8].iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revision_tracks_resolved_secret_versions() {
+        let spec = AppSpec { image: "img".into(), replicas: 1, ..Default::default() };
+        let v1 = BTreeSet::from([("db".to_string(), 1)]);
+        let v2 = BTreeSet::from([("db".to_string(), 2)]);
+        assert_eq!(revision(&spec, &v1).unwrap(), revision(&spec, &v1).unwrap());
+        assert_ne!(revision(&spec, &v1).unwrap(), revision(&spec, &v2).unwrap());
+    }
+}
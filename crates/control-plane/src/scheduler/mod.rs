@@ -1,3 +1,6 @@
+pub mod desired_state;
+pub mod rollout;
+
 use models::PgPool;
 use models::schema::Node;
 use sqlx::Row;
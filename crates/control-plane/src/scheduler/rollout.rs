@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use models::{app::AppSpec, PgPool};
+use sqlx::Row;
+use uuid::Uuid;
+
+use super::desired_state::{pin_missing, SecretVersions};
+
+/// Pause between replicas while rolling an app, so a bad secret or spec never
+/// takes every replica down at once.
+pub const ROLLING_RESTART_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Roll every app in `namespace` that follows the latest version of `secret`.
+pub async fn roll_secret(namespace: &str, secret: &str, db: &PgPool) -> Result<()> {
+    let rows = sqlx::query("SELECT a.id, a.spec FROM apps a JOIN namespaces n ON n.id = a.namespace_id WHERE n.name = $1")
+        .bind(namespace)
+        .fetch_all(db)
+        .await?;
+    let dependents: Vec<Uuid> = rows
+        .into_iter()
+        .filter(|row| serde_json::from_value::<AppSpec>(row.get("spec")).map(|spec| spec.follows_secret(secret)).unwrap_or(false))
+        .map(|row| row.get("id"))
+        .collect();
+
+    let rollouts = dependents.into_iter().map(|app_id| async move {
+        if let Err(e) = roll_app(app_id, db).await {
+            tracing::warn!(%app_id, error = %e, "rolling restart failed");
+        }
+    });
+    futures_util::future::join_all(rollouts).await;
+    Ok(())
+}
+
+/// Move the app's deployments to the latest versions of the secrets they
+/// follow, one replica at a time. Agents restart a container when the
+/// revision in its desired state changes.
+pub async fn roll_app(app_id: Uuid, db: &PgPool) -> Result<()> {
+    let row = sqlx::query("SELECT a.spec, n.name AS namespace FROM apps a JOIN namespaces n ON n.id = a.namespace_id WHERE a.id = $1")
+        .bind(app_id)
+        .fetch_one(db)
+        .await?;
+    let spec: AppSpec = serde_json::from_value(row.get("spec"))?;
+    let namespace: String = row.get("namespace");
+
+    let deployments = sqlx::query("SELECT id, container_id, secret_versions FROM container_deployments WHERE app_id = $1 ORDER BY created_at ASC")
+        .bind(app_id)
+        .fetch_all(db)
+        .await?;
+    let mut rolled = 0;
+    for deployment in deployments {
+        let id: Uuid = deployment.get("id");
+        let container_id: String = deployment.get("container_id");
+        let current: SecretVersions = serde_json::from_value(deployment.get("secret_versions")).unwrap_or_default();
+        let latest = pin_missing(&namespace, &spec, &SecretVersions::new(), db).await?;
+        if latest == current { continue; }
+
+        if rolled > 0 { tokio::time::sleep(ROLLING_RESTART_INTERVAL).await; }
+        sqlx::query("UPDATE container_deployments SET secret_versions = $1 WHERE id = $2")
+            .bind(serde_json::to_value(&latest)?)
+            .bind(id)
+            .execute(db)
+            .await?;
+        rolled += 1;
+        tracing::info!(%app_id, %container_id, "rolling restart: deployment moved to latest secret versions");
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use std::{collections::BTreeMap, sync::RwLock};
+
+use anyhow::{anyhow, Result};
+use crypto::envelope::{self, MasterKey};
+use models::PgPool;
+use sqlx::Row;
+
+/// Number of secrets re-wrapped per batch during a master key rotation.
+const REWRAP_BATCH: i64 = 100;
+
+/// Tables holding values sealed with a master key version.
+const SEALED_TABLES: &[&str] = &["secrets", "tls_certificates", "acme_accounts"];
+
+#[derive(Default)]
+struct Loaded {
+    keys: BTreeMap<i32, MasterKey>,
+    /// Highest version recorded in the database; new secrets are wrapped with it.
+    latest: Option<i32>,
+}
+
+/// Master key versions known to this control plane.
+///
+/// Versions are derived from `SPAN_MASTER_KEY` and recorded in `master_keys`
+/// by fingerprint only. `SPAN_MASTER_KEY_PREVIOUS` can hold the old root while
+/// moving to a new one, so versions derived from it stay readable until
+/// `rotate` has re-wrapped every data key under the new root.
+pub struct MasterKeyring {
+    roots: Vec<String>,
+    loaded: RwLock<Loaded>,
+}
+
+pub struct RotationReport {
+    pub version: i32,
+    pub rewrapped: u64,
+}
+
+impl MasterKeyring {
+    /// `roots[0]` is the current root secret; any others are only used to read older versions.
+    pub fn new(roots: Vec<String>) -> Self {
+        Self { roots, loaded: RwLock::new(Loaded::default()) }
+    }
+
+    pub fn from_env() -> Self {
+        let roots = ["SPAN_MASTER_KEY", "SPAN_MASTER_KEY_PREVIOUS"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+            .collect();
+        Self::new(roots)
+    }
+
+    pub fn is_configured(&self) -> bool { !self.roots.is_empty() }
+
+    /// Load every recorded master key version, recording version 1 on first use.
+    pub async fn load(&self, db: &PgPool) -> Result<()> {
+        let Some(root) = self.roots.first() else { return Ok(()) };
+        let first = MasterKey::derive(root.as_bytes(), 1);
+        sqlx::query("INSERT INTO master_keys (version, fingerprint) SELECT 1, $1 WHERE NOT EXISTS (SELECT 1 FROM master_keys)")
+            .bind(first.fingerprint())
+            .execute(db)
+            .await?;
+
+        let rows = sqlx::query("SELECT version, fingerprint FROM master_keys ORDER BY version ASC")
+            .fetch_all(db)
+            .await?;
+        let mut loaded = Loaded::default();
+        for row in rows {
+            let version: i32 = row.get("version");
+            let fingerprint: String = row.get("fingerprint");
+            loaded.latest = Some(version);
+            match self.derive_matching(version, &fingerprint) {
+                Some(key) => { loaded.keys.insert(version, key); }
+                None => tracing::warn!(version, "master key version does not match SPAN_MASTER_KEY or SPAN_MASTER_KEY_PREVIOUS; secrets wrapped with it are unreadable"),
+            }
+        }
+        *self.loaded.write().map_err(|_| anyhow!("keyring lock poisoned"))? = loaded;
+        Ok(())
+    }
+
+    fn derive_matching(&self, version: i32, fingerprint: &str) -> Option<MasterKey> {
+        self.roots
+            .iter()
+            .map(|root| MasterKey::derive(root.as_bytes(), version))
+            .find(|key| key.fingerprint() == fingerprint)
+    }
+
+    /// Key new secrets are wrapped with.
+    pub fn active(&self) -> Result<MasterKey> {
+        let loaded = self.loaded.read().map_err(|_| anyhow!("keyring lock poisoned"))?;
+        let latest = loaded.latest.ok_or_else(|| anyhow!("SPAN_MASTER_KEY is not configured"))?;
+        loaded.keys.get(&latest).cloned().ok_or_else(|| anyhow!("master key v{latest} is not derivable from SPAN_MASTER_KEY"))
+    }
+
+    /// Key for `version`, reloading from the database if another control plane
+    /// has rotated since this one last looked.
+    pub async fn key(&self, version: i32, db: &PgPool) -> Result<MasterKey> {
+        if let Some(key) = self.cached(version)? { return Ok(key); }
+        self.load(db).await?;
+        self.cached(version)?.ok_or_else(|| anyhow!("master key v{version} is not available"))
+    }
+
+    fn cached(&self, version: i32) -> Result<Option<MasterKey>> {
+        let loaded = self.loaded.read().map_err(|_| anyhow!("keyring lock poisoned"))?;
+        Ok(loaded.keys.get(&version).cloned())
+    }
+
+    /// Introduce a new master key version derived from the current root and
+    /// re-wrap every data key with it.
+    ///
+    /// New secrets use the new version as soon as it is recorded, and each row
+    /// names the version it is wrapped with, so reads keep working throughout.
+    pub async fn rotate(&self, db: &PgPool) -> Result<RotationReport> {
+        let root = self.roots.first().ok_or_else(|| anyhow!("SPAN_MASTER_KEY is not configured"))?;
+        let current: Option<i32> = sqlx::query_scalar("SELECT MAX(version) FROM master_keys")
+            .fetch_one(db)
+            .await?;
+        let next = MasterKey::derive(root.as_bytes(), current.unwrap_or(0) + 1);
+        sqlx::query("INSERT INTO master_keys (version, fingerprint) VALUES ($1, $2)")
+            .bind(next.version)
+            .bind(next.fingerprint())
+            .execute(db)
+            .await
+            .map_err(|e| anyhow!("could not record master key v{}, is another rotation running? ({e})", next.version))?;
+        self.load(db).await?;
+
+        let mut rewrapped = 0;
+        for table in SEALED_TABLES {
+            rewrapped += self.rewrap_table(table, &next, db).await?;
+        }
+        Ok(RotationReport { version: next.version, rewrapped })
+    }
+
+    async fn rewrap_table(&self, table: &str, next: &MasterKey, db: &PgPool) -> Result<u64> {
+        let mut rewrapped = 0;
+        loop {
+            let rows = sqlx::query(&format!("SELECT id, wrapped_key, master_key_version FROM {table} WHERE wrapped_key IS NOT NULL AND master_key_version < $1 LIMIT $2"))
+                .bind(next.version)
+                .bind(REWRAP_BATCH)
+                .fetch_all(db)
+                .await?;
+            if rows.is_empty() { break; }
+            for row in rows {
+                let id: uuid::Uuid = row.get("id");
+                let wrapped_key: Vec<u8> = row.get("wrapped_key");
+                let version: i32 = row.get("master_key_version");
+                let from = self.key(version, db).await?;
+                let wrapped_key = envelope::rewrap(&from, next, &wrapped_key)?;
+                // Guard on the old version so a concurrent writer is never overwritten
+                let updated = sqlx::query(&format!("UPDATE {table} SET wrapped_key = $1, master_key_version = $2 WHERE id = $3 AND master_key_version = $4"))
+                    .bind(wrapped_key)
+                    .bind(next.version)
+                    .bind(id)
+                    .bind(version)
+                    .execute(db)
+                    .await?;
+                rewrapped += updated.rows_affected();
+            }
+        }
+        Ok(rewrapped)
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::HashMap, sync::{LazyLock, Mutex}};
+
+use anyhow::Result;
+use crypto::envelope;
+use models::{route::BasicCredential, PgPool};
+use sqlx::Row;
+
+use super::keyring::MasterKeyring;
+
+pub struct SecretSummary {
+    pub name: String,
+    pub version: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Secret names end up as env var names and file names on nodes, so keep them plain.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 253 && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Store `value` as the next version of secret `name`, returning that version.
+///
+/// Every version gets its own data key, wrapped with the active master key.
+/// Concurrent writers race for the same version number; the loser retries.
+pub async fn put_secret(namespace: &str, name: &str, value: &[u8], keyring: &MasterKeyring, db: &PgPool) -> Result<i32> {
+    const ATTEMPTS: usize = 5;
+    let sealed = envelope::seal(&keyring.active()?, value)?;
+    let ns_id = models::namespace::ensure_namespace(namespace, db).await?;
+    let mut attempt = 1;
+    loop {
+        let inserted = sqlx::query_scalar("INSERT INTO secrets (namespace_id, name, version, encrypted_value, wrapped_key, master_key_version) SELECT $1, $2, COALESCE(MAX(version), 0) + 1, $3, $4, $5 FROM secrets WHERE namespace_id = $1 AND name = $2 RETURNING version")
+            .bind(ns_id)
+            .bind(name)
+            .bind(&sealed.ciphertext)
+            .bind(&sealed.wrapped_key)
+            .bind(sealed.master_version)
+            .fetch_one(db)
+            .await;
+        match inserted {
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() && attempt < ATTEMPTS => attempt += 1,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Latest live version of every secret in `namespace`. Values are never listed.
+pub async fn list_secrets(namespace: &str, db: &PgPool) -> Result<Vec<SecretSummary>> {
+    let rows = sqlx::query("SELECT DISTINCT ON (s.name) s.name, s.version, s.created_at FROM secrets s JOIN namespaces n ON n.id = s.namespace_id WHERE n.name = $1 AND s.deleted_at IS NULL ORDER BY s.name ASC, s.version DESC")
+        .bind(namespace)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|row| SecretSummary {
+        name: row.get("name"),
+        version: row.get("version"),
+        created_at: row.get("created_at"),
+    }).collect())
+}
+
+/// Latest live version of secret `name`, if it exists.
+pub async fn latest_version(namespace: &str, name: &str, db: &PgPool) -> Result<Option<i32>> {
+    let version = sqlx::query_scalar("SELECT MAX(s.version) FROM secrets s JOIN namespaces n ON n.id = s.namespace_id WHERE n.name = $1 AND s.name = $2 AND s.deleted_at IS NULL")
+        .bind(namespace)
+        .bind(name)
+        .fetch_one(db)
+        .await?;
+    Ok(version)
+}
+
+/// Whether `name` exists and is not deleted, at `version` if given.
+pub async fn secret_exists(namespace: &str, name: &str, version: Option<i32>, db: &PgPool) -> Result<bool> {
+    let exists = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM secrets s JOIN namespaces n ON n.id = s.namespace_id WHERE n.name = $1 AND s.name = $2 AND s.deleted_at IS NULL AND ($3::INTEGER IS NULL OR s.version = $3))")
+        .bind(namespace)
+        .bind(name)
+        .bind(version)
+        .fetch_one(db)
+        .await?;
+    Ok(exists)
+}
+
+/// Decrypt version `version` of secret `name`.
+pub async fn read_secret(namespace: &str, name: &str, version: i32, keyring: &MasterKeyring, db: &PgPool) -> Result<Option<Vec<u8>>> {
+    let row = sqlx::query("SELECT s.encrypted_value, s.wrapped_key, s.master_key_version FROM secrets s JOIN namespaces n ON n.id = s.namespace_id WHERE n.name = $1 AND s.name = $2 AND s.version = $3 AND s.deleted_at IS NULL")
+        .bind(namespace)
+        .bind(name)
+        .bind(version)
+        .fetch_optional(db)
+        .await?;
+    let Some(row) = row else { return Ok(None) };
+    let sealed = envelope::SealedSecret {
+        ciphertext: row.get::<Option<Vec<u8>>, _>("encrypted_value").unwrap_or_default(),
+        wrapped_key: row.get::<Option<Vec<u8>>, _>("wrapped_key").unwrap_or_default(),
+        master_version: row.get::<Option<i32>, _>("master_key_version").unwrap_or_default(),
+    };
+    let key = keyring.key(sealed.master_version, db).await?;
+    Ok(Some(envelope::open(&key, &sealed)?))
+}
+
+pub struct SecretVersion {
+    pub version: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Every version of `name` still on record, including soft-deleted ones.
+pub async fn list_versions(namespace: &str, name: &str, db: &PgPool) -> Result<Vec<SecretVersion>> {
+    let rows = sqlx::query("SELECT s.version, s.created_at, s.deleted_at FROM secrets s JOIN namespaces n ON n.id = s.namespace_id WHERE n.name = $1 AND s.name = $2 ORDER BY s.version DESC")
+        .bind(namespace)
+        .bind(name)
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|row| SecretVersion {
+        version: row.get("version"),
+        created_at: row.get("created_at"),
+        deleted_at: row.get("deleted_at"),
+    }).collect())
+}
+
+/// Soft-delete one version of `name`, or all of them. Returns how many were deleted.
+pub async fn soft_delete(namespace: &str, name: &str, version: Option<i32>, db: &PgPool) -> Result<u64> {
+    let result = sqlx::query("UPDATE secrets s SET deleted_at = NOW() FROM namespaces n WHERE n.id = s.namespace_id AND n.name = $1 AND s.name = $2 AND s.deleted_at IS NULL AND ($3::INTEGER IS NULL OR s.version = $3)")
+        .bind(namespace)
+        .bind(name)
+        .bind(version)
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Permanently remove versions that were soft-deleted more than `retention` ago.
+pub async fn purge_deleted(retention: std::time::Duration, db: &PgPool) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM secrets WHERE deleted_at IS NOT NULL AND deleted_at < NOW() - make_interval(secs => $1)")
+        .bind(retention.as_secs_f64())
+        .execute(db)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Apps in `namespace` that would lose access if `name` (or just `version`)
+/// went away: any reference for a whole secret, and for a single version the
+/// apps pinned to it or with a replica currently resolving it.
+pub async fn apps_using(namespace: &str, name: &str, version: Option<i32>, db: &PgPool) -> Result<Vec<String>> {
+    let rows = sqlx::query("SELECT a.name, a.spec, COALESCE(jsonb_agg(d.secret_versions) FILTER (WHERE d.id IS NOT NULL), '[]') AS pins FROM apps a JOIN namespaces n ON n.id = a.namespace_id LEFT JOIN container_deployments d ON d.app_id = a.id WHERE n.name = $1 GROUP BY a.id, a.name, a.spec ORDER BY a.name ASC")
+        .bind(namespace)
+        .fetch_all(db)
+        .await?;
+    let mut apps = Vec::new();
+    for row in rows {
+        let Ok(spec) = serde_json::from_value::<models::app::AppSpec>(row.get("spec")) else { continue };
+        let pins: Vec<std::collections::BTreeMap<String, i32>> = serde_json::from_value(row.get("pins")).unwrap_or_default();
+        let uses = spec.secret_refs().any(|r| r.name == name && match (version, r.version) {
+            (None, _) => true,
+            (Some(v), Some(pinned)) => v == pinned,
+            (Some(v), None) => pins.iter().any(|p| p.get(name) == Some(&v)),
+        });
+        if uses { apps.push(row.get("name")); }
+    }
+    Ok(apps)
+}
+
+/// Hashed basic auth users by namespace and secret, with the version they
+/// were read from. Hashing is slow on purpose and route tables are built
+/// often, so each version is only hashed once.
+type BasicUsersCache = HashMap<(String, String), (i32, Vec<BasicCredential>)>;
+static BASIC_USERS: LazyLock<Mutex<BasicUsersCache>> = LazyLock::new(Mutex::default);
+
+/// Basic auth users from the latest version of secret `name`, or none if it
+/// does not exist.
+pub async fn basic_auth_users(namespace: &str, name: &str, keyring: &MasterKeyring, db: &PgPool) -> Result<Option<Vec<BasicCredential>>> {
+    let Some(version) = latest_version(namespace, name, db).await? else { return Ok(None) };
+    let key = (namespace.to_string(), name.to_string());
+    if let Some((cached, users)) = BASIC_USERS.lock().map_err(|_| anyhow::anyhow!("basic auth cache poisoned"))?.get(&key) {
+        if *cached == version { return Ok(Some(users.clone())); }
+    }
+    let Some(value) = read_secret(namespace, name, version, keyring, db).await? else { return Ok(None) };
+    let users = tokio::task::spawn_blocking(move || parse_basic_users(&String::from_utf8_lossy(&value))).await??;
+    BASIC_USERS.lock().map_err(|_| anyhow::anyhow!("basic auth cache poisoned"))?.insert(key, (version, users.clone()));
+    Ok(Some(users))
+}
+
+/// One `user:password` per line; blank lines and `#` comments are skipped.
+/// Passwords are kept only as salted argon2id hashes.
+pub fn parse_basic_users(value: &str) -> Result<Vec<BasicCredential>> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(username, password)| Ok(BasicCredential { username: username.to_string(), password_hash: crypto::password::hash(password)? }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_basic_auth_users() {
+        let users = parse_basic_users("# grafana\nalice:s3cret:with-colon\n\n  bob:hunter2  \nmalformed\n").unwrap();
+        let names: Vec<&str> = users.iter().map(|u| u.username.as_str()).collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert!(crypto::password::verify(&users[1].password_hash, "hunter2"));
+        assert!(crypto::password::verify(&users[0].password_hash, "s3cret:with-colon"), "only the first colon separates");
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use models::PgPool;
-use crate::events::logs::LogHub;
+use crate::{audit::AuditLog, events::logs::LogHub, nodes::certs::RevocationList, oidc::OidcVerifier, ratelimit::RateLimiter, secrets::keyring::MasterKeyring};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -10,10 +10,19 @@ pub struct AppState {
     pub jwt_secret: String,
     pub nats: Option<async_nats::Client>,
     pub log_hub: Arc<LogHub>,
+    pub revocations: Arc<RevocationList>,
+    pub keyring: Arc<MasterKeyring>,
+    pub audit: Arc<AuditLog>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Set when an OIDC issuer is configured for `span login`.
+    pub oidc: Option<Arc<OidcVerifier>>,
+    /// Ports L4 routes may listen on.
+    pub l4_ports: models::route::L4PortRange,
     #[cfg(feature = "grpc")]
-    pub ca_pem: String,
+    pub ca: Arc<std::sync::RwLock<crypto::CaMaterial>>,
+    /// Notified when the CA set changes so the gRPC server reloads its TLS config.
     #[cfg(feature = "grpc")]
-    pub ca: Arc<rcgen::Certificate>,
+    pub ca_reload: Arc<tokio::sync::Notify>,
 }
 
 pub type SharedState = Arc<AppState>;
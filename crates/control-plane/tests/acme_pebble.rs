@@ -0,0 +1,57 @@
+use control_plane::acme::{client::AcmeClient, AcmeConfig};
+use crypto::acme::AccountKey;
+use models::route::AcmeChallenge;
+use testcontainers::{clients, core::WaitFor, GenericImage};
+
+/// Pebble is Let's Encrypt's test CA. With `PEBBLE_VA_ALWAYS_VALID` it
+/// accepts every challenge, so the whole order flow runs without a gateway.
+fn pebble() -> GenericImage {
+    GenericImage::new("ghcr.io/letsencrypt/pebble", "latest")
+        .with_env_var("PEBBLE_VA_ALWAYS_VALID", "1")
+        .with_env_var("PEBBLE_WFE_NONCEREJECT", "0")
+        .with_exposed_port(14000)
+        .with_wait_for(WaitFor::message_on_stdout("ACME directory available"))
+}
+
+async fn client(directory_url: &str) -> AcmeClient {
+    // Pebble serves its directory with a throwaway self-signed certificate
+    let cfg = AcmeConfig { directory_url: directory_url.into(), insecure: true, ..AcmeConfig::default() };
+    AcmeClient::connect(cfg.http_client().unwrap(), directory_url, AccountKey::generate().unwrap()).await.expect("directory")
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn issues_certificates_through_both_challenge_types() {
+    common::telemetry::init_tracing();
+    let docker = clients::Cli::default();
+    let node = docker.run(pebble());
+    let directory_url = format!("https://127.0.0.1:{}/dir", node.get_host_port_ipv4(14000));
+
+    let mut acme = client(&directory_url).await;
+    let account = acme.register(&["mailto:ops@example.com".into()]).await.expect("register");
+    // Registering the same key again finds the existing account
+    assert_eq!(acme.register(&[]).await.expect("re-register"), account);
+
+    for (host, kind) in [("app.example.com", AcmeChallenge::Http01), ("api.example.com", AcmeChallenge::TlsAlpn01)] {
+        let order = acme.new_order(host).await.expect("order");
+        let challenges = acme.challenges(&order, kind).await.expect("challenges");
+        assert_eq!(challenges.len(), 1);
+        assert!(challenges[0].key_authorization.starts_with(&format!("{}.", challenges[0].token)));
+        for challenge in &challenges {
+            acme.validate(challenge).await.expect("validate");
+        }
+
+        let request = crypto::acme::certificate_request(host).unwrap();
+        let chain = acme.finalize(&order, &request.csr).await.expect("finalize");
+        assert!(chain.starts_with("-----BEGIN CERTIFICATE-----"));
+        let not_after = crypto::acme::not_after(&chain).unwrap();
+        assert!(not_after > chrono::Utc::now().timestamp() + 24 * 60 * 60);
+    }
+
+    // A restarted control plane signs with the stored account instead of registering again
+    let key = AccountKey::from_pkcs8(acme.key().pkcs8()).unwrap();
+    let restored = AcmeClient::connect(AcmeConfig { insecure: true, ..AcmeConfig::default() }.http_client().unwrap(), &directory_url, key)
+        .await
+        .unwrap()
+        .with_account(account);
+    restored.new_order("www.example.com").await.expect("order with stored account");
+}
@@ -4,6 +4,7 @@ use axum::Router;
 use futures_util::StreamExt;
 use testcontainers::{clients, core::WaitFor, GenericImage};
 use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 
 use control_plane::{api::routes::router, state::AppState};
 use models::{create_pool, run_migrations};
@@ -28,6 +29,8 @@ async fn e2e_health_and_cluster_info() {
 
     // App state
     std::env::set_var("SPAN_MASTER_KEY", "mk123");
+    let keyring = Arc::new(control_plane::secrets::keyring::MasterKeyring::from_env());
+    keyring.load(&pool).await.expect("load keyring");
     let state = Arc::new(AppState {
         db: pool,
         version: control_plane::VERSION,
@@ -35,6 +38,16 @@ async fn e2e_health_and_cluster_info() {
         jwt_secret: "jwt-xyz".into(),
         nats: None,
         log_hub: Arc::new(control_plane::events::logs::LogHub::new()),
+        revocations: Arc::new(control_plane::nodes::certs::RevocationList::new()),
+        keyring,
+        audit: Arc::new(control_plane::audit::AuditLog::new(None)),
+        rate_limiter: Arc::new(control_plane::ratelimit::RateLimiter::new(&Default::default())),
+        oidc: None,
+        l4_ports: Default::default(),
+        #[cfg(feature = "grpc")]
+        ca: Arc::new(std::sync::RwLock::new(crypto::CaMaterial::generate().unwrap())),
+        #[cfg(feature = "grpc")]
+        ca_reload: Arc::new(tokio::sync::Notify::new()),
     });
 
     // Server
@@ -52,8 +65,142 @@ async fn e2e_health_and_cluster_info() {
     let ci: serde_json::Value = reqwest::get(format!("{base}/api/v1/cluster/info")).await.unwrap().json().await.unwrap();
     assert_eq!(ci["cluster_id"], "cluster-abc");
     assert_eq!(ci["node_count"], 0);
+    assert!(ci.get("jwt_secret").is_none(), "shared secrets are for cluster admins only");
+    assert!(ci.get("master_key").is_none());
+
+    // Secrets are envelope encrypted and survive a master key rotation
+    let hc = reqwest::Client::new();
+    let admin = common::auth::issue_token(&common::auth::Claims::new("ops", std::time::Duration::from_secs(60)).with_role("*", common::auth::Role::Admin), "jwt-xyz").unwrap();
+    let resp = hc.post(format!("{base}/api/v1/namespaces/default/secrets")).json(&serde_json::json!({ "name": "db-password", "value": "x" })).send().await.unwrap();
+    assert_eq!(resp.status(), 401);
+    for value in ["s3cret", "s3cret-2"] {
+        let resp = hc.post(format!("{base}/api/v1/namespaces/default/secrets"))
+            .bearer_auth(&admin)
+            .json(&serde_json::json!({ "name": "db-password", "value": value }))
+            .send().await.unwrap();
+        assert_eq!(resp.status(), 201);
+    }
+    let secrets: serde_json::Value = reqwest::get(format!("{base}/api/v1/namespaces/default/secrets")).await.unwrap().json().await.unwrap();
+    assert_eq!(secrets[0]["name"], "db-password");
+    assert_eq!(secrets[0]["version"], 2);
+    assert!(secrets[0].get("value").is_none());
+
+    let stored: Vec<u8> = sqlx::query_scalar("SELECT encrypted_value FROM secrets WHERE name = 'db-password' AND version = 1")
+        .fetch_one(&state.db).await.unwrap();
+    assert!(!String::from_utf8_lossy(&stored).contains("s3cret"));
+
+    assert_eq!(hc.post(format!("{base}/api/v1/secrets/master-key/rotate")).send().await.unwrap().status(), 401);
+    let rotated: serde_json::Value = hc.post(format!("{base}/api/v1/secrets/master-key/rotate")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(rotated["version"], 2);
+    assert_eq!(rotated["rewrapped"], 2);
+    let (ciphertext, wrapped_key, version): (Vec<u8>, Vec<u8>, i32) = sqlx::query_as("SELECT encrypted_value, wrapped_key, master_key_version FROM secrets WHERE name = 'db-password' AND version = 1")
+        .fetch_one(&state.db).await.unwrap();
+    assert_eq!(version, 2);
+    let key = state.keyring.key(2, &state.db).await.unwrap();
+    let sealed = crypto::envelope::SealedSecret { ciphertext, wrapped_key, master_version: version };
+    assert_eq!(crypto::envelope::open(&key, &sealed).unwrap(), b"s3cret");
+
+    let ci: serde_json::Value = hc.get(format!("{base}/api/v1/cluster/info")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
     assert_eq!(ci["jwt_secret"], "jwt-xyz");
     assert_eq!(ci["master_key"], "mk123");
+
+    // Apps reference secrets by name; the HTTP API only ever returns the reference
+    let apps_url = format!("{base}/api/v1/namespaces/default/apps");
+    let spec = serde_json::json!({ "image": "ghcr.io/example/api:1", "env": [{ "name": "DB_PASSWORD", "secretRef": { "name": "missing" } }] });
+    let resp = hc.post(&apps_url).bearer_auth(&admin).json(&serde_json::json!({ "name": "api", "spec": spec })).send().await.unwrap();
+    assert_eq!(resp.status(), 422);
+    // Applying and deleting apps needs the developer role
+    let reader = common::auth::issue_token(&common::auth::Claims::new("dev", std::time::Duration::from_secs(60)).with_role("default", common::auth::Role::Viewer), "jwt-xyz").unwrap();
+    let spec = serde_json::json!({ "image": "ghcr.io/example/api:1", "env": [{ "name": "DB_PASSWORD", "secretRef": { "name": "db-password" } }] });
+    let body = serde_json::json!({ "name": "api", "spec": spec });
+    assert_eq!(hc.post(&apps_url).json(&body).send().await.unwrap().status(), 401);
+    assert_eq!(hc.post(&apps_url).bearer_auth(&reader).json(&body).send().await.unwrap().status(), 403);
+    assert_eq!(hc.delete(format!("{apps_url}/api")).send().await.unwrap().status(), 401);
+    assert_eq!(hc.delete(format!("{apps_url}/api")).bearer_auth(&reader).send().await.unwrap().status(), 403);
+    let resp = hc.post(&apps_url).bearer_auth(&admin).json(&body).send().await.unwrap();
+    assert_eq!(resp.status(), 201);
+    let app = reqwest::get(format!("{base}/api/v1/namespaces/default/apps/api")).await.unwrap().text().await.unwrap();
+    assert!(app.contains("db-password"));
+    assert!(!app.contains("s3cret"));
+
+    // Reading values needs the admin role; old versions are soft-deleted
+    let secret_url = format!("{base}/api/v1/namespaces/default/secrets/db-password");
+    assert_eq!(hc.get(&secret_url).send().await.unwrap().status(), 401);
+    let viewer = common::auth::issue_token(&common::auth::Claims::new("dev", std::time::Duration::from_secs(60)).with_role("default", common::auth::Role::Viewer), "jwt-xyz").unwrap();
+    assert_eq!(hc.get(&secret_url).bearer_auth(&viewer).send().await.unwrap().status(), 403);
+    let secret: serde_json::Value = hc.get(format!("{secret_url}?version=1")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(secret["value"], "s3cret");
+
+    assert_eq!(hc.delete(&secret_url).bearer_auth(&viewer).send().await.unwrap().status(), 403);
+    let resp = hc.delete(&secret_url).bearer_auth(&admin).send().await.unwrap();
+    assert_eq!(resp.status(), 409);
+    let resp = hc.delete(format!("{secret_url}?version=1")).bearer_auth(&admin).send().await.unwrap();
+    assert!(resp.status().is_success());
+    let versions: serde_json::Value = hc.get(format!("{secret_url}/versions")).bearer_auth(&viewer).send().await.unwrap().json().await.unwrap();
+    assert!(versions[0]["deleted_at"].is_string() || versions[1]["deleted_at"].is_string());
+    assert_eq!(hc.get(format!("{secret_url}?version=1")).bearer_auth(&admin).send().await.unwrap().status(), 404);
+
+    // Mutations are audited with the caller, outcome and request id
+    let resp = hc.delete(&secret_url).bearer_auth(&admin).header("x-request-id", "req-42").send().await.unwrap();
+    assert_eq!(resp.headers()["x-request-id"], "req-42");
+    assert_eq!(hc.get(format!("{base}/api/v1/audit")).bearer_auth(&viewer).send().await.unwrap().status(), 403);
+    let events: serde_json::Value = hc.get(format!("{base}/api/v1/audit?actor=ops&action=secrets")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(events[0]["action"], "secrets.delete");
+    assert_eq!(events[0]["resource"], "namespaces/default/secrets/db-password");
+    assert_eq!(events[0]["request_id"], "req-42");
+    assert_eq!(events[0]["outcome"], "failure");
+    let events: serde_json::Value = hc.get(format!("{base}/api/v1/audit?action=apps.create")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(events[0]["actor"], "ops");
+    assert_eq!(events[0]["status"], 201);
+
+    // Routes are applied as manifests; the gateway table joins their backends' endpoints
+    let manifest = serde_json::json!({ "kind": "Route", "metadata": { "name": "api", "namespace": "default" }, "spec": { "host": "API.example.com", "pathPrefix": "/v1", "backendRef": "api" } });
+    assert_eq!(hc.post(format!("{base}/api/v1/namespaces/default/routes")).json(&manifest).send().await.unwrap().status(), 401);
+    let resp = hc.post(format!("{base}/api/v1/namespaces/default/routes")).bearer_auth(&admin).json(&manifest).send().await.unwrap();
+    assert_eq!(resp.status(), 201);
+    // Another namespace cannot claim the host
+    let developer = common::auth::issue_token(&common::auth::Claims::new("dev", std::time::Duration::from_secs(60)).with_role("other", common::auth::Role::Developer), "jwt-xyz").unwrap();
+    let takeover = serde_json::json!({ "kind": "Route", "metadata": { "name": "api" }, "spec": { "host": "api.example.com", "backendRef": "evil" } });
+    assert_eq!(hc.post(format!("{base}/api/v1/namespaces/other/routes")).bearer_auth(&developer).json(&takeover).send().await.unwrap().status(), 409);
+    assert_eq!(hc.delete(format!("{base}/api/v1/namespaces/default/routes/api")).bearer_auth(&developer).send().await.unwrap().status(), 403);
+    let site = serde_json::json!({ "kind": "Route", "metadata": { "name": "site" }, "spec": { "host": "site.example.com", "backendRef": "bucket:default/assets" } });
+    assert_eq!(hc.post(format!("{base}/api/v1/namespaces/other/routes")).bearer_auth(&developer).json(&site).send().await.unwrap().status(), 403);
+    let bad = serde_json::json!({ "kind": "Route", "metadata": { "name": "bad" }, "spec": { "host": "https://x", "backendRef": "api" } });
+    assert_eq!(hc.post(format!("{base}/api/v1/namespaces/default/routes")).bearer_auth(&admin).json(&bad).send().await.unwrap().status(), 400);
+    // L4 routes listen within the configured port range, one route per port
+    let l4 = |name: &str, port: u16| serde_json::json!({ "kind": "Route", "metadata": { "name": name }, "spec": { "host": "*", "backendRef": "minecraft", "l4": { "listenPort": port } } });
+    let apply_l4 = |manifest: serde_json::Value| hc.post(format!("{base}/api/v1/namespaces/games/routes")).bearer_auth(&admin).json(&manifest).send();
+    assert_eq!(apply_l4(l4("ssh", 22)).await.unwrap().status(), 400);
+    assert_eq!(apply_l4(l4("mc", 30565)).await.unwrap().status(), 201);
+    assert_eq!(apply_l4(l4("mc-copy", 30565)).await.unwrap().status(), 409);
+    assert_eq!(hc.delete(format!("{base}/api/v1/namespaces/games/routes/mc")).bearer_auth(&admin).send().await.unwrap().status(), 204);
+    let routes: serde_json::Value = reqwest::get(format!("{base}/api/v1/namespaces/default/routes")).await.unwrap().json().await.unwrap();
+    assert_eq!(routes[0]["host"], "api.example.com");
+
+    let endpoints_url = format!("{base}/api/v1/namespaces/default/apps/api/endpoints");
+    let endpoints = serde_json::json!({ "endpoints": [{ "address": "10.0.0.5:8080", "healthy": true }, { "address": "10.0.0.6:8080", "healthy": false }] });
+    assert_eq!(hc.put(&endpoints_url).json(&endpoints).send().await.unwrap().status(), 401);
+    let before: serde_json::Value = reqwest::get(format!("{base}/api/v1/gateway/routes")).await.unwrap().json().await.unwrap();
+    assert!(hc.put(&endpoints_url).bearer_auth(&admin).json(&endpoints).send().await.unwrap().status().is_success());
+    let table: serde_json::Value = reqwest::get(format!("{base}/api/v1/gateway/routes")).await.unwrap().json().await.unwrap();
+    assert!(table["version"].as_i64().unwrap() > before["version"].as_i64().unwrap());
+    assert_eq!(table["routes"][0]["path_prefix"], "/v1");
+    assert_eq!(table["routes"][0]["endpoints"].as_array().unwrap().len(), 2);
+    assert_eq!(table["routes"][0]["endpoints"][1]["healthy"], false);
+
+    // TLS routes record their ACME policy; certificates need the cluster admin role
+    let tls = serde_json::json!({ "kind": "Route", "metadata": { "name": "api" }, "spec": { "host": "api.example.com", "backendRef": "api", "tls": { "challenge": "tls-alpn-01" } } });
+    assert_eq!(hc.post(format!("{base}/api/v1/namespaces/default/routes")).bearer_auth(&admin).json(&tls).send().await.unwrap().status(), 200);
+    let routes: serde_json::Value = reqwest::get(format!("{base}/api/v1/namespaces/default/routes")).await.unwrap().json().await.unwrap();
+    assert_eq!(routes[0]["tls"]["challenge"], "tls-alpn-01");
+    let wildcard = serde_json::json!({ "kind": "Route", "metadata": { "name": "any" }, "spec": { "host": "*", "backendRef": "api", "tls": {} } });
+    assert_eq!(hc.post(format!("{base}/api/v1/namespaces/default/routes")).bearer_auth(&admin).json(&wildcard).send().await.unwrap().status(), 400);
+    assert_eq!(hc.get(format!("{base}/api/v1/gateway/certificates")).bearer_auth(&viewer).send().await.unwrap().status(), 403);
+    let certs: serde_json::Value = hc.get(format!("{base}/api/v1/gateway/certificates")).bearer_auth(&admin).send().await.unwrap().json().await.unwrap();
+    assert_eq!(certs, serde_json::json!([]));
+    assert_eq!(hc.get(format!("{base}/api/v1/gateway/acme/http-01/unknown")).send().await.unwrap().status(), 404);
+    assert_eq!(hc.delete(format!("{base}/api/v1/namespaces/default/routes/api")).bearer_auth(&admin).send().await.unwrap().status(), 204);
+    assert_eq!(hc.delete(format!("{base}/api/v1/namespaces/default/routes/api")).bearer_auth(&admin).send().await.unwrap().status(), 404);
 }
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -93,6 +240,16 @@ async fn e2e_websocket_log_streaming_with_nats() {
         jwt_secret: "jwt-123".into(),
         nats: Some(client.clone()),
         log_hub: hub.clone(),
+        revocations: Arc::new(control_plane::nodes::certs::RevocationList::new()),
+        keyring: Arc::new(control_plane::secrets::keyring::MasterKeyring::new(Vec::new())),
+        audit: Arc::new(control_plane::audit::AuditLog::new(None)),
+        rate_limiter: Arc::new(control_plane::ratelimit::RateLimiter::new(&Default::default())),
+        oidc: None,
+        l4_ports: Default::default(),
+        #[cfg(feature = "grpc")]
+        ca: Arc::new(std::sync::RwLock::new(crypto::CaMaterial::generate().unwrap())),
+        #[cfg(feature = "grpc")]
+        ca_reload: Arc::new(tokio::sync::Notify::new()),
     });
     let app: Router = router(state);
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -109,8 +266,20 @@ async fn e2e_websocket_log_streaming_with_nats() {
         tokio::time::sleep(Duration::from_millis(50)).await;
     }
 
+    // Build logs need the viewer role across the cluster, and a build id
+    // that is not a wildcard
+    let url = format!("ws://{addr}/api/v1/builds/e2e-build/logs");
+    assert!(tokio_tungstenite::connect_async(&url).await.is_err());
+    let viewer = common::auth::issue_token(&common::auth::Claims::new("dev", Duration::from_secs(60)).with_role(common::auth::ALL_NAMESPACES, common::auth::Role::Viewer), "jwt-123").unwrap();
+    let authorized = |url: &str| {
+        let mut request = url.into_client_request().unwrap();
+        request.headers_mut().insert("authorization", format!("Bearer {viewer}").parse().unwrap());
+        request
+    };
+    assert!(tokio_tungstenite::connect_async(authorized(&format!("ws://{addr}/api/v1/builds/%3E/logs"))).await.is_err());
+
     // Connect WS to full API route
-    let (mut ws_stream, _) = tokio_tungstenite::connect_async(format!("ws://{}/api/v1/builds/e2e-build/logs", addr)).await.unwrap();
+    let (mut ws_stream, _) = tokio_tungstenite::connect_async(authorized(&url)).await.unwrap();
 
     // Expect buffered
     let msg1 = ws_stream.next().await.unwrap().unwrap();
@@ -11,6 +11,16 @@ async fn health_endpoint_returns_ok() {
         jwt_secret: "test".into(),
         nats: None,
         log_hub: std::sync::Arc::new(control_plane::events::logs::LogHub::new()),
+        revocations: std::sync::Arc::new(control_plane::nodes::certs::RevocationList::new()),
+        keyring: std::sync::Arc::new(control_plane::secrets::keyring::MasterKeyring::new(Vec::new())),
+        audit: std::sync::Arc::new(control_plane::audit::AuditLog::new(None)),
+        rate_limiter: std::sync::Arc::new(control_plane::ratelimit::RateLimiter::new(&Default::default())),
+        oidc: None,
+        l4_ports: Default::default(),
+        #[cfg(feature = "grpc")]
+        ca: std::sync::Arc::new(std::sync::RwLock::new(crypto::CaMaterial::generate().unwrap())),
+        #[cfg(feature = "grpc")]
+        ca_reload: std::sync::Arc::new(tokio::sync::Notify::new()),
     });
     let app = control_plane::api::routes::router(state);
 
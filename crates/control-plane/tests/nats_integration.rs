@@ -6,7 +6,7 @@ use futures_util::StreamExt;
 use testcontainers::{clients, core::WaitFor, GenericImage};
 use tokio::net::TcpListener;
 
-use control_plane::events::logs::LogHub;
+use control_plane::events::logs::{LogHub, LogQuery, LogRetention};
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn pubsub_delivery_and_buffer() {
@@ -98,3 +98,83 @@ async fn websocket_streams_buffer_then_live() {
     let msg3 = ws_stream.next().await.unwrap().unwrap();
     assert_eq!(msg3.to_text().unwrap(), "live 1");
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn access_logs_are_buffered_and_counted_per_route() {
+    common::telemetry::init_tracing();
+    let docker = clients::Cli::default();
+    let image = GenericImage::new("nats", "2.10").with_exposed_port(4222).with_wait_for(WaitFor::message_on_stdout("Server is ready"));
+    let node = docker.run(image);
+
+    let port = node.get_host_port_ipv4(4222);
+    let url = format!("nats://127.0.0.1:{port}");
+    let client = async_nats::connect(url).await.expect("connect nats");
+
+    let hub = Arc::new(LogHub::new());
+    hub.clone().start_subscribers(client.clone()).await;
+
+    let record = common::events::AccessLog {
+        timestamp: chrono::Utc::now(),
+        namespace: "shop".into(),
+        route: "storefront".into(),
+        host: "shop.example.com".into(),
+        method: "GET".into(),
+        path: "/".into(),
+        status: 502,
+        upstream: Some("10.0.0.1:8080".into()),
+        release: None,
+        latency_ms: 12.5,
+        bytes_received: 0,
+        bytes_sent: 12,
+        client_ip: Some("192.0.2.1".into()),
+    };
+    let subject = common::events::access_log_subject("shop", "storefront");
+    client.publish(subject.clone(), serde_json::to_vec(&record).unwrap().into()).await.unwrap();
+
+    for _ in 0..50 {
+        if !hub.get_buffer(&subject).await.is_empty() { break; }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let buf = hub.get_buffer(&subject).await;
+    assert_eq!(serde_json::from_str::<common::events::AccessLog>(&buf[0]).unwrap(), record);
+    assert_eq!(control_plane::metrics::ROUTE_ERRORS.with_label_values(&["shop", "storefront"]).get(), 1);
+    assert_eq!(control_plane::metrics::ROUTE_REQUESTS.with_label_values(&["shop", "storefront", "5xx"]).get(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn jetstream_keeps_logs_across_restarts() {
+    common::telemetry::init_tracing();
+    let docker = clients::Cli::default();
+    let image = GenericImage::new("nats", "2.10").with_exposed_port(4222).with_wait_for(WaitFor::message_on_stdout("Server is ready"));
+    let node = docker.run((image, vec!["-js".to_string()]));
+
+    let port = node.get_host_port_ipv4(4222);
+    let url = format!("nats://127.0.0.1:{port}");
+    let client = async_nats::connect(url).await.expect("connect nats");
+    let retention = LogRetention { max_age: Duration::from_secs(3600), max_lines_per_subject: 3 };
+    let hub = LogHub::durable(client.clone(), &retention).await.unwrap();
+
+    let subject = "span.apps.shop.web.logs";
+    for i in 1..=4 {
+        client.publish(subject.to_string(), format!("line {i}").into()).await.unwrap();
+    }
+    client.flush().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // A new hub sees what the stream kept, the oldest line past the limit gone
+    drop(hub);
+    let hub = LogHub::durable(client.clone(), &retention).await.unwrap();
+    let reader = hub.open(subject, &LogQuery { follow: false, ..Default::default() }).await.unwrap();
+    assert_eq!(reader.history, ["line 2", "line 3", "line 4"]);
+
+    let mut reader = hub.open(subject, &LogQuery { tail: Some(1), ..Default::default() }).await.unwrap();
+    assert_eq!(reader.history, ["line 4"]);
+    client.publish(subject.to_string(), "line 5".into()).await.unwrap();
+    assert_eq!(reader.next().await.as_deref(), Some("line 5"));
+
+    let since = Some(chrono::Utc::now() + chrono::Duration::minutes(1));
+    let reader = hub.open(subject, &LogQuery { since, follow: false, ..Default::default() }).await.unwrap();
+    assert!(reader.history.is_empty());
+    let other = hub.open("span.apps.shop.api.logs", &LogQuery { follow: false, ..Default::default() }).await.unwrap();
+    assert!(other.history.is_empty(), "reads are filtered to the one log");
+}
@@ -0,0 +1,161 @@
+//! Key material for ACME (RFC 8555): the account key that signs requests,
+//! certificate signing requests and TLS-ALPN-01 challenge certificates
+//! (RFC 8737).
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rcgen::{Certificate, CertificateParams, CustomExtension, DistinguishedName};
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// ALPN protocol a validation server offers for TLS-ALPN-01.
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+fn b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// A P-256 ACME account key.
+pub struct AccountKey {
+    pair: EcdsaKeyPair,
+    pkcs8: Vec<u8>,
+}
+
+impl AccountKey {
+    pub fn generate() -> Result<Self> {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(|_| anyhow!("failed to generate account key"))?;
+        Self::from_pkcs8(pkcs8.as_ref())
+    }
+
+    pub fn from_pkcs8(der: &[u8]) -> Result<Self> {
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der, &SystemRandom::new())
+            .map_err(|e| anyhow!("invalid account key: {e}"))?;
+        Ok(Self { pair, pkcs8: der.to_vec() })
+    }
+
+    /// PKCS#8 DER encoding, for storage.
+    pub fn pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    /// Public key as a JWK, members in the lexicographic order RFC 7638
+    /// requires for thumbprints.
+    pub fn jwk(&self) -> Value {
+        // Uncompressed point: 0x04 || x || y
+        let point = self.pair.public_key().as_ref();
+        json!({ "crv": "P-256", "kty": "EC", "x": b64(&point[1..33]), "y": b64(&point[33..65]) })
+    }
+
+    /// RFC 7638 JWK thumbprint.
+    pub fn thumbprint(&self) -> String {
+        b64(&Sha256::digest(self.jwk().to_string().as_bytes()))
+    }
+
+    /// What the ACME server expects to find for challenge `token`.
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{token}.{}", self.thumbprint())
+    }
+
+    /// A flattened JWS for a request to `url`. Before the account exists
+    /// the JWS carries the public key; afterwards `kid` is the account URL.
+    /// `payload: None` makes a POST-as-GET.
+    pub fn sign(&self, url: &str, nonce: &str, kid: Option<&str>, payload: Option<&Value>) -> Result<Value> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = b64(protected.to_string().as_bytes());
+        let payload = payload.map(|p| b64(p.to_string().as_bytes())).unwrap_or_default();
+        let signature = self
+            .pair
+            .sign(&SystemRandom::new(), format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| anyhow!("failed to sign ACME request"))?;
+        Ok(json!({ "protected": protected, "payload": payload, "signature": b64(signature.as_ref()) }))
+    }
+}
+
+/// A fresh certificate key and the CSR for `host` signed with it.
+pub struct CertificateRequest {
+    /// base64url DER, as the finalize request takes it.
+    pub csr: String,
+    pub key_pem: String,
+}
+
+pub fn certificate_request(host: &str) -> Result<CertificateRequest> {
+    let mut params = CertificateParams::new(vec![host.to_string()]);
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.distinguished_name = DistinguishedName::new();
+    let cert = Certificate::from_params(params)?;
+    Ok(CertificateRequest { csr: b64(&cert.serialize_request_der()?), key_pem: cert.serialize_private_key_pem() })
+}
+
+/// Self-signed certificate for `host` carrying the acmeIdentifier extension
+/// a TLS-ALPN-01 validation looks for. Returns the certificate and key PEM.
+pub fn tls_alpn_challenge_cert(host: &str, key_authorization: &str) -> Result<(String, String)> {
+    let mut params = CertificateParams::new(vec![host.to_string()]);
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&Sha256::digest(key_authorization.as_bytes()))];
+    let cert = Certificate::from_params(params)?;
+    Ok((cert.serialize_pem()?, cert.serialize_private_key_pem()))
+}
+
+/// Expiry (unix seconds) of the first certificate in a PEM chain.
+pub fn not_after(chain_pem: &str) -> Result<i64> {
+    let (_rem, pem) = x509_parser::pem::parse_x509_pem(chain_pem.as_bytes()).map_err(|e| anyhow!("invalid certificate pem: {e}"))?;
+    let (_rem, cert) = x509_parser::parse_x509_certificate(&pem.contents).map_err(|e| anyhow!("invalid certificate: {e}"))?;
+    Ok(cert.validity().not_after.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+
+    #[test]
+    fn signs_verifiable_requests() {
+        let key = AccountKey::generate().unwrap();
+        let jws = key.sign("https://acme.test/new-order", "n0nce", Some("https://acme.test/acct/1"), Some(&json!({ "a": 1 }))).unwrap();
+        let protected: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(jws["protected"].as_str().unwrap()).unwrap()).unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["kid"], "https://acme.test/acct/1");
+        assert!(protected.get("jwk").is_none());
+
+        let signing_input = format!("{}.{}", jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap());
+        let signature = URL_SAFE_NO_PAD.decode(jws["signature"].as_str().unwrap()).unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.pair.public_key().as_ref())
+            .verify(signing_input.as_bytes(), &signature)
+            .expect("signature verifies");
+
+        let get = key.sign("https://acme.test/order/1", "n0nce", None, None).unwrap();
+        assert_eq!(get["payload"], "");
+        let protected: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(get["protected"].as_str().unwrap()).unwrap()).unwrap();
+        assert_eq!(protected["jwk"], key.jwk());
+    }
+
+    #[test]
+    fn account_keys_round_trip_through_pkcs8() {
+        let key = AccountKey::generate().unwrap();
+        let restored = AccountKey::from_pkcs8(key.pkcs8()).unwrap();
+        assert_eq!(restored.thumbprint(), key.thumbprint());
+        assert_eq!(key.key_authorization("tok"), format!("tok.{}", key.thumbprint()));
+        assert_eq!(key.thumbprint().len(), 43);
+    }
+
+    #[test]
+    fn challenge_cert_carries_critical_acme_identifier() {
+        let (cert_pem, _key) = tls_alpn_challenge_cert("app.example.com", "tok.thumb").unwrap();
+        let (_rem, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes()).unwrap();
+        let (_rem, cert) = x509_parser::parse_x509_certificate(&pem.contents).unwrap();
+        let ext = cert.extensions().iter().find(|e| e.oid.to_id_string() == "1.3.6.1.5.5.7.1.31").expect("acmeIdentifier");
+        assert!(ext.critical);
+        // OCTET STRING of the SHA-256 of the key authorization
+        assert_eq!(&ext.value[2..], Sha256::digest(b"tok.thumb").as_slice());
+        assert!(not_after(&cert_pem).unwrap() > 0);
+    }
+}
@@ -0,0 +1,128 @@
+//! Envelope encryption for secrets.
+//!
+//! Every stored value is encrypted with its own random data key. The data key
+//! is then wrapped by a versioned master key, so rotating the master key only
+//! means re-wrapping data keys, never re-encrypting the values themselves.
+
+use anyhow::{anyhow, Result};
+use chacha20poly1305::{aead::{Aead, AeadCore, KeyInit, OsRng, Payload}, ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+const NONCE_LEN: usize = 12;
+const DATA_AAD: &[u8] = b"span-secret-value";
+const WRAP_AAD: &[u8] = b"span-data-key";
+
+/// A master key derived from the cluster root secret (`SPAN_MASTER_KEY`).
+#[derive(Clone)]
+pub struct MasterKey {
+    pub version: i32,
+    key: Key,
+}
+
+impl MasterKey {
+    /// Derive master key `version` from `root`. The same root always yields
+    /// the same key for a given version, so control planes sharing the root
+    /// agree on every version without exchanging key material.
+    pub fn derive(root: &[u8], version: i32) -> Self {
+        let hk = Hkdf::<Sha256>::new(Some(b"span-master-key"), root);
+        let mut key = Key::default();
+        hk.expand(format!("v{version}").as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        MasterKey { version, key }
+    }
+
+    /// Short public identifier of the key, stored alongside its version to
+    /// detect a changed or mistyped root secret.
+    pub fn fingerprint(&self) -> String {
+        let digest = Sha256::new().chain_update(b"span-master-key-check").chain_update(self.key).finalize();
+        digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// A value encrypted under a data key, with the data key wrapped by master key `master_version`.
+pub struct SealedSecret {
+    pub ciphertext: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+    pub master_version: i32,
+}
+
+fn encrypt(key: &Key, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = ChaCha20Poly1305::new(key)
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| anyhow!("encryption failed"))?;
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn decrypt(key: &Key, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(anyhow!("ciphertext too short"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(key)
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| anyhow!("decryption failed"))
+}
+
+fn unwrap_data_key(master: &MasterKey, wrapped_key: &[u8]) -> Result<Key> {
+    let raw = decrypt(&master.key, wrapped_key, WRAP_AAD)?;
+    if raw.len() != 32 {
+        return Err(anyhow!("wrapped data key has invalid length"));
+    }
+    Ok(*Key::from_slice(&raw))
+}
+
+/// Encrypt `plaintext` under a fresh data key wrapped by `master`.
+pub fn seal(master: &MasterKey, plaintext: &[u8]) -> Result<SealedSecret> {
+    let data_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    Ok(SealedSecret {
+        ciphertext: encrypt(&data_key, plaintext, DATA_AAD)?,
+        wrapped_key: encrypt(&master.key, &data_key, WRAP_AAD)?,
+        master_version: master.version,
+    })
+}
+
+/// Decrypt a sealed value. `master` must be the key version it was wrapped with.
+pub fn open(master: &MasterKey, sealed: &SealedSecret) -> Result<Vec<u8>> {
+    if master.version != sealed.master_version {
+        return Err(anyhow!("secret is wrapped with master key v{}, not v{}", sealed.master_version, master.version));
+    }
+    let data_key = unwrap_data_key(master, &sealed.wrapped_key)?;
+    decrypt(&data_key, &sealed.ciphertext, DATA_AAD)
+}
+
+/// Re-wrap a data key from one master key version to another. The value
+/// encrypted under the data key is left untouched.
+pub fn rewrap(from: &MasterKey, to: &MasterKey, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+    let data_key = unwrap_data_key(from, wrapped_key)?;
+    encrypt(&to.key, &data_key, WRAP_AAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derivation_is_deterministic_per_version() {
+        let v1 = MasterKey::derive(b"root", 1);
+        assert_eq!(v1.fingerprint(), MasterKey::derive(b"root", 1).fingerprint());
+        assert_ne!(v1.fingerprint(), MasterKey::derive(b"root", 2).fingerprint());
+        assert_ne!(v1.fingerprint(), MasterKey::derive(b"other", 1).fingerprint());
+    }
+
+    #[test]
+    fn rewrapped_secret_opens_with_new_key_only() {
+        let v1 = MasterKey::derive(b"root", 1);
+        let v2 = MasterKey::derive(b"root", 2);
+        let sealed = seal(&v1, b"hunter2").unwrap();
+        assert_eq!(open(&v1, &sealed).unwrap(), b"hunter2");
+
+        let wrapped_key = rewrap(&v1, &v2, &sealed.wrapped_key).unwrap();
+        let rotated = SealedSecret { ciphertext: sealed.ciphertext.clone(), wrapped_key, master_version: 2 };
+        assert_eq!(open(&v2, &rotated).unwrap(), b"hunter2");
+        assert!(open(&v1, &SealedSecret { master_version: 1, ..rotated }).is_err());
+    }
+}
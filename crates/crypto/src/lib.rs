@@ -1,61 +1,358 @@
+pub mod acme;
+pub mod envelope;
+pub mod password;
+
 use anyhow::{anyhow, Context, Result};
 use dirs::home_dir;
-use rcgen::{BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa, KeyPair, SanType, SerialNumber};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, CertificateRevocationList, CertificateRevocationListParams, DistinguishedName, DnType, IsCa, KeyIdMethod, KeyPair, RevocationReason, RevokedCertParams, SanType, SerialNumber};
 use rustls_pki_types::{CertificateDer, PrivateKeyDer}; // reserved for future use
-use std::{fs, path::{Path, PathBuf}};
+use age::secrecy::SecretString;
+use std::{fs, io::{Read, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+use time::OffsetDateTime;
 
 pub struct CaMaterial {
     pub ca_cert_pem: String,
     pub ca: Certificate,
+    /// CA being phased out by a rotation. It stays trusted, and keeps signing the
+    /// server identity, until every node has been re-issued under `ca`.
+    pub previous: Option<RetiringCa>,
+}
+
+pub struct RetiringCa {
+    pub ca_cert_pem: String,
+    pub ca: Certificate,
+}
+
+impl CaMaterial {
+    /// A fresh CA kept only in memory, for tests and throwaway clusters.
+    pub fn generate() -> Result<Self> {
+        let ca = new_ca("Span Root CA", rcgen::date_time_ymd(2020, 1, 1), rcgen::date_time_ymd(2050, 1, 1))?;
+        Ok(CaMaterial { ca_cert_pem: ca.serialize_pem()?, ca, previous: None })
+    }
+
+    /// Serial of the CA currently issuing node certificates.
+    pub fn serial(&self) -> Result<String> {
+        ca_serial(&self.ca_cert_pem)
+    }
+
+    /// Every CA certificate that should be trusted for client authentication.
+    pub fn trust_bundle_pem(&self) -> String {
+        match &self.previous {
+            Some(prev) => format!("{}{}", self.ca_cert_pem, prev.ca_cert_pem),
+            None => self.ca_cert_pem.clone(),
+        }
+    }
+
+    /// CA used to sign the control plane's own server certificate. During a
+    /// rotation this is the retiring CA, since nodes that have not been
+    /// re-issued yet only trust that one.
+    pub fn server_signer(&self) -> &Certificate {
+        self.previous.as_ref().map(|p| &p.ca).unwrap_or(&self.ca)
+    }
 }
 
 pub fn ca_default_dir() -> PathBuf {
     if let Some(home) = home_dir() { home.join(".config/span/ca") } else { PathBuf::from("./.span/ca") }
 }
 
-pub fn load_or_init_ca(dir: Option<&Path>) -> Result<CaMaterial> {
-    let _owned;
-    let dir: &Path = match dir {
-        Some(p) => p,
-        None => { _owned = ca_default_dir(); _owned.as_path() }
+fn resolve_dir(dir: Option<&Path>) -> PathBuf {
+    dir.map(Path::to_path_buf).unwrap_or_else(ca_default_dir)
+}
+
+/// Name of the systemd credential (see `LoadCredential=`) holding the CA passphrase.
+const PASSPHRASE_CREDENTIAL: &str = "span-ca-passphrase";
+/// Name of the systemd credential holding an age identity for the CA key.
+const IDENTITY_CREDENTIAL: &str = "span-ca-age-identity";
+
+/// How CA private keys are protected on disk.
+///
+/// With `None` keys are stored as plaintext PEM in `ca.key`. Otherwise they
+/// are age-encrypted into `ca.key.age`, and an existing plaintext key is
+/// migrated the first time it is loaded.
+pub enum KeyProtection {
+    None,
+    Passphrase(SecretString),
+    Identity(age::x25519::Identity),
+}
+
+impl KeyProtection {
+    /// Resolve key protection from the environment, in order of precedence:
+    ///
+    /// - `SPAN_CA_PASSPHRASE`
+    /// - `SPAN_CA_PASSPHRASE_FILE`
+    /// - the `span-ca-passphrase` systemd credential
+    /// - `SPAN_CA_AGE_IDENTITY_FILE`
+    /// - the `span-ca-age-identity` systemd credential
+    ///
+    /// Empty variables are treated as unset.
+    pub fn from_env() -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        if let Some(passphrase) = var("SPAN_CA_PASSPHRASE") {
+            return Self::passphrase(passphrase);
+        }
+        if let Some(path) = var("SPAN_CA_PASSPHRASE_FILE") {
+            return Self::passphrase_file(Path::new(&path));
+        }
+        let credentials = std::env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+        if let Some(path) = credentials.as_ref().map(|d| d.join(PASSPHRASE_CREDENTIAL)).filter(|p| p.exists()) {
+            return Self::passphrase_file(&path);
+        }
+        if let Some(path) = var("SPAN_CA_AGE_IDENTITY_FILE") {
+            return Self::identity_file(Path::new(&path));
+        }
+        if let Some(path) = credentials.map(|d| d.join(IDENTITY_CREDENTIAL)).filter(|p| p.exists()) {
+            return Self::identity_file(&path);
+        }
+        Ok(KeyProtection::None)
+    }
+
+    fn passphrase(passphrase: String) -> Result<Self> {
+        if passphrase.is_empty() {
+            return Err(anyhow!("CA passphrase is empty"));
+        }
+        Ok(KeyProtection::Passphrase(SecretString::new(passphrase)))
+    }
+
+    fn passphrase_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("read CA passphrase from {}", path.display()))?;
+        Self::passphrase(contents.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn identity_file(path: &Path) -> Result<Self> {
+        let file = age::IdentityFile::from_file(path.to_string_lossy().into_owned())
+            .with_context(|| format!("read age identity from {}", path.display()))?;
+        file.into_identities()
+            .into_iter()
+            .find_map(|entry| match entry {
+                age::IdentityFileEntry::Native(identity) => Some(KeyProtection::Identity(identity)),
+                #[allow(unreachable_patterns)]
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("no age identity found in {}", path.display()))
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let encryptor = match self {
+            KeyProtection::None => return Ok(plaintext.to_vec()),
+            KeyProtection::Passphrase(passphrase) => age::Encryptor::with_user_passphrase(passphrase.clone()),
+            KeyProtection::Identity(identity) => age::Encryptor::with_recipients(vec![Box::new(identity.to_public())])
+                .ok_or_else(|| anyhow!("no age recipient"))?,
+        };
+        let mut out = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut out)?;
+        writer.write_all(plaintext)?;
+        writer.finish()?;
+        Ok(out)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], path: &Path) -> Result<Vec<u8>> {
+        let decryptor = age::Decryptor::new(ciphertext).with_context(|| format!("{} is not an age file", path.display()))?;
+        let mut reader = match (decryptor, self) {
+            (age::Decryptor::Passphrase(d), KeyProtection::Passphrase(passphrase)) => d.decrypt(passphrase, None),
+            (age::Decryptor::Recipients(d), KeyProtection::Identity(identity)) => d.decrypt(std::iter::once(identity as &dyn age::Identity)),
+            (_, KeyProtection::None) => {
+                return Err(anyhow!("{} is encrypted; set SPAN_CA_PASSPHRASE, SPAN_CA_PASSPHRASE_FILE or SPAN_CA_AGE_IDENTITY_FILE", path.display()))
+            }
+            _ => return Err(anyhow!("{} was encrypted with a different kind of key than the one configured", path.display())),
+        }
+        .with_context(|| format!("decrypt {}", path.display()))?;
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+}
+
+/// Write a file readable only by its owner, tightening the mode of an
+/// existing file as well.
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(contents)?;
+    }
+    #[cfg(not(unix))]
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Refuse to use a private key that any local user could read.
+fn ensure_not_world_readable(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o004 != 0 {
+            return Err(anyhow!("refusing to use {}: it is world-readable (mode {:o}); run `chmod 600` on it", path.display(), mode & 0o777));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Read the private key stored as `{stem}.key` or `{stem}.key.age`,
+/// migrating a plaintext key to an encrypted one when protection is configured.
+fn read_key(dir: &Path, stem: &str, protection: &KeyProtection) -> Result<Option<String>> {
+    let plain_path = dir.join(format!("{stem}.key"));
+    let encrypted_path = dir.join(format!("{stem}.key.age"));
+
+    if encrypted_path.exists() {
+        ensure_not_world_readable(&encrypted_path)?;
+        let plaintext = protection.decrypt(&fs::read(&encrypted_path)?, &encrypted_path)?;
+        return Ok(Some(String::from_utf8(plaintext).context("CA key is not valid PEM")?));
+    }
+    if !plain_path.exists() {
+        return Ok(None);
+    }
+    ensure_not_world_readable(&plain_path)?;
+    let key_pem = fs::read_to_string(&plain_path)?;
+    if !matches!(protection, KeyProtection::None) {
+        write_key(dir, stem, &key_pem, protection)?;
+    }
+    Ok(Some(key_pem))
+}
+
+/// Store a private key as `{stem}.key` or, when protected, `{stem}.key.age`,
+/// removing the other form so only one copy exists on disk.
+fn write_key(dir: &Path, stem: &str, key_pem: &str, protection: &KeyProtection) -> Result<()> {
+    let plain_path = dir.join(format!("{stem}.key"));
+    let encrypted_path = dir.join(format!("{stem}.key.age"));
+    let (path, stale) = match protection {
+        KeyProtection::None => (plain_path, encrypted_path),
+        _ => (encrypted_path, plain_path),
     };
-    fs::create_dir_all(dir).ok();
+    write_private(&path, &protection.encrypt(key_pem.as_bytes())?)?;
+    if stale.exists() { fs::remove_file(stale)?; }
+    Ok(())
+}
+
+/// Load the CA from `dir` (or the default directory), creating it on first
+/// use. Key protection is taken from the environment, see [`KeyProtection::from_env`].
+pub fn load_or_init_ca(dir: Option<&Path>) -> Result<CaMaterial> {
+    load_or_init_ca_with(dir, &KeyProtection::from_env()?)
+}
+
+pub fn load_or_init_ca_with(dir: Option<&Path>, protection: &KeyProtection) -> Result<CaMaterial> {
+    let dir = resolve_dir(dir);
+    fs::create_dir_all(&dir).ok();
     let cert_path = dir.join("ca.crt");
-    let key_path = dir.join("ca.key");
 
-    if cert_path.exists() && key_path.exists() {
-        let cert_pem = fs::read_to_string(&cert_path)?;
-        let key_pem = fs::read_to_string(&key_path)?;
-        let key = KeyPair::from_pem(&key_pem).context("invalid CA key pem")?;
-        let mut params = CertificateParams::default();
-        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
-        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
-        params.distinguished_name = DistinguishedName::new();
-        params.key_pair = Some(key);
-        let ca = Certificate::from_params(params).context("build CA from existing key")?;
-        return Ok(CaMaterial { ca_cert_pem: cert_pem, ca });
+    if cert_path.exists() {
+        if let Some(key_pem) = read_key(&dir, "ca", protection)? {
+            let (ca_cert_pem, ca) = load_ca(&cert_path, &key_pem)?;
+            let prev_cert_path = dir.join("ca.prev.crt");
+            let previous = match read_key(&dir, "ca.prev", protection)? {
+                Some(key_pem) if prev_cert_path.exists() => {
+                    let (ca_cert_pem, ca) = load_ca(&prev_cert_path, &key_pem)?;
+                    Some(RetiringCa { ca_cert_pem, ca })
+                }
+                _ => None,
+            };
+            return Ok(CaMaterial { ca_cert_pem, ca, previous });
+        }
     }
 
+    let ca = new_ca("Span Root CA", rcgen::date_time_ymd(2020, 1, 1), rcgen::date_time_ymd(2050, 1, 1))?;
+    let cert_pem = ca.serialize_pem()?;
+    let key_pem = ca.serialize_private_key_pem();
+
+    fs::write(&cert_path, &cert_pem)?;
+    write_key(&dir, "ca", &key_pem, protection)?;
+
+    Ok(CaMaterial { ca_cert_pem: cert_pem, ca, previous: None })
+}
+
+/// Rebuild a CA from its stored certificate and key, keeping the subject,
+/// validity and key identifier so newly signed certificates chain to the
+/// `ca.crt` on disk.
+fn load_ca(cert_path: &Path, key_pem: &str) -> Result<(String, Certificate)> {
+    let cert_pem = fs::read_to_string(cert_path)?;
+    let key = KeyPair::from_pem(key_pem).context("invalid CA key pem")?;
+    let params = CertificateParams::from_ca_cert_pem(&cert_pem, key).context("invalid CA certificate")?;
+    let ca = Certificate::from_params(params).context("build CA from existing key")?;
+    Ok((cert_pem, ca))
+}
+
+fn new_ca(common_name: &str, not_before: OffsetDateTime, not_after: OffsetDateTime) -> Result<Certificate> {
     let mut params = CertificateParams::default();
     params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
     params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
     let mut dn = DistinguishedName::new();
-    dn.push(DnType::CommonName, "Span Root CA");
+    dn.push(DnType::CommonName, common_name);
     params.distinguished_name = dn;
-    params.not_before = rcgen::date_time_ymd(2020, 1, 1);
-    params.not_after = rcgen::date_time_ymd(2050, 1, 1);
+    params.not_before = not_before;
+    params.not_after = not_after;
+    params.serial_number = Some(SerialNumber::from(rand::random::<u64>()));
+    Ok(Certificate::from_params(params)?)
+}
+
+/// Serial of a PEM encoded CA certificate, used to record which CA issued a node certificate.
+pub fn ca_serial(ca_cert_pem: &str) -> Result<String> {
+    let (_rem, pem) = x509_parser::pem::parse_x509_pem(ca_cert_pem.as_bytes()).map_err(|e| anyhow!("invalid CA pem: {e}"))?;
+    serial_from_der(&pem.contents)
+}
 
-    let ca = Certificate::from_params(params)?;
+/// Start a CA rotation: the current CA becomes the retiring CA and a new one
+/// takes over issuing. Fails if a rotation is already in progress.
+pub fn rotate_ca(dir: Option<&Path>) -> Result<CaMaterial> {
+    rotate_ca_with(dir, &KeyProtection::from_env()?)
+}
+
+pub fn rotate_ca_with(dir: Option<&Path>, protection: &KeyProtection) -> Result<CaMaterial> {
+    let dir = resolve_dir(dir);
+    let current = load_or_init_ca_with(Some(&dir), protection)?;
+    if current.previous.is_some() {
+        return Err(anyhow!("a CA rotation is already in progress; retire the previous CA first"));
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let name = format!("Span Root CA {}", now.date());
+    let ca = new_ca(&name, now - Duration::from_secs(60 * 60), now + Duration::from_secs(25 * 365 * 24 * 60 * 60))?;
     let cert_pem = ca.serialize_pem()?;
     let key_pem = ca.serialize_private_key_pem();
 
-    fs::write(&cert_path, &cert_pem)?;
-    fs::write(&key_path, &key_pem)?;
+    // Keep the retiring CA before overwriting so an interrupted rotation never loses it
+    fs::write(dir.join("ca.prev.crt"), &current.ca_cert_pem)?;
+    write_key(&dir, "ca.prev", &current.ca.serialize_private_key_pem(), protection)?;
+    fs::write(dir.join("ca.crt"), &cert_pem)?;
+    write_key(&dir, "ca", &key_pem, protection)?;
 
-    Ok(CaMaterial { ca_cert_pem: cert_pem, ca })
+    load_or_init_ca_with(Some(&dir), protection)
+}
+
+/// Finish a CA rotation by dropping the retiring CA from disk and from trust.
+pub fn retire_previous_ca(dir: Option<&Path>) -> Result<CaMaterial> {
+    retire_previous_ca_with(dir, &KeyProtection::from_env()?)
+}
+
+pub fn retire_previous_ca_with(dir: Option<&Path>, protection: &KeyProtection) -> Result<CaMaterial> {
+    let dir = resolve_dir(dir);
+    for name in ["ca.prev.crt", "ca.prev.key", "ca.prev.key.age"] {
+        let path = dir.join(name);
+        if path.exists() { fs::remove_file(path)?; }
+    }
+    load_or_init_ca_with(Some(&dir), protection)
+}
+
+/// A freshly issued node certificate together with its serial number.
+///
+/// The serial is rendered as lowercase hex without leading zeros, which is the
+/// same form `serial_from_der` produces for a presented peer certificate.
+pub struct NodeCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub serial: String,
 }
 
 pub fn generate_node_cert(node_id: &str, ca: &Certificate) -> Result<(String, String)> {
+    let issued = issue_node_cert(node_id, ca)?;
+    Ok((issued.cert_pem, issued.key_pem))
+}
+
+pub fn issue_node_cert(node_id: &str, ca: &Certificate) -> Result<NodeCertificate> {
+    let serial = rand::random::<u64>();
     let mut params = CertificateParams::new(vec![node_id.to_string()]);
     params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
     params.not_before = rcgen::date_time_ymd(2020, 1, 1);
@@ -64,13 +361,54 @@ pub fn generate_node_cert(node_id: &str, ca: &Certificate) -> Result<(String, St
     let mut dn = DistinguishedName::new();
     dn.push(DnType::CommonName, format!("node-{}", node_id));
     params.distinguished_name = dn;
-    params.serial_number = Some(SerialNumber::from(rand::random::<u64>()));
+    params.serial_number = Some(SerialNumber::from(serial));
     params.subject_alt_names = vec![SanType::DnsName(node_id.into())];
 
     let cert = Certificate::from_params(params)?;
     let cert_pem = cert.serialize_pem_with_signer(ca)?;
     let key_pem = cert.serialize_private_key_pem();
-    Ok((cert_pem, key_pem))
+    Ok(NodeCertificate { cert_pem, key_pem, serial: format!("{serial:x}") })
+}
+
+/// Extract the serial number of a DER encoded certificate in the same hex form
+/// used by [`NodeCertificate::serial`].
+pub fn serial_from_der(der: &[u8]) -> Result<String> {
+    let (_rem, parsed) = x509_parser::parse_x509_certificate(der).map_err(|e| anyhow!("invalid certificate: {e}"))?;
+    Ok(parsed.tbs_certificate.serial.to_str_radix(16))
+}
+
+pub struct RevokedSerial {
+    pub serial: String,
+    pub revoked_at: SystemTime,
+}
+
+/// Build a PEM encoded CRL signed by `ca` listing every revoked serial.
+/// The CRL is valid for `validity`; consumers should refetch before it lapses.
+pub fn build_crl(ca: &Certificate, revoked: &[RevokedSerial], crl_number: u64, validity: Duration) -> Result<String> {
+    let now = OffsetDateTime::now_utc();
+    let revoked_certs = revoked
+        .iter()
+        .map(|r| {
+            let serial = u64::from_str_radix(&r.serial, 16).with_context(|| format!("invalid serial {}", r.serial))?;
+            Ok(RevokedCertParams {
+                serial_number: SerialNumber::from(serial),
+                revocation_time: OffsetDateTime::from(r.revoked_at),
+                reason_code: Some(RevocationReason::CessationOfOperation),
+                invalidity_date: None,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let params = CertificateRevocationListParams {
+        this_update: now,
+        next_update: now + validity,
+        crl_number: SerialNumber::from(crl_number),
+        issuing_distribution_point: None,
+        revoked_certs,
+        alg: &rcgen::PKCS_ECDSA_P256_SHA256,
+        key_identifier_method: KeyIdMethod::Sha256,
+    };
+    let crl = CertificateRevocationList::from_params(params)?;
+    Ok(crl.serialize_pem_with_signer(ca)?)
 }
 
 pub fn load_identity_from_pem(cert_pem: &str, key_pem: &str) -> Result<(Vec<u8>, Vec<u8>)> {
@@ -82,7 +420,8 @@ mod tests {
     use super::*;
     #[test]
     fn node_cert_contains_san() {
-        let ca = load_or_init_ca(None).expect("ca");
+        let dir = temp_ca_dir();
+        let ca = load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("ca");
         let node_id = "123e4567-e89b-12d3-a456-426614174000";
         let (cert_pem, _key) = generate_node_cert(node_id, &ca.ca).expect("node cert");
         // Parse PEM and extract DER using x509-parser's pem helper
@@ -100,5 +439,123 @@ mod tests {
         } else {
             panic!("san");
         }
+        fs::remove_dir_all(dir).ok();
+    }
+
+    fn temp_ca_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("span-ca-test-{:x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn issuer_of(cert_pem: &str) -> String {
+        let (_rem, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes()).expect("pem");
+        let (_rem, parsed) = x509_parser::parse_x509_certificate(&pem.contents).expect("x509");
+        parsed.issuer().to_string()
+    }
+
+    fn subject_of(cert_pem: &str) -> String {
+        let (_rem, pem) = x509_parser::pem::parse_x509_pem(cert_pem.as_bytes()).expect("pem");
+        let (_rem, parsed) = x509_parser::parse_x509_certificate(&pem.contents).expect("x509");
+        parsed.subject().to_string()
+    }
+
+    #[test]
+    fn reloaded_ca_signs_with_stored_subject() {
+        let dir = temp_ca_dir();
+        let first = load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("init");
+        let reloaded = load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("reload");
+        assert_eq!(first.ca_cert_pem, reloaded.ca_cert_pem);
+
+        let (cert_pem, _key) = generate_node_cert("node-x", &reloaded.ca).expect("node cert");
+        assert_eq!(issuer_of(&cert_pem), subject_of(&reloaded.ca_cert_pem));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn rotation_trusts_both_until_retired() {
+        let dir = temp_ca_dir();
+        let original = load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("init");
+        let rotated = rotate_ca_with(Some(&dir), &KeyProtection::None).expect("rotate");
+        assert_ne!(rotated.serial().unwrap(), original.serial().unwrap());
+        let prev = rotated.previous.as_ref().expect("previous ca");
+        assert_eq!(prev.ca_cert_pem, original.ca_cert_pem);
+        assert!(rotated.trust_bundle_pem().contains(&original.ca_cert_pem));
+        assert!(rotated.trust_bundle_pem().contains(&rotated.ca_cert_pem));
+        assert!(rotate_ca_with(Some(&dir), &KeyProtection::None).is_err());
+
+        let (cert_pem, _key) = generate_node_cert("node-y", &rotated.ca).expect("node cert");
+        assert_eq!(issuer_of(&cert_pem), subject_of(&rotated.ca_cert_pem));
+
+        let retired = retire_previous_ca_with(Some(&dir), &KeyProtection::None).expect("retire");
+        assert!(retired.previous.is_none());
+        assert_eq!(retired.trust_bundle_pem(), rotated.ca_cert_pem);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn passphrase_protected_key_is_encrypted_and_migrated() {
+        let dir = temp_ca_dir();
+        let plain = load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("init");
+        assert!(dir.join("ca.key").exists());
+
+        let protection = KeyProtection::Passphrase(SecretString::new("correct horse".to_string()));
+        let migrated = load_or_init_ca_with(Some(&dir), &protection).expect("migrate");
+        assert_eq!(migrated.ca_cert_pem, plain.ca_cert_pem);
+        assert!(!dir.join("ca.key").exists());
+        let stored = fs::read(dir.join("ca.key.age")).unwrap();
+        assert!(!String::from_utf8_lossy(&stored).contains("PRIVATE KEY"));
+
+        assert!(load_or_init_ca_with(Some(&dir), &KeyProtection::None).is_err());
+        let wrong = KeyProtection::Passphrase(SecretString::new("wrong".to_string()));
+        assert!(load_or_init_ca_with(Some(&dir), &wrong).is_err());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn identity_protected_rotation_round_trips() {
+        let dir = temp_ca_dir();
+        let protection = KeyProtection::Identity(age::x25519::Identity::generate());
+        let original = load_or_init_ca_with(Some(&dir), &protection).expect("init");
+        let rotated = rotate_ca_with(Some(&dir), &protection).expect("rotate");
+        assert!(dir.join("ca.prev.key.age").exists());
+        assert!(!dir.join("ca.prev.key").exists());
+
+        let reloaded = load_or_init_ca_with(Some(&dir), &protection).expect("reload");
+        assert_eq!(reloaded.ca_cert_pem, rotated.ca_cert_pem);
+        assert_eq!(reloaded.previous.as_ref().map(|p| p.ca_cert_pem.clone()), Some(original.ca_cert_pem));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_files_are_private_and_world_readable_keys_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_ca_dir();
+        load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("init");
+        let key_path = dir.join("ca.key");
+        assert_eq!(fs::metadata(&key_path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let err = load_or_init_ca_with(Some(&dir), &KeyProtection::None).err().expect("world-readable key accepted");
+        assert!(err.to_string().contains("world-readable"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn crl_lists_revoked_serials() {
+        let dir = temp_ca_dir();
+        let ca = load_or_init_ca_with(Some(&dir), &KeyProtection::None).expect("ca");
+        let issued = issue_node_cert("node-a", &ca.ca).expect("node cert");
+        let (_rem, pem) = x509_parser::pem::parse_x509_pem(issued.cert_pem.as_bytes()).expect("pem");
+        assert_eq!(serial_from_der(&pem.contents).unwrap(), issued.serial);
+
+        let revoked = vec![RevokedSerial { serial: issued.serial.clone(), revoked_at: SystemTime::now() }];
+        let crl_pem = build_crl(&ca.ca, &revoked, 7, Duration::from_secs(3600)).expect("crl");
+        let (_rem, pem) = x509_parser::pem::parse_x509_pem(crl_pem.as_bytes()).expect("crl pem");
+        let (_rem, crl) = x509_parser::parse_x509_crl(&pem.contents).expect("crl der");
+        let serials: Vec<String> = crl.iter_revoked_certificates().map(|r| r.serial().to_str_radix(16)).collect();
+        assert_eq!(serials, vec![issued.serial]);
+        fs::remove_dir_all(dir).ok();
     }
 }
@@ -0,0 +1,35 @@
+//! Password hashes for credentials that leave the control plane, such as
+//! basic auth users sent to gateways.
+//!
+//! Hashes are argon2id PHC strings with a random salt, so a leaked route
+//! table does not give away passwords through lookup tables or cheap guessing.
+
+use anyhow::{anyhow, Result};
+use argon2::{password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Argon2};
+
+/// Hash `password` with a fresh salt.
+pub fn hash(password: &str) -> Result<String> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).map_err(|e| anyhow!("salt: {e}"))?;
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt).map_err(|e| anyhow!("hash password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+/// Whether `password` matches `hash`. Malformed hashes match nothing.
+pub fn verify(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salted_hashes_verify() {
+        let first = hash("hunter2").unwrap();
+        assert!(first.starts_with("$argon2id$"));
+        assert_ne!(first, hash("hunter2").unwrap(), "every hash gets its own salt");
+        assert!(verify(&first, "hunter2"));
+        assert!(!verify(&first, "hunter3"));
+        assert!(!verify("f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7", "hunter2"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{body::Body, extract::Request, response::Response};
+use common::events::{access_log_subject, AccessLog};
+use hyper::body::{Frame, SizeHint};
+use models::route::{Endpoint, GatewayRoute};
+use tokio::sync::mpsc;
+
+/// Records waiting to be published; past this, new ones are dropped rather
+/// than slowing requests down.
+const QUEUE_CAPACITY: usize = 10_000;
+
+pub type AccessLogSender = mpsc::Sender<AccessLog>;
+
+pub fn channel() -> (AccessLogSender, mpsc::Receiver<AccessLog>) {
+    mpsc::channel(QUEUE_CAPACITY)
+}
+
+/// Publish records to their route's access log subject.
+pub async fn publish(client: async_nats::Client, mut records: mpsc::Receiver<AccessLog>) {
+    while let Some(record) = records.recv().await {
+        let Ok(payload) = serde_json::to_vec(&record) else { continue };
+        if let Err(e) = client.publish(access_log_subject(&record.namespace, &record.route), payload.into()).await {
+            tracing::debug!(error = %e, "failed to publish access log");
+        }
+    }
+}
+
+/// The access log record of a request being handled. It is sent when the
+/// response body it is attached to by [`Entry::finish`] is done or dropped,
+/// so latency and size cover the whole response.
+pub struct Entry {
+    record: AccessLog,
+    started: Instant,
+    received: Arc<AtomicU64>,
+    sink: Option<AccessLogSender>,
+}
+
+impl Entry {
+    /// Start the record for `request` on `route` and count its body as it is
+    /// read.
+    pub fn new(sink: Option<AccessLogSender>, route: &GatewayRoute, host: &str, request: &mut Request, client_ip: Option<IpAddr>) -> Self {
+        let received = Arc::new(AtomicU64::new(0));
+        if sink.is_some() {
+            let body = std::mem::take(request.body_mut());
+            *request.body_mut() = Body::new(Counted { inner: body, bytes: received.clone() });
+        }
+        let record = AccessLog {
+            timestamp: chrono::Utc::now(),
+            namespace: route.namespace.clone(),
+            route: route.name.clone(),
+            host: host.to_string(),
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            status: 0,
+            upstream: None,
+            release: None,
+            latency_ms: 0.0,
+            bytes_received: 0,
+            bytes_sent: 0,
+            client_ip: client_ip.map(|ip| ip.to_string()),
+        };
+        Self { record, started: Instant::now(), received, sink }
+    }
+
+    /// Note the endpoint the request was sent to.
+    pub fn upstream(&mut self, endpoint: &Endpoint) {
+        self.record.upstream = Some(endpoint.address.clone());
+        self.record.release = endpoint.release.clone();
+    }
+
+    /// Attach the record to `response`.
+    pub fn finish(mut self, response: Response) -> Response {
+        self.record.status = response.status().as_u16();
+        if self.sink.is_none() {
+            return response;
+        }
+        response.map(|body| Body::new(Logged { inner: body, entry: self }))
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let Some(sink) = &self.sink else { return };
+        self.record.latency_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        self.record.bytes_received = self.received.load(Ordering::Relaxed);
+        if sink.try_send(self.record.clone()).is_err() {
+            tracing::debug!(namespace = %self.record.namespace, route = %self.record.route, "access log queue full; dropping record");
+        }
+    }
+}
+
+/// A request body counting the bytes read from it.
+struct Counted<B> {
+    inner: B,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<B: hyper::body::Body<Data = axum::body::Bytes> + Unpin> hyper::body::Body for Counted<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// A response body that counts the bytes sent and carries the request's
+/// [`Entry`] until it is done.
+struct Logged {
+    inner: Body,
+    entry: Entry,
+}
+
+impl hyper::body::Body for Logged {
+    type Data = axum::body::Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.entry.record.bytes_sent += data.len() as u64;
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
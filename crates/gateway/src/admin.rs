@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use axum::{extract::State, routing::get, Json, Router};
+use models::route::GatewayRoute;
+use serde_json::json;
+
+use crate::{proxy::Gateway, resilience::CircuitStatus};
+
+/// Health and introspection, served on `BIND_ADMIN`.
+pub fn router(gateway: Arc<Gateway>) -> Router {
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/routes", get(routes))
+        .route("/certificates", get(certificates))
+        .route("/upstreams", get(upstreams))
+        .route("/circuits", get(circuits))
+        .with_state(gateway)
+}
+
+/// The routing table being served and the config version it was built from.
+async fn routes(State(gateway): State<Arc<Gateway>>) -> Json<serde_json::Value> {
+    let table = gateway.table();
+    // Password digests stay inside the gateway
+    let routes: Vec<GatewayRoute> = table.routes().into_iter().cloned().map(|mut r| { r.basic_auth_users.clear(); r }).collect();
+    Json(json!({ "version": table.version(), "routes": routes }))
+}
+
+/// Hosts TLS is terminated for and the config version they were loaded at.
+async fn certificates(State(gateway): State<Arc<Gateway>>) -> Json<serde_json::Value> {
+    let certs = gateway.certificates();
+    Json(json!({ "version": certs.version(), "hosts": certs.hosts() }))
+}
+
+/// Every endpoint with its in-flight requests and whether it is failing
+/// health checks or ejected.
+async fn upstreams(State(gateway): State<Arc<Gateway>>) -> Json<serde_json::Value> {
+    Json(json!(gateway.upstreams().statuses()))
+}
+
+/// Circuit state and retries spent for every route with a circuit breaker,
+/// ordered by namespace and name.
+async fn circuits(State(gateway): State<Arc<Gateway>>) -> Json<serde_json::Value> {
+    let table = gateway.table();
+    let mut circuits: Vec<CircuitStatus> = table
+        .backends()
+        .filter_map(|b| b.route.resilience.as_ref()?.circuit_breaker.as_ref().map(|policy| b.guard().status(&b.route.namespace, &b.route.name, policy)))
+        .collect();
+    circuits.sort_by(|a, b| (&a.namespace, &a.route).cmp(&(&b.namespace, &b.route)));
+    Json(json!(circuits))
+}
@@ -0,0 +1,402 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use models::route::{BackendProtocol, Endpoint, ForwardAuth, GatewayRoute, JwtAuth, RouteAuth};
+use sha2::{Digest, Sha256};
+
+use crate::proxy::{self, Gateway};
+
+/// Header basic auth users reach the backend with.
+const X_FORWARDED_USER: &str = "x-forwarded-user";
+const X_FORWARDED_METHOD: &str = "x-forwarded-method";
+const X_FORWARDED_URI: &str = "x-forwarded-uri";
+/// How long a JWKS is used before it is fetched again.
+const JWKS_TTL: Duration = Duration::from_secs(10 * 60);
+/// Tokens naming an unknown key refetch the JWKS at most this often, so
+/// forged tokens cannot hammer the issuer.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+const FORWARD_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Verified basic auth credentials remembered per route table.
+const MAX_VERIFIED_CREDENTIALS: usize = 1024;
+
+/// A route's authentication, ready to check requests against.
+#[derive(Debug)]
+pub enum EdgeAuth {
+    Basic {
+        challenge: HeaderValue,
+        users: BasicUsers,
+    },
+    Jwt(JwtAuth),
+    Forward {
+        policy: ForwardAuth,
+        endpoints: Vec<Endpoint>,
+        next: AtomicUsize,
+    },
+}
+
+impl EdgeAuth {
+    pub fn new(route: &GatewayRoute) -> Option<Self> {
+        Some(match route.auth.as_ref()? {
+            RouteAuth::Basic(basic) => {
+                // Without users (the secret is missing, or this gateway has no
+                // admin token) nobody gets in
+                let users = BasicUsers::new(route.basic_auth_users.iter().map(|u| (u.username.clone(), u.password_hash.clone())).collect());
+                let challenge = HeaderValue::from_str(&format!("Basic realm=\"{}\", charset=\"UTF-8\"", basic.realm))
+                    .unwrap_or_else(|_| HeaderValue::from_static("Basic"));
+                EdgeAuth::Basic { challenge, users }
+            }
+            RouteAuth::Jwt(jwt) => EdgeAuth::Jwt(jwt.clone()),
+            RouteAuth::ForwardAuth(forward) => {
+                EdgeAuth::Forward { policy: forward.clone(), endpoints: route.auth_endpoints.iter().filter(|e| e.healthy).cloned().collect(), next: AtomicUsize::new(0) }
+            }
+        })
+    }
+}
+
+/// A route's basic auth users with their argon2id password hashes.
+#[derive(Debug)]
+pub struct BasicUsers {
+    hashes: HashMap<String, String>,
+    /// Digests of credentials that verified, keyed with their user's hash,
+    /// so a returning user does not pay for the slow hash on every request.
+    verified: Mutex<HashSet<[u8; 32]>>,
+}
+
+impl BasicUsers {
+    fn new(hashes: HashMap<String, String>) -> Self {
+        Self { hashes, verified: Mutex::default() }
+    }
+
+    /// The user the request's basic credentials belong to, if they are valid.
+    async fn check(&self, headers: &HeaderMap) -> Option<String> {
+        let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, encoded) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        let hash = self.hashes.get(user)?;
+        let digest: [u8; 32] = Sha256::new().chain_update(hash).chain_update([0]).chain_update(password).finalize().into();
+        if self.verified.lock().ok()?.contains(&digest) {
+            return Some(user.to_string());
+        }
+        let (hash, password) = (hash.clone(), password.to_string());
+        if !tokio::task::spawn_blocking(move || crypto::password::verify(&hash, &password)).await.ok()? {
+            return None;
+        }
+        let mut verified = self.verified.lock().ok()?;
+        if verified.len() >= MAX_VERIFIED_CREDENTIALS {
+            verified.clear();
+        }
+        verified.insert(digest);
+        Some(user.to_string())
+    }
+}
+
+/// Check a request against its route's authentication. Admitted requests
+/// come back carrying what the backend should know about the client;
+/// others get the response to send instead.
+pub async fn authenticate(gateway: &Gateway, auth: &EdgeAuth, grpc: bool, host: &str, client_ip: Option<IpAddr>, request: &mut Request) -> Result<(), Response> {
+    match auth {
+        EdgeAuth::Basic { challenge, users } => {
+            request.headers_mut().remove(X_FORWARDED_USER);
+            let Some(user) = users.check(request.headers()).await else {
+                let mut response = proxy::reply(grpc, StatusCode::UNAUTHORIZED, "unauthorized\n");
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, challenge.clone());
+                return Err(response);
+            };
+            let user = HeaderValue::from_str(&user).map_err(|_| proxy::reply(grpc, StatusCode::UNAUTHORIZED, "unauthorized\n"))?;
+            let headers = request.headers_mut();
+            headers.remove(header::AUTHORIZATION);
+            headers.insert(X_FORWARDED_USER, user);
+            Ok(())
+        }
+        EdgeAuth::Jwt(policy) => {
+            let claims = verify_jwt(gateway, policy, request.headers()).await.map_err(|status| {
+                let mut response = proxy::reply(grpc, status, if status == StatusCode::UNAUTHORIZED { "unauthorized\n" } else { "authentication unavailable\n" });
+                if status == StatusCode::UNAUTHORIZED {
+                    response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                }
+                response
+            })?;
+            forward_claims(policy, &claims, request.headers_mut());
+            Ok(())
+        }
+        EdgeAuth::Forward { policy, endpoints, next } => {
+            if endpoints.is_empty() {
+                return Err(proxy::reply(grpc, StatusCode::SERVICE_UNAVAILABLE, "no healthy auth backend available\n"));
+            }
+            let endpoint = &endpoints[next.fetch_add(1, Ordering::Relaxed) % endpoints.len()];
+            forward_auth(gateway, policy, endpoint, grpc, host, client_ip, request).await
+        }
+    }
+}
+
+/// The bearer token, or the policy's cookie.
+fn jwt_token<'a>(policy: &JwtAuth, headers: &'a HeaderMap) -> Option<&'a str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim());
+    bearer.or_else(|| {
+        let cookie = policy.cookie.as_deref()?;
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == cookie)
+            .map(|(_, value)| value)
+    })
+}
+
+/// Claims of a valid token: 401 without one, 503 when the JWKS cannot be
+/// fetched.
+async fn verify_jwt(gateway: &Gateway, policy: &JwtAuth, headers: &HeaderMap) -> Result<serde_json::Map<String, serde_json::Value>, StatusCode> {
+    let token = jwt_token(policy, headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    let jwt_header = decode_header(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    // A JWKS holds public keys; HMAC would make them the shared secret
+    if matches!(jwt_header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let key = gateway.jwks().key(&policy.jwks_url, jwt_header.kid.as_deref()).await.map_err(|e| {
+        tracing::warn!(url = %policy.jwks_url, error = %e, "failed to fetch JWKS");
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+    let key = key.ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let mut validation = Validation::new(jwt_header.alg);
+    if let Some(issuer) = &policy.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    if policy.audiences.is_empty() {
+        validation.validate_aud = false;
+    } else {
+        validation.set_audience(&policy.audiences);
+    }
+    decode::<serde_json::Map<String, serde_json::Value>>(token, &key, &validation).map(|data| data.claims).map_err(|_| StatusCode::UNAUTHORIZED)
+}
+
+/// Pass the policy's claims to the backend, replacing whatever the client
+/// sent in those headers.
+fn forward_claims(policy: &JwtAuth, claims: &serde_json::Map<String, serde_json::Value>, headers: &mut HeaderMap) {
+    for (claim, name) in &policy.forward_claims {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else { continue };
+        headers.remove(&name);
+        let value = match claims.get(claim) {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(serde_json::Value::Array(items)) => items.iter().map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string)).collect::<Vec<_>>().join(","),
+            Some(serde_json::Value::Null) | None => continue,
+            Some(other) => other.to_string(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Ask the auth app about a request. See [`ForwardAuth`].
+async fn forward_auth(gateway: &Gateway, policy: &ForwardAuth, endpoint: &Endpoint, grpc: bool, host: &str, client_ip: Option<IpAddr>, request: &mut Request) -> Result<(), Response> {
+    let mut headers = request.headers().clone();
+    headers.remove(header::CONTENT_LENGTH);
+    let client_ip = client_ip.map(|ip| ip.to_string());
+    proxy::forwarded_headers(&mut headers, host, client_ip.as_deref(), proxy::request_proto(request));
+    headers.insert(X_FORWARDED_METHOD, HeaderValue::from_str(request.method().as_str()).expect("methods are valid header values"));
+    if let Ok(uri) = HeaderValue::from_str(request.uri().path_and_query().map_or("/", |p| p.as_str())) {
+        headers.insert(X_FORWARDED_URI, uri);
+    }
+    let mut check = Request::new(Body::empty());
+    *check.headers_mut() = headers;
+    match format!("http://{}{}", endpoint.address, policy.path).parse() {
+        Ok(uri) => *check.uri_mut() = uri,
+        Err(_) => {
+            tracing::error!(address = %endpoint.address, "invalid auth endpoint address");
+            return Err(proxy::reply(grpc, StatusCode::BAD_GATEWAY, "bad gateway\n"));
+        }
+    }
+
+    let response = match tokio::time::timeout(FORWARD_AUTH_TIMEOUT, gateway.clients().request(BackendProtocol::Http1, check)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            tracing::warn!(address = %endpoint.address, error = %e, "forward auth request failed");
+            return Err(proxy::reply(grpc, StatusCode::BAD_GATEWAY, "authentication unavailable\n"));
+        }
+        Err(_) => {
+            tracing::warn!(address = %endpoint.address, "forward auth request timed out");
+            return Err(proxy::reply(grpc, StatusCode::GATEWAY_TIMEOUT, "authentication unavailable\n"));
+        }
+    };
+    if !response.status().is_success() {
+        let mut response = response.map(Body::new);
+        proxy::strip_hop_by_hop(response.headers_mut());
+        return Err(response);
+    }
+    for name in &policy.response_headers {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else { continue };
+        request.headers_mut().remove(&name);
+        for value in response.headers().get_all(&name) {
+            request.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    Ok(())
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched: Instant,
+}
+
+impl CachedJwks {
+    fn stale(&self, kid: Option<&str>) -> bool {
+        let age = self.fetched.elapsed();
+        age >= JWKS_TTL || (find_key(&self.keys, kid).is_none() && age >= JWKS_MIN_REFRESH)
+    }
+}
+
+/// The key set at one URL. `refresh` is held by the one task refetching it;
+/// lookups only take `cached` briefly, so a slow issuer holds up nobody but
+/// the requests that need its keys and have none.
+#[derive(Default)]
+struct JwksSlot {
+    cached: RwLock<Option<Arc<CachedJwks>>>,
+    refresh: tokio::sync::Mutex<()>,
+}
+
+impl JwksSlot {
+    fn get(&self) -> Option<Arc<CachedJwks>> {
+        self.cached.read().ok()?.clone()
+    }
+}
+
+/// Key sets of JWT routes, by URL. Sets are refetched when they get old,
+/// and sooner when a token names a key id they lack, so key rotation at the
+/// issuer needs no reload.
+pub struct JwksCache {
+    http: reqwest::Client,
+    slots: Mutex<HashMap<String, Arc<JwksSlot>>>,
+}
+
+impl Default for JwksCache {
+    fn default() -> Self {
+        let http = reqwest::Client::builder().timeout(Duration::from_secs(5)).build().unwrap_or_default();
+        Self { http, slots: Mutex::default() }
+    }
+}
+
+impl JwksCache {
+    /// The key tokens with `kid` are signed with, if the set at `url` has it.
+    /// Errors only when the set cannot be fetched and none is cached.
+    pub async fn key(&self, url: &str, kid: Option<&str>) -> anyhow::Result<Option<DecodingKey>> {
+        let slot = self.slots.lock().map_err(|_| anyhow!("JWKS cache poisoned"))?.entry(url.to_string()).or_default().clone();
+        let mut cached = slot.get();
+        if cached.as_ref().is_none_or(|c| c.stale(kid)) {
+            // With a set cached, use it rather than wait for someone else's refresh
+            let refresh = match (&cached, slot.refresh.try_lock()) {
+                (_, Ok(guard)) => Some(guard),
+                (Some(_), Err(_)) => None,
+                (None, Err(_)) => Some(slot.refresh.lock().await),
+            };
+            if let Some(_refresh) = refresh {
+                cached = slot.get();
+                if cached.as_ref().is_none_or(|c| c.stale(kid)) {
+                    match self.fetch(url).await {
+                        Ok(keys) => {
+                            let fresh = Arc::new(CachedJwks { keys, fetched: Instant::now() });
+                            *slot.cached.write().map_err(|_| anyhow!("JWKS cache poisoned"))? = Some(fresh.clone());
+                            cached = Some(fresh);
+                        }
+                        // Keep using the old set through an issuer outage
+                        Err(e) if cached.is_some() => tracing::warn!(%url, error = %e, "failed to refresh JWKS"),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        let Some(jwk) = cached.as_ref().and_then(|cached| find_key(&cached.keys, kid)) else { return Ok(None) };
+        DecodingKey::from_jwk(jwk).map(Some).map_err(|e| anyhow!("unusable JWK: {e}"))
+    }
+
+    async fn fetch(&self, url: &str) -> anyhow::Result<JwkSet> {
+        Ok(self.http.get(url).send().await?.error_for_status()?.json().await?)
+    }
+}
+
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        // Issuers with a single key may omit `kid`
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::route::BasicCredential;
+
+    #[tokio::test]
+    async fn checks_basic_credentials() {
+        let route = GatewayRoute {
+            auth: Some(RouteAuth::Basic(models::route::BasicAuth { secret_ref: "users".into(), realm: "Grafana".into() })),
+            basic_auth_users: vec![BasicCredential { username: "bob".into(), password_hash: crypto::password::hash("hunter2").unwrap() }],
+            ..Default::default()
+        };
+        let Some(EdgeAuth::Basic { challenge, users }) = EdgeAuth::new(&route) else { panic!("basic auth expected") };
+        assert_eq!(challenge, "Basic realm=\"Grafana\", charset=\"UTF-8\"");
+        let headers = |credentials: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Basic {}", STANDARD.encode(credentials))).unwrap());
+            headers
+        };
+        assert_eq!(users.check(&headers("bob:hunter2")).await.as_deref(), Some("bob"));
+        assert_eq!(users.verified.lock().unwrap().len(), 1);
+        assert_eq!(users.check(&headers("bob:hunter2")).await.as_deref(), Some("bob"), "remembered credentials still check out");
+        assert_eq!(users.check(&headers("bob:hunter3")).await, None);
+        assert_eq!(users.check(&headers("alice:hunter2")).await, None);
+        assert_eq!(users.check(&HeaderMap::new()).await, None);
+    }
+
+    #[test]
+    fn reads_tokens_and_forwards_claims() {
+        let policy = JwtAuth {
+            jwks_url: "https://idp.example.com/jwks".into(),
+            issuer: None,
+            audiences: Vec::new(),
+            cookie: Some("session".into()),
+            forward_claims: [("email".to_string(), "x-email".to_string()), ("groups".to_string(), "x-groups".to_string())].into(),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("theme=dark; session=abc.def.ghi"));
+        assert_eq!(jwt_token(&policy, &headers), Some("abc.def.ghi"));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer xyz"));
+        assert_eq!(jwt_token(&policy, &headers), Some("xyz"));
+
+        headers.insert("x-email", HeaderValue::from_static("spoofed@example.com"));
+        headers.insert("x-groups", HeaderValue::from_static("admins"));
+        let claims = serde_json::json!({ "email": "ann@example.com", "groups": ["dev", "ops"] });
+        forward_claims(&policy, claims.as_object().unwrap(), &mut headers);
+        assert_eq!(headers["x-email"], "ann@example.com");
+        assert_eq!(headers["x-groups"], "dev,ops");
+        let claims = serde_json::json!({});
+        forward_claims(&policy, claims.as_object().unwrap(), &mut headers);
+        assert!(headers.get("x-email").is_none(), "clients cannot supply missing claims");
+    }
+}
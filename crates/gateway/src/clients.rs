@@ -0,0 +1,78 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context};
+use axum::body::Body;
+use hyper::{body::Incoming, Request, Response};
+use hyper_rustls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client, Error},
+    rt::TokioExecutor,
+};
+use models::route::BackendProtocol;
+use rustls::{ClientConfig, RootCertStore};
+
+/// Upstream clients, one per backend protocol. Each keeps its own pool.
+pub struct Clients {
+    http1: Client<HttpConnector, Body>,
+    h2c: Client<HttpConnector, Body>,
+    h2: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl Clients {
+    /// `extra_roots` (PEM) are trusted for `h2` backends on top of the
+    /// public web roots, for backends with an internal CA.
+    pub fn new(connect_timeout: Duration, extra_roots: Option<&str>) -> anyhow::Result<Self> {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(connect_timeout));
+        connector.set_nodelay(true);
+
+        let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        if let Some(pem) = extra_roots {
+            for cert in rustls_pemfile::certs(&mut pem.as_bytes()) {
+                roots.add(cert.context("invalid upstream CA PEM")?).map_err(|e| anyhow!("unusable upstream CA certificate: {e}"))?;
+            }
+        }
+        let tls = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut https = HttpConnector::new();
+        https.set_connect_timeout(Some(connect_timeout));
+        https.set_nodelay(true);
+        https.enforce_http(false);
+        let https = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls).https_only().enable_http2().wrap_connector(https);
+
+        Ok(Self {
+            http1: Client::builder(TokioExecutor::new()).build(connector.clone()),
+            h2c: Client::builder(TokioExecutor::new()).http2_only(true).build(connector),
+            h2: Client::builder(TokioExecutor::new()).http2_only(true).build(https),
+        })
+    }
+
+    /// Send `request`, whose URI must use [`scheme`] for `protocol`.
+    pub async fn request(&self, protocol: BackendProtocol, request: Request<Body>) -> Result<Response<Incoming>, Error> {
+        match protocol {
+            BackendProtocol::Http1 => self.http1.request(request).await,
+            BackendProtocol::H2c | BackendProtocol::Grpc => self.h2c.request(request).await,
+            BackendProtocol::H2 => self.h2.request(request).await,
+        }
+    }
+}
+
+/// URI scheme for requests to a backend speaking `protocol`. TLS backends
+/// are verified against their endpoint address, so their certificates must
+/// name it.
+pub fn scheme(protocol: BackendProtocol) -> &'static str {
+    match protocol {
+        BackendProtocol::H2 => "https",
+        BackendProtocol::Http1 | BackendProtocol::H2c | BackendProtocol::Grpc => "http",
+    }
+}
+
+/// HTTP version requests to a backend speaking `protocol` are sent with.
+pub fn version(protocol: BackendProtocol) -> hyper::Version {
+    match protocol {
+        BackendProtocol::Http1 => hyper::Version::HTTP_11,
+        BackendProtocol::H2c | BackendProtocol::H2 | BackendProtocol::Grpc => hyper::Version::HTTP_2,
+    }
+}
@@ -0,0 +1,86 @@
+use std::{net::{IpAddr, SocketAddr}, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use models::route::L4PortRange;
+
+/// Gateway settings, read from the environment.
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    /// Where proxied traffic is accepted (`BIND_HTTP`).
+    pub bind_http: SocketAddr,
+    /// Where TLS is terminated, by SNI, with certificates from the control
+    /// plane (`BIND_HTTPS`; empty disables it).
+    pub bind_https: Option<SocketAddr>,
+    /// Address the listen ports of L4 routes are opened on (`BIND_L4`).
+    pub bind_l4: IpAddr,
+    /// Ports L4 routes may listen on, as `start-end` (`SPAN_L4_PORTS`); keep
+    /// it in line with the control plane's.
+    pub l4_ports: L4PortRange,
+    /// Health and introspection endpoints, kept off the proxied listener so
+    /// they never shadow a route (`BIND_ADMIN`).
+    pub bind_admin: SocketAddr,
+    /// Control plane the routing table is loaded from (`CONTROL_PLANE_URL`).
+    pub control_plane_url: String,
+    /// Bearer token sent to the control plane, if any (`SPAN_TOKEN`). Loading
+    /// certificates requires a cluster-wide admin token.
+    pub token: Option<String>,
+    /// NATS server carrying route and endpoint change events (`NATS_URL`).
+    /// Without it the gateway relies on the periodic full sync alone.
+    pub nats_url: Option<String>,
+    /// How often the whole routing table is refetched, catching changes
+    /// whose events were missed (`SPAN_GATEWAY_SYNC_SECS`).
+    pub sync_interval: Duration,
+    /// How often per-release request and error counts are reported to the
+    /// control plane (`SPAN_GATEWAY_STATS_SECS`).
+    pub stats_interval: Duration,
+    /// Upstream connect timeout (`SPAN_GATEWAY_CONNECT_TIMEOUT_MS`).
+    pub connect_timeout: Duration,
+    /// PEM file of CA certificates trusted for `h2` backends on top of the
+    /// public roots (`SPAN_GATEWAY_UPSTREAM_CA`).
+    pub upstream_ca: Option<PathBuf>,
+    /// Where the objects of buckets served by bucket routes are read from
+    /// (`MINIO_ENDPOINT`; unset disables bucket routes).
+    pub object_store: Option<ObjectStoreConfig>,
+}
+
+/// An S3-compatible object store holding bucket objects by content hash,
+/// at `<bucket>/blobs/<sha256>`.
+#[derive(Debug, Clone)]
+pub struct ObjectStoreConfig {
+    pub url: String,
+    /// `SPAN_OBJECT_STORE_BUCKET`
+    pub bucket: String,
+    /// `MINIO_ROOT_USER`
+    pub access_key: String,
+    /// `MINIO_ROOT_PASSWORD`
+    pub secret_key: String,
+    /// `SPAN_OBJECT_STORE_REGION`
+    pub region: String,
+}
+
+impl GatewayConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let var = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+        Ok(Self {
+            bind_http: var("BIND_HTTP", "0.0.0.0:80").parse().context("invalid BIND_HTTP")?,
+            bind_https: Some(var("BIND_HTTPS", "0.0.0.0:443")).filter(|v| !v.is_empty()).map(|v| v.parse()).transpose().context("invalid BIND_HTTPS")?,
+            bind_l4: var("BIND_L4", "0.0.0.0").parse().context("invalid BIND_L4")?,
+            l4_ports: std::env::var("SPAN_L4_PORTS").ok().filter(|v| !v.is_empty()).map(|v| v.parse().map_err(anyhow::Error::msg)).transpose().context("invalid SPAN_L4_PORTS")?.unwrap_or_default(),
+            bind_admin: var("BIND_ADMIN", "127.0.0.1:9901").parse().context("invalid BIND_ADMIN")?,
+            control_plane_url: var("CONTROL_PLANE_URL", "http://127.0.0.1:8080"),
+            token: std::env::var("SPAN_TOKEN").ok().filter(|t| !t.is_empty()),
+            nats_url: std::env::var("NATS_URL").ok().filter(|u| !u.is_empty()),
+            sync_interval: Duration::from_secs(var("SPAN_GATEWAY_SYNC_SECS", "30").parse().context("invalid SPAN_GATEWAY_SYNC_SECS")?),
+            stats_interval: Duration::from_secs(var("SPAN_GATEWAY_STATS_SECS", "10").parse().context("invalid SPAN_GATEWAY_STATS_SECS")?),
+            connect_timeout: Duration::from_millis(var("SPAN_GATEWAY_CONNECT_TIMEOUT_MS", "2000").parse().context("invalid SPAN_GATEWAY_CONNECT_TIMEOUT_MS")?),
+            upstream_ca: std::env::var("SPAN_GATEWAY_UPSTREAM_CA").ok().filter(|p| !p.is_empty()).map(PathBuf::from),
+            object_store: std::env::var("MINIO_ENDPOINT").ok().filter(|u| !u.is_empty()).map(|url| ObjectStoreConfig {
+                url,
+                bucket: var("SPAN_OBJECT_STORE_BUCKET", "span"),
+                access_key: var("MINIO_ROOT_USER", "admin"),
+                secret_key: var("MINIO_ROOT_PASSWORD", ""),
+                region: var("SPAN_OBJECT_STORE_REGION", "us-east-1"),
+            }),
+        })
+    }
+}
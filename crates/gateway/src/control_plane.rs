@@ -0,0 +1,101 @@
+use anyhow::Context;
+use models::{bucket::BucketObject, route::{GatewayCertificate, GatewaySnapshot, ReleaseStats}};
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::config::GatewayConfig;
+
+/// Characters escaped in object keys sent as a path segment. `.` and `/`
+/// are escaped too so no part of a key is read as a dot segment.
+const KEY: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'~');
+
+/// Client for the control plane endpoints the gateway relies on.
+#[derive(Clone)]
+pub struct ControlPlane {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChallengeCert {
+    cert_pem: String,
+    key_pem: String,
+}
+
+impl ControlPlane {
+    pub fn new(cfg: &GatewayConfig) -> Self {
+        let http = reqwest::Client::builder().timeout(std::time::Duration::from_secs(10)).build().unwrap_or_default();
+        Self { http, url: cfg.control_plane_url.trim_end_matches('/').to_string(), token: cfg.token.clone() }
+    }
+
+    /// Whether requests carry a token; certificates and TLS-ALPN-01
+    /// challenges are only handed out to admins.
+    pub fn has_token(&self) -> bool {
+        self.token.is_some()
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let req = self.http.get(format!("{}{path}", self.url));
+        match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let req = self.http.post(format!("{}{path}", self.url));
+        match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    /// Every route and its endpoints.
+    pub async fn snapshot(&self) -> anyhow::Result<GatewaySnapshot> {
+        let path = "/api/v1/gateway/routes";
+        self.get(path).send().await?.error_for_status()?.json().await.with_context(|| format!("parsing {path}"))
+    }
+
+    /// Certificates for TLS routes, with their private keys.
+    pub async fn certificates(&self) -> anyhow::Result<Vec<GatewayCertificate>> {
+        let path = "/api/v1/gateway/certificates";
+        self.get(path).send().await?.error_for_status()?.json().await.with_context(|| format!("parsing {path}"))
+    }
+
+    /// Key authorization for an HTTP-01 challenge token, if one is pending.
+    pub async fn http01(&self, token: &str) -> anyhow::Result<Option<String>> {
+        let response = self.get(&format!("/api/v1/gateway/acme/http-01/{token}")).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.text().await?))
+    }
+
+    /// Certificate and key PEM for a pending TLS-ALPN-01 challenge on `host`.
+    pub async fn tls_alpn01(&self, host: &str) -> anyhow::Result<Option<(String, String)>> {
+        let response = self.get(&format!("/api/v1/gateway/acme/tls-alpn-01/{host}")).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let cert: ChallengeCert = response.error_for_status()?.json().await?;
+        Ok(Some((cert.cert_pem, cert.key_pem)))
+    }
+
+    /// Metadata of the object at `key` in a bucket served by a bucket route,
+    /// if there is one.
+    pub async fn bucket_object(&self, namespace: &str, bucket: &str, key: &str) -> anyhow::Result<Option<BucketObject>> {
+        let key = percent_encoding::utf8_percent_encode(key, KEY).to_string();
+        let response = self.get(&format!("/api/v1/gateway/buckets/{namespace}/{bucket}/objects/{key}")).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+
+    /// Requests and server errors per release since the last report.
+    pub async fn report_release_stats(&self, stats: &[ReleaseStats]) -> anyhow::Result<()> {
+        self.post("/api/v1/gateway/stats").json(stats).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
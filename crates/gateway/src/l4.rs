@@ -0,0 +1,379 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::http::HeaderMap;
+use models::route::{L4PortRange, L4Protocol};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::AbortHandle,
+};
+
+use crate::{proxy::Gateway, upstream::InFlight};
+
+/// How long a passthrough connection may take to send its TLS client hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest TLS record (RFC 8446 5.2), with its header.
+const MAX_HELLO_BYTES: usize = 5 + 16384 + 2048;
+/// UDP flows with no datagram either way for this long are forgotten.
+const UDP_IDLE: Duration = Duration::from_secs(60);
+/// Flows tracked per UDP port; datagrams of new clients past it are dropped.
+const MAX_UDP_SESSIONS: usize = 10_000;
+/// Ports that failed to open are retried this often.
+const BIND_RETRY: Duration = Duration::from_secs(30);
+
+/// Open a listener for every L4 route's port on `bind` and close those no
+/// route uses any more, following the gateway's table. Only ports within
+/// `ports` are opened, and TCP ports in `reserved` are the gateway's own and
+/// never opened.
+pub async fn run(gateway: Arc<Gateway>, bind: IpAddr, ports: L4PortRange, reserved: Vec<u16>) {
+    let mut versions = gateway.watch_table();
+    let mut listeners: HashMap<(L4Protocol, u16), AbortHandle> = HashMap::new();
+    loop {
+        let wanted: HashSet<(L4Protocol, u16)> = gateway.table().l4_ports().collect();
+        listeners.retain(|(protocol, port), listener| {
+            let keep = wanted.contains(&(*protocol, *port));
+            if !keep {
+                listener.abort();
+                tracing::info!(protocol = protocol.as_str(), port, "L4 listener closed");
+            }
+            keep
+        });
+        let mut failed = false;
+        for (protocol, port) in wanted {
+            if listeners.contains_key(&(protocol, port)) {
+                continue;
+            }
+            if !ports.contains(port) {
+                tracing::warn!(protocol = protocol.as_str(), port, range = %ports, "L4 route wants a port outside the allowed range");
+                continue;
+            }
+            if protocol == L4Protocol::Tcp && reserved.contains(&port) {
+                tracing::warn!(port, "L4 route wants a port the gateway itself listens on");
+                continue;
+            }
+            match listen(&gateway, SocketAddr::new(bind, port), protocol).await {
+                Ok(listener) => {
+                    tracing::info!(protocol = protocol.as_str(), port, "L4 listener opened");
+                    listeners.insert((protocol, port), listener);
+                }
+                Err(e) => {
+                    tracing::warn!(protocol = protocol.as_str(), port, error = %e, "failed to open L4 listener");
+                    failed = true;
+                }
+            }
+        }
+        tokio::select! {
+            changed = versions.changed() => if changed.is_err() { return },
+            _ = tokio::time::sleep(BIND_RETRY), if failed => {}
+        }
+    }
+}
+
+async fn listen(gateway: &Arc<Gateway>, addr: SocketAddr, protocol: L4Protocol) -> io::Result<AbortHandle> {
+    let gateway = gateway.clone();
+    let port = addr.port();
+    Ok(match protocol {
+        L4Protocol::Tcp => {
+            let listener = TcpListener::bind(addr).await?;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => {
+                            tokio::spawn(tcp_connection(gateway.clone(), port, stream, peer));
+                        }
+                        Err(e) => tracing::debug!(port, error = %e, "failed to accept L4 connection"),
+                    }
+                }
+            })
+            .abort_handle()
+        }
+        L4Protocol::Udp => {
+            let socket = UdpSocket::bind(addr).await?;
+            tokio::spawn(serve_udp(gateway, port, socket)).abort_handle()
+        }
+    })
+}
+
+/// Pick a route and endpoint for a connection to `port` and splice the two
+/// together. Ports with passthrough routes read the TLS client hello first
+/// and pick the route by its SNI; the hello is then sent on untouched.
+async fn tcp_connection(gateway: Arc<Gateway>, port: u16, mut client: TcpStream, peer: SocketAddr) {
+    let table = gateway.table();
+    let mut hello = Vec::new();
+    if table.passthrough(L4Protocol::Tcp, port) {
+        if let Err(e) = tokio::time::timeout(HELLO_TIMEOUT, read_client_hello(&mut client, &mut hello)).await.unwrap_or(Ok(())) {
+            tracing::debug!(port, %peer, error = %e, "failed to read client hello");
+            return;
+        }
+    }
+    let sni = client_hello_sni(&hello);
+    let Some(backend) = table.lookup_l4(L4Protocol::Tcp, port, sni.as_deref()) else {
+        tracing::debug!(port, %peer, sni = sni.as_deref().unwrap_or("-"), "no L4 route for connection");
+        return;
+    };
+    let route = &backend.route;
+    if !backend.admits(peer.ip()) {
+        return;
+    }
+    let Some(l4) = &route.l4 else { return };
+    let Some((endpoint, in_flight)) = backend.pick(&HeaderMap::new()) else {
+        tracing::warn!(namespace = %route.namespace, route = %route.name, backend = %route.backend_ref, "no healthy endpoints");
+        return;
+    };
+    let target = l4.target(&endpoint.address);
+    let connected = tokio::time::timeout(gateway.connect_timeout(), TcpStream::connect(target.as_str())).await;
+    let failed = !matches!(connected, Ok(Ok(_)));
+    backend.record(&in_flight, failed);
+    if let Some(release) = &endpoint.release {
+        gateway.release_counters().record(&route.namespace, &route.backend_ref, release, failed);
+    }
+    let mut upstream = match connected {
+        Ok(Ok(upstream)) => upstream,
+        Ok(Err(e)) => {
+            tracing::warn!(namespace = %route.namespace, route = %route.name, %target, error = %e, "L4 upstream connection failed");
+            return;
+        }
+        Err(_) => {
+            tracing::warn!(namespace = %route.namespace, route = %route.name, %target, "L4 upstream connection timed out");
+            return;
+        }
+    };
+    let _ = upstream.set_nodelay(true);
+    let _ = client.set_nodelay(true);
+    // The table is not needed past this point; do not hold on to it for the
+    // life of the connection
+    let _in_flight: InFlight = in_flight;
+    drop(table);
+    if upstream.write_all(&hello).await.is_ok() {
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    }
+}
+
+/// Read what the client sends until its first TLS record is complete, or
+/// it turns out not to be TLS.
+async fn read_client_hello(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<()> {
+    let mut chunk = [0; 4096];
+    loop {
+        let wanted = match buf.get(..5) {
+            _ if buf.first().is_some_and(|b| *b != TLS_HANDSHAKE) => return Ok(()),
+            Some(header) => (5 + usize::from(u16::from_be_bytes([header[3], header[4]]))).min(MAX_HELLO_BYTES),
+            None => 5,
+        };
+        if buf.len() >= wanted {
+            return Ok(());
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+const TLS_HANDSHAKE: u8 = 0x16;
+const CLIENT_HELLO: u8 = 1;
+const SERVER_NAME: usize = 0;
+
+/// The server name a TLS client hello asks for (RFC 8446 4.1.2, RFC 6066
+/// 3), if `record` holds one in full.
+fn client_hello_sni(record: &[u8]) -> Option<String> {
+    let mut record = Reader(record);
+    if record.u8()? != TLS_HANDSHAKE {
+        return None;
+    }
+    record.take(2)?;
+    let len = record.u16()?;
+    let mut handshake = Reader(record.take(len)?);
+    if handshake.u8()? != CLIENT_HELLO {
+        return None;
+    }
+    // Length, legacy version and random
+    handshake.take(3 + 2 + 32)?;
+    let session_id = handshake.u8()?;
+    handshake.take(usize::from(session_id))?;
+    let cipher_suites = handshake.u16()?;
+    handshake.take(cipher_suites)?;
+    let compression = handshake.u8()?;
+    handshake.take(usize::from(compression))?;
+    let extensions = handshake.u16()?;
+    let mut extensions = Reader(handshake.take(extensions)?);
+    while let Some(kind) = extensions.u16() {
+        let len = extensions.u16()?;
+        let data = extensions.take(len)?;
+        if kind != SERVER_NAME {
+            continue;
+        }
+        let mut list = Reader(data);
+        let len = list.u16()?;
+        let mut names = Reader(list.take(len)?);
+        while let Some(name_type) = names.u8() {
+            let len = names.u16()?;
+            let name = names.take(len)?;
+            // host_name
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(str::to_string);
+            }
+        }
+        return None;
+    }
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<usize> {
+        let bytes = self.take(2)?;
+        Some(usize::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+    }
+}
+
+/// A client's flow through a UDP route: datagrams from the client go out on
+/// `upstream`, connected to the endpoint picked for its first datagram, and
+/// answers come back through the listener.
+struct Session {
+    upstream: UdpSocket,
+    last_active: Mutex<Instant>,
+    _in_flight: InFlight,
+}
+
+impl Session {
+    fn touch(&self) {
+        *self.last_active.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn idle(&self) -> bool {
+        self.last_active.lock().unwrap_or_else(|e| e.into_inner()).elapsed() >= UDP_IDLE
+    }
+}
+
+type Sessions = Arc<Mutex<HashMap<SocketAddr, Arc<Session>>>>;
+
+async fn serve_udp(gateway: Arc<Gateway>, port: u16, socket: UdpSocket) {
+    let socket = Arc::new(socket);
+    let sessions = Sessions::default();
+    let mut buf = vec![0; 65535];
+    loop {
+        let (n, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                // Errors for earlier sends (ICMP unreachable) show up here
+                tracing::debug!(port, error = %e, "failed to receive L4 datagram");
+                continue;
+            }
+        };
+        let existing = sessions.lock().unwrap_or_else(|e| e.into_inner()).get(&peer).cloned();
+        let session = match existing {
+            Some(session) => session,
+            None => match open_session(&gateway, port, peer, &socket, &sessions).await {
+                Some(session) => session,
+                None => continue,
+            },
+        };
+        session.touch();
+        if let Err(e) = session.upstream.send(&buf[..n]).await {
+            tracing::debug!(port, %peer, error = %e, "failed to forward L4 datagram");
+        }
+    }
+}
+
+/// Start a flow for the first datagram from `peer`, with a task relaying
+/// the endpoint's answers until the flow goes idle.
+async fn open_session(gateway: &Gateway, port: u16, peer: SocketAddr, listener: &Arc<UdpSocket>, sessions: &Sessions) -> Option<Arc<Session>> {
+    if sessions.lock().unwrap_or_else(|e| e.into_inner()).len() >= MAX_UDP_SESSIONS {
+        tracing::debug!(port, %peer, "too many UDP flows, dropping datagram");
+        return None;
+    }
+    let table = gateway.table();
+    let backend = table.lookup_l4(L4Protocol::Udp, port, None)?;
+    let route = &backend.route;
+    if !backend.admits(peer.ip()) {
+        return None;
+    }
+    let Some((endpoint, in_flight)) = backend.pick(&HeaderMap::new()) else {
+        tracing::warn!(namespace = %route.namespace, route = %route.name, backend = %route.backend_ref, "no healthy endpoints");
+        return None;
+    };
+    let target = route.l4.as_ref()?.target(&endpoint.address);
+    let local = if target.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" };
+    let upstream = match UdpSocket::bind(local).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            tracing::warn!(port, error = %e, "failed to open UDP socket");
+            return None;
+        }
+    };
+    if let Err(e) = upstream.connect(target.as_str()).await {
+        tracing::warn!(namespace = %route.namespace, route = %route.name, %target, error = %e, "L4 upstream unreachable");
+        backend.record(&in_flight, true);
+        return None;
+    }
+    let session = Arc::new(Session { upstream, last_active: Mutex::new(Instant::now()), _in_flight: in_flight });
+    sessions.lock().unwrap_or_else(|e| e.into_inner()).insert(peer, session.clone());
+
+    let (relay, listener, sessions) = (session.clone(), listener.clone(), sessions.clone());
+    tokio::spawn(async move {
+        let mut buf = vec![0; 65535];
+        loop {
+            match tokio::time::timeout(UDP_IDLE, relay.upstream.recv(&mut buf)).await {
+                Ok(Ok(n)) => {
+                    relay.touch();
+                    let _ = listener.send_to(&buf[..n], peer).await;
+                }
+                // Nothing listening on the endpoint's port
+                Ok(Err(_)) => break,
+                Err(_) if relay.idle() => break,
+                Err(_) => {}
+            }
+        }
+        sessions.lock().unwrap_or_else(|e| e.into_inner()).remove(&peer);
+    });
+    Some(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore};
+
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let mut connection = ClientConnection::new(Arc::new(config), ServerName::try_from(server_name.to_string()).unwrap()).unwrap();
+        let mut hello = Vec::new();
+        connection.write_tls(&mut hello).unwrap();
+        hello
+    }
+
+    #[test]
+    fn reads_the_server_name_of_client_hellos() {
+        let hello = client_hello("db.example.com");
+        assert_eq!(client_hello_sni(&hello).as_deref(), Some("db.example.com"));
+        assert_eq!(client_hello_sni(&hello[..hello.len() - 1]), None, "incomplete hellos have no name yet");
+        // Postgres' SSLRequest, or anything else that is not TLS
+        assert_eq!(client_hello_sni(&[0, 0, 0, 8, 4, 210, 22, 47]), None);
+        assert_eq!(client_hello_sni(&client_hello("10.0.0.1")), None, "IP addresses are not sent as SNI");
+    }
+}
@@ -0,0 +1,18 @@
+pub mod access;
+pub mod admin;
+pub mod auth;
+pub mod clients;
+pub mod config;
+pub mod l4;
+pub mod limits;
+pub mod objects;
+pub mod control_plane;
+pub mod proxy;
+pub mod resilience;
+pub mod rules;
+pub mod site;
+pub mod sync;
+pub mod stats;
+pub mod table;
+pub mod tls;
+pub mod upstream;
@@ -0,0 +1,228 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::events::GATEWAY_RATE_LIMIT_SUBJECT;
+use futures_util::StreamExt;
+use models::route::{GatewayRoute, RateLimit, RateLimitKey};
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::Gateway;
+
+/// Buckets idle this long are forgotten once the table grows large.
+const IDLE_EVICTION: Duration = Duration::from_secs(10 * 60);
+const MAX_TRACKED_KEYS: usize = 100_000;
+/// How often hits on shared limits are sent to the other gateways.
+const SHARE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Route (`namespace/name`) and client a bucket belongs to.
+type Key = (String, String);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.requests_per_second).min(f64::from(limit.burst()));
+        self.updated = now;
+    }
+}
+
+/// Requests other gateways admitted on shared limits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharedHits {
+    /// Sender, so a gateway skips its own hits.
+    pub gateway: u64,
+    pub hits: Vec<SharedHit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharedHit {
+    pub namespace: String,
+    pub route: String,
+    pub client: String,
+    pub count: u32,
+}
+
+/// Token buckets per route and client. Buckets outlive table reloads and
+/// follow the route's current limit.
+pub struct RateLimits {
+    id: u64,
+    buckets: Mutex<HashMap<Key, Bucket>>,
+    /// Hits on shared limits not yet sent to the other gateways.
+    unshared: Mutex<HashMap<Key, u32>>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self { id: RandomState::new().hash_one(std::process::id()), buckets: Mutex::default(), unshared: Mutex::default() }
+    }
+}
+
+/// The client a request is counted against under `limit`.
+pub fn client_key(limit: &RateLimit, headers: &hyper::HeaderMap, ip: Option<IpAddr>) -> String {
+    if let RateLimitKey::Header(name) = &limit.key {
+        if let Some(value) = headers.get(name.as_str()).and_then(|v| v.to_str().ok()) {
+            return format!("header:{value}");
+        }
+    }
+    ip.map_or_else(|| "unknown".to_string(), |ip| format!("ip:{ip}"))
+}
+
+fn route_key(route: &GatewayRoute) -> String {
+    format!("{}/{}", route.namespace, route.name)
+}
+
+impl RateLimits {
+    /// Admit a request from `client` on `route`, or return how long until
+    /// the client may send another.
+    pub fn check(&self, route: &GatewayRoute, limit: &RateLimit, client: &str) -> Result<(), Duration> {
+        self.check_at(route, limit, client, Instant::now())
+    }
+
+    fn check_at(&self, route: &GatewayRoute, limit: &RateLimit, client: &str, now: Instant) -> Result<(), Duration> {
+        let key = (route_key(route), client.to_string());
+        {
+            let mut buckets = self.buckets.lock().unwrap();
+            if buckets.len() >= MAX_TRACKED_KEYS {
+                buckets.retain(|_, b| now.saturating_duration_since(b.updated) < IDLE_EVICTION);
+            }
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket { tokens: f64::from(limit.burst()), updated: now });
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0 {
+                return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.requests_per_second));
+            }
+            bucket.tokens -= 1.0;
+        }
+        if limit.shared {
+            *self.unshared.lock().unwrap().entry(key).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Hits on shared limits since the last call.
+    pub fn take_shared(&self) -> Option<SharedHits> {
+        let unshared = std::mem::take(&mut *self.unshared.lock().unwrap());
+        if unshared.is_empty() {
+            return None;
+        }
+        let hits = unshared
+            .into_iter()
+            .filter_map(|((route, client), count)| {
+                let (namespace, route) = route.split_once('/')?;
+                Some(SharedHit { namespace: namespace.to_string(), route: route.to_string(), client, count })
+            })
+            .collect();
+        Some(SharedHits { gateway: self.id, hits })
+    }
+
+    /// Take tokens for requests another gateway admitted. Buckets may go
+    /// into debt, so a burst spread over several gateways is paid back
+    /// before the client gets through again.
+    pub fn absorb(&self, route: &GatewayRoute, limit: &RateLimit, client: &str, count: u32) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry((route_key(route), client.to_string())).or_insert(Bucket { tokens: f64::from(limit.burst()), updated: now });
+        bucket.refill(limit, now);
+        bucket.tokens = (bucket.tokens - f64::from(count)).max(-f64::from(limit.burst()));
+    }
+}
+
+/// Exchange hits on shared limits with the other gateways over NATS.
+pub async fn run_sharing(gateway: Arc<Gateway>, client: async_nats::Client) {
+    let mut sub = match client.subscribe(GATEWAY_RATE_LIMIT_SUBJECT).await {
+        Ok(sub) => sub,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to subscribe to rate limit hits; shared rate limits are enforced per gateway");
+            return;
+        }
+    };
+    let receiver = gateway.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = sub.next().await {
+            let Ok(shared) = serde_json::from_slice::<SharedHits>(&msg.payload) else { continue };
+            if shared.gateway == receiver.rate_limits().id {
+                continue;
+            }
+            let table = receiver.table();
+            let routes: HashMap<(&str, &str), &GatewayRoute> = table.backends().map(|b| ((b.route.namespace.as_str(), b.route.name.as_str()), &b.route)).collect();
+            for hit in shared.hits {
+                let route = routes.get(&(hit.namespace.as_str(), hit.route.as_str())).copied();
+                if let Some((route, limit)) = route.and_then(|r| Some((r, r.rate_limit.as_ref().filter(|l| l.shared)?))) {
+                    receiver.rate_limits().absorb(route, limit, &hit.client, hit.count);
+                }
+            }
+        }
+    });
+
+    let mut ticker = tokio::time::interval(SHARE_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let Some(hits) = gateway.rate_limits().take_shared() else { continue };
+        let Ok(payload) = serde_json::to_vec(&hits) else { continue };
+        if let Err(e) = client.publish(GATEWAY_RATE_LIMIT_SUBJECT, payload.into()).await {
+            tracing::debug!(error = %e, "failed to share rate limit hits");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(rps: f64, burst: u32, shared: bool) -> (GatewayRoute, RateLimit) {
+        let limit = RateLimit { requests_per_second: rps, burst: Some(burst), key: RateLimitKey::ClientIp, shared };
+        let route = GatewayRoute { namespace: "default".into(), name: "web".into(), rate_limit: Some(limit.clone()), ..Default::default() };
+        (route, limit)
+    }
+
+    #[test]
+    fn buckets_refill_at_the_route_rate() {
+        let limits = RateLimits::default();
+        let (route, limit) = limited(2.0, 2, false);
+        let start = Instant::now();
+        assert!(limits.check_at(&route, &limit, "ip:1", start).is_ok());
+        assert!(limits.check_at(&route, &limit, "ip:1", start).is_ok());
+        assert_eq!(limits.check_at(&route, &limit, "ip:1", start), Err(Duration::from_millis(500)));
+        // Other clients have their own bucket
+        assert!(limits.check_at(&route, &limit, "ip:2", start).is_ok());
+        assert!(limits.check_at(&route, &limit, "ip:1", start + Duration::from_millis(500)).is_ok());
+        assert!(limits.take_shared().is_none());
+    }
+
+    #[test]
+    fn shared_hits_drain_other_gateways_buckets() {
+        let (route, limit) = limited(1.0, 5, true);
+        let (a, b) = (RateLimits::default(), RateLimits::default());
+        for _ in 0..4 {
+            assert!(a.check(&route, &limit, "ip:1").is_ok());
+        }
+        let shared = a.take_shared().unwrap();
+        assert_eq!(shared.hits, [SharedHit { namespace: "default".into(), route: "web".into(), client: "ip:1".into(), count: 4 }]);
+        assert!(a.take_shared().is_none());
+
+        for hit in &shared.hits {
+            b.absorb(&route, &limit, &hit.client, hit.count);
+        }
+        assert!(b.check(&route, &limit, "ip:1").is_ok());
+        assert!(b.check(&route, &limit, "ip:1").is_err());
+    }
+
+    #[test]
+    fn header_keys_fall_back_to_the_client_ip() {
+        let limit = RateLimit { requests_per_second: 1.0, burst: None, key: RateLimitKey::Header("x-api-key".into()), shared: false };
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let mut headers = hyper::HeaderMap::new();
+        assert_eq!(client_key(&limit, &headers, ip), "ip:192.0.2.1");
+        headers.insert("x-api-key", "k1".parse().unwrap());
+        assert_eq!(client_key(&limit, &headers, ip), "header:k1");
+    }
+}
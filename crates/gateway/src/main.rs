@@ -1,18 +1,70 @@
-use axum::{routing::get, Router};
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Context;
+use gateway::{access, admin, config::GatewayConfig, control_plane::ControlPlane, l4, limits, objects::ObjectStore, proxy::{self, Gateway}, stats, sync::Syncer, tls::TlsTerminator, upstream};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     common::telemetry::init_tracing();
-    let bind = std::env::var("BIND_HTTP").unwrap_or_else(|_| "0.0.0.0:80".into());
-    let addr: SocketAddr = bind.parse().expect("invalid BIND_HTTP");
+    let cfg = GatewayConfig::from_env()?;
+    let control_plane = ControlPlane::new(&cfg);
+    let mut gateway = Gateway::new(cfg.connect_timeout).with_control_plane(control_plane.clone());
+    if let Some(path) = &cfg.upstream_ca {
+        let pem = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        gateway = gateway.with_upstream_ca(&pem)?;
+    }
+    if let Some(store) = cfg.object_store.clone() {
+        gateway = gateway.with_object_store(ObjectStore::new(store)?);
+    }
+    let nats = match &cfg.nats_url {
+        Some(url) => match async_nats::connect(url.as_str()).await {
+            Ok(client) => Some(client),
+            Err(e) => {
+                tracing::warn!(%url, error = %e, "failed to connect to NATS; access logs are not published and shared rate limits are enforced per gateway");
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(client) = &nats {
+        let (sink, records) = access::channel();
+        gateway = gateway.with_access_log(sink);
+        tokio::spawn(access::publish(client.clone(), records));
+    }
+    let gateway = Arc::new(gateway);
+
+    tokio::spawn(Syncer::new(gateway.clone(), cfg.clone()).run());
+    tokio::spawn(upstream::run_health_checks(gateway.clone()));
+    let reserved = [Some(cfg.bind_http), cfg.bind_https, Some(cfg.bind_admin)].into_iter().flatten().map(|addr| addr.port()).collect();
+    tokio::spawn(l4::run(gateway.clone(), cfg.bind_l4, cfg.l4_ports, reserved));
+    if let Some(client) = nats {
+        tokio::spawn(limits::run_sharing(gateway.clone(), client));
+    }
+    tokio::spawn(stats::run_reporter(gateway.clone(), control_plane.clone(), cfg.stats_interval));
+
+    let admin_app = admin::router(gateway.clone());
+    let admin_listener = tokio::net::TcpListener::bind(cfg.bind_admin).await?;
+    tracing::info!(addr = %cfg.bind_admin, "Gateway admin listening");
+
+    let listener = tokio::net::TcpListener::bind(cfg.bind_http).await?;
+    tracing::info!(addr = %cfg.bind_http, "Gateway listening");
+    let app = proxy::router(gateway.clone());
 
-    let app = Router::new()
-        .route("/health", get(|| async { "ok" }))
-        .route("/", get(|| async { "span-gateway" }));
+    let https = async {
+        let Some(addr) = cfg.bind_https else { return std::future::pending().await };
+        if cfg.token.is_none() {
+            tracing::warn!("SPAN_TOKEN not set; no certificates can be loaded for TLS routes");
+        }
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!(%addr, "Gateway listening for TLS");
+        TlsTerminator::new(gateway.certificates().clone(), Some(control_plane))?.serve(listener, app.clone()).await?;
+        anyhow::Ok(())
+    };
 
-    tracing::info!(%addr, "Gateway listening");
-    axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app)
-        .await
-        .unwrap();
+    tokio::try_join!(
+        async { anyhow::Ok(axum::serve(admin_listener, admin_app).await?) },
+        async { anyhow::Ok(axum::serve(listener, app.clone().into_make_service_with_connect_info::<SocketAddr>()).await?) },
+        https,
+    )?;
+    Ok(())
 }
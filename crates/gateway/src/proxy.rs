@@ -0,0 +1,645 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Request, State},
+    http::{header, uri::PathAndQuery, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use arc_swap::ArcSwap;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::{body::{Body as _, Bytes}, upgrade::OnUpgrade};
+use hyper_util::rt::TokioIo;
+use tokio::sync::watch;
+use models::route::{bucket_backend, BackendProtocol, Timeouts};
+
+use crate::{
+    access::{AccessLogSender, Entry},
+    auth::{self, JwksCache},
+    clients::{self, Clients},
+    control_plane::ControlPlane,
+    limits::{self, RateLimits},
+    objects::ObjectStore,
+    resilience::{self, IdleTimeout, RouteGuards},
+    rules,
+    site::{self, ObjectCache},
+    stats::ReleaseCounters,
+    table::{Backend, RouteTable},
+    tls::{CertStore, TlsConnection},
+    upstream::{InFlight, Tracked, Upstreams},
+};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+/// Largest request body kept in memory so the request can be retried.
+/// Bigger (or unsized) bodies are streamed and sent once.
+const MAX_REPLAY_BYTES: u64 = 64 * 1024;
+
+/// Headers that describe a single connection and must not be forwarded
+/// (RFC 9110 7.6.1).
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The proxy: the routing table and certificates currently served, the
+/// state of every endpoint and the upstream clients.
+pub struct Gateway {
+    table: ArcSwap<RouteTable>,
+    certs: Arc<CertStore>,
+    upstreams: Upstreams,
+    releases: ReleaseCounters,
+    rate_limits: RateLimits,
+    guards: RouteGuards,
+    jwks: JwksCache,
+    clients: Arc<Clients>,
+    /// Clients for routes with their own connect timeout, by timeout.
+    clients_by_connect_timeout: Mutex<HashMap<Duration, Arc<Clients>>>,
+    connect_timeout: Duration,
+    upstream_ca: Option<String>,
+    control_plane: Option<ControlPlane>,
+    access_log: Option<AccessLogSender>,
+    object_store: Option<ObjectStore>,
+    objects: ObjectCache,
+    /// Version of the table being served, for listeners that follow it.
+    versions: watch::Sender<i64>,
+}
+
+impl Gateway {
+    pub fn new(connect_timeout: Duration) -> Self {
+        Self {
+            table: ArcSwap::from_pointee(RouteTable::default()),
+            certs: Arc::new(CertStore::default()),
+            upstreams: Upstreams::default(),
+            releases: ReleaseCounters::default(),
+            rate_limits: RateLimits::default(),
+            guards: RouteGuards::default(),
+            jwks: JwksCache::default(),
+            clients: Arc::new(Clients::new(connect_timeout, None).expect("default upstream TLS config")),
+            clients_by_connect_timeout: Mutex::default(),
+            connect_timeout,
+            upstream_ca: None,
+            control_plane: None,
+            access_log: None,
+            object_store: None,
+            objects: ObjectCache::default(),
+            versions: watch::Sender::new(0),
+        }
+    }
+
+    /// Trust the CA certificates in `pem` for `h2` backends too.
+    pub fn with_upstream_ca(mut self, pem: &str) -> anyhow::Result<Self> {
+        self.clients = Arc::new(Clients::new(self.connect_timeout, Some(pem))?);
+        self.upstream_ca = Some(pem.to_string());
+        Ok(self)
+    }
+
+    /// Answer ACME HTTP-01 challenges with key authorizations from
+    /// `control_plane`.
+    pub fn with_control_plane(mut self, control_plane: ControlPlane) -> Self {
+        self.control_plane = Some(control_plane);
+        self
+    }
+
+    /// Serve the objects of bucket routes from `store`.
+    pub fn with_object_store(mut self, store: ObjectStore) -> Self {
+        self.object_store = Some(store);
+        self
+    }
+
+    /// Send an access log record for every routed request to `sink`.
+    pub fn with_access_log(mut self, sink: AccessLogSender) -> Self {
+        self.access_log = Some(sink);
+        self
+    }
+
+    pub fn certificates(&self) -> &Arc<CertStore> {
+        &self.certs
+    }
+
+    pub fn upstreams(&self) -> &Upstreams {
+        &self.upstreams
+    }
+
+    pub fn release_counters(&self) -> &ReleaseCounters {
+        &self.releases
+    }
+
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    pub(crate) fn control_plane(&self) -> Option<&ControlPlane> {
+        self.control_plane.as_ref()
+    }
+
+    pub(crate) fn object_store(&self) -> Option<&ObjectStore> {
+        self.object_store.as_ref()
+    }
+
+    pub(crate) fn objects(&self) -> &ObjectCache {
+        &self.objects
+    }
+
+    pub(crate) fn jwks(&self) -> &JwksCache {
+        &self.jwks
+    }
+
+    pub(crate) fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub(crate) fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    /// Clients for a route connecting with `connect_timeout` rather than the
+    /// gateway's own. Each distinct timeout gets its own pools, built on
+    /// first use.
+    fn clients_for(&self, connect_timeout: Option<Duration>) -> Arc<Clients> {
+        let Some(timeout) = connect_timeout.filter(|t| *t != self.connect_timeout) else {
+            return self.clients.clone();
+        };
+        let mut by_timeout = self.clients_by_connect_timeout.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(clients) = by_timeout.get(&timeout) {
+            return clients.clone();
+        }
+        match Clients::new(timeout, self.upstream_ca.as_deref()) {
+            Ok(clients) => by_timeout.entry(timeout).or_insert(Arc::new(clients)).clone(),
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to build upstream clients, using the default connect timeout");
+                self.clients.clone()
+            }
+        }
+    }
+
+    pub fn table(&self) -> Arc<RouteTable> {
+        self.table.load_full()
+    }
+
+    /// Version of the table being served.
+    pub fn version(&self) -> i64 {
+        self.table.load().version()
+    }
+
+    /// Notified whenever another table is served.
+    pub fn watch_table(&self) -> watch::Receiver<i64> {
+        self.versions.subscribe()
+    }
+
+    /// Serve `table` from now on. The swap is atomic: each request is routed
+    /// entirely by the old table or entirely by the new one. Health check
+    /// results and ejections carry over for endpoints still in the table,
+    /// circuits and retry budgets for routes still in it.
+    pub fn replace(&self, mut table: RouteTable) {
+        table.link(&self.upstreams);
+        table.link_guards(&self.guards);
+        let version = table.version();
+        self.table.store(Arc::new(table));
+        self.versions.send_replace(version);
+    }
+}
+
+/// Every request on the proxied listeners goes through [`proxy`], except
+/// ACME HTTP-01 challenges the control plane is waiting on.
+pub fn router(gateway: Arc<Gateway>) -> Router {
+    Router::new()
+        .route("/.well-known/acme-challenge/:token", get(acme_challenge))
+        .fallback(proxy)
+        .with_state(gateway)
+}
+
+/// Serve the key authorization for a pending challenge. Tokens the control
+/// plane does not know are proxied, so apps can run their own ACME clients.
+async fn acme_challenge(State(gateway): State<Arc<Gateway>>, Path(token): Path<String>, peer: Option<ConnectInfo<SocketAddr>>, request: Request) -> Response {
+    if let Some(control_plane) = &gateway.control_plane {
+        match control_plane.http01(&token).await {
+            Ok(Some(key_authorization)) => return key_authorization.into_response(),
+            Ok(None) => {}
+            Err(e) => tracing::warn!(error = %e, "failed to look up ACME challenge"),
+        }
+    }
+    proxy(State(gateway), peer, request).await
+}
+
+/// An error from the gateway itself. gRPC clients get it as a gRPC status,
+/// since they do not look at HTTP ones.
+pub(crate) fn reply(grpc: bool, status: StatusCode, message: &'static str) -> Response {
+    if grpc {
+        return grpc_error(status, message.trim_end());
+    }
+    (status, message).into_response()
+}
+
+/// A trailers-only gRPC response, with the status gRPC clients derive from
+/// `status` (see gRPC's http-grpc-status-mapping).
+fn grpc_error(status: StatusCode, message: &'static str) -> Response {
+    let code = match status {
+        StatusCode::BAD_REQUEST => "13",
+        StatusCode::UNAUTHORIZED => "16",
+        StatusCode::FORBIDDEN => "7",
+        StatusCode::NOT_FOUND => "12",
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => "14",
+        _ => "2",
+    };
+    let mut response = StatusCode::OK.into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    headers.insert(GRPC_STATUS, HeaderValue::from_static(code));
+    headers.insert(GRPC_MESSAGE, HeaderValue::from_static(message));
+    response
+}
+
+fn is_grpc(headers: &HeaderMap) -> bool {
+    headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_some_and(|v| v.starts_with("application/grpc"))
+}
+
+/// The protocol an HTTP/1.1 request asks to switch to, if it is an upgrade.
+fn upgrade_protocol(headers: &HeaderMap) -> Option<HeaderValue> {
+    let connection_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    connection_upgrade.then(|| headers.get(header::UPGRADE).cloned()).flatten()
+}
+
+/// Route a request by host and path, apply the route's rules and forward it
+/// to one of the backend's healthy endpoints, picked by the route's load
+/// balancing policy and sent with the route's backend protocol: 404 without a
+/// matching route, 403 for clients outside its IP filter, 429 past its rate
+/// limit, 401 (or the forward auth app's answer) for clients failing its
+/// auth, 413 for bodies over its size limit, 503 when the backend has no
+/// healthy endpoint or its circuit is open, 502 when the endpoint cannot be
+/// reached, 504 past the route's timeouts. Idempotent requests are retried
+/// per the route's retry policy. HTTP/1.1 upgrades are tunneled once the
+/// backend accepts them. Bucket routes are served from the bucket's objects
+/// instead.
+pub async fn proxy(State(gateway): State<Arc<Gateway>>, peer: Option<ConnectInfo<SocketAddr>>, mut request: Request) -> Response {
+    let grpc = is_grpc(request.headers());
+    let Some(host) = request_host(&request) else {
+        return reply(grpc, StatusCode::BAD_REQUEST, "missing Host header\n");
+    };
+    let table = gateway.table();
+    let Some(backend) = table.lookup(&host, request.uri().path()) else {
+        return reply(grpc, StatusCode::NOT_FOUND, "no route for this host and path\n");
+    };
+    let client_ip = peer.map(|ConnectInfo(addr)| addr.ip());
+    let mut entry = Entry::new(gateway.access_log.clone(), &backend.route, &host, &mut request, client_ip);
+    let response = match rules::apply(&backend.route.rules, &host, &mut request) {
+        rules::Outcome::Forward(response_rules) => {
+            let mut response = forward(&gateway, backend, grpc, &host, client_ip, request, &mut entry).await;
+            response_rules.apply(response.headers_mut());
+            response
+        }
+        rules::Outcome::Redirect(response) => response,
+    };
+    entry.finish(response)
+}
+
+/// Apply a route's policies to a request and send it to one of its
+/// endpoints.
+async fn forward(gateway: &Gateway, backend: &Backend, grpc: bool, host: &str, client_ip: Option<IpAddr>, mut request: Request, entry: &mut Entry) -> Response {
+    let route = &backend.route;
+    let grpc = grpc || route.protocol == BackendProtocol::Grpc;
+    // Without a peer address a filtered route cannot tell who is asking
+    if !client_ip.map_or(route.ip_filter.is_none(), |ip| backend.admits(ip)) {
+        return reply(grpc, StatusCode::FORBIDDEN, "forbidden\n");
+    }
+    if let Some(limit) = &route.rate_limit {
+        let client = limits::client_key(limit, request.headers(), client_ip);
+        if let Err(wait) = gateway.rate_limits.check(route, limit, &client) {
+            let mut response = reply(grpc, StatusCode::TOO_MANY_REQUESTS, "too many requests\n");
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(wait.as_secs_f64().ceil().max(1.0) as u64));
+            return response;
+        }
+    }
+    if let Some(edge_auth) = backend.auth() {
+        if let Err(response) = auth::authenticate(gateway, edge_auth, grpc, host, client_ip, &mut request).await {
+            return response;
+        }
+    }
+    if let Some((namespace, bucket)) = bucket_backend(&route.backend_ref) {
+        return site::serve(gateway, route, namespace, bucket, request).await;
+    }
+    if let Some(max) = route.max_body_bytes {
+        let declared = request.headers().get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+        if declared.is_some_and(|len| len > max) {
+            return reply(grpc, StatusCode::PAYLOAD_TOO_LARGE, "request body too large\n");
+        }
+        // Bodies without a length are cut off once they pass the limit
+        let body = std::mem::take(request.body_mut());
+        *request.body_mut() = Body::new(Limited::new(body, usize::try_from(max).unwrap_or(usize::MAX)));
+    }
+    let resilience = route.resilience.as_ref();
+    let breaker = resilience.and_then(|r| r.circuit_breaker.as_ref());
+    let retries = resilience.and_then(|r| r.retries.as_ref());
+    let timeouts = resilience.map(|r| &r.timeouts);
+    let guard = backend.guard();
+    if breaker.is_some_and(|b| !guard.admit(b)) {
+        return reply(grpc, StatusCode::SERVICE_UNAVAILABLE, "circuit open\n");
+    }
+
+    // Upgrades only exist in HTTP/1.1
+    let upgrade = upgrade_protocol(request.headers()).filter(|_| route.protocol == BackendProtocol::Http1);
+    let client_upgrade = upgrade.is_some().then(|| hyper::upgrade::on(&mut request));
+    let client_ip = client_ip.map(|ip| ip.to_string());
+    let proto = request_proto(&request);
+    forwarded_headers(request.headers_mut(), host, client_ip.as_deref(), proto);
+    if let Some(protocol) = upgrade {
+        request.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        request.headers_mut().insert(header::UPGRADE, protocol);
+    }
+    let replay = match retries {
+        Some(_) if client_upgrade.is_none() && resilience::idempotent(request.method()) => {
+            guard.count_request();
+            match replayable_body(grpc, &mut request).await {
+                Ok(replay) => replay,
+                Err(response) => return response,
+            }
+        }
+        _ => None,
+    };
+
+    let (parts, body) = request.into_parts();
+    let mut body = Some(body);
+    let path = parts.uri.path_and_query().cloned().unwrap_or_else(|| PathAndQuery::from_static("/"));
+    let clients = gateway.clients_for(timeouts.and_then(|t| t.connect_ms).map(Duration::from_millis));
+    let deadline = timeouts.and_then(|t| t.request_ms).map(|ms| Instant::now() + Duration::from_millis(ms));
+    let mut attempt = 0;
+    loop {
+        let Some((endpoint, in_flight)) = backend.pick(&parts.headers) else {
+            tracing::warn!(namespace = %route.namespace, route = %route.name, backend = %route.backend_ref, "no healthy endpoints");
+            return reply(grpc, StatusCode::SERVICE_UNAVAILABLE, "no healthy backend available\n");
+        };
+        entry.upstream(endpoint);
+        let Ok(uri) = Uri::builder().scheme(clients::scheme(route.protocol)).authority(endpoint.address.as_str()).path_and_query(path.clone()).build() else {
+            tracing::error!(address = %endpoint.address, "invalid endpoint address");
+            return reply(grpc, StatusCode::BAD_GATEWAY, "bad gateway\n");
+        };
+        let body = match &replay {
+            Some(bytes) => Body::from(bytes.clone()),
+            None => body.take().expect("requests without a replayable body are only sent once"),
+        };
+        let mut upstream_request = Request::new(body);
+        *upstream_request.method_mut() = parts.method.clone();
+        *upstream_request.uri_mut() = uri;
+        *upstream_request.version_mut() = clients::version(route.protocol);
+        *upstream_request.headers_mut() = parts.headers.clone();
+
+        let sent = clients.request(route.protocol, upstream_request);
+        let outcome = match attempt_timeout(timeouts, deadline) {
+            Some(timeout) => tokio::time::timeout(timeout, sent).await.ok(),
+            None => Some(sent.await),
+        };
+        let failed = !matches!(&outcome, Some(Ok(response)) if !response.status().is_server_error());
+        if !matches!(&outcome, Some(Err(e)) if body_too_large(e)) {
+            backend.record(&in_flight, failed);
+            if let Some(release) = &endpoint.release {
+                gateway.releases.record(&route.namespace, &route.backend_ref, release, failed);
+            }
+            if let Some(breaker) = breaker {
+                guard.record(breaker, failed);
+            }
+        }
+        let retryable = match &outcome {
+            Some(Ok(response)) => resilience::retryable(response.status()),
+            Some(Err(e)) => !body_too_large(e),
+            None => true,
+        };
+        if let (true, Some(retries), Some(_)) = (retryable, retries, &replay) {
+            let backoff = Duration::from_millis(retries.backoff_ms.saturating_mul(1 << attempt.min(16)));
+            let in_time = deadline.is_none_or(|d| Instant::now() + backoff < d);
+            if attempt < retries.attempts && in_time && breaker.is_none_or(|b| guard.admit(b)) && guard.try_retry(retries) {
+                attempt += 1;
+                tracing::debug!(namespace = %route.namespace, route = %route.name, address = %endpoint.address, attempt, "retrying upstream request");
+                drop(outcome);
+                tokio::time::sleep(backoff).await;
+                continue;
+            }
+        }
+
+        let mut response = match outcome {
+            Some(Ok(response)) => response,
+            Some(Err(e)) if body_too_large(&e) => return reply(grpc, StatusCode::PAYLOAD_TOO_LARGE, "request body too large\n"),
+            Some(Err(e)) => {
+                tracing::warn!(namespace = %route.namespace, route = %route.name, address = %endpoint.address, error = %e, "upstream request failed");
+                return reply(grpc, StatusCode::BAD_GATEWAY, "bad gateway\n");
+            }
+            None => {
+                tracing::warn!(namespace = %route.namespace, route = %route.name, address = %endpoint.address, "upstream request timed out");
+                return reply(grpc, StatusCode::GATEWAY_TIMEOUT, "upstream timed out\n");
+            }
+        };
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            if let Some(client_upgrade) = client_upgrade {
+                let protocol = response.headers().get(header::UPGRADE).cloned();
+                let upstream_upgrade = hyper::upgrade::on(&mut response);
+                tokio::spawn(tunnel(client_upgrade, upstream_upgrade, in_flight));
+                let mut response = response.map(|_| Body::empty());
+                strip_hop_by_hop(response.headers_mut());
+                response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+                if let Some(protocol) = protocol {
+                    response.headers_mut().insert(header::UPGRADE, protocol);
+                }
+                return response;
+            }
+        }
+        let read_timeout = timeouts.and_then(|t| t.read_ms).map(Duration::from_millis);
+        let mut response = response.map(|body| {
+            let body = Tracked::new(body, in_flight);
+            match read_timeout {
+                Some(timeout) => Body::new(IdleTimeout::new(body, timeout)),
+                None => Body::new(body),
+            }
+        });
+        strip_hop_by_hop(response.headers_mut());
+        return response;
+    }
+}
+
+/// How long one attempt may wait for its response to start: the route's read
+/// timeout, cut short by what is left of its request timeout.
+fn attempt_timeout(timeouts: Option<&Timeouts>, deadline: Option<Instant>) -> Option<Duration> {
+    let read = timeouts.and_then(|t| t.read_ms).map(Duration::from_millis);
+    let left = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+    match (read, left) {
+        (Some(read), Some(left)) => Some(read.min(left)),
+        (read, left) => read.or(left),
+    }
+}
+
+/// Read a request body small enough to keep for retries. Streaming bodies
+/// and bodies over [`MAX_REPLAY_BYTES`] are left alone.
+async fn replayable_body(grpc: bool, request: &mut Request) -> Result<Option<Bytes>, Response> {
+    if request.body().size_hint().exact().is_none_or(|len| len > MAX_REPLAY_BYTES) {
+        return Ok(None);
+    }
+    match std::mem::take(request.body_mut()).collect().await {
+        Ok(collected) => Ok(Some(collected.to_bytes())),
+        Err(e) if body_too_large(&e) => Err(reply(grpc, StatusCode::PAYLOAD_TOO_LARGE, "request body too large\n")),
+        Err(e) => {
+            tracing::debug!(error = %e, "failed to read request body");
+            Err(reply(grpc, StatusCode::BAD_REQUEST, "failed to read request body\n"))
+        }
+    }
+}
+
+/// Whether sending a request failed because its body passed the route's
+/// `maxBodyBytes`, rather than because of the backend.
+fn body_too_large(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Copy bytes both ways between an upgraded client connection and the
+/// upgraded backend connection until either side closes. The request stays
+/// in flight for as long as the tunnel is open.
+async fn tunnel(client: OnUpgrade, upstream: OnUpgrade, in_flight: InFlight) {
+    let (client, upstream) = match tokio::try_join!(client, upstream) {
+        Ok(upgraded) => upgraded,
+        Err(e) => {
+            tracing::debug!(address = %in_flight.upstream().address(), error = %e, "upgrade failed");
+            return;
+        }
+    };
+    let (mut client, mut upstream) = (TokioIo::new(client), TokioIo::new(upstream));
+    if let Err(e) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+        tracing::debug!(address = %in_flight.upstream().address(), error = %e, "upgraded connection closed with an error");
+    }
+}
+
+/// Scheme the client reached the gateway with.
+pub(crate) fn request_proto(request: &Request) -> &'static str {
+    if request.extensions().get::<TlsConnection>().is_some() { "https" } else { "http" }
+}
+
+/// Host a request was sent to: the Host header, or the authority of an
+/// absolute-form (or HTTP/2) request.
+fn request_host(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| request.uri().authority().map(|a| a.as_str()))
+        .map(str::to_string)
+}
+
+pub(crate) fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    // Headers named in Connection are hop-by-hop too
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+/// Drop hop-by-hop headers and tell the backend who the client is.
+pub(crate) fn forwarded_headers(headers: &mut HeaderMap, host: &str, client_ip: Option<&str>, proto: &'static str) {
+    // `TE: trailers` is the one TE value HTTP/2 allows, and gRPC servers
+    // insist on it
+    let te_trailers = headers.get_all(header::TE).iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')).any(|t| t.trim().eq_ignore_ascii_case("trailers"));
+    strip_hop_by_hop(headers);
+    if te_trailers {
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+    }
+    if let Some(ip) = client_ip {
+        let chain = match headers.get(X_FORWARDED_FOR).and_then(|v| v.to_str().ok()) {
+            Some(prior) => format!("{prior}, {ip}"),
+            None => ip.to_string(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&chain) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+    }
+    if let Ok(value) = HeaderValue::from_str(host) {
+        // HTTP/2 clients send no Host header; keep the original host upstream
+        if !headers.contains_key(header::HOST) {
+            headers.insert(header::HOST, value.clone());
+        }
+        headers.insert(X_FORWARDED_HOST, value);
+    }
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_client_and_strips_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive, x-session"));
+        headers.insert("x-session", HeaderValue::from_static("abc"));
+        headers.insert(header::TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("203.0.113.7"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("text/html"));
+        forwarded_headers(&mut headers, "app.example.com", Some("10.0.0.9"), "https");
+
+        assert!(headers.get(header::CONNECTION).is_none());
+        assert!(headers.get("x-session").is_none());
+        assert!(headers.get(header::TRANSFER_ENCODING).is_none());
+        assert_eq!(headers[X_FORWARDED_FOR], "203.0.113.7, 10.0.0.9");
+        assert_eq!(headers[X_FORWARDED_HOST], "app.example.com");
+        assert_eq!(headers[X_FORWARDED_PROTO], "https");
+        assert_eq!(headers[header::ACCEPT], "text/html");
+    }
+
+    #[test]
+    fn keeps_te_trailers_and_detects_upgrades() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::TE, HeaderValue::from_static("gzip, trailers"));
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive, Upgrade"));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        assert_eq!(upgrade_protocol(&headers).unwrap(), "websocket");
+        forwarded_headers(&mut headers, "app.example.com", None, "http");
+        assert_eq!(headers[header::TE], "trailers");
+        assert!(upgrade_protocol(&headers).is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        assert!(upgrade_protocol(&headers).is_none(), "Upgrade without Connection: upgrade is ignored");
+    }
+
+    #[test]
+    fn gateway_errors_become_grpc_statuses_for_grpc_clients() {
+        let response = reply(true, StatusCode::SERVICE_UNAVAILABLE, "no healthy backend available\n");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[GRPC_STATUS], "14");
+        assert_eq!(response.headers()[GRPC_MESSAGE], "no healthy backend available");
+        assert_eq!(reply(true, StatusCode::NOT_FOUND, "no route\n").headers()[GRPC_STATUS], "12");
+        assert_eq!(reply(false, StatusCode::NOT_FOUND, "no route\n").status(), StatusCode::NOT_FOUND);
+    }
+}
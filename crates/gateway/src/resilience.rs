@@ -0,0 +1,340 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::http::{Method, StatusCode};
+use hyper::body::{Body, Frame, SizeHint};
+use models::route::{CircuitBreaker, Retries};
+use serde::Serialize;
+use tokio::time::Sleep;
+
+/// Retry budgets are counted over windows this long.
+const BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+/// Methods that can be sent again without changing their effect
+/// (RFC 9110 9.2.2).
+pub fn idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE)
+}
+
+/// Upstream responses worth another attempt on a different endpoint.
+pub fn retryable(status: StatusCode) -> bool {
+    matches!(status, StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    /// Failed attempts in a row while closed.
+    failures: u32,
+    /// When the circuit last opened, or went half-open.
+    since: Instant,
+    /// Trial requests let through since going half-open.
+    trials: u32,
+    opened: u64,
+}
+
+impl Circuit {
+    fn new(now: Instant) -> Self {
+        Self { state: CircuitState::Closed, failures: 0, since: now, trials: 0, opened: 0 }
+    }
+
+    fn admit(&mut self, policy: &CircuitBreaker, now: Instant) -> bool {
+        let open_for = Duration::from_secs(policy.open_secs);
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open if now.saturating_duration_since(self.since) < open_for => false,
+            CircuitState::Open => {
+                self.state = CircuitState::HalfOpen;
+                self.since = now;
+                self.trials = 1;
+                true
+            }
+            CircuitState::HalfOpen => {
+                // Trials that never report back (the client went away) must
+                // not hold the circuit half-open forever
+                if now.saturating_duration_since(self.since) >= open_for {
+                    self.since = now;
+                    self.trials = 0;
+                }
+                if self.trials < policy.half_open_requests {
+                    self.trials += 1;
+                    return true;
+                }
+                false
+            }
+        }
+    }
+
+    fn record(&mut self, policy: &CircuitBreaker, failed: bool, now: Instant) {
+        match (self.state, failed) {
+            (CircuitState::Closed, false) => self.failures = 0,
+            (CircuitState::Closed, true) => {
+                self.failures += 1;
+                if self.failures >= policy.consecutive_failures {
+                    self.open(now);
+                }
+            }
+            (CircuitState::HalfOpen, false) => {
+                self.state = CircuitState::Closed;
+                self.failures = 0;
+            }
+            (CircuitState::HalfOpen, true) => self.open(now),
+            // Stragglers admitted before the circuit opened
+            (CircuitState::Open, _) => {}
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.since = now;
+        self.failures = 0;
+        self.opened += 1;
+    }
+}
+
+#[derive(Debug)]
+struct RetryBudget {
+    window_start: Instant,
+    requests: u64,
+    retries: u64,
+}
+
+impl RetryBudget {
+    fn roll(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.window_start) >= BUDGET_WINDOW {
+            self.window_start = now;
+            self.requests = 0;
+            self.retries = 0;
+        }
+    }
+
+    fn try_retry(&mut self, policy: &Retries, now: Instant) -> bool {
+        self.roll(now);
+        let allowed = f64::from(policy.min_retries_per_second) * BUDGET_WINDOW.as_secs_f64() + policy.budget_ratio * self.requests as f64;
+        if (self.retries as f64) < allowed {
+            self.retries += 1;
+            return true;
+        }
+        false
+    }
+}
+
+/// A route's circuit and retry budget. Shared through [`RouteGuards`] so
+/// they carry over table reloads.
+#[derive(Debug)]
+pub struct RouteGuard {
+    circuit: Mutex<Circuit>,
+    budget: Mutex<RetryBudget>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CircuitStatus {
+    pub namespace: String,
+    pub route: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// Seconds until an open circuit lets trial requests through.
+    pub retry_in_secs: Option<u64>,
+    /// Times the circuit has opened since the gateway started.
+    pub opened: u64,
+    /// Retries spent in the current budget window.
+    pub retries: u64,
+}
+
+impl Default for RouteGuard {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self { circuit: Mutex::new(Circuit::new(now)), budget: Mutex::new(RetryBudget { window_start: now, requests: 0, retries: 0 }) }
+    }
+}
+
+impl RouteGuard {
+    /// Whether the circuit lets a request (or retry) through to the backend.
+    pub fn admit(&self, policy: &CircuitBreaker) -> bool {
+        self.circuit.lock().unwrap_or_else(|e| e.into_inner()).admit(policy, Instant::now())
+    }
+
+    /// Count the outcome of an attempt against the circuit.
+    pub fn record(&self, policy: &CircuitBreaker, failed: bool) {
+        self.circuit.lock().unwrap_or_else(|e| e.into_inner()).record(policy, failed, Instant::now());
+    }
+
+    /// Count a request towards the retry budget.
+    pub fn count_request(&self) {
+        let mut budget = self.budget.lock().unwrap_or_else(|e| e.into_inner());
+        budget.roll(Instant::now());
+        budget.requests += 1;
+    }
+
+    /// Spend a retry, if the budget has one left.
+    pub fn try_retry(&self, policy: &Retries) -> bool {
+        self.budget.lock().unwrap_or_else(|e| e.into_inner()).try_retry(policy, Instant::now())
+    }
+
+    pub fn status(&self, namespace: &str, route: &str, policy: &CircuitBreaker) -> CircuitStatus {
+        let now = Instant::now();
+        let retries = {
+            let mut budget = self.budget.lock().unwrap_or_else(|e| e.into_inner());
+            budget.roll(now);
+            budget.retries
+        };
+        let circuit = self.circuit.lock().unwrap_or_else(|e| e.into_inner());
+        let retry_in_secs = (circuit.state == CircuitState::Open)
+            .then(|| Duration::from_secs(policy.open_secs).saturating_sub(now.saturating_duration_since(circuit.since)).as_secs_f64().ceil() as u64);
+        CircuitStatus {
+            namespace: namespace.to_string(),
+            route: route.to_string(),
+            state: circuit.state,
+            consecutive_failures: circuit.failures,
+            retry_in_secs,
+            opened: circuit.opened,
+            retries,
+        }
+    }
+}
+
+/// Every route's guard, by `namespace/name`.
+#[derive(Debug, Default)]
+pub struct RouteGuards {
+    by_route: Mutex<HashMap<String, Arc<RouteGuard>>>,
+}
+
+impl RouteGuards {
+    /// The shared guard for a route, created on first use.
+    pub fn get(&self, namespace: &str, name: &str) -> Arc<RouteGuard> {
+        let mut by_route = self.by_route.lock().unwrap_or_else(|e| e.into_inner());
+        by_route.entry(format!("{namespace}/{name}")).or_default().clone()
+    }
+
+    /// Drop guards of routes (`namespace/name`) no longer served.
+    pub fn retain(&self, routes: &HashSet<String>) {
+        self.by_route.lock().unwrap_or_else(|e| e.into_inner()).retain(|route, _| routes.contains(route));
+    }
+}
+
+/// A response body read from upstream took longer than the route's read
+/// timeout to arrive.
+#[derive(Debug)]
+pub struct ReadTimeout;
+
+impl fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("upstream read timed out")
+    }
+}
+
+impl std::error::Error for ReadTimeout {}
+
+/// A response body that fails with [`ReadTimeout`] when its backend goes
+/// quiet for longer than `timeout` between frames.
+pub struct IdleTimeout<B> {
+    inner: B,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<B> IdleTimeout<B> {
+    pub fn new(inner: B, timeout: Duration) -> Self {
+        Self { inner, timeout, sleep: Box::pin(tokio::time::sleep(timeout)) }
+    }
+}
+
+impl<B> Body for IdleTimeout<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Data = B::Data;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        match Pin::new(&mut this.inner).poll_frame(cx) {
+            Poll::Ready(frame) => {
+                let deadline = tokio::time::Instant::now() + this.timeout;
+                this.sleep.as_mut().reset(deadline);
+                Poll::Ready(frame.map(|f| f.map_err(Into::into)))
+            }
+            Poll::Pending => match this.sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Some(Err(ReadTimeout.into()))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures_and_closes_after_a_trial() {
+        let policy = CircuitBreaker { consecutive_failures: 3, open_secs: 30, half_open_requests: 1 };
+        let start = Instant::now();
+        let mut circuit = Circuit::new(start);
+        circuit.record(&policy, true, start);
+        circuit.record(&policy, true, start);
+        circuit.record(&policy, false, start);
+        assert_eq!(circuit.state, CircuitState::Closed, "a success resets the count");
+        for _ in 0..3 {
+            assert!(circuit.admit(&policy, start));
+            circuit.record(&policy, true, start);
+        }
+        assert_eq!(circuit.state, CircuitState::Open);
+        assert!(!circuit.admit(&policy, start + Duration::from_secs(29)));
+
+        let later = start + Duration::from_secs(30);
+        assert!(circuit.admit(&policy, later), "one trial once open_secs have passed");
+        assert_eq!(circuit.state, CircuitState::HalfOpen);
+        assert!(!circuit.admit(&policy, later), "only half_open_requests trials");
+        circuit.record(&policy, true, later);
+        assert_eq!(circuit.state, CircuitState::Open, "a failed trial reopens");
+        assert_eq!(circuit.opened, 2);
+
+        let later = later + Duration::from_secs(30);
+        assert!(circuit.admit(&policy, later));
+        circuit.record(&policy, false, later);
+        assert_eq!(circuit.state, CircuitState::Closed);
+        assert!(circuit.admit(&policy, later));
+    }
+
+    #[test]
+    fn retries_are_capped_by_the_budget() {
+        let policy = Retries { budget_ratio: 0.5, min_retries_per_second: 0, ..Default::default() };
+        let start = Instant::now();
+        let mut budget = RetryBudget { window_start: start, requests: 0, retries: 0 };
+        assert!(!budget.try_retry(&policy, start), "no requests, no retries");
+        budget.requests = 4;
+        assert!(budget.try_retry(&policy, start));
+        assert!(budget.try_retry(&policy, start));
+        assert!(!budget.try_retry(&policy, start));
+        assert!(!budget.try_retry(&policy, start + BUDGET_WINDOW), "a new window starts without requests");
+
+        let policy = Retries { min_retries_per_second: 1, ..policy };
+        let mut budget = RetryBudget { window_start: start, requests: 0, retries: 0 };
+        assert_eq!((0..20).filter(|_| budget.try_retry(&policy, start)).count(), 10);
+    }
+}
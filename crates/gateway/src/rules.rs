@@ -0,0 +1,234 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, uri::PathAndQuery, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    response::Response,
+};
+use models::route::{HeaderRule, RedirectRule, RewriteRule, RouteRule, RuleAction, RuleMatch};
+
+use crate::{proxy::request_proto, table::prefix_matches};
+
+/// Response header changes collected while applying a route's rules, made
+/// once the response is in.
+#[derive(Debug, Default)]
+pub struct ResponseRules<'a>(Vec<&'a HeaderRule>);
+
+impl ResponseRules<'_> {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for rule in &self.0 {
+            apply_headers(rule, headers);
+        }
+    }
+}
+
+/// What is left to do with a request once a route's rules are applied.
+pub enum Outcome<'a> {
+    /// Forward it, then change the response's headers.
+    Forward(ResponseRules<'a>),
+    /// Answer with this redirect instead.
+    Redirect(Response),
+}
+
+/// Apply a route's rules to `request` in order, each seeing the request as
+/// earlier rules left it. A matching redirect ends evaluation.
+pub fn apply<'a>(rules: &'a [RouteRule], host: &str, request: &mut Request) -> Outcome<'a> {
+    let mut response_rules = ResponseRules::default();
+    for rule in rules {
+        if !matches(&rule.when, request) {
+            continue;
+        }
+        match &rule.action {
+            RuleAction::Redirect(redirect) => {
+                let mut response = redirect_response(redirect, host, request);
+                response_rules.apply(response.headers_mut());
+                return Outcome::Redirect(response);
+            }
+            RuleAction::Rewrite(rewrite) => rewrite_request(rewrite, request),
+            RuleAction::RequestHeaders(headers) => apply_headers(headers, request.headers_mut()),
+            RuleAction::ResponseHeaders(headers) => response_rules.0.push(headers),
+        }
+    }
+    Outcome::Forward(response_rules)
+}
+
+fn matches(when: &RuleMatch, request: &Request) -> bool {
+    when.scheme.as_ref().is_none_or(|s| s == request_proto(request))
+        && when.path_prefix.as_ref().is_none_or(|p| prefix_matches(p, request.uri().path()))
+        && (when.methods.is_empty() || when.methods.iter().any(|m| m == request.method().as_str()))
+}
+
+/// The request's URL with the redirect's parts swapped in.
+fn redirect_response(redirect: &RedirectRule, host: &str, request: &Request) -> Response {
+    let scheme = redirect.scheme.as_deref().unwrap_or(request_proto(request));
+    let (hostname, port) = split_port(host);
+    let hostname = redirect.host.as_deref().unwrap_or(hostname);
+    let port = match redirect.port {
+        Some(port) => Some(port.to_string()),
+        // The original port belongs to the original scheme
+        None if scheme != request_proto(request) => None,
+        None => port.map(str::to_string),
+    };
+    let path = redirect.path.as_deref().unwrap_or(request.uri().path());
+    let mut location = format!("{scheme}://{hostname}");
+    if let Some(port) = port {
+        location.push(':');
+        location.push_str(&port);
+    }
+    location.push_str(path);
+    if let Some(query) = request.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = StatusCode::from_u16(redirect.status).unwrap_or(StatusCode::MOVED_PERMANENTLY);
+    if let Ok(value) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(header::LOCATION, value);
+    }
+    response
+}
+
+/// Host and port of a Host header value.
+fn split_port(host: &str) -> (&str, Option<&str>) {
+    // IPv6 literal: [::1]:8080
+    if let Some(end) = host.find(']') {
+        return (&host[..=end], host[end + 1..].strip_prefix(':'));
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    }
+}
+
+fn rewrite_request(rewrite: &RewriteRule, request: &mut Request) {
+    if let Some(host) = &rewrite.host {
+        if let Ok(value) = HeaderValue::from_str(host) {
+            request.headers_mut().insert(header::HOST, value);
+        }
+    }
+    if rewrite.strip_prefix.is_none() && rewrite.add_prefix.is_none() {
+        return;
+    }
+    let mut path = request.uri().path().to_string();
+    if let Some(prefix) = rewrite.strip_prefix.as_deref().map(|p| p.trim_end_matches('/')).filter(|p| prefix_matches(p, &path)) {
+        path = match &path[prefix.len()..] {
+            "" => "/".to_string(),
+            rest => rest.to_string(),
+        };
+    }
+    if let Some(prefix) = &rewrite.add_prefix {
+        path = format!("{}{path}", prefix.trim_end_matches('/'));
+    }
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let Ok(path_and_query) = PathAndQuery::try_from(path_and_query) else { return };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query);
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+}
+
+fn apply_headers(rule: &HeaderRule, headers: &mut HeaderMap) {
+    // The control plane validated names and values; anything else is skipped
+    for name in &rule.remove {
+        headers.remove(name.as_str());
+    }
+    for (name, value) in &rule.set {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            headers.insert(name, value);
+        }
+    }
+    for (name, value) in &rule.add {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            headers.append(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+
+    fn rules(value: serde_json::Value) -> Vec<RouteRule> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn request(method: Method, uri: &str) -> Request {
+        let mut request = Request::new(Body::empty());
+        *request.method_mut() = method;
+        *request.uri_mut() = uri.parse().unwrap();
+        request
+    }
+
+    fn redirect(outcome: Outcome) -> Response {
+        match outcome {
+            Outcome::Redirect(response) => response,
+            Outcome::Forward(_) => panic!("expected a redirect"),
+        }
+    }
+
+    fn forward(outcome: Outcome) -> ResponseRules {
+        match outcome {
+            Outcome::Forward(response_rules) => response_rules,
+            Outcome::Redirect(response) => panic!("unexpected redirect to {:?}", response.headers().get(header::LOCATION)),
+        }
+    }
+
+    #[test]
+    fn redirects_keep_what_they_do_not_replace() {
+        let https = rules(serde_json::json!([{ "when": { "scheme": "http" }, "redirect": { "scheme": "https" } }]));
+        let mut req = request(Method::GET, "/a/b?x=1");
+        let response = redirect(apply(&https, "www.example.com:8080", &mut req));
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "https://www.example.com/a/b?x=1", "the port went with the scheme");
+
+        let moved = rules(serde_json::json!([{ "redirect": { "host": "new.example.com", "path": "/welcome", "status": 308 } }]));
+        let response = redirect(apply(&moved, "www.example.com:8080", &mut request(Method::POST, "/old?x=1")));
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "http://new.example.com:8080/welcome?x=1");
+
+        let port = rules(serde_json::json!([{ "redirect": { "scheme": "https", "port": 8443 } }]));
+        let response = redirect(apply(&port, "[::1]:8080", &mut request(Method::GET, "/")));
+        assert_eq!(response.headers()[header::LOCATION], "https://[::1]:8443/");
+    }
+
+    #[test]
+    fn rules_apply_in_order_to_the_rewritten_request() {
+        let route_rules = rules(serde_json::json!([
+            { "responseHeaders": { "set": { "Strict-Transport-Security": "max-age=31536000" } } },
+            { "when": { "pathPrefix": "/api" }, "rewrite": { "stripPrefix": "/api", "addPrefix": "/v2", "host": "api.internal" } },
+            { "when": { "pathPrefix": "/v2/users" }, "requestHeaders": { "set": { "X-Team": "users" }, "remove": ["X-Debug"] } },
+            { "when": { "pathPrefix": "/api" }, "requestHeaders": { "set": { "X-Never": "1" } } },
+            { "when": { "methods": ["OPTIONS"] }, "redirect": { "path": "/preflight" } },
+            { "responseHeaders": { "add": { "Vary": "Origin" }, "remove": ["Server"] } },
+        ]));
+        let mut req = request(Method::GET, "/api/users/7?full=1");
+        req.headers_mut().insert("x-debug", HeaderValue::from_static("1"));
+        let response_rules = forward(apply(&route_rules, "www.example.com", &mut req));
+        assert_eq!(req.uri(), "/v2/users/7?full=1");
+        assert_eq!(req.headers()[header::HOST], "api.internal");
+        assert_eq!(req.headers()["x-team"], "users");
+        assert!(req.headers().get("x-debug").is_none());
+        assert!(req.headers().get("x-never").is_none(), "the path no longer starts with /api");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::SERVER, HeaderValue::from_static("nginx"));
+        headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        response_rules.apply(&mut headers);
+        assert_eq!(headers["strict-transport-security"], "max-age=31536000");
+        assert_eq!(headers.get_all(header::VARY).iter().collect::<Vec<_>>(), ["Accept-Encoding", "Origin"]);
+        assert!(headers.get(header::SERVER).is_none());
+
+        let response = redirect(apply(&route_rules, "www.example.com", &mut request(Method::OPTIONS, "/")));
+        assert_eq!(response.headers()[header::LOCATION], "http://www.example.com/preflight");
+        assert_eq!(response.headers()["strict-transport-security"], "max-age=31536000", "earlier response rules apply to redirects");
+        assert!(response.headers().get(header::VARY).is_none(), "later ones do not");
+
+        let mut req = request(Method::GET, "/api");
+        forward(apply(&route_rules, "www.example.com", &mut req));
+        assert_eq!(req.uri(), "/v2/");
+    }
+}
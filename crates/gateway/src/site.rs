@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use models::{bucket::BucketObject, route::{GatewayRoute, StaticSite}};
+use percent_encoding::percent_decode_str;
+
+use crate::proxy::Gateway;
+
+/// How long object metadata is reused before asking the control plane
+/// again. Missing objects are remembered as well.
+const METADATA_TTL: Duration = Duration::from_secs(5);
+/// Past this many cached lookups the cache starts over.
+const MAX_CACHED: usize = 10_000;
+
+/// Object metadata recently fetched from the control plane, by
+/// `namespace/bucket/key`.
+#[derive(Debug, Default)]
+pub struct ObjectCache {
+    entries: Mutex<HashMap<String, (Instant, Option<BucketObject>)>>,
+}
+
+impl ObjectCache {
+    fn get(&self, key: &str) -> Option<Option<BucketObject>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(key).filter(|(at, _)| at.elapsed() < METADATA_TTL).map(|(_, object)| object.clone())
+    }
+
+    fn insert(&self, key: String, object: Option<BucketObject>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_CACHED {
+            entries.clear();
+        }
+        entries.insert(key, (Instant::now(), object));
+    }
+}
+
+/// Serve a request for a bucket route from the bucket's objects: the path
+/// under the route's prefix names the key, paths ending in `/` get the
+/// index document and directories without the slash a redirect to it.
+/// Unknown paths fall back to the index document for single-page apps, or
+/// the not-found document with 404. Objects carry their hash as `ETag`,
+/// honour conditional and single range requests and stream from the
+/// object store.
+pub(crate) async fn serve(gateway: &Gateway, route: &GatewayRoute, namespace: &str, bucket: &str, request: Request) -> Response {
+    let (parts, _) = request.into_parts();
+    if parts.method != Method::GET && parts.method != Method::HEAD {
+        return (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, "GET, HEAD")], "method not allowed\n").into_response();
+    }
+    let default_site = StaticSite::default();
+    let site = route.site.as_ref().unwrap_or(&default_site);
+    let path = parts.uri.path();
+    let relative = path.strip_prefix(route.path_prefix.trim_end_matches('/')).unwrap_or(path).trim_start_matches('/');
+    let Ok(relative) = percent_decode_str(relative).decode_utf8() else {
+        return (StatusCode::BAD_REQUEST, "invalid path\n").into_response();
+    };
+    let directory = relative.is_empty() || relative.ends_with('/');
+    let key = if directory { format!("{relative}{}", site.index_document) } else { relative.to_string() };
+    if key.split('/').any(|segment| segment.is_empty() || segment == "." || segment == "..") {
+        return (StatusCode::BAD_REQUEST, "invalid path\n").into_response();
+    }
+
+    let lookup = |key: String| lookup(gateway, namespace, bucket, key);
+    match lookup(key).await {
+        Ok(Some(object)) => return object_response(gateway, site, &object, &parts.method, &parts.headers, StatusCode::OK).await,
+        Ok(None) => {}
+        Err(response) => return response,
+    }
+    if !directory {
+        match lookup(format!("{relative}/{}", site.index_document)).await {
+            Ok(Some(_)) => {
+                let location = match parts.uri.query() {
+                    Some(query) => format!("{path}/?{query}"),
+                    None => format!("{path}/"),
+                };
+                return (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, location)]).into_response();
+            }
+            Ok(None) => {}
+            Err(response) => return response,
+        }
+    }
+    let last_segment = relative.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    if site.spa_fallback && !last_segment.contains('.') {
+        match lookup(site.index_document.clone()).await {
+            Ok(Some(object)) => return object_response(gateway, site, &object, &parts.method, &parts.headers, StatusCode::OK).await,
+            Ok(None) => {}
+            Err(response) => return response,
+        }
+    }
+    if let Some(document) = &site.not_found_document {
+        match lookup(document.clone()).await {
+            Ok(Some(object)) => return object_response(gateway, site, &object, &parts.method, &parts.headers, StatusCode::NOT_FOUND).await,
+            Ok(None) => {}
+            Err(response) => return response,
+        }
+    }
+    (StatusCode::NOT_FOUND, "not found\n").into_response()
+}
+
+/// Metadata of the object at `key`, from the cache or the control plane.
+async fn lookup(gateway: &Gateway, namespace: &str, bucket: &str, key: String) -> Result<Option<BucketObject>, Response> {
+    let cache_key = format!("{namespace}/{bucket}/{key}");
+    if let Some(object) = gateway.objects().get(&cache_key) {
+        return Ok(object);
+    }
+    let Some(control_plane) = gateway.control_plane() else {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "no control plane to look up objects\n").into_response());
+    };
+    match control_plane.bucket_object(namespace, bucket, &key).await {
+        Ok(object) => {
+            gateway.objects().insert(cache_key, object.clone());
+            Ok(object)
+        }
+        Err(e) => {
+            tracing::warn!(%namespace, %bucket, %key, error = %e, "failed to look up object");
+            Err((StatusCode::BAD_GATEWAY, "bad gateway\n").into_response())
+        }
+    }
+}
+
+/// Answer with `object`. Only `200 OK` answers are conditional and ranged;
+/// the not-found document is always sent whole.
+async fn object_response(gateway: &Gateway, site: &StaticSite, object: &BucketObject, method: &Method, headers: &HeaderMap, status: StatusCode) -> Response {
+    let etag = format!("\"{}\"", object.sha256);
+    let modified = SystemTime::from(object.created_at);
+    let mut response = Response::new(Body::empty());
+    let response_headers = response.headers_mut();
+    let content_type = object.content_type.clone()
+        .unwrap_or_else(|| mime_guess::from_path(&object.key).first_raw().unwrap_or("application/octet-stream").to_string());
+    if let Ok(value) = HeaderValue::from_str(&content_type) {
+        response_headers.insert(header::CONTENT_TYPE, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&site.cache_control) {
+        response_headers.insert(header::CACHE_CONTROL, value);
+    }
+    if status == StatusCode::OK {
+        response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            response_headers.insert(header::ETAG, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(modified)) {
+            response_headers.insert(header::LAST_MODIFIED, value);
+        }
+        if not_modified(headers, &etag, modified) {
+            response_headers.remove(header::CONTENT_TYPE);
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return response;
+        }
+    }
+
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        // A range of an older version of the object is no use to the client
+        Some(range) if status == StatusCode::OK && if_range_matches(headers, &etag) => byte_range(range, object.size),
+        _ => Ok(None),
+    };
+    let (status, start, len) = match range {
+        Ok(Some((start, end))) => {
+            let content_range = format!("bytes {start}-{end}/{}", object.size);
+            response.headers_mut().insert(header::CONTENT_RANGE, HeaderValue::from_str(&content_range).expect("digits and ASCII"));
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        Ok(None) => (status, 0, object.size),
+        Err(Unsatisfiable) => {
+            let content_range = format!("bytes */{}", object.size);
+            let headers = [(header::CONTENT_RANGE, content_range)];
+            return (StatusCode::RANGE_NOT_SATISFIABLE, headers, "range not satisfiable\n").into_response();
+        }
+    };
+    *response.status_mut() = status;
+    response.headers_mut().insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    if method == Method::HEAD || len == 0 {
+        return response;
+    }
+
+    let Some(store) = gateway.object_store() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "no object store configured\n").into_response();
+    };
+    let range = (status == StatusCode::PARTIAL_CONTENT).then(|| format!("bytes={start}-{}", start + len - 1));
+    match store.get(&object.sha256, range.as_deref()).await {
+        Ok(stored) if stored.status().is_success() => {
+            *response.body_mut() = Body::new(stored.into_body());
+            response
+        }
+        Ok(stored) => {
+            tracing::warn!(sha256 = %object.sha256, status = %stored.status(), "object store refused object");
+            (StatusCode::BAD_GATEWAY, "bad gateway\n").into_response()
+        }
+        Err(e) => {
+            tracing::warn!(sha256 = %object.sha256, error = %e, "failed to read object");
+            (StatusCode::BAD_GATEWAY, "bad gateway\n").into_response()
+        }
+    }
+}
+
+/// Whether the client's cached copy is current (RFC 9110 13.1.2, 13.1.3):
+/// `If-None-Match` naming the object's ETag or, without it,
+/// `If-Modified-Since` no earlier than its creation.
+fn not_modified(headers: &HeaderMap, etag: &str, modified: SystemTime) -> bool {
+    if let Some(tags) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        return tags.split(',').map(|t| t.trim().trim_start_matches("W/")).any(|t| t == "*" || t == etag);
+    }
+    let since = headers.get(header::IF_MODIFIED_SINCE).and_then(|v| v.to_str().ok()).and_then(|v| httpdate::parse_http_date(v).ok());
+    // HTTP dates have whole seconds
+    let modified_secs = modified.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    since.and_then(|s| s.duration_since(SystemTime::UNIX_EPOCH).ok()).is_some_and(|s| modified_secs <= s.as_secs())
+}
+
+/// Whether a range request applies: without `If-Range`, or with one naming
+/// the object's ETag.
+fn if_range_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get(header::IF_RANGE).is_none_or(|v| v.as_bytes() == etag.as_bytes())
+}
+
+#[derive(Debug, PartialEq)]
+struct Unsatisfiable;
+
+/// The first and last byte a `Range` header asks for out of `size`. Ranges
+/// that do not parse, and multiple ranges, are ignored and the whole object
+/// sent.
+fn byte_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, Unsatisfiable> {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else { return Ok(None) };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((first, last)) = spec.split_once('-') else { return Ok(None) };
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        // Suffix: the last `n` bytes
+        let Ok(n) = last.parse::<u64>() else { return Ok(None) };
+        if n == 0 || size == 0 {
+            return Err(Unsatisfiable);
+        }
+        return Ok(Some((size.saturating_sub(n), size - 1)));
+    }
+    let Ok(first) = first.parse::<u64>() else { return Ok(None) };
+    let last = match last {
+        "" => u64::MAX,
+        last => match last.parse::<u64>() {
+            Ok(last) if last >= first => last,
+            _ => return Ok(None),
+        },
+    };
+    if first >= size {
+        return Err(Unsatisfiable);
+    }
+    Ok(Some((first, last.min(size - 1))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(byte_range("bytes=0-9", 100), Ok(Some((0, 9))));
+        assert_eq!(byte_range("bytes=90-", 100), Ok(Some((90, 99))));
+        assert_eq!(byte_range("bytes=90-200", 100), Ok(Some((90, 99))));
+        assert_eq!(byte_range("bytes=-10", 100), Ok(Some((90, 99))));
+        assert_eq!(byte_range("bytes=-200", 100), Ok(Some((0, 99))));
+        assert_eq!(byte_range("bytes=100-", 100), Err(Unsatisfiable));
+        assert_eq!(byte_range("bytes=-0", 100), Err(Unsatisfiable));
+        assert_eq!(byte_range("bytes=0-", 0), Err(Unsatisfiable));
+        // Ignored: the whole object is sent
+        assert_eq!(byte_range("bytes=0-1,5-6", 100), Ok(None));
+        assert_eq!(byte_range("bytes=9-0", 100), Ok(None));
+        assert_eq!(byte_range("lines=1-2", 100), Ok(None));
+    }
+
+    #[test]
+    fn conditional_requests_match_etag_then_date() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let etag = "\"abc\"";
+        let headers = |pairs: &[(header::HeaderName, &str)]| pairs.iter().map(|(n, v)| (n.clone(), HeaderValue::from_str(v).unwrap())).collect::<HeaderMap>();
+        let date = httpdate::fmt_http_date(modified);
+        let earlier = httpdate::fmt_http_date(modified - Duration::from_secs(1));
+
+        assert!(not_modified(&headers(&[(header::IF_NONE_MATCH, "\"x\", W/\"abc\"")]), etag, modified));
+        assert!(!not_modified(&headers(&[(header::IF_NONE_MATCH, "\"x\""), (header::IF_MODIFIED_SINCE, &date)]), etag, modified), "ETags win over dates");
+        assert!(not_modified(&headers(&[(header::IF_MODIFIED_SINCE, &date)]), etag, modified));
+        assert!(!not_modified(&headers(&[(header::IF_MODIFIED_SINCE, &earlier)]), etag, modified));
+        assert!(!not_modified(&HeaderMap::new(), etag, modified));
+        assert!(if_range_matches(&headers(&[(header::IF_RANGE, etag)]), etag));
+        assert!(!if_range_matches(&headers(&[(header::IF_RANGE, &date)]), etag));
+    }
+}
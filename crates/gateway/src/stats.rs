@@ -0,0 +1,85 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use models::route::ReleaseStats;
+
+use crate::{control_plane::ControlPlane, proxy::Gateway};
+
+type Key = (String, String, String);
+
+/// Requests and server errors per release since the last report, which the
+/// control plane turns into error rates to promote or abort canaries by.
+#[derive(Default)]
+pub struct ReleaseCounters {
+    counts: Mutex<HashMap<Key, (u64, u64)>>,
+}
+
+impl ReleaseCounters {
+    pub fn record(&self, namespace: &str, backend: &str, release: &str, server_error: bool) {
+        let mut counts = self.counts.lock().unwrap();
+        let entry = counts.entry((namespace.to_string(), backend.to_string(), release.to_string())).or_default();
+        entry.0 += 1;
+        entry.1 += u64::from(server_error);
+    }
+
+    /// Counts since the last call, resetting them.
+    pub fn take(&self) -> Vec<ReleaseStats> {
+        std::mem::take(&mut *self.counts.lock().unwrap())
+            .into_iter()
+            .map(|((namespace, backend, release), (requests, errors))| ReleaseStats { namespace, backend, release, requests, errors })
+            .collect()
+    }
+
+    /// Add counts back after a failed report, so the next one carries them.
+    pub fn restore(&self, stats: Vec<ReleaseStats>) {
+        let mut counts = self.counts.lock().unwrap();
+        for s in stats {
+            let entry = counts.entry((s.namespace, s.backend, s.release)).or_default();
+            entry.0 += s.requests;
+            entry.1 += s.errors;
+        }
+    }
+}
+
+/// Report per-release counts to the control plane every `interval`.
+/// Reporting needs a cluster-wide admin token.
+pub async fn run_reporter(gateway: Arc<Gateway>, control_plane: ControlPlane, interval: Duration) {
+    if !control_plane.has_token() {
+        tracing::warn!("SPAN_TOKEN not set; release error rates are not reported");
+        return;
+    }
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let stats = gateway.release_counters().take();
+        if stats.is_empty() {
+            continue;
+        }
+        if let Err(e) = control_plane.report_release_stats(&stats).await {
+            tracing::warn!(error = %e, "failed to report release stats");
+            gateway.release_counters().restore(stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_reports_are_carried_over() {
+        let counters = ReleaseCounters::default();
+        counters.record("default", "web", "v12", false);
+        counters.record("default", "web", "v13", true);
+        let stats = counters.take();
+        assert_eq!(stats.len(), 2);
+        assert!(counters.take().is_empty());
+
+        counters.record("default", "web", "v13", false);
+        counters.restore(stats);
+        let mut stats = counters.take();
+        stats.sort_by(|a, b| a.release.cmp(&b.release));
+        assert_eq!((stats[0].requests, stats[0].errors), (1, 0));
+        assert_eq!((stats[1].requests, stats[1].errors), (2, 1));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use common::events::{SpanEvent, GATEWAY_SUBJECTS};
+use futures_util::StreamExt;
+use models::route::GatewaySnapshot;
+use tokio::sync::Notify;
+
+use crate::{config::GatewayConfig, control_plane::ControlPlane, proxy::Gateway, table::RouteTable};
+
+/// Keeps the gateway's routing table and certificates in step with the
+/// control plane.
+pub struct Syncer {
+    gateway: Arc<Gateway>,
+    control_plane: ControlPlane,
+    cfg: GatewayConfig,
+}
+
+impl Syncer {
+    pub fn new(gateway: Arc<Gateway>, cfg: GatewayConfig) -> Self {
+        Self { gateway, control_plane: ControlPlane::new(&cfg), cfg }
+    }
+
+    /// Load every route and its endpoints from the control plane.
+    pub async fn fetch(&self) -> anyhow::Result<GatewaySnapshot> {
+        self.control_plane.snapshot().await
+    }
+
+    /// Fetch the table and swap it in unless it is the version already
+    /// served, then do the same for certificates. Returns the version now
+    /// being served.
+    pub async fn reload(&self) -> anyhow::Result<i64> {
+        let snapshot = self.fetch().await?;
+        let serving = self.gateway.version();
+        if snapshot.version != serving {
+            tracing::info!(version = snapshot.version, previous = serving, routes = snapshot.routes.len(), "routing table updated");
+            self.gateway.replace(RouteTable::new(snapshot.version, snapshot.routes));
+        }
+        // Certificates carry private keys and are only handed out with a token
+        let certs = self.gateway.certificates();
+        if self.control_plane.has_token() && certs.version() != snapshot.version {
+            // A failure leaves the version behind, so the next sync retries
+            match self.control_plane.certificates().await {
+                Ok(certificates) => {
+                    tracing::info!(version = snapshot.version, certificates = certificates.len(), "certificates updated");
+                    certs.replace(snapshot.version, certificates);
+                }
+                Err(e) => tracing::warn!(error = %e, "failed to load certificates from the control plane"),
+            }
+        }
+        Ok(snapshot.version)
+    }
+
+    /// Reload on every change event from NATS and every `sync_interval`.
+    /// A failed load keeps the table being served, so a control plane
+    /// outage does not take traffic down.
+    pub async fn run(self) {
+        let wake = Arc::new(Notify::new());
+        if let Some(url) = &self.cfg.nats_url {
+            match async_nats::connect(url.as_str()).await {
+                Ok(client) => watch(client, self.gateway.clone(), wake.clone()).await,
+                Err(e) => tracing::warn!(%url, error = %e, "failed to connect to NATS; relying on periodic sync"),
+            }
+        }
+        let mut interval = tokio::time::interval(self.cfg.sync_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = wake.notified() => {}
+            }
+            if let Err(e) = self.reload().await {
+                tracing::warn!(error = %e, url = %self.cfg.control_plane_url, "failed to load routes from the control plane");
+            }
+        }
+    }
+}
+
+/// Subscribe to route, endpoint and certificate changes and wake the sync loop for any
+/// event newer than the table being served.
+async fn watch(client: async_nats::Client, gateway: Arc<Gateway>, wake: Arc<Notify>) {
+    let mut sub = match client.subscribe(GATEWAY_SUBJECTS).await {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to subscribe to gateway changes; relying on periodic sync");
+            return;
+        }
+    };
+    tracing::info!(subject = GATEWAY_SUBJECTS, "watching for route changes");
+    tokio::spawn(async move {
+        while let Some(msg) = sub.next().await {
+            // Unreadable events still trigger a reload; it is cheap and always safe
+            let version = serde_json::from_slice::<SpanEvent>(&msg.payload).ok().and_then(|event| event_version(&event));
+            if version.is_none_or(|v| v > gateway.version()) {
+                wake.notify_one();
+            }
+        }
+    });
+}
+
+fn event_version(event: &SpanEvent) -> Option<i64> {
+    match event {
+        SpanEvent::RouteChanged { version, .. } | SpanEvent::EndpointsChanged { version, .. } | SpanEvent::CertificateIssued { version, .. } => Some(*version),
+        _ => None,
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use axum::http::{header, HeaderMap};
+use ipnet::IpNet;
+use models::route::{parse_network, Endpoint, GatewayRoute, HashOn, L4Protocol, LbPolicy, TrafficSplit, ANY_HOST};
+
+use crate::{
+    auth::EdgeAuth,
+    resilience::{RouteGuard, RouteGuards},
+    upstream::{self, InFlight, Upstream, Upstreams},
+};
+
+/// A route, the state of its backend's endpoints and the round-robin cursor
+/// over them.
+#[derive(Debug)]
+pub struct Backend {
+    pub route: GatewayRoute,
+    /// One per endpoint of the route, in the same order.
+    upstreams: Vec<Arc<Upstream>>,
+    next: AtomicUsize,
+    /// Smooth weighted round-robin state, one per release of the split.
+    release_cursor: Mutex<Vec<i64>>,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    guard: Arc<RouteGuard>,
+    auth: Option<EdgeAuth>,
+}
+
+impl Backend {
+    fn new(route: GatewayRoute) -> Self {
+        let upstreams = route.endpoints.iter().map(|e| Arc::new(Upstream::new(e.address.as_str()))).collect();
+        let releases = route.split.as_ref().map_or(0, |s| s.releases.len());
+        // The control plane validated these; anything unparsable is skipped
+        let networks = |list: Option<&Vec<String>>| list.into_iter().flatten().filter_map(|n| parse_network(n)).collect();
+        let allow = networks(route.ip_filter.as_ref().map(|f| &f.allow));
+        let deny = networks(route.ip_filter.as_ref().map(|f| &f.deny));
+        let auth = EdgeAuth::new(&route);
+        Self { route, upstreams, next: AtomicUsize::new(0), release_cursor: Mutex::new(vec![0; releases]), allow, deny, guard: Arc::default(), auth }
+    }
+
+    /// Whether the route's IP filter lets `ip` through.
+    pub fn admits(&self, ip: IpAddr) -> bool {
+        // IPv4 clients on a dual-stack listener show up as mapped addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        !self.deny.iter().any(|n| n.contains(&ip)) && (self.allow.is_empty() || self.allow.iter().any(|n| n.contains(&ip)))
+    }
+
+    pub fn upstreams(&self) -> &[Arc<Upstream>] {
+        &self.upstreams
+    }
+
+    /// The route's authentication, if it has any.
+    pub fn auth(&self) -> Option<&EdgeAuth> {
+        self.auth.as_ref()
+    }
+
+    /// The route's circuit and retry budget.
+    pub fn guard(&self) -> &RouteGuard {
+        &self.guard
+    }
+
+    /// Pick an endpoint for a request with `headers` by the route's load
+    /// balancing policy, among endpoints the control plane reports healthy
+    /// that are neither failing health checks nor ejected. Routes with a
+    /// traffic split first pick a release by weight. The guard counts the
+    /// request as in flight until dropped.
+    pub fn pick(&self, headers: &HeaderMap) -> Option<(&Endpoint, InFlight)> {
+        let now = Instant::now();
+        let mut candidates: Vec<(&Endpoint, &Arc<Upstream>)> = self.route.endpoints.iter()
+            .zip(&self.upstreams)
+            .filter(|(e, u)| e.healthy && u.available(now))
+            .collect();
+        if let Some(split) = &self.route.split {
+            let release = self.pick_release(split, &candidates, headers)?;
+            candidates.retain(|(e, _)| e.release.as_deref() == Some(release));
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        let key = match (&self.route.load_balancer.policy, &self.route.load_balancer.hash_on) {
+            (LbPolicy::ConsistentHash, Some(hash_on)) => hash_key(hash_on, headers),
+            _ => None,
+        };
+        let (endpoint, upstream) = match (self.route.load_balancer.policy, key) {
+            // Rendezvous hashing: only keys on an endpoint that goes away move
+            (LbPolicy::ConsistentHash, Some(key)) => candidates.into_iter().max_by_key(|(e, _)| fnv1a(&[key.as_bytes(), e.address.as_bytes()]))?,
+            (LbPolicy::LeastConnections, _) => {
+                // Start the scan at the cursor so ties rotate
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                let n = candidates.len();
+                (0..n).map(|i| candidates[(start + i) % n]).min_by_key(|(_, u)| u.in_flight())?
+            }
+            _ => candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()],
+        };
+        Some((endpoint, upstream.begin()))
+    }
+
+    /// The release a request goes to: among releases with weight and an
+    /// available endpoint, the one owning the request's slice of the key
+    /// space for sticky requests, otherwise by smooth weighted round-robin
+    /// so traffic follows the weights exactly.
+    fn pick_release<'a>(&self, split: &'a TrafficSplit, candidates: &[(&Endpoint, &Arc<Upstream>)], headers: &HeaderMap) -> Option<&'a str> {
+        let eligible: Vec<bool> = split.releases.iter()
+            .map(|r| r.weight > 0 && candidates.iter().any(|(e, _)| e.release.as_deref() == Some(r.release.as_str())))
+            .collect();
+        if let Some(key) = split.sticky_on.as_ref().and_then(|on| hash_key(on, headers)) {
+            let total: u64 = split.releases.iter().map(|r| u64::from(r.weight)).sum();
+            if total > 0 {
+                let mut point = fnv1a(&[key.as_bytes()]) % total;
+                for (release, eligible) in split.releases.iter().zip(&eligible) {
+                    if point < u64::from(release.weight) {
+                        // A sticky client whose release is down is spread like any other
+                        if *eligible {
+                            return Some(&release.release);
+                        }
+                        break;
+                    }
+                    point -= u64::from(release.weight);
+                }
+            }
+        }
+        let total: i64 = split.releases.iter().zip(&eligible).filter(|(_, e)| **e).map(|(r, _)| i64::from(r.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut cursor = self.release_cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let mut best: Option<usize> = None;
+        for (i, release) in split.releases.iter().enumerate() {
+            if !eligible[i] {
+                continue;
+            }
+            cursor[i] += i64::from(release.weight);
+            if best.is_none_or(|b| cursor[i] > cursor[b]) {
+                best = Some(i);
+            }
+        }
+        let best = best?;
+        cursor[best] -= total;
+        Some(&split.releases[best].release)
+    }
+
+    /// Count the outcome of a request for passive outlier detection, if the
+    /// route has it.
+    pub fn record(&self, in_flight: &InFlight, server_error: bool) {
+        if let Some(outliers) = &self.route.outlier_detection {
+            upstream::record_outcome(in_flight.upstream(), &self.upstreams, outliers, server_error);
+        }
+    }
+}
+
+/// The value a consistent-hash route hashes requests by.
+fn hash_key(hash_on: &HashOn, headers: &HeaderMap) -> Option<String> {
+    match hash_on {
+        HashOn::Header(name) => headers.get(name.as_str()).and_then(|v| v.to_str().ok()).map(str::to_string),
+        HashOn::Cookie(name) => headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.to_string()),
+    }
+}
+
+/// 64-bit FNV-1a: stable across processes, so every gateway maps a key to
+/// the same endpoint.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in *part {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        // Separate the parts so ("ab", "c") and ("a", "bc") differ
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Routes grouped by host, each group ordered longest prefix first, and L4
+/// routes grouped by listen port.
+#[derive(Debug, Default)]
+pub struct RouteTable {
+    /// Control plane config version the table was built from; 0 until the
+    /// first load.
+    version: i64,
+    hosts: HashMap<String, Vec<Backend>>,
+    /// Exact hosts first, so `*` only takes clients without a match.
+    ports: HashMap<(L4Protocol, u16), Vec<Backend>>,
+    len: usize,
+}
+
+impl RouteTable {
+    pub fn new(version: i64, routes: Vec<GatewayRoute>) -> Self {
+        let len = routes.len();
+        let mut hosts: HashMap<String, Vec<Backend>> = HashMap::new();
+        let mut ports: HashMap<(L4Protocol, u16), Vec<Backend>> = HashMap::new();
+        for route in routes {
+            match &route.l4 {
+                Some(l4) => ports.entry((l4.protocol, l4.listen_port)).or_default().push(Backend::new(route)),
+                None => hosts.entry(route.host.to_ascii_lowercase()).or_default().push(Backend::new(route)),
+            }
+        }
+        for backends in ports.values_mut() {
+            backends.sort_by(|a, b| {
+                (a.route.host == ANY_HOST).cmp(&(b.route.host == ANY_HOST))
+                    .then_with(|| (&a.route.namespace, &a.route.name).cmp(&(&b.route.namespace, &b.route.name)))
+            });
+        }
+        for backends in hosts.values_mut() {
+            // Ties are broken by name so every gateway picks the same route
+            backends.sort_by(|a, b| {
+                b.route.path_prefix.len().cmp(&a.route.path_prefix.len())
+                    .then_with(|| (&a.route.namespace, &a.route.name).cmp(&(&b.route.namespace, &b.route.name)))
+            });
+        }
+        Self { version, hosts, ports, len }
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    pub fn backends(&self) -> impl Iterator<Item = &Backend> {
+        self.hosts.values().chain(self.ports.values()).flatten()
+    }
+
+    /// Share endpoint state through `upstreams`, so it outlives this table,
+    /// and drop state for endpoints no route uses any more.
+    pub fn link(&mut self, upstreams: &Upstreams) {
+        for backend in self.hosts.values_mut().chain(self.ports.values_mut()).flatten() {
+            backend.upstreams = backend.route.endpoints.iter().map(|e| upstreams.get(&e.address)).collect();
+        }
+        let addresses: HashSet<&str> = self.backends().flat_map(|b| &b.route.endpoints).map(|e| e.address.as_str()).collect();
+        let checked: HashSet<&str> = self.backends()
+            .filter(|b| b.route.health_check.is_some())
+            .flat_map(|b| &b.route.endpoints)
+            .map(|e| e.address.as_str())
+            .collect();
+        upstreams.retain(&addresses, &checked);
+    }
+
+    /// Share each route's circuit and retry budget through `guards`, so they
+    /// outlive this table, and drop those of routes that are gone.
+    pub fn link_guards(&mut self, guards: &RouteGuards) {
+        for backend in self.hosts.values_mut().chain(self.ports.values_mut()).flatten() {
+            backend.guard = guards.get(&backend.route.namespace, &backend.route.name);
+        }
+        guards.retain(&self.backends().map(|b| format!("{}/{}", b.route.namespace, b.route.name)).collect());
+    }
+
+    /// Every route, ordered by namespace and name.
+    pub fn routes(&self) -> Vec<&GatewayRoute> {
+        let mut routes: Vec<&GatewayRoute> = self.backends().map(|b| &b.route).collect();
+        routes.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+        routes
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The route for a request: the longest matching prefix among routes for
+    /// `host`, falling back to routes for any host (`*`).
+    pub fn lookup(&self, host: &str, path: &str) -> Option<&Backend> {
+        let host = normalize_host(host);
+        [host.as_str(), ANY_HOST]
+            .iter()
+            .filter_map(|h| self.hosts.get(*h))
+            .find_map(|backends| backends.iter().find(|b| prefix_matches(&b.route.path_prefix, path)))
+    }
+
+    /// Ports L4 routes listen on.
+    pub fn l4_ports(&self) -> impl Iterator<Item = (L4Protocol, u16)> + '_ {
+        self.ports.keys().copied()
+    }
+
+    /// Whether connections to `port` are routed by the SNI of their TLS
+    /// client hello.
+    pub fn passthrough(&self, protocol: L4Protocol, port: u16) -> bool {
+        self.ports.get(&(protocol, port)).is_some_and(|backends| backends.iter().any(|b| b.route.l4.as_ref().is_some_and(|l4| l4.tls_passthrough)))
+    }
+
+    /// The L4 route for a connection to `port`: the one for its SNI, if it
+    /// sent one, or the port's `*` route.
+    pub fn lookup_l4(&self, protocol: L4Protocol, port: u16, sni: Option<&str>) -> Option<&Backend> {
+        let sni = sni.map(normalize_host);
+        self.ports.get(&(protocol, port))?.iter().find(|b| b.route.host == ANY_HOST || sni.as_deref().is_some_and(|h| b.route.host.eq_ignore_ascii_case(h)))
+    }
+}
+
+/// Lowercase a Host header and drop its port and any trailing dot.
+fn normalize_host(host: &str) -> String {
+    let host = match host.strip_prefix('[') {
+        // IPv6 literal: [::1]:8080
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => host.rsplit_once(':').map_or(host, |(h, _)| h),
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Prefixes match whole path segments: `/api` matches `/api` and `/api/v1`
+/// but not `/apix`.
+pub(crate) fn prefix_matches(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use models::route::{IpFilter, L4Route, OutlierDetection, ReleaseWeight};
+
+    use super::*;
+
+    fn route(name: &str, host: &str, prefix: &str, endpoints: &[(&str, bool)]) -> GatewayRoute {
+        GatewayRoute {
+            namespace: "default".into(),
+            name: name.into(),
+            host: host.into(),
+            path_prefix: prefix.into(),
+            backend_ref: name.into(),
+            endpoints: endpoints.iter().map(|(a, h)| Endpoint { address: a.to_string(), healthy: *h, release: None }).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn lookup<'a>(table: &'a RouteTable, host: &str, path: &str) -> Option<&'a str> {
+        table.lookup(host, path).map(|b| b.route.name.as_str())
+    }
+
+    #[test]
+    fn picks_longest_prefix_per_host() {
+        let table = RouteTable::new(1, vec![
+            route("web", "app.example.com", "/", &[]),
+            route("api", "app.example.com", "/api", &[]),
+            route("v2", "app.example.com", "/api/v2/", &[]),
+            route("other", "other.example.com", "/", &[]),
+        ]);
+        assert_eq!(lookup(&table, "app.example.com", "/"), Some("web"));
+        assert_eq!(lookup(&table, "app.example.com", "/api"), Some("api"));
+        assert_eq!(lookup(&table, "app.example.com", "/api/v1/users"), Some("api"));
+        assert_eq!(lookup(&table, "app.example.com", "/api/v2/users"), Some("v2"));
+        assert_eq!(lookup(&table, "app.example.com", "/apix"), Some("web"));
+        assert_eq!(lookup(&table, "other.example.com", "/api"), Some("other"));
+        assert_eq!(lookup(&table, "unknown.example.com", "/"), None);
+    }
+
+    #[test]
+    fn normalizes_hosts_and_falls_back_to_any_host() {
+        let table = RouteTable::new(1, vec![
+            route("api", "app.example.com", "/api", &[]),
+            route("default", "*", "/", &[]),
+        ]);
+        assert_eq!(lookup(&table, "APP.example.com:8080", "/api/x"), Some("api"));
+        assert_eq!(lookup(&table, "app.example.com.", "/api"), Some("api"));
+        assert_eq!(lookup(&table, "app.example.com", "/"), Some("default"));
+        assert_eq!(lookup(&table, "[::1]:80", "/"), Some("default"));
+        assert_eq!(normalize_host("[::1]:80"), "::1");
+    }
+
+    #[test]
+    fn round_robins_over_healthy_endpoints() {
+        let table = RouteTable::new(1, vec![route("web", "a", "/", &[("10.0.0.1:80", true), ("10.0.0.2:80", false), ("10.0.0.3:80", true)])]);
+        let backend = table.lookup("a", "/").unwrap();
+        let picks: Vec<&str> = (0..4).map(|_| backend.pick(&HeaderMap::new()).unwrap().0.address.as_str()).collect();
+        assert_eq!(picks, ["10.0.0.1:80", "10.0.0.3:80", "10.0.0.1:80", "10.0.0.3:80"]);
+
+        let table = RouteTable::new(1, vec![route("web", "a", "/", &[("10.0.0.2:80", false)])]);
+        assert!(table.lookup("a", "/").unwrap().pick(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn least_connections_prefers_idle_endpoints() {
+        let mut web = route("web", "a", "/", &[("10.0.0.1:80", true), ("10.0.0.2:80", true), ("10.0.0.3:80", true)]);
+        web.load_balancer.policy = LbPolicy::LeastConnections;
+        let table = RouteTable::new(1, vec![web]);
+        let backend = table.lookup("a", "/").unwrap();
+
+        let (first, busy) = backend.pick(&HeaderMap::new()).unwrap();
+        let (second, _busy) = backend.pick(&HeaderMap::new()).unwrap();
+        assert_ne!(first.address, second.address);
+        let (third, _) = backend.pick(&HeaderMap::new()).unwrap();
+        assert!(third.address != first.address && third.address != second.address);
+        // The first endpoint's request finishing makes it the idlest again
+        drop(busy);
+        let (next, _) = backend.pick(&HeaderMap::new()).unwrap();
+        assert_eq!(next.address, first.address);
+    }
+
+    #[test]
+    fn consistent_hash_keeps_keys_on_one_endpoint() {
+        let addresses = [("10.0.0.1:80", true), ("10.0.0.2:80", true), ("10.0.0.3:80", true)];
+        let mut by_cookie = route("web", "a", "/", &addresses);
+        by_cookie.load_balancer.policy = LbPolicy::ConsistentHash;
+        by_cookie.load_balancer.hash_on = Some(HashOn::Cookie("session".into()));
+        let table = RouteTable::new(1, vec![by_cookie.clone()]);
+        let backend = table.lookup("a", "/").unwrap();
+        let pick = |cookie: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::COOKIE, cookie.parse().unwrap());
+            backend.pick(&headers).unwrap().0.address.clone()
+        };
+
+        let chosen = pick("theme=dark; session=user-1");
+        for _ in 0..5 {
+            assert_eq!(pick("session=user-1"), chosen);
+        }
+        let spread: HashSet<String> = (0..50).map(|i| pick(&format!("session=user-{i}"))).collect();
+        assert!(spread.len() > 1);
+
+        // Keys on the remaining endpoints stay put when one goes away
+        let kept: Vec<(String, String)> = (0..50).map(|i| format!("session=user-{i}")).map(|c| (pick(&c), c)).filter(|(a, _)| a != "10.0.0.3:80").collect();
+        let mut fewer = by_cookie;
+        fewer.endpoints[2].healthy = false;
+        let table = RouteTable::new(1, vec![fewer]);
+        let backend = table.lookup("a", "/").unwrap();
+        for (address, cookie) in kept {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::COOKIE, cookie.parse().unwrap());
+            assert_eq!(backend.pick(&headers).unwrap().0.address, address);
+        }
+        // Requests without the cookie are still served
+        assert!(backend.pick(&HeaderMap::new()).is_some());
+    }
+
+    fn canary(sticky_on: Option<HashOn>) -> GatewayRoute {
+        let mut web = route("web", "a", "/", &[("10.0.0.1:80", true), ("10.0.0.2:80", true), ("10.0.0.3:80", true)]);
+        for (endpoint, release) in web.endpoints.iter_mut().zip(["v12", "v12", "v13"]) {
+            endpoint.release = Some(release.into());
+        }
+        web.split = Some(TrafficSplit {
+            releases: vec![ReleaseWeight { release: "v12".into(), weight: 90 }, ReleaseWeight { release: "v13".into(), weight: 10 }],
+            sticky_on,
+        });
+        web
+    }
+
+    fn release_of(backend: &Backend, headers: &HeaderMap) -> String {
+        backend.pick(headers).unwrap().0.release.clone().unwrap()
+    }
+
+    #[test]
+    fn splits_traffic_between_releases_by_weight() {
+        let table = RouteTable::new(1, vec![canary(None)]);
+        let backend = table.lookup("a", "/").unwrap();
+        let releases: Vec<String> = (0..100).map(|_| release_of(backend, &HeaderMap::new())).collect();
+        assert_eq!(releases.iter().filter(|r| *r == "v13").count(), 10);
+
+        // With the canary's only endpoint down everything goes to v12
+        let mut down = canary(None);
+        down.endpoints[2].healthy = false;
+        let table = RouteTable::new(1, vec![down]);
+        let backend = table.lookup("a", "/").unwrap();
+        assert!((0..20).all(|_| release_of(backend, &HeaderMap::new()) == "v12"));
+
+        // Endpoints without a release in the split get nothing
+        let mut unlabeled = canary(None);
+        for endpoint in &mut unlabeled.endpoints {
+            endpoint.release = None;
+        }
+        let table = RouteTable::new(1, vec![unlabeled]);
+        assert!(table.lookup("a", "/").unwrap().pick(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn sticky_clients_keep_their_release() {
+        let table = RouteTable::new(1, vec![canary(Some(HashOn::Header("x-user".into())))]);
+        let backend = table.lookup("a", "/").unwrap();
+        let user = |id: usize| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-user", id.to_string().parse().unwrap());
+            headers
+        };
+        let first: Vec<String> = (0..200).map(|i| release_of(backend, &user(i))).collect();
+        let canaries = first.iter().filter(|r| *r == "v13").count();
+        assert!((5..=40).contains(&canaries), "{canaries} of 200 users on the canary");
+        for (i, release) in first.iter().enumerate() {
+            assert_eq!(&release_of(backend, &user(i)), release);
+        }
+
+        // Growing the canary only moves users onto it
+        let mut grown = canary(Some(HashOn::Header("x-user".into())));
+        grown.split.as_mut().unwrap().set_weight("v13", 50).unwrap();
+        let table = RouteTable::new(2, vec![grown]);
+        let backend = table.lookup("a", "/").unwrap();
+        for (i, release) in first.iter().enumerate() {
+            if release == "v13" {
+                assert_eq!(release_of(backend, &user(i)), "v13");
+            }
+        }
+    }
+
+    #[test]
+    fn skips_ejected_endpoints_and_shares_state_across_tables() {
+        let mut web = route("web", "a", "/", &[("10.0.0.1:80", true), ("10.0.0.2:80", true)]);
+        web.outlier_detection = Some(OutlierDetection { consecutive_5xx: 1, ejection_secs: 60, max_ejection_percent: 50 });
+        let upstreams = Upstreams::default();
+        let mut table = RouteTable::new(1, vec![web.clone()]);
+        table.link(&upstreams);
+        let backend = table.lookup("a", "/").unwrap();
+
+        let (failing, in_flight) = backend.pick(&HeaderMap::new()).unwrap();
+        let failing = failing.address.clone();
+        backend.record(&in_flight, true);
+        for _ in 0..4 {
+            assert_ne!(backend.pick(&HeaderMap::new()).unwrap().0.address, failing);
+        }
+
+        // A reloaded table still knows about the ejection
+        let mut table = RouteTable::new(2, vec![web]);
+        table.link(&upstreams);
+        let backend = table.lookup("a", "/").unwrap();
+        for _ in 0..4 {
+            assert_ne!(backend.pick(&HeaderMap::new()).unwrap().0.address, failing);
+        }
+    }
+
+    #[test]
+    fn picks_l4_routes_by_port_and_sni() {
+        let l4 = |name: &str, host: &str, port: u16, passthrough: bool| GatewayRoute {
+            l4: Some(L4Route { protocol: L4Protocol::Tcp, listen_port: port, target_port: None, tls_passthrough: passthrough }),
+            ..route(name, host, "/", &[("10.0.0.1:80", true)])
+        };
+        let table = RouteTable::new(1, vec![
+            l4("fallback", "*", 5432, true),
+            l4("alice", "alice.db.example.com", 5432, true),
+            l4("minecraft", "*", 25565, false),
+            route("web", "*", "/", &[]),
+        ]);
+        let name = |b: Option<&Backend>| b.map(|b| b.route.name.clone());
+        assert_eq!(name(table.lookup_l4(L4Protocol::Tcp, 5432, Some("Alice.DB.example.com"))), Some("alice".into()));
+        assert_eq!(name(table.lookup_l4(L4Protocol::Tcp, 5432, Some("bob.db.example.com"))), Some("fallback".into()));
+        assert_eq!(name(table.lookup_l4(L4Protocol::Tcp, 5432, None)), Some("fallback".into()));
+        assert_eq!(name(table.lookup_l4(L4Protocol::Tcp, 25565, None)), Some("minecraft".into()));
+        assert_eq!(name(table.lookup_l4(L4Protocol::Udp, 25565, None)), None);
+        assert!(table.passthrough(L4Protocol::Tcp, 5432));
+        assert!(!table.passthrough(L4Protocol::Tcp, 25565));
+        assert_eq!(lookup(&table, "db.example.com", "/"), Some("web"), "L4 routes take no HTTP traffic");
+        assert_eq!(table.routes().len(), 4);
+    }
+
+    #[test]
+    fn ip_filters_deny_before_allowing() {
+        let mut web = route("web", "a", "/", &[]);
+        web.ip_filter = Some(IpFilter { allow: vec!["10.0.0.0/8".into(), "2001:db8::/32".into()], deny: vec!["10.1.0.0/16".into()] });
+        let table = RouteTable::new(1, vec![web, route("open", "b", "/", &[])]);
+        let filtered = table.lookup("a", "/").unwrap();
+        let admits = |ip: &str| filtered.admits(ip.parse().unwrap());
+        assert!(admits("10.2.3.4"));
+        assert!(admits("::ffff:10.2.3.4"));
+        assert!(admits("2001:db8::1"));
+        assert!(!admits("10.1.2.3"));
+        assert!(!admits("192.0.2.1"));
+        assert!(table.lookup("b", "/").unwrap().admits("192.0.2.1".parse().unwrap()));
+    }
+}
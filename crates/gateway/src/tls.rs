@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use arc_swap::ArcSwap;
+use axum::{extract::ConnectInfo, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use models::route::GatewayCertificate;
+use rustls::{
+    server::{Acceptor, ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::LazyConfigAcceptor;
+use tower::ServiceExt;
+
+use crate::control_plane::ControlPlane;
+
+/// How long a client gets to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ALPN protocol ACME validation servers offer for TLS-ALPN-01 (RFC 8737).
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Marks requests that arrived over TLS.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsConnection;
+
+/// Certificates served by SNI host name.
+#[derive(Debug)]
+pub struct CertStore {
+    certs: ArcSwap<HashMap<String, Arc<CertifiedKey>>>,
+    /// Control plane config version the certificates were loaded at.
+    version: AtomicI64,
+}
+
+impl Default for CertStore {
+    fn default() -> Self {
+        Self { certs: ArcSwap::from_pointee(HashMap::new()), version: AtomicI64::new(0) }
+    }
+}
+
+impl CertStore {
+    pub fn version(&self) -> i64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    pub fn hosts(&self) -> Vec<String> {
+        let mut hosts: Vec<String> = self.certs.load().keys().cloned().collect();
+        hosts.sort();
+        hosts
+    }
+
+    pub fn get(&self, host: &str) -> Option<Arc<CertifiedKey>> {
+        self.certs.load().get(&host.to_ascii_lowercase()).cloned()
+    }
+
+    /// Serve `certificates` from now on. Ones that fail to parse are logged
+    /// and left out rather than failing the whole set.
+    pub fn replace(&self, version: i64, certificates: Vec<GatewayCertificate>) {
+        let mut certs = HashMap::new();
+        for cert in certificates {
+            match certified_key(&cert.cert_pem, &cert.key_pem) {
+                Ok(key) => {
+                    certs.insert(cert.host.to_ascii_lowercase(), Arc::new(key));
+                }
+                Err(e) => tracing::warn!(host = %cert.host, error = %e, "skipping unusable certificate"),
+            }
+        }
+        self.certs.store(Arc::new(certs));
+        self.version.store(version, Ordering::Relaxed);
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.get(client_hello.server_name()?)
+    }
+}
+
+/// Presents one certificate whatever the client asks for.
+#[derive(Debug)]
+struct ChallengeCert(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for ChallengeCert {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Parse a PEM certificate chain and private key.
+pub fn certified_key(cert_pem: &str, key_pem: &str) -> anyhow::Result<CertifiedKey> {
+    let chain = rustls_pemfile::certs(&mut cert_pem.as_bytes()).collect::<Result<Vec<_>, _>>().context("invalid certificate PEM")?;
+    if chain.is_empty() {
+        return Err(anyhow!("no certificate in PEM"));
+    }
+    let key = rustls_pemfile::private_key(&mut key_pem.as_bytes()).context("invalid key PEM")?.ok_or_else(|| anyhow!("no private key in PEM"))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key).map_err(|e| anyhow!("unsupported private key: {e}"))?;
+    Ok(CertifiedKey::new(chain, key))
+}
+
+fn server_config(resolver: Arc<dyn ResolvesServerCert>, alpn: Vec<Vec<u8>>) -> anyhow::Result<Arc<ServerConfig>> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = alpn;
+    Ok(Arc::new(config))
+}
+
+/// Terminates TLS by SNI and hands requests to the proxy router.
+pub struct TlsTerminator {
+    config: Arc<ServerConfig>,
+    control_plane: Option<ControlPlane>,
+}
+
+impl TlsTerminator {
+    /// `control_plane` answers TLS-ALPN-01 validations; without it they fail.
+    pub fn new(certs: Arc<CertStore>, control_plane: Option<ControlPlane>) -> anyhow::Result<Self> {
+        let config = server_config(certs, vec![b"h2".to_vec(), b"http/1.1".to_vec()])?;
+        Ok(Self { config, control_plane })
+    }
+
+    /// Accept connections on `listener` forever.
+    pub async fn serve(self, listener: TcpListener, app: Router) -> io::Result<()> {
+        let terminator = Arc::new(self);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    // Usually out of file descriptors; back off instead of spinning
+                    tracing::warn!(error = %e, "failed to accept TLS connection");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let terminator = terminator.clone();
+            let app = app.clone();
+            tokio::spawn(async move {
+                if let Err(e) = terminator.handle(stream, peer, app).await {
+                    tracing::debug!(%peer, error = %e, "TLS connection failed");
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: TcpStream, peer: SocketAddr, app: Router) -> anyhow::Result<()> {
+        let handshake = async {
+            let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+            let hello = start.client_hello();
+            let config = if hello.alpn().is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN)) {
+                let host = hello.server_name().ok_or_else(|| anyhow!("TLS-ALPN-01 hello without SNI"))?.to_ascii_lowercase();
+                self.challenge_config(&host).await?
+            } else {
+                self.config.clone()
+            };
+            anyhow::Ok(start.into_stream(config).await?)
+        };
+        let tls = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await.context("TLS handshake timed out")??;
+        // A validation connection ends with the handshake
+        if tls.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN) {
+            return Ok(());
+        }
+
+        let service = hyper::service::service_fn(move |mut request: hyper::Request<hyper::body::Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(peer));
+            request.extensions_mut().insert(TlsConnection);
+            app.clone().oneshot(request)
+        });
+        auto::Builder::new(TokioExecutor::new())
+            .serve_connection_with_upgrades(TokioIo::new(tls), service)
+            .await
+            .map_err(|e| anyhow!("{e}"))
+    }
+
+    /// Config presenting the TLS-ALPN-01 challenge certificate for `host`.
+    async fn challenge_config(&self, host: &str) -> anyhow::Result<Arc<ServerConfig>> {
+        let control_plane = self.control_plane.as_ref().ok_or_else(|| anyhow!("no control plane to fetch challenges from"))?;
+        let (cert_pem, key_pem) = control_plane.tls_alpn01(host).await?.ok_or_else(|| anyhow!("no TLS-ALPN-01 challenge pending for {host}"))?;
+        let key = Arc::new(certified_key(&cert_pem, &key_pem)?);
+        tracing::info!(%host, "answering TLS-ALPN-01 challenge");
+        server_config(Arc::new(ChallengeCert(key)), vec![ACME_TLS_ALPN.to_vec()])
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+};
+use hyper::body::{Frame, SizeHint};
+use models::route::{BackendProtocol, HealthCheck, OutlierDetection, ANY_HOST};
+use serde::Serialize;
+
+use crate::{clients, proxy::Gateway};
+
+/// How often the health checker looks for endpoints that are due a probe.
+const CHECK_TICK: Duration = Duration::from_secs(1);
+
+/// What the gateway knows about one endpoint address. Shared by every route
+/// that sends traffic to it, and kept across table reloads so ejections and
+/// failed checks are not forgotten on each config change.
+#[derive(Debug)]
+pub struct Upstream {
+    address: String,
+    in_flight: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Taken out by failed active health checks.
+    down: bool,
+    passes: u32,
+    failures: u32,
+    last_checked: Option<Instant>,
+    consecutive_5xx: u32,
+    ejected_until: Option<Instant>,
+}
+
+/// Health of one endpoint, as shown on the admin listener.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpstreamStatus {
+    pub address: String,
+    pub in_flight: usize,
+    pub down: bool,
+    pub ejected: bool,
+    pub consecutive_5xx: u32,
+}
+
+impl Upstream {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self { address: address.into(), in_flight: AtomicUsize::new(0), health: Mutex::new(Health::default()) }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn health(&self) -> std::sync::MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the endpoint may get traffic: not failing its health checks
+    /// and not ejected for server errors.
+    pub fn available(&self, now: Instant) -> bool {
+        let health = self.health();
+        !health.down && health.ejected_until.is_none_or(|until| until <= now)
+    }
+
+    fn ejected(&self, now: Instant) -> bool {
+        self.health().ejected_until.is_some_and(|until| until > now)
+    }
+
+    pub fn status(&self) -> UpstreamStatus {
+        let now = Instant::now();
+        let health = self.health();
+        UpstreamStatus {
+            address: self.address.clone(),
+            in_flight: self.in_flight(),
+            down: health.down,
+            ejected: health.ejected_until.is_some_and(|until| until > now),
+            consecutive_5xx: health.consecutive_5xx,
+        }
+    }
+
+    /// Count a request against the endpoint until the guard is dropped.
+    pub fn begin(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// Apply the result of an active health check.
+    pub fn record_check(&self, passed: bool, check: &HealthCheck) {
+        let mut health = self.health();
+        if passed {
+            health.failures = 0;
+            health.passes += 1;
+            if health.down && health.passes >= check.healthy_threshold {
+                health.down = false;
+                tracing::info!(address = %self.address, "upstream passed its health checks; back in rotation");
+            }
+        } else {
+            health.passes = 0;
+            health.failures += 1;
+            if !health.down && health.failures >= check.unhealthy_threshold {
+                health.down = true;
+                tracing::warn!(address = %self.address, failures = health.failures, "upstream failed its health checks; taken out of rotation");
+            }
+        }
+    }
+
+    /// Forget active health check results, once no route checks the endpoint.
+    fn clear_checks(&self) {
+        let mut health = self.health();
+        health.down = false;
+        health.passes = 0;
+        health.failures = 0;
+        health.last_checked = None;
+    }
+
+    /// Claim the next health check if `interval` has passed since the last.
+    fn check_due(&self, interval: Duration, now: Instant) -> bool {
+        let mut health = self.health();
+        if health.last_checked.is_some_and(|last| now.duration_since(last) < interval) {
+            return false;
+        }
+        health.last_checked = Some(now);
+        true
+    }
+
+    /// Count a server error (or failed request) towards ejection. Returns
+    /// whether the endpoint reached `consecutive_5xx` errors in a row; the
+    /// caller decides if it may be ejected.
+    fn record_failure(&self, outliers: &OutlierDetection) -> bool {
+        let mut health = self.health();
+        health.consecutive_5xx += 1;
+        health.consecutive_5xx >= outliers.consecutive_5xx
+    }
+
+    fn record_success(&self) {
+        self.health().consecutive_5xx = 0;
+    }
+
+    fn eject(&self, outliers: &OutlierDetection, now: Instant) {
+        let mut health = self.health();
+        health.consecutive_5xx = 0;
+        health.ejected_until = Some(now + Duration::from_secs(outliers.ejection_secs));
+        tracing::warn!(address = %self.address, secs = outliers.ejection_secs, "upstream ejected after repeated server errors");
+    }
+}
+
+/// Passive outlier detection for one backend: count the outcome of a
+/// request to `upstream`, ejecting it once it has answered too many server
+/// errors in a row unless `max_ejection_percent` of `endpoints` already are.
+pub fn record_outcome(upstream: &Upstream, endpoints: &[Arc<Upstream>], outliers: &OutlierDetection, server_error: bool) {
+    if !server_error {
+        upstream.record_success();
+        return;
+    }
+    if !upstream.record_failure(outliers) {
+        return;
+    }
+    let now = Instant::now();
+    let ejected = endpoints.iter().filter(|e| e.ejected(now)).count();
+    let allowed = endpoints.len() * usize::from(outliers.max_ejection_percent) / 100;
+    if ejected < allowed {
+        upstream.eject(outliers, now);
+    } else {
+        tracing::warn!(address = %upstream.address, ejected, "upstream keeps failing but too many endpoints are ejected already");
+    }
+}
+
+/// Keeps a request counted against its endpoint for least-connections.
+#[derive(Debug)]
+pub struct InFlight(Arc<Upstream>);
+
+impl InFlight {
+    pub fn upstream(&self) -> &Arc<Upstream> {
+        &self.0
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A response body that holds its request's [`InFlight`] guard until the
+/// body has been sent, so streaming responses count as connections too.
+pub struct Tracked<B> {
+    inner: B,
+    _in_flight: InFlight,
+}
+
+impl<B> Tracked<B> {
+    pub fn new(inner: B, in_flight: InFlight) -> Self {
+        Self { inner, _in_flight: in_flight }
+    }
+}
+
+impl<B: hyper::body::Body + Unpin> hyper::body::Body for Tracked<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Every endpoint the gateway has routed to, by address.
+#[derive(Debug, Default)]
+pub struct Upstreams {
+    by_address: Mutex<HashMap<String, Arc<Upstream>>>,
+}
+
+impl Upstreams {
+    /// The shared state for `address`, created on first use.
+    pub fn get(&self, address: &str) -> Arc<Upstream> {
+        let mut by_address = self.by_address.lock().unwrap_or_else(|e| e.into_inner());
+        by_address.entry(address.to_string()).or_insert_with(|| Arc::new(Upstream::new(address))).clone()
+    }
+
+    /// Drop endpoints no route uses any more, and clear health check
+    /// results for ones no longer checked.
+    pub fn retain(&self, addresses: &HashSet<&str>, checked: &HashSet<&str>) {
+        let mut by_address = self.by_address.lock().unwrap_or_else(|e| e.into_inner());
+        by_address.retain(|address, _| addresses.contains(address.as_str()));
+        for (address, upstream) in by_address.iter() {
+            if !checked.contains(address.as_str()) {
+                upstream.clear_checks();
+            }
+        }
+    }
+
+    /// Every endpoint, ordered by address.
+    pub fn statuses(&self) -> Vec<UpstreamStatus> {
+        let by_address = self.by_address.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statuses: Vec<UpstreamStatus> = by_address.values().map(|u| u.status()).collect();
+        statuses.sort_by(|a, b| a.address.cmp(&b.address));
+        statuses
+    }
+}
+
+/// Probe endpoints of routes with a health check, forever. An endpoint
+/// shared by several checked routes is probed per the first route (by
+/// namespace and name) that checks it.
+pub async fn run_health_checks(gateway: Arc<Gateway>) {
+    let mut tick = tokio::time::interval(CHECK_TICK);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        let table = gateway.table();
+        let now = Instant::now();
+        let mut seen = HashSet::new();
+        for backend in table.backends() {
+            let Some(check) = &backend.route.health_check else { continue };
+            for upstream in backend.upstreams() {
+                if !seen.insert(upstream.address().to_string()) || !upstream.check_due(Duration::from_secs(check.interval_secs), now) {
+                    continue;
+                }
+                let host = if backend.route.host == ANY_HOST { upstream.address().to_string() } else { backend.route.host.clone() };
+                let (gateway, upstream, check, protocol) = (gateway.clone(), upstream.clone(), check.clone(), backend.route.protocol);
+                tokio::spawn(async move {
+                    let passed = probe(&gateway, protocol, upstream.address(), &host, &check).await;
+                    upstream.record_check(passed, &check);
+                });
+            }
+        }
+    }
+}
+
+/// GET the health check path in the backend's protocol; any 2xx or 3xx
+/// within the timeout passes.
+async fn probe(gateway: &Gateway, protocol: BackendProtocol, address: &str, host: &str, check: &HealthCheck) -> bool {
+    let request = Request::get(format!("{}://{address}{}", clients::scheme(protocol), check.path))
+        .version(clients::version(protocol))
+        .header(header::HOST, host)
+        .header(header::USER_AGENT, "span-gateway-health-check")
+        .body(Body::empty());
+    let Ok(request) = request else {
+        return false;
+    };
+    match tokio::time::timeout(Duration::from_millis(check.timeout_ms), gateway.clients().request(protocol, request)).await {
+        Ok(Ok(response)) => {
+            let status = response.status();
+            if !(status.is_success() || status.is_redirection()) {
+                tracing::debug!(%address, %status, "health check failed");
+            }
+            status.is_success() || status.is_redirection()
+        }
+        Ok(Err(e)) => {
+            tracing::debug!(%address, error = %e, "health check failed");
+            false
+        }
+        Err(_) => {
+            tracing::debug!(%address, "health check timed out");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check() -> HealthCheck {
+        HealthCheck { path: "/healthz".into(), interval_secs: 1, timeout_ms: 100, unhealthy_threshold: 2, healthy_threshold: 2 }
+    }
+
+    #[test]
+    fn health_checks_need_consecutive_results_to_flip() {
+        let upstream = Upstream::new("10.0.0.1:80");
+        let now = Instant::now();
+        upstream.record_check(false, &check());
+        assert!(upstream.available(now));
+        upstream.record_check(true, &check());
+        upstream.record_check(false, &check());
+        assert!(upstream.available(now), "failures must be consecutive");
+        upstream.record_check(false, &check());
+        assert!(!upstream.available(now));
+
+        upstream.record_check(true, &check());
+        assert!(!upstream.available(now));
+        upstream.record_check(true, &check());
+        assert!(upstream.available(now));
+    }
+
+    #[test]
+    fn ejects_after_consecutive_server_errors_within_the_limit() {
+        let endpoints: Vec<Arc<Upstream>> = (1..=4).map(|i| Arc::new(Upstream::new(format!("10.0.0.{i}:80")))).collect();
+        let outliers = OutlierDetection { consecutive_5xx: 2, ejection_secs: 30, max_ejection_percent: 50 };
+        let now = Instant::now();
+
+        record_outcome(&endpoints[0], &endpoints, &outliers, true);
+        record_outcome(&endpoints[0], &endpoints, &outliers, false);
+        record_outcome(&endpoints[0], &endpoints, &outliers, true);
+        assert!(endpoints[0].available(now), "a success resets the count");
+        record_outcome(&endpoints[0], &endpoints, &outliers, true);
+        assert!(!endpoints[0].available(now));
+        assert!(endpoints[0].status().ejected);
+
+        for _ in 0..2 {
+            record_outcome(&endpoints[1], &endpoints, &outliers, true);
+        }
+        assert!(!endpoints[1].available(now));
+        // Half the endpoints are out already
+        for _ in 0..2 {
+            record_outcome(&endpoints[2], &endpoints, &outliers, true);
+        }
+        assert!(endpoints[2].available(now));
+        // Ejections expire
+        assert!(endpoints[0].available(now + Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn keeps_state_only_for_routed_addresses() {
+        let upstreams = Upstreams::default();
+        let a = upstreams.get("10.0.0.1:80");
+        assert!(Arc::ptr_eq(&a, &upstreams.get("10.0.0.1:80")));
+        upstreams.get("10.0.0.2:80");
+        for _ in 0..2 {
+            a.record_check(false, &check());
+        }
+        assert!(a.status().down);
+
+        upstreams.retain(&HashSet::from(["10.0.0.1:80"]), &HashSet::new());
+        let statuses = upstreams.statuses();
+        assert_eq!(statuses.len(), 1);
+        assert!(!statuses[0].down, "results of checks no route asks for are cleared");
+    }
+}
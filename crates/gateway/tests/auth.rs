@@ -0,0 +1,161 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::Request, http::StatusCode, response::IntoResponse, routing::{any, get}, Json, Router};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use gateway::{proxy::{self, Gateway}, table::RouteTable};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use models::route::{BasicAuth, BasicCredential, Endpoint, ForwardAuth, GatewayRoute, JwtAuth, RouteAuth};
+
+/// A backend that echoes the identity headers it received.
+async fn backend() -> SocketAddr {
+    let app = Router::new().fallback(any(|req: Request| async move {
+        let header = |n: &str| req.headers().get(n).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+        format!("user={} email={} authorization={}", header("x-forwarded-user"), header("x-email"), header("authorization"))
+    }));
+    serve(app).await
+}
+
+async fn serve(app: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+async fn serve_gateway(gateway: Arc<Gateway>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = proxy::router(gateway).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn route(name: &str, backend: SocketAddr, auth: RouteAuth) -> GatewayRoute {
+    GatewayRoute {
+        namespace: "tools".into(),
+        name: name.into(),
+        host: format!("{name}.example.com"),
+        path_prefix: "/".into(),
+        backend_ref: name.into(),
+        endpoints: vec![Endpoint { address: backend.to_string(), healthy: true, release: None }],
+        auth: Some(auth),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn basic_auth_checks_users_from_the_secret() {
+    let app = backend().await;
+    let mut grafana = route("grafana", app, RouteAuth::Basic(BasicAuth { secret_ref: "grafana-users".into(), realm: "Grafana".into() }));
+    grafana.basic_auth_users = vec![BasicCredential { username: "bob".into(), password_hash: crypto::password::hash("hunter2").unwrap() }];
+    // Missing secret, or a gateway without an admin token
+    let locked = route("locked", app, RouteAuth::Basic(BasicAuth { secret_ref: "missing".into(), realm: "Restricted".into() }));
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![grafana, locked]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+    let get = |host: &'static str| http.get(format!("http://{addr}/")).header("host", host).header("x-forwarded-user", "admin");
+
+    let resp = get("grafana.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(resp.headers()["www-authenticate"], "Basic realm=\"Grafana\", charset=\"UTF-8\"");
+    let resp = get("grafana.example.com").basic_auth("bob", Some("wrong")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = get("grafana.example.com").basic_auth("bob", Some("hunter2")).send().await.unwrap();
+    assert_eq!(resp.text().await.unwrap(), "user=bob email=- authorization=-");
+
+    let resp = get("locked.example.com").basic_auth("bob", Some("hunter2")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "routes without users let nobody in");
+}
+
+#[tokio::test]
+async fn jwt_auth_verifies_tokens_against_the_jwks() {
+    let key = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+    let point = key.public_key_raw();
+    let jwks = serde_json::json!({ "keys": [{
+        "kty": "EC", "crv": "P-256", "kid": "k1", "alg": "ES256", "use": "sig",
+        "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+        "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+    }] });
+    let idp = serve(Router::new().route("/jwks", get(move || async move { Json(jwks) }))).await;
+    let signing = EncodingKey::from_ec_pem(key.serialize_pem().as_bytes()).unwrap();
+    let token = |kid: &str, claims: serde_json::Value| {
+        let header = Header { kid: Some(kid.into()), ..Header::new(Algorithm::ES256) };
+        jsonwebtoken::encode(&header, &claims, &signing).unwrap()
+    };
+    let exp = chrono::Utc::now().timestamp() + 600;
+
+    let app = backend().await;
+    let policy = JwtAuth {
+        jwks_url: format!("http://{idp}/jwks"),
+        issuer: Some("https://idp.example.com".into()),
+        audiences: vec!["grafana".into()],
+        cookie: Some("session".into()),
+        forward_claims: [("email".to_string(), "x-email".to_string())].into(),
+    };
+    // An issuer that takes its time answering
+    let slow_idp = serve(Router::new().route("/jwks", get(|| async {
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        Json(serde_json::json!({ "keys": [] }))
+    }))).await;
+    let slow = JwtAuth { jwks_url: format!("http://{slow_idp}/jwks"), ..policy.clone() };
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![route("grafana", app, RouteAuth::Jwt(policy)), route("slow", app, RouteAuth::Jwt(slow))]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+    let get = || http.get(format!("http://{addr}/")).header("host", "grafana.example.com");
+
+    let valid = token("k1", serde_json::json!({ "sub": "ann", "email": "ann@example.com", "iss": "https://idp.example.com", "aud": "grafana", "exp": exp }));
+    let resp = get().bearer_auth(&valid).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), format!("user=- email=ann@example.com authorization=Bearer {valid}"));
+    let resp = get().header("cookie", format!("session={valid}")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = get().send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(resp.headers()["www-authenticate"], "Bearer");
+    let wrong_audience = token("k1", serde_json::json!({ "iss": "https://idp.example.com", "aud": "other", "exp": exp }));
+    let expired = token("k1", serde_json::json!({ "iss": "https://idp.example.com", "aud": "grafana", "exp": exp - 3600 }));
+    let unknown_key = token("k2", serde_json::json!({ "iss": "https://idp.example.com", "aud": "grafana", "exp": exp }));
+    for bad in [wrong_audience, expired, unknown_key, "not-a-jwt".to_string()] {
+        assert_eq!(get().bearer_auth(&bad).send().await.unwrap().status(), StatusCode::UNAUTHORIZED, "{bad}");
+    }
+
+    // Waiting on one issuer's keys does not hold up other routes
+    let pending = tokio::spawn(http.get(format!("http://{addr}/")).header("host", "slow.example.com").bearer_auth(&valid).send());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let started = std::time::Instant::now();
+    assert_eq!(get().bearer_auth(&valid).send().await.unwrap().status(), StatusCode::OK);
+    assert!(started.elapsed() < Duration::from_secs(1), "blocked behind the slow issuer for {:?}", started.elapsed());
+    assert_eq!(pending.await.unwrap().unwrap().status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn forward_auth_asks_the_auth_app() {
+    let auth_app = serve(Router::new().route("/verify", get(|req: Request| async move {
+        let header = |n: &str| req.headers().get(n).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+        if header("cookie") == "token=ok" {
+            assert_eq!((header("x-forwarded-method"), header("x-forwarded-uri"), header("x-forwarded-host")), ("GET".into(), "/dash?x=1".into(), "grafana.example.com".into()));
+            ([("x-forwarded-user", "carol")], "ok").into_response()
+        } else {
+            (StatusCode::FOUND, [("location", "https://login.example.com/")], "log in").into_response()
+        }
+    }))).await;
+    let app = backend().await;
+    let policy = ForwardAuth { backend_ref: "oauth2-proxy".into(), path: "/verify".into(), response_headers: vec!["X-Forwarded-User".into()] };
+    let mut grafana = route("grafana", app, RouteAuth::ForwardAuth(policy));
+    grafana.auth_endpoints = vec![Endpoint { address: auth_app.to_string(), healthy: true, release: None }];
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![grafana]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    let get = || http.get(format!("http://{addr}/dash?x=1")).header("host", "grafana.example.com").header("x-forwarded-user", "admin");
+
+    let resp = get().send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert_eq!(resp.headers()["location"], "https://login.example.com/");
+    let resp = get().header("cookie", "token=ok").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "user=carol email=- authorization=-");
+}
@@ -0,0 +1,161 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use gateway::{l4, proxy::Gateway, table::RouteTable};
+use models::route::{Endpoint, GatewayRoute, L4PortRange, L4Protocol, L4Route};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+/// A port nothing listens on right now.
+async fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port()
+}
+
+fn any_port() -> L4PortRange {
+    "1024-65535".parse().unwrap()
+}
+
+/// A TCP backend answering each connection's first read with its name and
+/// what it read.
+async fn tcp_backend(name: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let mut answer = format!("{name}:").into_bytes();
+                answer.extend_from_slice(&buf[..n]);
+                let _ = stream.write_all(&answer).await;
+            });
+        }
+    });
+    addr
+}
+
+fn route(name: &str, host: &str, l4: L4Route, endpoints: &[SocketAddr]) -> GatewayRoute {
+    GatewayRoute {
+        namespace: "default".into(),
+        name: name.into(),
+        host: host.into(),
+        path_prefix: "/".into(),
+        backend_ref: name.into(),
+        endpoints: endpoints.iter().map(|a| Endpoint { address: a.to_string(), healthy: true, release: None }).collect(),
+        l4: Some(l4),
+        ..Default::default()
+    }
+}
+
+/// Send `data` to the gateway's `port` and read back the answer, waiting
+/// for the listener to open.
+async fn exchange(port: u16, data: &[u8]) -> Vec<u8> {
+    for _ in 0..50 {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            stream.write_all(data).await.unwrap();
+            let mut answer = Vec::new();
+            stream.read_to_end(&mut answer).await.unwrap();
+            return answer;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("the gateway never listened on {port}");
+}
+
+fn client_hello(server_name: &str) -> Vec<u8> {
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(RootCertStore::empty())
+        .with_no_client_auth();
+    let mut connection = ClientConnection::new(Arc::new(config), ServerName::try_from(server_name.to_string()).unwrap()).unwrap();
+    let mut hello = Vec::new();
+    connection.write_tls(&mut hello).unwrap();
+    hello
+}
+
+#[tokio::test]
+async fn forwards_tcp_ports_and_follows_the_table() {
+    let backend = tcp_backend("minecraft").await;
+    let port = free_port().await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let l4 = L4Route { protocol: L4Protocol::Tcp, listen_port: port, target_port: None, tls_passthrough: false };
+    gateway.replace(RouteTable::new(1, vec![route("minecraft", "*", l4, &[backend])]));
+    tokio::spawn(l4::run(gateway.clone(), "127.0.0.1".parse().unwrap(), any_port(), vec![]));
+
+    assert_eq!(exchange(port, b"hello").await, b"minecraft:hello");
+
+    // Removing the route closes the port
+    gateway.replace(RouteTable::new(2, vec![]));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+}
+
+#[tokio::test]
+async fn only_opens_ports_in_the_allowed_range() {
+    let backend = tcp_backend("ssh").await;
+    let port = free_port().await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let l4 = L4Route { protocol: L4Protocol::Tcp, listen_port: port, target_port: None, tls_passthrough: false };
+    gateway.replace(RouteTable::new(1, vec![route("ssh", "*", l4, &[backend])]));
+    let below = L4PortRange { start: 1024, end: port - 1 };
+    tokio::spawn(l4::run(gateway, "127.0.0.1".parse().unwrap(), below, vec![]));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+}
+
+#[tokio::test]
+async fn passes_tls_through_by_server_name() {
+    let (db, cache) = (tcp_backend("db").await, tcp_backend("cache").await);
+    let port = free_port().await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let l4 = L4Route { protocol: L4Protocol::Tcp, listen_port: port, target_port: None, tls_passthrough: true };
+    gateway.replace(RouteTable::new(
+        1,
+        vec![route("db", "db.example.com", l4.clone(), &[db]), route("cache", "*", l4, &[cache])],
+    ));
+    tokio::spawn(l4::run(gateway, "127.0.0.1".parse().unwrap(), any_port(), vec![]));
+
+    // The backend sees the client hello untouched
+    let hello = client_hello("db.example.com");
+    assert_eq!(exchange(port, &hello).await, [b"db:".as_slice(), &hello].concat());
+    let hello = client_hello("redis.example.com");
+    assert_eq!(exchange(port, &hello).await, [b"cache:".as_slice(), &hello].concat(), "other names go to the wildcard route");
+}
+
+#[tokio::test]
+async fn relays_udp_datagrams_both_ways() {
+    let backend = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+        loop {
+            let (n, peer) = backend.recv_from(&mut buf).await.unwrap();
+            let answer = [b"dns:".as_slice(), &buf[..n]].concat();
+            backend.send_to(&answer, peer).await.unwrap();
+        }
+    });
+    let port = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let l4 = L4Route { protocol: L4Protocol::Udp, listen_port: port, target_port: None, tls_passthrough: false };
+    gateway.replace(RouteTable::new(1, vec![route("dns", "*", l4, &[backend_addr])]));
+    tokio::spawn(l4::run(gateway, "127.0.0.1".parse().unwrap(), any_port(), vec![]));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(("127.0.0.1", port)).await.unwrap();
+    let mut buf = [0; 1500];
+    for query in [b"one".as_slice(), b"two"] {
+        // Datagrams sent before the listener opens are lost; send again
+        let n = loop {
+            client.send(query).await.unwrap();
+            if let Ok(Ok(n)) = tokio::time::timeout(Duration::from_millis(100), client.recv(&mut buf)).await {
+                break n;
+            }
+        };
+        assert_eq!(&buf[..n], [b"dns:".as_slice(), query].concat());
+    }
+}
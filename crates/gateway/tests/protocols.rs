@@ -0,0 +1,294 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{ws::{Message as WsMessage, WebSocketUpgrade}, Request},
+    routing::{any, get},
+    Router,
+};
+use futures_util::{SinkExt, StreamExt};
+use gateway::{proxy::{self, Gateway}, table::RouteTable};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use models::route::{BackendProtocol, Endpoint, GatewayRoute};
+use tonic::{
+    codec::ProstCodec,
+    codegen::{http, BoxFuture, BoxStream, Context, Poll, Service},
+    server::{Grpc, NamedService, ServerStreamingService, UnaryService},
+    transport::{Body, Endpoint as GrpcEndpoint},
+    Code, Status,
+};
+use tower::ServiceExt;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct EchoRequest {
+    #[prost(string, tag = "1")]
+    message: String,
+    /// How many replies the streaming method sends before failing.
+    #[prost(uint32, tag = "2")]
+    repeat: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct EchoReply {
+    #[prost(string, tag = "1")]
+    message: String,
+}
+
+/// `test.Echo`: `Unary` echoes the message and the caller's metadata,
+/// `Stream` echoes it `repeat` times and then fails with a status that can
+/// only arrive in trailers.
+#[derive(Clone)]
+struct Echo;
+
+impl NamedService for Echo {
+    const NAME: &'static str = "test.Echo";
+}
+
+struct Unary;
+
+impl UnaryService<EchoRequest> for Unary {
+    type Response = EchoReply;
+    type Future = BoxFuture<tonic::Response<EchoReply>, Status>;
+
+    fn call(&mut self, request: tonic::Request<EchoRequest>) -> Self::Future {
+        Box::pin(async move {
+            let who = request.metadata().get("x-user").and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+            Ok(tonic::Response::new(EchoReply { message: format!("{} from {who}", request.get_ref().message) }))
+        })
+    }
+}
+
+struct Streaming;
+
+impl ServerStreamingService<EchoRequest> for Streaming {
+    type Response = EchoReply;
+    type ResponseStream = BoxStream<EchoReply>;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: tonic::Request<EchoRequest>) -> Self::Future {
+        let EchoRequest { message, repeat } = request.into_inner();
+        let replies = (0..repeat).map(move |i| Ok(EchoReply { message: format!("{message} {i}") }));
+        let end = std::iter::once(Err(Status::aborted("stream over")));
+        // Yield between replies: tonic drops replies still buffered with a
+        // failing status, and a ready stream would be buffered whole
+        let stream = futures_util::stream::iter(replies.chain(end)).then(|reply| async move {
+            tokio::task::yield_now().await;
+            reply
+        });
+        Box::pin(async move { Ok(tonic::Response::new(Box::pin(stream) as BoxStream<EchoReply>)) })
+    }
+}
+
+impl Service<http::Request<Body>> for Echo {
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::<EchoReply, EchoRequest>::default());
+            Ok(match request.uri().path() {
+                "/test.Echo/Unary" => grpc.unary(Unary, request).await,
+                "/test.Echo/Stream" => grpc.server_streaming(Streaming, request).await,
+                _ => Status::unimplemented("no such method").to_http(),
+            })
+        })
+    }
+}
+
+async fn grpc_backend() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let incoming = futures_util::stream::unfold(listener, |listener| async move {
+        Some((listener.accept().await.map(|(stream, _)| stream), listener))
+    });
+    tokio::spawn(tonic::transport::Server::builder().add_service(Echo).serve_with_incoming(incoming));
+    addr
+}
+
+/// A backend that reports the HTTP version requests reach it with.
+fn version_app() -> Router {
+    Router::new().fallback(any(|req: Request| async move { format!("{:?}", req.version()) }))
+}
+
+async fn ws_backend() -> SocketAddr {
+    let app = Router::new().route("/ws", get(|ws: WebSocketUpgrade| async move {
+        ws.on_upgrade(|mut socket| async move {
+            while let Some(Ok(WsMessage::Text(text))) = socket.recv().await {
+                if socket.send(WsMessage::Text(format!("echo: {text}"))).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }));
+    serve_plain(app).await
+}
+
+async fn serve_plain(app: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+/// Serve `app` over TLS offering only h2, with a certificate for 127.0.0.1.
+/// Returns the address and the certificate to trust.
+async fn h2_tls_backend(app: Router) -> (SocketAddr, String) {
+    let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".into()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let key = gateway::tls::certified_key(&cert_pem, &cert.serialize_private_key_pem()).unwrap();
+    let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(key.cert.clone(), rustls_pemfile::private_key(&mut cert.serialize_private_key_pem().as_bytes()).unwrap().unwrap())
+        .unwrap();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (acceptor, app) = (acceptor.clone(), app.clone());
+            tokio::spawn(async move {
+                let Ok(tls) = acceptor.accept(stream).await else { return };
+                let service = hyper::service::service_fn(move |request: hyper::Request<hyper::body::Incoming>| app.clone().oneshot(request));
+                let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new()).serve_connection(TokioIo::new(tls), service).await;
+            });
+        }
+    });
+    (addr, cert_pem)
+}
+
+async fn serve_gateway(gateway: Arc<Gateway>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = proxy::router(gateway).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn route(name: &str, host: &str, address: SocketAddr, protocol: BackendProtocol) -> GatewayRoute {
+    GatewayRoute {
+        namespace: "default".into(),
+        name: name.into(),
+        host: host.into(),
+        path_prefix: "/".into(),
+        backend_ref: name.into(),
+        endpoints: vec![Endpoint { address: address.to_string(), healthy: true, release: None }],
+        protocol,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn tunnels_websocket_upgrades() {
+    let backend = ws_backend().await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![route("chat", "chat.example.com", backend, BackendProtocol::Http1)]));
+    let addr = serve_gateway(gateway.clone()).await;
+
+    let request = tokio_tungstenite::tungstenite::handshake::client::Request::builder()
+        .uri(format!("ws://{addr}/ws"))
+        .header("host", "chat.example.com")
+        .header("connection", "Upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", tokio_tungstenite::tungstenite::handshake::client::generate_key())
+        .body(())
+        .unwrap();
+    let (mut socket, response) = tokio_tungstenite::connect_async(request).await.expect("upgrade through the gateway");
+    assert_eq!(response.status(), 101);
+    for text in ["hello", "again"] {
+        socket.send(tokio_tungstenite::tungstenite::Message::Text(text.into())).await.unwrap();
+        let reply = socket.next().await.unwrap().unwrap();
+        assert_eq!(reply.into_text().unwrap(), format!("echo: {text}"));
+    }
+    // The tunnel counts as a request in flight until it closes
+    assert_eq!(gateway.upstreams().statuses()[0].in_flight, 1);
+    socket.close(None).await.unwrap();
+    drop(socket);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(gateway.upstreams().statuses()[0].in_flight, 0);
+}
+
+#[tokio::test]
+async fn proxies_grpc_with_trailers_end_to_end() {
+    let backend = grpc_backend().await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![route("echo", "grpc.example.com", backend, BackendProtocol::Grpc)]));
+    let addr = serve_gateway(gateway).await;
+
+    let channel = GrpcEndpoint::from_shared(format!("http://{addr}")).unwrap()
+        .origin("http://grpc.example.com".parse().unwrap())
+        .connect()
+        .await
+        .unwrap();
+    let mut client = tonic::client::Grpc::new(channel);
+    let unary = http::uri::PathAndQuery::from_static("/test.Echo/Unary");
+
+    client.ready().await.unwrap();
+    let mut request = tonic::Request::new(EchoRequest { message: "hi".into(), repeat: 0 });
+    request.metadata_mut().insert("x-user", "alice".parse().unwrap());
+    let reply: tonic::Response<EchoReply> = client.unary(request, unary.clone(), ProstCodec::default()).await.unwrap();
+    assert_eq!(reply.into_inner().message, "hi from alice");
+
+    // The status after the messages travels in trailers
+    client.ready().await.unwrap();
+    let request = tonic::Request::new(EchoRequest { message: "tick".into(), repeat: 3 });
+    let path = http::uri::PathAndQuery::from_static("/test.Echo/Stream");
+    let mut stream = client.server_streaming::<_, EchoReply, _>(request, path, ProstCodec::default()).await.unwrap().into_inner();
+    let mut messages = Vec::new();
+    let status = loop {
+        match stream.message().await {
+            Ok(Some(reply)) => messages.push(reply.message),
+            Ok(None) => panic!("stream ended without its status"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(messages, ["tick 0", "tick 1", "tick 2"]);
+    assert_eq!((status.code(), status.message()), (Code::Aborted, "stream over"));
+
+    // The gateway's own errors reach gRPC clients as statuses
+    let channel = GrpcEndpoint::from_shared(format!("http://{addr}")).unwrap()
+        .origin("http://unknown.example.com".parse().unwrap())
+        .connect()
+        .await
+        .unwrap();
+    let mut client = tonic::client::Grpc::new(channel);
+    client.ready().await.unwrap();
+    let request = tonic::Request::new(EchoRequest { message: "hi".into(), repeat: 0 });
+    let status = client.unary::<_, EchoReply, _>(request, unary, ProstCodec::default()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unimplemented);
+}
+
+#[tokio::test]
+async fn speaks_http2_to_h2c_and_h2_backends() {
+    let plain = serve_plain(version_app()).await;
+    let (tls, ca) = h2_tls_backend(version_app()).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)).with_upstream_ca(&ca).unwrap());
+    gateway.replace(RouteTable::new(1, vec![
+        route("http1", "http1.example.com", plain, BackendProtocol::Http1),
+        route("h2c", "h2c.example.com", plain, BackendProtocol::H2c),
+        route("h2", "h2.example.com", tls, BackendProtocol::H2),
+    ]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+
+    for (host, version) in [("http1.example.com", "HTTP/1.1"), ("h2c.example.com", "HTTP/2.0"), ("h2.example.com", "HTTP/2.0")] {
+        let body = http.get(format!("http://{addr}/")).header("host", host).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, version, "{host}");
+    }
+
+    // Without the CA the backend's certificate is not trusted
+    let untrusting = Arc::new(Gateway::new(Duration::from_secs(1)));
+    untrusting.replace(RouteTable::new(1, vec![route("h2", "h2.example.com", tls, BackendProtocol::H2)]));
+    let addr = serve_gateway(untrusting).await;
+    let resp = http.get(format!("http://{addr}/")).header("host", "h2.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), 502);
+}
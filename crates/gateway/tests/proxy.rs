@@ -0,0 +1,171 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::Request, routing::any, Router};
+use gateway::{access, proxy::{self, Gateway}, table::RouteTable};
+use models::route::{Endpoint, GatewayRoute, IpFilter, RateLimit, RateLimitKey};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// A backend that echoes who it is and what it received.
+async fn backend(name: &'static str) -> SocketAddr {
+    let app = Router::new().fallback(any(move |req: Request| async move {
+        let header = |n: &str| req.headers().get(n).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+        format!("{name} {} host={} xff={} xfh={}", req.uri(), header("host"), header("x-forwarded-for"), header("x-forwarded-host"))
+    }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+async fn serve_gateway(gateway: Arc<Gateway>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = proxy::router(gateway).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn route(name: &str, host: &str, prefix: &str, endpoints: Vec<Endpoint>) -> GatewayRoute {
+    GatewayRoute { namespace: "default".into(), name: name.into(), host: host.into(), path_prefix: prefix.into(), backend_ref: name.into(), endpoints, ..Default::default() }
+}
+
+fn healthy(addr: impl ToString) -> Endpoint {
+    Endpoint { address: addr.to_string(), healthy: true, release: None }
+}
+
+#[tokio::test]
+async fn proxies_by_host_and_longest_prefix() {
+    let web = backend("web").await;
+    let api = backend("api").await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![
+        route("web", "app.example.com", "/", vec![healthy(web)]),
+        route("api", "app.example.com", "/api", vec![healthy(api)]),
+    ]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+
+    let body = http.get(format!("http://{addr}/api/users?page=2")).header("host", "app.example.com").send().await.unwrap().text().await.unwrap();
+    assert_eq!(body, "api /api/users?page=2 host=app.example.com xff=127.0.0.1 xfh=app.example.com");
+
+    let body = http.get(format!("http://{addr}/apix")).header("host", "app.example.com").send().await.unwrap().text().await.unwrap();
+    assert!(body.starts_with("web /apix "), "{body}");
+
+    let resp = http.get(format!("http://{addr}/")).header("host", "other.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn answers_503_without_healthy_endpoints_and_502_when_unreachable() {
+    // Bind and drop a listener to get a port nothing listens on
+    let dead = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![
+        route("down", "down.example.com", "/", vec![Endpoint { address: "127.0.0.1:9".into(), healthy: false, release: None }]),
+        route("empty", "empty.example.com", "/", vec![]),
+        route("dead", "dead.example.com", "/", vec![healthy(dead)]),
+    ]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+
+    for host in ["down.example.com", "empty.example.com"] {
+        let resp = http.get(format!("http://{addr}/")).header("host", host).send().await.unwrap();
+        assert_eq!(resp.status(), 503, "{host}");
+    }
+    let resp = http.get(format!("http://{addr}/")).header("host", "dead.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), 502);
+}
+
+#[tokio::test]
+async fn swapped_tables_take_effect_for_new_requests() {
+    let v1 = backend("v1").await;
+    let v2 = backend("v2").await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![route("web", "*", "/", vec![healthy(v1)])]));
+    let addr = serve_gateway(gateway.clone()).await;
+    let http = reqwest::Client::new();
+
+    let body = http.get(format!("http://{addr}/")).send().await.unwrap().text().await.unwrap();
+    assert!(body.starts_with("v1 "), "{body}");
+    gateway.replace(RouteTable::new(1, vec![route("web", "*", "/", vec![healthy(v2)])]));
+    let body = http.get(format!("http://{addr}/")).send().await.unwrap().text().await.unwrap();
+    assert!(body.starts_with("v2 "), "{body}");
+}
+
+#[tokio::test]
+async fn enforces_ip_filters_rate_limits_and_body_size() {
+    // Reads the whole body, so oversized chunked bodies reach the limit
+    let app = Router::new().fallback(any(|body: axum::body::Bytes| async move { format!("{} bytes", body.len()) }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upload = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let mut private = route("private", "private.example.com", "/", vec![healthy(upload)]);
+    private.ip_filter = Some(IpFilter { allow: vec!["10.0.0.0/8".into()], deny: vec![] });
+    let mut limited = route("limited", "limited.example.com", "/", vec![healthy(upload)]);
+    limited.rate_limit = Some(RateLimit { requests_per_second: 0.5, burst: Some(2), key: RateLimitKey::Header("x-api-key".into()), shared: false });
+    let mut small = route("small", "small.example.com", "/", vec![healthy(upload)]);
+    small.max_body_bytes = Some(16);
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    gateway.replace(RouteTable::new(1, vec![private, limited, small]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+
+    let resp = http.get(format!("http://{addr}/")).header("host", "private.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), 403);
+
+    for _ in 0..2 {
+        let resp = http.get(format!("http://{addr}/")).header("host", "limited.example.com").header("x-api-key", "a").send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+    let resp = http.get(format!("http://{addr}/")).header("host", "limited.example.com").header("x-api-key", "a").send().await.unwrap();
+    assert_eq!(resp.status(), 429);
+    assert_eq!(resp.headers()["retry-after"], "2");
+    // Another key has its own budget
+    let resp = http.get(format!("http://{addr}/")).header("host", "limited.example.com").header("x-api-key", "b").send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let resp = http.post(format!("http://{addr}/")).header("host", "small.example.com").body("x".repeat(16)).send().await.unwrap();
+    assert_eq!(resp.text().await.unwrap(), "16 bytes");
+    let resp = http.post(format!("http://{addr}/")).header("host", "small.example.com").body("x".repeat(17)).send().await.unwrap();
+    assert_eq!(resp.status(), 413);
+
+    // A chunked body gives no length up front and is cut off on the way
+    let mut conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let chunk = "x".repeat(10);
+    let request = format!("POST / HTTP/1.1\r\nhost: small.example.com\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\na\r\n{chunk}\r\na\r\n{chunk}\r\n0\r\n\r\n");
+    conn.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+}
+
+#[tokio::test]
+async fn logs_every_routed_request_once_its_response_is_sent() {
+    let web = backend("web").await;
+    let (sink, mut records) = access::channel();
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)).with_access_log(sink));
+    let mut limited = route("web", "app.example.com", "/", vec![healthy(web)]);
+    limited.rate_limit = Some(RateLimit { requests_per_second: 0.1, burst: Some(1), key: RateLimitKey::ClientIp, shared: false });
+    gateway.replace(RouteTable::new(1, vec![limited]));
+    let addr = serve_gateway(gateway).await;
+    let http = reqwest::Client::new();
+
+    let body = http.post(format!("http://{addr}/upload?x=1")).header("host", "App.Example.com").body("hello").send().await.unwrap().text().await.unwrap();
+    let record = tokio::time::timeout(Duration::from_secs(5), records.recv()).await.unwrap().unwrap();
+    assert_eq!((record.namespace.as_str(), record.route.as_str(), record.method.as_str(), record.path.as_str()), ("default", "web", "POST", "/upload"));
+    assert_eq!((record.status, record.upstream), (200, Some(web.to_string())));
+    assert_eq!((record.bytes_received, record.bytes_sent), (5, body.len() as u64));
+    assert_eq!(record.client_ip.as_deref(), Some("127.0.0.1"));
+
+    // Answers from the gateway itself are logged without an upstream
+    let resp = http.get(format!("http://{addr}/")).header("host", "app.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), 429);
+    resp.bytes().await.unwrap();
+    let record = tokio::time::timeout(Duration::from_secs(5), records.recv()).await.unwrap().unwrap();
+    assert_eq!((record.status, record.upstream), (429, None));
+
+    // Unrouted requests belong to no route's log
+    http.get(format!("http://{addr}/")).header("host", "other.example.com").send().await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(200), records.recv()).await.is_err());
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Request},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use gateway::{
+    config::{GatewayConfig, ObjectStoreConfig},
+    control_plane::ControlPlane,
+    objects::ObjectStore,
+    proxy::{self, Gateway},
+    table::RouteTable,
+};
+use models::{bucket::BucketObject, route::{GatewayRoute, StaticSite}};
+
+async fn serve(app: Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+/// Objects of bucket `web/site`, by key, with their contents.
+fn objects() -> Vec<(BucketObject, &'static str)> {
+    let object = |key: &str, sha256: &str, content_type: Option<&str>, data: &'static str| {
+        let created_at = chrono::DateTime::parse_from_rfc3339("2026-01-02T03:04:05Z").unwrap().into();
+        (BucketObject { key: key.into(), sha256: sha256.into(), size: data.len() as u64, content_type: content_type.map(Into::into), created_at }, data)
+    };
+    vec![
+        object("index.html", "aaaa", Some("text/html"), "<h1>home</h1>"),
+        object("docs/index.html", "bbbb", Some("text/html"), "<h1>docs</h1>"),
+        object("app.js", "cccc", None, "console.log('0123456789')"),
+        object("404.html", "dddd", Some("text/html"), "<h1>missing</h1>"),
+    ]
+}
+
+/// A control plane answering object lookups and an S3 store serving their
+/// contents (honouring ranges), as the gateway uses them.
+async fn backends() -> (SocketAddr, SocketAddr) {
+    let by_key: HashMap<String, BucketObject> = objects().into_iter().map(|(o, _)| (o.key.clone(), o)).collect();
+    let cp = serve(Router::new().route("/api/v1/gateway/buckets/:namespace/:bucket/objects/*key", get(move |Path((ns, bucket, key)): Path<(String, String, String)>| {
+        // `web/private` holds a copy of the home page the route must not reach
+        let object = by_key.get(&key).filter(|_| ns == "web" && (bucket == "site" || bucket == "private" && key == "index.html")).cloned();
+        async move { object.map(Json).ok_or(StatusCode::NOT_FOUND) }
+    }))).await;
+    let by_hash: HashMap<String, &str> = objects().into_iter().map(|(o, data)| (o.sha256, data)).collect();
+    let store = serve(Router::new().route("/span/blobs/:sha256", get(move |Path(sha256): Path<String>, req: Request| {
+        let data = by_hash.get(&sha256).copied();
+        async move {
+            let signed = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).is_some_and(|v| v.starts_with("AWS4-HMAC-SHA256 Credential=span/"));
+            let Some(data) = data.filter(|_| signed) else { return StatusCode::FORBIDDEN.into_response() };
+            match req.headers().get(header::RANGE).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("bytes=")).and_then(|v| v.split_once('-')) {
+                Some((first, last)) => {
+                    let (first, last): (usize, usize) = (first.parse().unwrap(), last.parse().unwrap());
+                    (StatusCode::PARTIAL_CONTENT, data[first..=last].to_string()).into_response()
+                }
+                None => data.into_response(),
+            }
+        }
+    }))).await;
+    (cp, store)
+}
+
+async fn gateway(site: StaticSite) -> SocketAddr {
+    let (cp, store) = backends().await;
+    let config = GatewayConfig {
+        bind_http: "127.0.0.1:0".parse().unwrap(),
+        bind_https: None,
+        bind_l4: "127.0.0.1".parse().unwrap(),
+        l4_ports: Default::default(),
+        bind_admin: "127.0.0.1:0".parse().unwrap(),
+        control_plane_url: format!("http://{cp}"),
+        token: None,
+        nats_url: None,
+        sync_interval: Duration::from_secs(30),
+        stats_interval: Duration::from_secs(10),
+        connect_timeout: Duration::from_secs(1),
+        upstream_ca: None,
+        object_store: None,
+    };
+    let store = ObjectStore::new(ObjectStoreConfig {
+        url: format!("http://{store}"),
+        bucket: "span".into(),
+        access_key: "span".into(),
+        secret_key: "secret".into(),
+        region: "us-east-1".into(),
+    })
+    .unwrap();
+    let gateway = Gateway::new(Duration::from_secs(1)).with_control_plane(ControlPlane::new(&config)).with_object_store(store);
+    let route = GatewayRoute {
+        namespace: "web".into(),
+        name: "site".into(),
+        host: "www.example.com".into(),
+        path_prefix: "/".into(),
+        backend_ref: "bucket:web/site".into(),
+        site: Some(site),
+        ..Default::default()
+    };
+    gateway.replace(RouteTable::new(1, vec![route]));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = proxy::router(Arc::new(gateway)).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn serves_bucket_objects_with_index_documents_and_ranges() {
+    let addr = gateway(StaticSite { cache_control: "public, max-age=60".into(), ..Default::default() }).await;
+    let http = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+    let get = |path: &str| http.get(format!("http://{addr}{path}")).header("host", "www.example.com");
+
+    let resp = get("/").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/html");
+    assert_eq!(resp.headers()["etag"], "\"aaaa\"");
+    assert_eq!(resp.headers()["cache-control"], "public, max-age=60");
+    assert_eq!(resp.headers()["last-modified"], "Fri, 02 Jan 2026 03:04:05 GMT");
+    assert_eq!(resp.text().await.unwrap(), "<h1>home</h1>");
+
+    let resp = get("/docs?v=1").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(resp.headers()["location"], "/docs/?v=1");
+    assert_eq!(get("/docs/").send().await.unwrap().text().await.unwrap(), "<h1>docs</h1>");
+
+    let resp = get("/app.js").send().await.unwrap();
+    assert_eq!(resp.headers()["content-type"], "text/javascript", "guessed without a stored content type");
+    let resp = get("/app.js").header("if-none-match", "\"cccc\"").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let resp = get("/app.js").header("range", "bytes=13-22").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(resp.headers()["content-range"], "bytes 13-22/25");
+    assert_eq!(resp.text().await.unwrap(), "0123456789");
+    let resp = get("/app.js").header("range", "bytes=100-").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(resp.headers()["content-range"], "bytes */25");
+
+    let resp = http.head(format!("http://{addr}/app.js")).header("host", "www.example.com").send().await.unwrap();
+    assert_eq!(resp.headers()["content-length"], "25");
+    let resp = http.post(format!("http://{addr}/app.js")).header("host", "www.example.com").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(get("/settings").send().await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    for path in ["/..%2F..%2Fprivate%2Fobjects%2Findex.html", "/docs/..%2Findex.html", "/docs//index.html", "/.%2Findex.html"] {
+        assert_eq!(get(path).send().await.unwrap().status(), StatusCode::BAD_REQUEST, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn falls_back_to_the_app_shell_and_not_found_document() {
+    let addr = gateway(StaticSite { spa_fallback: true, not_found_document: Some("404.html".into()), ..Default::default() }).await;
+    let http = reqwest::Client::new();
+    let get = |path: &str| http.get(format!("http://{addr}{path}")).header("host", "www.example.com");
+
+    let resp = get("/settings/profile").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "<h1>home</h1>");
+    let resp = get("/missing.png").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(resp.headers().get("etag").is_none());
+    assert_eq!(resp.text().await.unwrap(), "<h1>missing</h1>");
+}
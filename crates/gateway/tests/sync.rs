@@ -0,0 +1,139 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::State, routing::get, Json, Router};
+use gateway::{admin, config::GatewayConfig, proxy::Gateway, sync::Syncer};
+use models::route::{Endpoint, GatewayCertificate, GatewayRoute, GatewaySnapshot};
+
+type Shared = Arc<Mutex<GatewaySnapshot>>;
+
+/// A control plane serving whatever snapshot the test puts in `Shared`.
+async fn control_plane(snapshot: Shared) -> SocketAddr {
+    async fn routes(State(s): State<Shared>) -> Json<GatewaySnapshot> {
+        Json(s.lock().unwrap().clone())
+    }
+    // Certificates are only handed out with a token, like the real endpoint
+    async fn certificates(headers: axum::http::HeaderMap) -> Result<Json<Vec<GatewayCertificate>>, axum::http::StatusCode> {
+        headers.get("authorization").ok_or(axum::http::StatusCode::UNAUTHORIZED)?;
+        let cert = rcgen::generate_simple_self_signed(vec!["app.example.com".into()]).unwrap();
+        Ok(Json(vec![GatewayCertificate {
+            host: "app.example.com".into(),
+            cert_pem: cert.serialize_pem().unwrap(),
+            key_pem: cert.serialize_private_key_pem(),
+            not_after: chrono::Utc::now() + chrono::Duration::days(90),
+        }]))
+    }
+    let app = Router::new()
+        .route("/api/v1/gateway/routes", get(routes))
+        .route("/api/v1/gateway/certificates", get(certificates))
+        .with_state(snapshot);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn config(cp: SocketAddr) -> GatewayConfig {
+    GatewayConfig {
+        bind_http: "127.0.0.1:0".parse().unwrap(),
+        bind_https: None,
+        bind_l4: "127.0.0.1".parse().unwrap(),
+        l4_ports: Default::default(),
+        bind_admin: "127.0.0.1:0".parse().unwrap(),
+        control_plane_url: format!("http://{cp}"),
+        token: None,
+        nats_url: None,
+        sync_interval: Duration::from_secs(30),
+        stats_interval: Duration::from_secs(10),
+        connect_timeout: Duration::from_secs(1),
+        upstream_ca: None,
+        object_store: None,
+    }
+}
+
+fn route(name: &str, address: &str) -> GatewayRoute {
+    GatewayRoute {
+        namespace: "default".into(),
+        name: name.into(),
+        host: "app.example.com".into(),
+        path_prefix: "/".into(),
+        backend_ref: name.into(),
+        endpoints: vec![Endpoint { address: address.into(), healthy: true, release: None }],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn reload_swaps_only_newer_versions() {
+    let snapshot: Shared = Arc::new(Mutex::new(GatewaySnapshot { version: 3, routes: vec![route("web", "10.0.0.1:80")] }));
+    let cp = control_plane(snapshot.clone()).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let syncer = Syncer::new(gateway.clone(), config(cp));
+
+    assert_eq!(gateway.version(), 0);
+    assert_eq!(syncer.reload().await.unwrap(), 3);
+    let before = gateway.table();
+    assert_eq!(before.version(), 3);
+    assert_eq!(before.routes()[0].endpoints[0].address, "10.0.0.1:80");
+
+    // Same version: the table being served is kept as is
+    syncer.reload().await.unwrap();
+    assert!(Arc::ptr_eq(&before, &gateway.table()));
+
+    *snapshot.lock().unwrap() = GatewaySnapshot { version: 4, routes: vec![route("web", "10.0.0.2:80"), route("api", "10.0.0.3:80")] };
+    syncer.reload().await.unwrap();
+    let after = gateway.table();
+    assert_eq!(after.version(), 4);
+    assert_eq!(after.len(), 2);
+    // Requests holding the old table still see it unchanged
+    assert_eq!(before.routes()[0].endpoints[0].address, "10.0.0.1:80");
+}
+
+#[tokio::test]
+async fn reload_loads_certificates_with_a_token() {
+    let snapshot: Shared = Arc::new(Mutex::new(GatewaySnapshot { version: 5, routes: vec![route("web", "10.0.0.1:80")] }));
+    let cp = control_plane(snapshot).await;
+
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    Syncer::new(gateway.clone(), config(cp)).reload().await.unwrap();
+    assert!(gateway.certificates().hosts().is_empty());
+
+    let cfg = GatewayConfig { token: Some("admin-token".into()), ..config(cp) };
+    Syncer::new(gateway.clone(), cfg).reload().await.unwrap();
+    assert_eq!(gateway.certificates().hosts(), ["app.example.com"]);
+    assert_eq!(gateway.certificates().version(), 5);
+    assert!(gateway.certificates().get("APP.example.com").is_some());
+}
+
+#[tokio::test]
+async fn failed_reload_keeps_serving_the_current_table() {
+    let snapshot: Shared = Arc::new(Mutex::new(GatewaySnapshot { version: 2, routes: vec![route("web", "10.0.0.1:80")] }));
+    let cp = control_plane(snapshot).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    Syncer::new(gateway.clone(), config(cp)).reload().await.unwrap();
+
+    let dead = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    assert!(Syncer::new(gateway.clone(), config(dead)).reload().await.is_err());
+    assert_eq!(gateway.version(), 2);
+    assert_eq!(gateway.table().len(), 1);
+}
+
+#[tokio::test]
+async fn admin_endpoint_reports_the_served_version() {
+    let snapshot: Shared = Arc::new(Mutex::new(GatewaySnapshot { version: 9, routes: vec![route("web", "10.0.0.1:80")] }));
+    let cp = control_plane(snapshot).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    Syncer::new(gateway.clone(), config(cp)).reload().await.unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, admin::router(gateway)).await.unwrap() });
+
+    let body: serde_json::Value = reqwest::get(format!("http://{addr}/routes")).await.unwrap().json().await.unwrap();
+    assert_eq!(body["version"], 9);
+    assert_eq!(body["routes"][0]["name"], "web");
+    assert_eq!(body["routes"][0]["endpoints"][0]["address"], "10.0.0.1:80");
+}
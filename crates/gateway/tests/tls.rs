@@ -0,0 +1,198 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::{Path, Request}, http::StatusCode, routing::{any, get}, Json, Router};
+use gateway::{
+    config::GatewayConfig,
+    control_plane::ControlPlane,
+    proxy::{self, Gateway},
+    table::RouteTable,
+    tls::TlsTerminator,
+};
+use models::route::{Endpoint, GatewayCertificate, GatewayRoute};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use serde_json::json;
+
+const HOST: &str = "app.example.com";
+
+/// A backend that reports the scheme the gateway says the client used.
+async fn backend() -> SocketAddr {
+    let app = Router::new().fallback(any(|req: Request| async move {
+        let proto = req.headers().get("x-forwarded-proto").and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+        format!("{} proto={proto}", req.uri().path())
+    }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+/// A control plane with one pending challenge of each kind for `HOST`.
+async fn control_plane(alpn_cert: (String, String)) -> SocketAddr {
+    let app = Router::new()
+        .route("/api/v1/gateway/acme/http-01/:token", get(|Path(token): Path<String>| async move {
+            if token == "tok" { Ok("tok.thumbprint") } else { Err(StatusCode::NOT_FOUND) }
+        }))
+        .route("/api/v1/gateway/acme/tls-alpn-01/:host", get(move |Path(host): Path<String>| async move {
+            if host == HOST { Ok(Json(json!({ "cert_pem": alpn_cert.0, "key_pem": alpn_cert.1 }))) } else { Err(StatusCode::NOT_FOUND) }
+        }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn config(cp: SocketAddr) -> GatewayConfig {
+    GatewayConfig {
+        bind_http: "127.0.0.1:0".parse().unwrap(),
+        bind_https: Some("127.0.0.1:0".parse().unwrap()),
+        bind_l4: "127.0.0.1".parse().unwrap(),
+        l4_ports: Default::default(),
+        bind_admin: "127.0.0.1:0".parse().unwrap(),
+        control_plane_url: format!("http://{cp}"),
+        token: Some("admin-token".into()),
+        nats_url: None,
+        sync_interval: Duration::from_secs(30),
+        stats_interval: Duration::from_secs(10),
+        connect_timeout: Duration::from_secs(1),
+        upstream_ca: None,
+        object_store: None,
+    }
+}
+
+struct Setup {
+    gateway: Arc<Gateway>,
+    http: SocketAddr,
+    https: SocketAddr,
+    cert_pem: String,
+    alpn_cert_pem: String,
+}
+
+async fn setup() -> Setup {
+    let alpn_cert = crypto::acme::tls_alpn_challenge_cert(HOST, "tok.thumbprint").unwrap();
+    let alpn_cert_pem = alpn_cert.0.clone();
+    let control_plane = ControlPlane::new(&config(self::control_plane(alpn_cert).await));
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)).with_control_plane(control_plane.clone()));
+
+    let backend = backend().await;
+    gateway.replace(RouteTable::new(1, vec![GatewayRoute {
+        namespace: "default".into(),
+        name: "web".into(),
+        host: HOST.into(),
+        path_prefix: "/".into(),
+        backend_ref: "web".into(),
+        endpoints: vec![Endpoint { address: backend.to_string(), healthy: true, release: None }],
+        ..Default::default()
+    }]));
+    let cert = rcgen::generate_simple_self_signed(vec![HOST.into()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    gateway.certificates().replace(1, vec![GatewayCertificate {
+        host: HOST.into(),
+        cert_pem: cert_pem.clone(),
+        key_pem: cert.serialize_private_key_pem(),
+        not_after: chrono::Utc::now() + chrono::Duration::days(90),
+    }]);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let https = listener.local_addr().unwrap();
+    let terminator = TlsTerminator::new(gateway.certificates().clone(), Some(control_plane)).unwrap();
+    tokio::spawn(terminator.serve(listener, proxy::router(gateway.clone())));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http = listener.local_addr().unwrap();
+    let app = proxy::router(gateway.clone()).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    Setup { gateway, http, https, cert_pem, alpn_cert_pem }
+}
+
+fn https_client(root_pem: &str, host: &str, addr: SocketAddr) -> reqwest::Client {
+    reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(root_pem.as_bytes()).unwrap())
+        .resolve(host, addr)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn terminates_tls_by_sni() {
+    let setup = setup().await;
+    let port = setup.https.port();
+
+    let client = https_client(&setup.cert_pem, HOST, setup.https);
+    let body = client.get(format!("https://{HOST}:{port}/hello")).send().await.unwrap().text().await.unwrap();
+    assert_eq!(body, "/hello proto=https");
+
+    // No certificate for the name: the handshake fails
+    let client = https_client(&setup.cert_pem, "other.example.com", setup.https);
+    assert!(client.get(format!("https://other.example.com:{port}/")).send().await.is_err());
+
+    // Plain HTTP is still proxied as http
+    let body = reqwest::Client::new().get(format!("http://{}/hello", setup.http)).header("host", HOST).send().await.unwrap().text().await.unwrap();
+    assert_eq!(body, "/hello proto=http");
+
+    // Certificates that go away stop being served
+    setup.gateway.certificates().replace(2, vec![]);
+    let client = https_client(&setup.cert_pem, HOST, setup.https);
+    assert!(client.get(format!("https://{HOST}:{port}/")).send().await.is_err());
+}
+
+#[tokio::test]
+async fn answers_http01_challenges_and_proxies_unknown_tokens() {
+    let setup = setup().await;
+    let http = reqwest::Client::new();
+
+    let resp = http.get(format!("http://{}/.well-known/acme-challenge/tok", setup.http)).header("host", HOST).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "tok.thumbprint");
+
+    let body = http.get(format!("http://{}/.well-known/acme-challenge/other", setup.http)).header("host", HOST).send().await.unwrap().text().await.unwrap();
+    assert_eq!(body, "/.well-known/acme-challenge/other proto=http");
+}
+
+/// Accepts any certificate: the challenge certificate is self-signed and
+/// carries a critical extension no verifier knows.
+#[derive(Debug)]
+struct AcceptAny;
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(&self, _: &CertificateDer<'_>, _: &[CertificateDer<'_>], _: &ServerName<'_>, _: &[u8], _: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, _: &[u8], _: &CertificateDer<'_>, _: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(&self, _: &[u8], _: &CertificateDer<'_>, _: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider().signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[tokio::test]
+async fn presents_challenge_certificate_for_acme_tls_alpn() {
+    let setup = setup().await;
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"acme-tls/1".to_vec()];
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+
+    let stream = tokio::net::TcpStream::connect(setup.https).await.unwrap();
+    let tls = connector.connect(ServerName::try_from(HOST).unwrap(), stream).await.unwrap();
+    let (_, session) = tls.get_ref();
+    assert_eq!(session.alpn_protocol(), Some(&b"acme-tls/1"[..]));
+    let presented = session.peer_certificates().unwrap()[0].clone();
+    let expected = rustls_pemfile::certs(&mut setup.alpn_cert_pem.as_bytes()).next().unwrap().unwrap();
+    assert_eq!(presented, expected);
+}
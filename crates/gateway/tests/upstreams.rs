@@ -0,0 +1,254 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, routing::{get, post}, Json, Router};
+use gateway::{admin, config::GatewayConfig, control_plane::ControlPlane, proxy::{self, Gateway}, stats, table::RouteTable, upstream};
+use models::route::{CircuitBreaker, Endpoint, GatewayRoute, HashOn, HealthCheck, OutlierDetection, ReleaseStats, ReleaseWeight, Resilience, Retries, Timeouts, TrafficSplit};
+
+const HOST: &str = "app.example.com";
+
+/// A backend answering its name, or 500 while `failing` is set. Its
+/// health check follows the same switch.
+async fn backend(name: &'static str, failing: Arc<AtomicBool>) -> SocketAddr {
+    let status = move || if failing.load(Ordering::Relaxed) { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::OK };
+    let app = Router::new()
+        .route("/healthz", get({ let status = status.clone(); move || async move { status() } }))
+        .fallback(move || async move { (status(), name) });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+async fn serve_gateway(gateway: Arc<Gateway>) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = proxy::router(gateway).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn route(endpoints: &[SocketAddr]) -> GatewayRoute {
+    GatewayRoute {
+        namespace: "default".into(),
+        name: "web".into(),
+        host: HOST.into(),
+        path_prefix: "/".into(),
+        backend_ref: "web".into(),
+        endpoints: endpoints.iter().map(|a| Endpoint { address: a.to_string(), healthy: true, release: None }).collect(),
+        ..Default::default()
+    }
+}
+
+/// Bodies of `n` requests through the gateway.
+async fn responses(addr: SocketAddr, n: usize) -> Vec<String> {
+    let http = reqwest::Client::new();
+    let mut bodies = Vec::new();
+    for _ in 0..n {
+        let resp = http.get(format!("http://{addr}/")).header("host", HOST).send().await.unwrap();
+        bodies.push(format!("{} {}", resp.status().as_u16(), resp.text().await.unwrap()));
+    }
+    bodies
+}
+
+#[tokio::test]
+async fn health_checks_take_failing_endpoints_out_of_rotation() {
+    let sick = Arc::new(AtomicBool::new(false));
+    let a = backend("a", Arc::new(AtomicBool::new(false))).await;
+    let b = backend("b", sick.clone()).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let mut web = route(&[a, b]);
+    web.health_check = Some(HealthCheck { path: "/healthz".into(), interval_secs: 1, timeout_ms: 500, unhealthy_threshold: 1, healthy_threshold: 1 });
+    gateway.replace(RouteTable::new(1, vec![web]));
+    tokio::spawn(upstream::run_health_checks(gateway.clone()));
+    let addr = serve_gateway(gateway.clone()).await;
+    let status = |gateway: &Gateway| gateway.upstreams().statuses().into_iter().find(|s| s.address == b.to_string()).unwrap();
+
+    let bodies = responses(addr, 4).await;
+    assert!(bodies.contains(&"200 b".to_string()), "{bodies:?}");
+
+    sick.store(true, Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert!(status(&gateway).down);
+    assert!(responses(addr, 4).await.iter().all(|b| b == "200 a"));
+
+    sick.store(false, Ordering::Relaxed);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert!(!status(&gateway).down);
+    assert!(responses(addr, 4).await.contains(&"200 b".to_string()));
+}
+
+#[tokio::test]
+async fn ejects_endpoints_answering_repeated_server_errors() {
+    let good = backend("good", Arc::new(AtomicBool::new(false))).await;
+    let broken = backend("broken", Arc::new(AtomicBool::new(true))).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let mut web = route(&[good, broken]);
+    web.outlier_detection = Some(OutlierDetection { consecutive_5xx: 2, ejection_secs: 60, max_ejection_percent: 50 });
+    gateway.replace(RouteTable::new(1, vec![web.clone()]));
+    let addr = serve_gateway(gateway.clone()).await;
+
+    // Round-robin sends every other request to the broken endpoint until
+    // its second error ejects it
+    let bodies = responses(addr, 10).await;
+    assert_eq!(bodies.iter().filter(|b| *b == "500 broken").count(), 2, "{bodies:?}");
+    assert!(bodies[4..].iter().all(|b| b == "200 good"), "{bodies:?}");
+    let ejected: Vec<String> = gateway.upstreams().statuses().into_iter().filter(|s| s.ejected).map(|s| s.address).collect();
+    assert_eq!(ejected, [broken.to_string()]);
+
+    // A config reload does not bring it back early
+    gateway.replace(RouteTable::new(2, vec![web]));
+    assert!(responses(addr, 4).await.iter().all(|b| b == "200 good"));
+}
+
+/// A control plane collecting the release stats gateways report.
+async fn stats_sink(reports: Arc<std::sync::Mutex<Vec<ReleaseStats>>>) -> SocketAddr {
+    async fn report(State(reports): State<Arc<std::sync::Mutex<Vec<ReleaseStats>>>>, Json(stats): Json<Vec<ReleaseStats>>) {
+        reports.lock().unwrap().extend(stats);
+    }
+    let app = Router::new().route("/api/v1/gateway/stats", post(report)).with_state(reports);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn splits_traffic_by_release_and_reports_error_rates() {
+    let stable = backend("v12", Arc::new(AtomicBool::new(false))).await;
+    let canary = backend("v13", Arc::new(AtomicBool::new(true))).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+    let mut web = route(&[]);
+    web.endpoints = vec![
+        Endpoint { address: stable.to_string(), healthy: true, release: Some("v12".into()) },
+        Endpoint { address: canary.to_string(), healthy: true, release: Some("v13".into()) },
+    ];
+    web.split = Some(TrafficSplit {
+        releases: vec![ReleaseWeight { release: "v12".into(), weight: 80 }, ReleaseWeight { release: "v13".into(), weight: 20 }],
+        sticky_on: Some(HashOn::Header("x-user".into())),
+    });
+    gateway.replace(RouteTable::new(1, vec![web]));
+    let addr = serve_gateway(gateway.clone()).await;
+
+    let bodies = responses(addr, 10).await;
+    assert_eq!(bodies.iter().filter(|b| *b == "200 v12").count(), 8, "{bodies:?}");
+    assert_eq!(bodies.iter().filter(|b| *b == "500 v13").count(), 2, "{bodies:?}");
+
+    // The same user always lands on the same release
+    let http = reqwest::Client::new();
+    let mut seen = std::collections::HashSet::new();
+    for _ in 0..5 {
+        let resp = http.get(format!("http://{addr}/")).header("host", HOST).header("x-user", "alice").send().await.unwrap();
+        seen.insert(resp.text().await.unwrap());
+    }
+    assert_eq!(seen.len(), 1, "{seen:?}");
+
+    let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let cp = stats_sink(reports.clone()).await;
+    let cfg = GatewayConfig {
+        bind_http: "127.0.0.1:0".parse().unwrap(),
+        bind_https: None,
+        bind_l4: "127.0.0.1".parse().unwrap(),
+        l4_ports: Default::default(),
+        bind_admin: "127.0.0.1:0".parse().unwrap(),
+        control_plane_url: format!("http://{cp}"),
+        token: Some("admin".into()),
+        nats_url: None,
+        sync_interval: Duration::from_secs(30),
+        stats_interval: Duration::from_millis(100),
+        connect_timeout: Duration::from_secs(1),
+        upstream_ca: None,
+        object_store: None,
+    };
+    tokio::spawn(stats::run_reporter(gateway.clone(), ControlPlane::new(&cfg), cfg.stats_interval));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let reports = reports.lock().unwrap();
+    let total = |release: &str| {
+        reports.iter().filter(|s| s.release == release).fold((0, 0), |(r, e), s| {
+            assert_eq!((s.namespace.as_str(), s.backend.as_str()), ("default", "web"));
+            (r + s.requests, e + s.errors)
+        })
+    };
+    let (v12, v13) = (total("v12"), total("v13"));
+    assert_eq!(v12.0 + v13.0, 15);
+    assert_eq!(v12.1, 0);
+    assert_eq!(v13.0, v13.1);
+    assert!(v13.0 >= 2);
+}
+
+/// A backend answering `status` to everything, after `delay`.
+async fn answering(status: StatusCode, delay: Duration) -> SocketAddr {
+    let app = Router::new().fallback(move || async move {
+        tokio::time::sleep(delay).await;
+        (status, status.as_str().to_string())
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn retries_idempotent_requests_and_opens_circuits_on_timeouts() {
+    let ok = backend("ok", Arc::new(AtomicBool::new(false))).await;
+    let unavailable = answering(StatusCode::SERVICE_UNAVAILABLE, Duration::ZERO).await;
+    let slow = answering(StatusCode::OK, Duration::from_secs(5)).await;
+    let gateway = Arc::new(Gateway::new(Duration::from_secs(1)));
+
+    let mut retried = route(&[unavailable, ok]);
+    retried.resilience = Some(Resilience { retries: Some(Retries { attempts: 1, backoff_ms: 1, ..Default::default() }), ..Default::default() });
+    let mut timed = route(&[slow]);
+    timed.name = "slow".into();
+    timed.host = "slow.example.com".into();
+    timed.resilience = Some(Resilience {
+        timeouts: Timeouts { read_ms: Some(200), ..Default::default() },
+        circuit_breaker: Some(CircuitBreaker { consecutive_failures: 2, open_secs: 60, half_open_requests: 1 }),
+        ..Default::default()
+    });
+    gateway.replace(RouteTable::new(1, vec![retried, timed]));
+    let addr = serve_gateway(gateway.clone()).await;
+    let http = reqwest::Client::new();
+
+    // Every GET lands on the healthy endpoint, one way or another
+    assert!(responses(addr, 6).await.iter().all(|b| b == "200 ok"));
+    // POSTs are not idempotent, so half of them see the 503
+    let mut statuses = Vec::new();
+    for _ in 0..4 {
+        statuses.push(http.post(format!("http://{addr}/")).header("host", HOST).body("x").send().await.unwrap().status());
+    }
+    assert_eq!(statuses.iter().filter(|s| **s == StatusCode::SERVICE_UNAVAILABLE).count(), 2, "{statuses:?}");
+
+    let slow = |path: &'static str| {
+        let http = http.clone();
+        async move {
+            let started = std::time::Instant::now();
+            let resp = http.get(format!("http://{addr}{path}")).header("host", "slow.example.com").send().await.unwrap();
+            (resp.status(), resp.text().await.unwrap(), started.elapsed())
+        }
+    };
+    for _ in 0..2 {
+        let (status, _, elapsed) = slow("/").await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+    let (status, body, elapsed) = slow("/").await;
+    assert_eq!((status, body.as_str()), (StatusCode::SERVICE_UNAVAILABLE, "circuit open\n"));
+    assert!(elapsed < Duration::from_millis(100), "an open circuit answers without asking the backend");
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let admin_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, admin::router(gateway)).await.unwrap() });
+    let circuits: serde_json::Value = http.get(format!("http://{admin_addr}/circuits")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(circuits.as_array().unwrap().len(), 1, "{circuits}");
+    assert_eq!(circuits[0]["route"], "slow");
+    assert_eq!(circuits[0]["state"], "open");
+    assert_eq!(circuits[0]["opened"], 1);
+    assert!(circuits[0]["retry_in_secs"].as_u64().unwrap() > 50);
+}
@@ -0,0 +1,9 @@
+-- Certificates issued to nodes, kept after node removal so revocations persist
+CREATE TABLE IF NOT EXISTS node_certificates (
+    serial TEXT PRIMARY KEY,
+    node_id UUID NOT NULL,
+    issued_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS idx_node_certificates_node ON node_certificates(node_id);
+CREATE INDEX IF NOT EXISTS idx_node_certificates_revoked ON node_certificates(revoked_at) WHERE revoked_at IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Track which CA issued each node certificate so rotations know who still needs re-issuing
+ALTER TABLE node_certificates ADD COLUMN IF NOT EXISTS ca_serial TEXT;
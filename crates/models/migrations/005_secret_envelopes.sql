@@ -0,0 +1,11 @@
+-- Master key versions used to wrap secret data keys. Keys are derived from
+-- SPAN_MASTER_KEY and never stored; the fingerprint detects a changed root.
+CREATE TABLE IF NOT EXISTS master_keys (
+    version INTEGER PRIMARY KEY,
+    fingerprint TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE secrets ADD COLUMN IF NOT EXISTS wrapped_key BYTEA;
+ALTER TABLE secrets ADD COLUMN IF NOT EXISTS master_key_version INTEGER REFERENCES master_keys(version);
+CREATE INDEX IF NOT EXISTS idx_secrets_master_key_version ON secrets(master_key_version);
@@ -0,0 +1,4 @@
+-- Secret versions each deployment is pinned to. Unpinned secretRefs resolve
+-- through this map so a new secret version reaches replicas one at a time.
+ALTER TABLE container_deployments ADD COLUMN IF NOT EXISTS secret_versions JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX IF NOT EXISTS idx_container_deployments_app ON container_deployments(app_id);
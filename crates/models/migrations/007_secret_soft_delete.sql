@@ -0,0 +1,3 @@
+-- Deleted secret versions are kept for a retention period before being purged
+ALTER TABLE secrets ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS idx_secrets_deleted ON secrets(deleted_at) WHERE deleted_at IS NOT NULL;
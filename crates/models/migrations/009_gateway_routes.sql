@@ -0,0 +1,15 @@
+-- Routes are addressed by name within a namespace, like apps
+ALTER TABLE routes ADD COLUMN IF NOT EXISTS name TEXT;
+UPDATE routes SET name = id::TEXT WHERE name IS NULL;
+ALTER TABLE routes ALTER COLUMN name SET NOT NULL;
+ALTER TABLE routes ADD CONSTRAINT routes_namespace_name_key UNIQUE (namespace_id, name);
+ALTER TABLE routes ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Endpoints serve a backend (an app) in a namespace; the gateway only sends
+-- traffic to healthy ones
+ALTER TABLE service_endpoints ALTER COLUMN node_id DROP NOT NULL;
+ALTER TABLE service_endpoints ADD COLUMN IF NOT EXISTS namespace_id UUID REFERENCES namespaces(id) ON DELETE CASCADE;
+ALTER TABLE service_endpoints ADD COLUMN IF NOT EXISTS backend TEXT;
+ALTER TABLE service_endpoints ADD COLUMN IF NOT EXISTS healthy BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE service_endpoints ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+CREATE INDEX IF NOT EXISTS idx_service_endpoints_backend ON service_endpoints(namespace_id, backend);
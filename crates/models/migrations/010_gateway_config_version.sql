@@ -0,0 +1,5 @@
+-- Bumped on every route or endpoint change; gateways serve the table of one
+-- version and reload when they see a newer one. Starts at 1 so routes created
+-- before this migration are loaded.
+CREATE SEQUENCE IF NOT EXISTS gateway_config_version;
+SELECT setval('gateway_config_version', 1);
@@ -0,0 +1,39 @@
+-- Certificates obtained via ACME for routes with a TLS policy. Private keys
+-- are envelope-encrypted like secrets; cert_pem stays NULL until the first
+-- issuance succeeds. attempted_at doubles as the claim that stops two
+-- control planes ordering the same certificate at once.
+CREATE TABLE IF NOT EXISTS tls_certificates (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    host TEXT NOT NULL UNIQUE,
+    cert_pem TEXT,
+    encrypted_key BYTEA,
+    wrapped_key BYTEA,
+    master_key_version INTEGER,
+    not_after TIMESTAMPTZ,
+    issued_at TIMESTAMPTZ,
+    attempted_at TIMESTAMPTZ,
+    last_error TEXT
+);
+
+-- One ACME account per directory (CA), shared by every control plane
+CREATE TABLE IF NOT EXISTS acme_accounts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    directory_url TEXT NOT NULL UNIQUE,
+    account_url TEXT NOT NULL,
+    encrypted_key BYTEA NOT NULL,
+    wrapped_key BYTEA NOT NULL,
+    master_key_version INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Challenges being validated, answered by whichever gateway the CA reaches
+CREATE TABLE IF NOT EXISTS acme_challenges (
+    host TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    token TEXT NOT NULL,
+    key_authorization TEXT NOT NULL,
+    cert_pem TEXT,
+    key_pem TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_acme_challenges_token ON acme_challenges(token);
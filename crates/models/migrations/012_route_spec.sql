@@ -0,0 +1,3 @@
+-- The whole Route spec, for settings beyond host, prefix and backend
+-- (load balancing, health checks, ...). NULL for routes applied before.
+ALTER TABLE routes ADD COLUMN IF NOT EXISTS spec JSONB;
@@ -0,0 +1,29 @@
+-- Endpoints carry the release they run, so routes can split traffic
+-- between releases of a backend
+ALTER TABLE service_endpoints ADD COLUMN IF NOT EXISTS release TEXT;
+
+-- Requests and server errors per release as reported by gateways, in
+-- one-minute buckets
+CREATE TABLE IF NOT EXISTS release_stats (
+    namespace_id UUID NOT NULL REFERENCES namespaces(id) ON DELETE CASCADE,
+    backend TEXT NOT NULL,
+    release TEXT NOT NULL,
+    bucket TIMESTAMPTZ NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    errors BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (namespace_id, backend, release, bucket)
+);
+CREATE INDEX IF NOT EXISTS idx_release_stats_bucket ON release_stats(bucket);
+
+-- Gradual weight shifts in progress, one per route
+CREATE TABLE IF NOT EXISTS route_weight_shifts (
+    route_id UUID PRIMARY KEY REFERENCES routes(id) ON DELETE CASCADE,
+    release TEXT NOT NULL,
+    target_weight INTEGER NOT NULL,
+    step INTEGER NOT NULL,
+    interval_secs INTEGER NOT NULL,
+    max_error_rate DOUBLE PRECISION,
+    min_requests BIGINT NOT NULL DEFAULT 0,
+    next_step_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
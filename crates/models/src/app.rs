@@ -1 +1,156 @@
-// Placeholder module for model-specific logic
+use serde::{Deserialize, Serialize};
+
+/// The `spec` of an App manifest as stored in `apps.spec`.
+///
+/// Secret values never appear here: env vars and files point at a secret with
+/// `secretRef`, and the control plane resolves them only when building a
+/// node's desired state.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppSpec {
+    pub image: String,
+    #[serde(default = "default_replicas")]
+    pub replicas: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<EnvVar>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileMount>,
+}
+
+fn default_replicas() -> u32 { 1 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvVar {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_ref: Option<SecretRef>,
+}
+
+/// A secret written to `path` inside the container.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileMount {
+    pub path: String,
+    pub secret_ref: SecretRef,
+    /// Octal file mode, defaults to 0400.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+/// Reference to a secret in the app's namespace. Without `version` the latest
+/// version is used, and publishing a new one rolls the app.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SecretRef {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+}
+
+impl AppSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.image.trim().is_empty() {
+            return Err("spec.image is required".into());
+        }
+        for var in &self.env {
+            if var.name.is_empty() {
+                return Err("env entries need a name".into());
+            }
+            match (&var.value, &var.secret_ref) {
+                (Some(_), Some(_)) => return Err(format!("env {}: set either value or secretRef, not both", var.name)),
+                (None, None) => return Err(format!("env {}: value or secretRef is required", var.name)),
+                _ => {}
+            }
+        }
+        for file in &self.files {
+            if !file.path.starts_with('/') || file.path.split('/').any(|part| part == "..") {
+                return Err(format!("files: {} must be an absolute path without '..'", file.path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every secret reference in the spec, env vars first.
+    pub fn secret_refs(&self) -> impl Iterator<Item = &SecretRef> {
+        self.env.iter().filter_map(|v| v.secret_ref.as_ref()).chain(self.files.iter().map(|f| &f.secret_ref))
+    }
+
+    /// Whether the app follows the latest version of secret `name`.
+    pub fn follows_secret(&self, name: &str) -> bool {
+        self.secret_refs().any(|r| r.name == name && r.version.is_none())
+    }
+}
+
+/// Point every `secretRef` to `secret` in a raw app spec at `version`, or
+/// back at the latest version with `None`. Works on the stored JSON so fields
+/// this crate doesn't model are preserved. Returns the number of references changed.
+pub fn pin_secret_refs(spec: &mut serde_json::Value, secret: &str, version: Option<i32>) -> usize {
+    let mut changed = 0;
+    for list in ["env", "files"] {
+        let Some(entries) = spec.get_mut(list).and_then(|v| v.as_array_mut()) else { continue };
+        for entry in entries {
+            let Some(r) = entry.get_mut("secretRef").and_then(|v| v.as_object_mut()) else { continue };
+            if r.get("name").and_then(|n| n.as_str()) != Some(secret) { continue; }
+            match version {
+                Some(v) => { r.insert("version".into(), v.into()); }
+                None => { r.remove("version"); }
+            }
+            changed += 1;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_secret_refs_from_manifest_spec() {
+        let spec: AppSpec = serde_json::from_value(serde_json::json!({
+            "image": "ghcr.io/example/api:1",
+            "env": [
+                { "name": "LOG_LEVEL", "value": "info" },
+                { "name": "DB_PASSWORD", "secretRef": { "name": "db" } }
+            ],
+            "files": [{ "path": "/etc/api/tls.key", "secretRef": { "name": "tls", "version": 3 } }]
+        })).unwrap();
+        assert_eq!(spec.replicas, 1);
+        assert!(spec.validate().is_ok());
+        assert!(spec.follows_secret("db"));
+        assert!(!spec.follows_secret("tls"));
+        assert_eq!(spec.secret_refs().count(), 2);
+    }
+
+    #[test]
+    fn pins_and_unpins_raw_spec_refs() {
+        let mut raw = serde_json::json!({
+            "image": "img",
+            "healthcheck": { "path": "/ready" },
+            "env": [{ "name": "A", "secretRef": { "name": "db" } }, { "name": "B", "value": "x" }],
+            "files": [{ "path": "/etc/db", "secretRef": { "name": "db", "version": 1 } }]
+        });
+        assert_eq!(pin_secret_refs(&mut raw, "db", Some(4)), 2);
+        assert_eq!(raw["env"][0]["secretRef"]["version"], 4);
+        assert_eq!(raw["files"][0]["secretRef"]["version"], 4);
+        assert_eq!(raw["healthcheck"]["path"], "/ready");
+
+        assert_eq!(pin_secret_refs(&mut raw, "db", None), 2);
+        let spec: AppSpec = serde_json::from_value(raw.clone()).unwrap();
+        assert!(spec.follows_secret("db"));
+        assert_eq!(pin_secret_refs(&mut raw, "other", Some(1)), 0);
+    }
+
+    #[test]
+    fn rejects_ambiguous_env_and_relative_files() {
+        let mut spec = AppSpec { image: "img".into(), ..Default::default() };
+        spec.env.push(EnvVar { name: "A".into(), value: Some("x".into()), secret_ref: Some(SecretRef { name: "s".into(), version: None }) });
+        assert!(spec.validate().is_err());
+
+        spec.env.clear();
+        spec.files.push(FileMount { path: "../etc/passwd".into(), secret_ref: SecretRef { name: "s".into(), version: None }, mode: None });
+        assert!(spec.validate().is_err());
+    }
+}
@@ -1 +1,33 @@
-// Placeholder module for model-specific logic
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::PgPool;
+
+/// An object's metadata, as the gateway needs it to serve the object. The
+/// data itself lives in the object store, addressed by `sha256`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketObject {
+    pub key: String,
+    pub sha256: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The object at `key` in bucket `name` of `namespace`, if there is one.
+pub async fn find_object(namespace: &str, name: &str, key: &str, db: &PgPool) -> anyhow::Result<Option<BucketObject>> {
+    let row = sqlx::query("SELECT o.key, o.sha256, o.size, o.content_type, o.created_at FROM objects o JOIN buckets b ON b.id = o.bucket_id JOIN namespaces n ON n.id = b.namespace_id WHERE n.name = $1 AND b.name = $2 AND o.key = $3")
+        .bind(namespace)
+        .bind(name)
+        .bind(key)
+        .fetch_optional(db)
+        .await?;
+    Ok(row.map(|row| BucketObject {
+        key: row.get("key"),
+        sha256: row.get("sha256"),
+        size: u64::try_from(row.get::<i64, _>("size")).unwrap_or_default(),
+        content_type: row.get("content_type"),
+        created_at: row.get("created_at"),
+    }))
+}
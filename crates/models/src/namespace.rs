@@ -1 +1,12 @@
-// Placeholder module for model-specific logic
+use uuid::Uuid;
+
+use crate::PgPool;
+
+/// Id of namespace `name`, creating it on first use.
+pub async fn ensure_namespace(name: &str, db: &PgPool) -> anyhow::Result<Uuid> {
+    let id = sqlx::query_scalar("INSERT INTO namespaces (name) VALUES ($1) ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id")
+        .bind(name)
+        .fetch_one(db)
+        .await?;
+    Ok(id)
+}
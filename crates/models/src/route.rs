@@ -1 +1,1539 @@
-// Placeholder module for model-specific logic
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::PgPool;
+
+/// Host of a route that matches requests no other route's host claims.
+pub const ANY_HOST: &str = "*";
+
+/// Prefix of a `backendRef` naming a bucket to serve as a static site, as
+/// `bucket:<namespace>/<name>`.
+pub const BUCKET_BACKEND_PREFIX: &str = "bucket:";
+
+/// The `spec` of a Route manifest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteSpec {
+    pub host: String,
+    #[serde(default = "default_path_prefix")]
+    pub path_prefix: String,
+    /// Name of the app in the route's namespace that serves the traffic, or
+    /// `bucket:<namespace>/<name>` for a static site served from a bucket.
+    pub backend_ref: String,
+    /// Terminate TLS for `host` with a certificate obtained via ACME.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsPolicy>,
+    #[serde(default, skip_serializing_if = "LoadBalancer::is_default")]
+    pub load_balancer: LoadBalancer,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outlier_detection: Option<OutlierDetection>,
+    /// What the backend speaks; the gateway accepts HTTP/1.1 and HTTP/2
+    /// from clients either way.
+    #[serde(default, skip_serializing_if = "BackendProtocol::is_default")]
+    pub protocol: BackendProtocol,
+    /// Weighted split between releases of the backend, for canaries.
+    /// Without it every endpoint of the backend gets traffic.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split: Option<TrafficSplit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_filter: Option<IpFilter>,
+    /// Largest request body the gateway forwards; bigger ones get 413.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resilience: Option<Resilience>,
+    /// Authentication the gateway requires before traffic reaches the
+    /// backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<RouteAuth>,
+    /// How a bucket backend is served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<StaticSite>,
+    /// Redirects, rewrites and header changes, applied in order before
+    /// anything else the gateway does with a request.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RouteRule>,
+    /// Forward raw TCP or UDP from a gateway port instead of HTTP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l4: Option<L4Route>,
+}
+
+/// Namespace and name of the bucket a `bucket:<namespace>/<name>` backend
+/// serves.
+pub fn bucket_backend(backend_ref: &str) -> Option<(&str, &str)> {
+    let (namespace, name) = backend_ref.strip_prefix(BUCKET_BACKEND_PREFIX)?.split_once('/')?;
+    (valid_app_name(namespace) && valid_app_name(name)).then_some((namespace, name))
+}
+
+/// How the gateway serves a bucket's objects. Paths under the route's
+/// prefix map to keys with the prefix removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaticSite {
+    /// Served for paths ending in `/`; directories requested without the
+    /// slash are redirected to it.
+    #[serde(default = "default_index_document")]
+    pub index_document: String,
+    /// Serve the root index document for paths without a file extension
+    /// that match no object, for single-page apps routing in the browser.
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// Served with 404 for paths matching no object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_found_document: Option<String>,
+    /// `Cache-Control` sent with every object.
+    #[serde(default = "default_cache_control")]
+    pub cache_control: String,
+}
+
+fn default_index_document() -> String { "index.html".into() }
+fn default_cache_control() -> String { "public, max-age=0, must-revalidate".into() }
+
+impl Default for StaticSite {
+    fn default() -> Self {
+        Self { index_document: default_index_document(), spa_fallback: false, not_found_document: None, cache_control: default_cache_control() }
+    }
+}
+
+/// A step of a route's request handling: its action, taken when the
+/// request (as rewritten by earlier rules) matches `when`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteRule {
+    #[serde(default, skip_serializing_if = "RuleMatch::is_any")]
+    pub when: RuleMatch,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+/// Conditions a request must all meet for a rule to apply. An empty match
+/// applies to every request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleMatch {
+    /// `http` or `https`, as the client reached the gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    /// Matches whole path segments, like `spec.pathPrefix`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+}
+
+impl RuleMatch {
+    pub fn is_any(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleAction {
+    /// Answer with a redirect instead of forwarding; later rules are
+    /// skipped.
+    Redirect(RedirectRule),
+    /// Change the path or host the backend sees.
+    Rewrite(RewriteRule),
+    RequestHeaders(HeaderRule),
+    /// Applied to whatever the client gets back, gateway errors included.
+    ResponseHeaders(HeaderRule),
+}
+
+/// Where a redirect points: the request's own URL with the parts set here
+/// replaced. Changing the scheme drops the port unless one is given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Replaces the whole path; the query string is kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+fn default_redirect_status() -> u16 { 301 }
+
+/// Path and host changes made before the request is forwarded. The prefix
+/// is stripped before `addPrefix` is added.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewriteRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_prefix: Option<String>,
+    /// Host header sent to the backend; `X-Forwarded-Host` keeps the
+    /// original.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+/// Header changes, made in the order `remove`, `set`, `add`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeaderRule {
+    /// Replace any values of the header.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    /// Append a value, keeping existing ones.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub add: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+}
+
+/// Headers rules may not touch: they describe the connection, or are
+/// managed by the gateway.
+const RESERVED_HEADERS: &[&str] = &["connection", "content-length", "host", "keep-alive", "proxy-connection", "te", "trailer", "transfer-encoding", "upgrade"];
+
+impl RouteRule {
+    /// Most rules a route may have.
+    pub const MAX_RULES: usize = 32;
+
+    fn validate(&self, i: usize) -> Result<(), String> {
+        let at = format!("spec.rules[{i}]");
+        if let Some(scheme) = &self.when.scheme {
+            if scheme != "http" && scheme != "https" {
+                return Err(format!("{at}.when.scheme must be http or https"));
+            }
+        }
+        if self.when.path_prefix.as_ref().is_some_and(|p| !p.starts_with('/')) {
+            return Err(format!("{at}.when.pathPrefix must start with '/'"));
+        }
+        if let Some(bad) = self.when.methods.iter().find(|m| m.is_empty() || !m.chars().all(|c| c.is_ascii_uppercase())) {
+            return Err(format!("{at}.when.methods: {bad:?} is not an HTTP method"));
+        }
+        match &self.action {
+            RuleAction::Redirect(redirect) => {
+                if !matches!(redirect.status, 301 | 302 | 303 | 307 | 308) {
+                    return Err(format!("{at}.redirect.status must be 301, 302, 303, 307 or 308"));
+                }
+                if redirect.scheme.as_ref().is_some_and(|s| s != "http" && s != "https") {
+                    return Err(format!("{at}.redirect.scheme must be http or https"));
+                }
+                if redirect.host.as_ref().is_some_and(|h| !valid_rule_host(h)) {
+                    return Err(format!("{at}.redirect.host must be a bare hostname"));
+                }
+                if redirect.path.as_ref().is_some_and(|p| !valid_rule_path(p)) {
+                    return Err(format!("{at}.redirect.path must start with '/' and be printable ASCII without '?' or '#'"));
+                }
+                if redirect.scheme.is_none() && redirect.host.is_none() && redirect.port.is_none() && redirect.path.is_none() {
+                    return Err(format!("{at}.redirect must change the scheme, host, port or path"));
+                }
+            }
+            RuleAction::Rewrite(rewrite) => {
+                if let Some(bad) = rewrite.strip_prefix.iter().chain(&rewrite.add_prefix).find(|p| !valid_rule_path(p)) {
+                    return Err(format!("{at}.rewrite: {bad:?} must start with '/' and be printable ASCII without '?' or '#'"));
+                }
+                if rewrite.host.as_ref().is_some_and(|h| !valid_rule_host(h)) {
+                    return Err(format!("{at}.rewrite.host must be a bare hostname"));
+                }
+                if *rewrite == RewriteRule::default() {
+                    return Err(format!("{at}.rewrite needs stripPrefix, addPrefix or host"));
+                }
+            }
+            RuleAction::RequestHeaders(headers) | RuleAction::ResponseHeaders(headers) => {
+                let names = headers.set.keys().chain(headers.add.keys()).chain(&headers.remove);
+                if let Some(bad) = names.into_iter().find(|n| !valid_header_name(n) || RESERVED_HEADERS.contains(&n.to_ascii_lowercase().as_str())) {
+                    return Err(format!("{at}: header {bad:?} is invalid or managed by the gateway"));
+                }
+                let values = headers.set.values().chain(headers.add.values());
+                if values.into_iter().any(|v| !v.chars().all(|c| c == ' ' || c == '\t' || c.is_ascii_graphic())) {
+                    return Err(format!("{at}: header values must be printable ASCII"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn valid_rule_host(host: &str) -> bool {
+    !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+fn valid_rule_path(path: &str) -> bool {
+    path.starts_with('/') && path.chars().all(|c| c.is_ascii_graphic() && c != '?' && c != '#')
+}
+
+/// A raw TCP or UDP route: connections to `listenPort` on the gateway go to
+/// `targetPort` of the backend's endpoints. Routes only share a listen port
+/// when they all pass TLS through, and are then picked by the SNI of the
+/// client's hello matching `spec.host` (`*` takes clients without a match).
+/// Other routes must use `*` as their host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct L4Route {
+    #[serde(default)]
+    pub protocol: L4Protocol,
+    pub listen_port: u16,
+    /// Defaults to the port of each endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_port: Option<u16>,
+    /// TCP only: route by SNI without terminating TLS.
+    #[serde(default)]
+    pub tls_passthrough: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum L4Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl L4Protocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            L4Protocol::Tcp => "tcp",
+            L4Protocol::Udp => "udp",
+        }
+    }
+}
+
+impl L4Route {
+    /// Where to send a connection for `endpoint` (an `address:port`).
+    pub fn target(&self, endpoint: &str) -> String {
+        match (self.target_port, endpoint.rsplit_once(':')) {
+            (Some(port), Some((address, _))) => format!("{address}:{port}"),
+            _ => endpoint.to_string(),
+        }
+    }
+
+    /// Whether two routes can listen on the same port: both must pass TLS
+    /// through, for different hosts.
+    pub fn conflicts(&self, host: &str, other: &L4Route, other_host: &str) -> bool {
+        self.protocol == other.protocol
+            && self.listen_port == other.listen_port
+            && (!self.tls_passthrough || !other.tls_passthrough || host.eq_ignore_ascii_case(other_host))
+    }
+}
+
+/// Gateway ports L4 routes may listen on, both ends included, written as
+/// `start-end`. Keeps routes off the ports of the node's own services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct L4PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Default for L4PortRange {
+    fn default() -> Self {
+        Self { start: 30000, end: 32767 }
+    }
+}
+
+impl L4PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+
+    /// Refuse an L4 route listening outside the range.
+    pub fn check(&self, l4: &L4Route) -> Result<(), String> {
+        if self.contains(l4.listen_port) {
+            Ok(())
+        } else {
+            Err(format!("spec.l4.listenPort must be within the gateway's L4 port range {self}"))
+        }
+    }
+}
+
+impl std::fmt::Display for L4PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl std::str::FromStr for L4PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid port range {s:?}, expected <start>-<end>");
+        let (start, end) = s.trim().split_once('-').ok_or_else(invalid)?;
+        let (start, end): (u16, u16) = (start.trim().parse().map_err(|_| invalid())?, end.trim().parse().map_err(|_| invalid())?);
+        if start == 0 || start > end {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for L4PortRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<L4PortRange> for String {
+    fn from(range: L4PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+/// How the gateway authenticates a route's clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteAuth {
+    Basic(BasicAuth),
+    Jwt(JwtAuth),
+    ForwardAuth(ForwardAuth),
+}
+
+/// HTTP basic auth against the users in a secret of the route's
+/// namespace, one `user:password` per line. The backend gets the user in
+/// `X-Forwarded-User` and no `Authorization` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BasicAuth {
+    pub secret_ref: String,
+    #[serde(default = "default_realm")]
+    pub realm: String,
+}
+
+fn default_realm() -> String { "Restricted".into() }
+
+/// A bearer token (or cookie) holding a JWT signed by a key in the JWKS at
+/// `jwksUrl`. Tokens must not be expired and must match `issuer` and one of
+/// `audiences` when set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtAuth {
+    pub jwks_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audiences: Vec<String>,
+    /// Cookie the token is read from when there is no bearer token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+    /// Claims passed to the backend, as the header to send each in.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub forward_claims: BTreeMap<String, String>,
+}
+
+/// Ask an app in the route's namespace whether to let each request
+/// through. It gets a bodiless GET to `path` with the request's headers and
+/// `X-Forwarded-Method`, `-Uri`, `-Host`, `-Proto` and `-For`. A 2xx lets
+/// the request through, with `responseHeaders` copied from the answer;
+/// anything else is sent back to the client as is, so the app can redirect
+/// to a login page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardAuth {
+    pub backend_ref: String,
+    #[serde(default = "default_path_prefix")]
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub response_headers: Vec<String>,
+}
+
+/// A basic auth user as handed to gateways: the password is only sent as
+/// a salted argon2id hash (PHC string).
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BasicCredential {
+    pub username: String,
+    pub password_hash: String,
+}
+
+impl std::fmt::Debug for BasicCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BasicCredential").field("username", &self.username).finish_non_exhaustive()
+    }
+}
+
+/// Whether `name` can be sent as an HTTP header name.
+fn valid_header_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+fn valid_app_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Timeouts, retries and circuit breaking for a route's upstream requests.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resilience {
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retries>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+/// Upstream timeouts; requests running into one get 504.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timeouts {
+    /// Connecting to an endpoint. Defaults to the gateway's own setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    /// Waiting for the response to start, and then between parts of its
+    /// body, on each attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_ms: Option<u64>,
+    /// From the request arriving until its response starts, across
+    /// retries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_ms: Option<u64>,
+}
+
+/// Retries of idempotent requests that could not reach an endpoint, timed
+/// out or got 502, 503 or 504. Retries are capped by a budget so they
+/// cannot pile onto a struggling backend: `budgetRatio` of the route's
+/// requests plus `minRetriesPerSecond`, over a ten second window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Retries {
+    /// Retries after the first attempt.
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_retry_budget_ratio")]
+    pub budget_ratio: f64,
+    #[serde(default = "default_min_retries_per_second")]
+    pub min_retries_per_second: u32,
+}
+
+fn default_retry_attempts() -> u32 { 2 }
+fn default_retry_backoff_ms() -> u64 { 25 }
+fn default_retry_budget_ratio() -> f64 { 0.2 }
+fn default_min_retries_per_second() -> u32 { 3 }
+
+impl Default for Retries {
+    fn default() -> Self {
+        Self { attempts: default_retry_attempts(), backoff_ms: default_retry_backoff_ms(), budget_ratio: default_retry_budget_ratio(), min_retries_per_second: default_min_retries_per_second() }
+    }
+}
+
+/// Stop sending a route's requests upstream for `openSecs` after
+/// `consecutiveFailures` failed attempts in a row, answering 503 instead.
+/// Then `halfOpenRequests` trial requests decide whether it closes again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitBreaker {
+    #[serde(default = "default_breaker_failures")]
+    pub consecutive_failures: u32,
+    #[serde(default = "default_breaker_open_secs")]
+    pub open_secs: u64,
+    #[serde(default = "default_half_open_requests")]
+    pub half_open_requests: u32,
+}
+
+fn default_breaker_failures() -> u32 { 5 }
+fn default_breaker_open_secs() -> u64 { 30 }
+fn default_half_open_requests() -> u32 { 1 }
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self { consecutive_failures: default_breaker_failures(), open_secs: default_breaker_open_secs(), half_open_requests: default_half_open_requests() }
+    }
+}
+
+/// Requests per second a client may send to a route, enforced by every
+/// gateway with a token bucket per client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    /// Requests a client may send at once after being idle. Defaults to one
+    /// second's worth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(default)]
+    pub key: RateLimitKey,
+    /// Count requests across all gateways, via NATS, instead of each
+    /// gateway on its own. Shared counts lag by a fraction of a second.
+    #[serde(default)]
+    pub shared: bool,
+}
+
+impl RateLimit {
+    pub fn burst(&self) -> u32 {
+        self.burst.unwrap_or(self.requests_per_second.ceil() as u32).max(1)
+    }
+}
+
+/// What identifies a client for rate limiting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RateLimitKey {
+    #[default]
+    ClientIp,
+    /// A header such as an API key. Requests without it are limited by
+    /// client IP, so leaving it out does not get around the limit.
+    Header(String),
+}
+
+/// Client networks a route admits. Denied networks win over allowed ones;
+/// with an allowlist, clients outside it are refused too.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpFilter {
+    /// CIDRs or single addresses.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+/// Parse a CIDR such as `10.0.0.0/8`, or a single address.
+pub fn parse_network(network: &str) -> Option<ipnet::IpNet> {
+    let network = network.trim();
+    network.parse().ok().or_else(|| network.parse::<std::net::IpAddr>().ok().map(ipnet::IpNet::from))
+}
+
+/// How a route's traffic is divided between releases of its backend.
+/// Weights are percentages and add up to 100.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficSplit {
+    pub releases: Vec<ReleaseWeight>,
+    /// Send requests with the same header or cookie value to the same
+    /// release. Releases take slices of the key space in list order, so
+    /// with the stable release listed first a sticky client stays on the
+    /// canary as its weight grows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticky_on: Option<HashOn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseWeight {
+    /// Release label of the backend's endpoints, such as `v13`.
+    pub release: String,
+    pub weight: u32,
+}
+
+impl TrafficSplit {
+    pub const TOTAL_WEIGHT: u32 = 100;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.releases.is_empty() {
+            return Err("spec.split.releases must name at least one release".into());
+        }
+        for (i, entry) in self.releases.iter().enumerate() {
+            if !valid_release(&entry.release) {
+                return Err(format!("spec.split: release {:?} may only contain letters, digits, '-', '_' and '.'", entry.release));
+            }
+            if self.releases[..i].iter().any(|e| e.release == entry.release) {
+                return Err(format!("spec.split: release {} is listed twice", entry.release));
+            }
+            // Checked before summing, so the total cannot wrap
+            if entry.weight > Self::TOTAL_WEIGHT {
+                return Err(format!("spec.split: weight {} of release {} is over {}", entry.weight, entry.release, Self::TOTAL_WEIGHT));
+            }
+        }
+        let total: u32 = self.releases.iter().map(|e| e.weight).sum();
+        if total != Self::TOTAL_WEIGHT {
+            return Err(format!("spec.split weights must add up to {}, not {total}", Self::TOTAL_WEIGHT));
+        }
+        if let Some(HashOn::Header(name) | HashOn::Cookie(name)) = &self.sticky_on {
+            if name.is_empty() {
+                return Err("spec.split.stickyOn needs a header or cookie name".into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn weight(&self, release: &str) -> Option<u32> {
+        self.releases.iter().find(|e| e.release == release).map(|e| e.weight)
+    }
+
+    /// Set `release` to `weight`, keeping the total at 100: weight it gains
+    /// comes from the other releases, largest first, and weight it gives up
+    /// goes to the largest other release (the first listed on a tie).
+    pub fn set_weight(&mut self, release: &str, weight: u32) -> Result<(), String> {
+        if weight > Self::TOTAL_WEIGHT {
+            return Err(format!("weights are percentages; {weight} is over {}", Self::TOTAL_WEIGHT));
+        }
+        let index = self.releases.iter().position(|e| e.release == release).ok_or_else(|| format!("release {release} is not part of the split"))?;
+        let current = self.releases[index].weight;
+        let largest_other = |releases: &[ReleaseWeight]| {
+            releases.iter().enumerate().filter(|(i, e)| *i != index && (weight < current || e.weight > 0)).max_by(|(ai, a), (bi, b)| a.weight.cmp(&b.weight).then(bi.cmp(ai))).map(|(i, _)| i)
+        };
+        if weight < current {
+            let other = largest_other(&self.releases).ok_or_else(|| format!("release {release} is the only one in the split and must keep all the traffic"))?;
+            self.releases[other].weight += current - weight;
+        } else {
+            let mut needed = weight - current;
+            while needed > 0 {
+                // Others hold exactly 100 - current, so this cannot run dry
+                let Some(other) = largest_other(&self.releases) else { break };
+                let taken = needed.min(self.releases[other].weight);
+                self.releases[other].weight -= taken;
+                needed -= taken;
+            }
+        }
+        self.releases[index].weight = weight;
+        Ok(())
+    }
+}
+
+fn valid_release(release: &str) -> bool {
+    !release.is_empty() && release.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Requests and server errors a gateway saw for one release of a backend
+/// since its last report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReleaseStats {
+    pub namespace: String,
+    pub backend: String,
+    pub release: String,
+    pub requests: u64,
+    pub errors: u64,
+}
+
+/// Protocol the gateway uses to reach a route's backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendProtocol {
+    /// HTTP/1.1, with upgrades (WebSockets) passed through.
+    #[default]
+    Http1,
+    /// HTTP/2 over cleartext, with prior knowledge.
+    H2c,
+    /// HTTP/2 over TLS, verified against the gateway's upstream roots.
+    H2,
+    /// gRPC over h2c. Errors from the gateway itself are sent as gRPC
+    /// statuses instead of HTTP ones.
+    Grpc,
+}
+
+impl BackendProtocol {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackendProtocol::Http1 => "http1",
+            BackendProtocol::H2c => "h2c",
+            BackendProtocol::H2 => "h2",
+            BackendProtocol::Grpc => "grpc",
+        }
+    }
+}
+
+/// How the gateway spreads a route's requests over its backend's endpoints.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadBalancer {
+    #[serde(default)]
+    pub policy: LbPolicy,
+    /// What a `consistent-hash` policy hashes; requests without it are
+    /// spread round-robin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_on: Option<HashOn>,
+}
+
+impl LoadBalancer {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LbPolicy {
+    #[default]
+    RoundRobin,
+    /// The endpoint with the fewest requests in flight.
+    LeastConnections,
+    /// The same key always goes to the same endpoint while it is healthy.
+    ConsistentHash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HashOn {
+    Header(String),
+    Cookie(String),
+}
+
+/// Active health checking: the gateway probes every endpoint and stops
+/// sending traffic to one after `unhealthy_threshold` failures in a row.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheck {
+    /// Path probed with a GET; any 2xx or 3xx answer passes.
+    pub path: String,
+    #[serde(default = "default_check_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_check_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+    /// Passing checks in a row before a failed endpoint gets traffic again.
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32,
+}
+
+fn default_check_interval_secs() -> u64 { 10 }
+fn default_check_timeout_ms() -> u64 { 2000 }
+fn default_unhealthy_threshold() -> u32 { 3 }
+fn default_healthy_threshold() -> u32 { 2 }
+
+/// Passive outlier detection: endpoints answering `consecutive_5xx` server
+/// errors in a row are ejected for `ejection_secs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlierDetection {
+    #[serde(default = "default_consecutive_5xx")]
+    pub consecutive_5xx: u32,
+    #[serde(default = "default_ejection_secs")]
+    pub ejection_secs: u64,
+    /// Never eject more than this share of a backend's endpoints.
+    #[serde(default = "default_max_ejection_percent")]
+    pub max_ejection_percent: u8,
+}
+
+fn default_consecutive_5xx() -> u32 { 5 }
+fn default_ejection_secs() -> u64 { 30 }
+fn default_max_ejection_percent() -> u8 { 50 }
+
+impl Default for OutlierDetection {
+    fn default() -> Self {
+        Self { consecutive_5xx: default_consecutive_5xx(), ejection_secs: default_ejection_secs(), max_ejection_percent: default_max_ejection_percent() }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsPolicy {
+    #[serde(default)]
+    pub challenge: AcmeChallenge,
+}
+
+/// How the ACME server validates control of a route's host.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AcmeChallenge {
+    /// A token served over plain HTTP on port 80.
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    /// A special certificate presented on port 443 (RFC 8737).
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+impl AcmeChallenge {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AcmeChallenge::Http01 => "http-01",
+            AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+        }
+    }
+}
+
+fn default_path_prefix() -> String { "/".into() }
+
+impl RouteSpec {
+    pub fn validate(&self) -> Result<(), String> {
+        let host = self.host.trim();
+        if host.is_empty() {
+            return Err("spec.host is required".into());
+        }
+        if host != ANY_HOST && !host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+            return Err(format!("spec.host {host:?} must be a bare hostname (no scheme, port or path) or \"*\""));
+        }
+        if !self.path_prefix.starts_with('/') {
+            return Err("spec.pathPrefix must start with '/'".into());
+        }
+        let bucket = self.backend_ref.starts_with(BUCKET_BACKEND_PREFIX);
+        if bucket && bucket_backend(&self.backend_ref).is_none() {
+            return Err("spec.backendRef must name a bucket as bucket:<namespace>/<name>".into());
+        }
+        if !bucket && !valid_app_name(&self.backend_ref) {
+            return Err("spec.backendRef must name an app in the route's namespace".into());
+        }
+        if bucket && (self.split.is_some() || self.health_check.is_some() || self.outlier_detection.is_some() || self.resilience.is_some()) {
+            return Err("spec.split, spec.healthCheck, spec.outlierDetection and spec.resilience need an app backend".into());
+        }
+        if let Some(site) = &self.site {
+            if !bucket {
+                return Err("spec.site needs a bucket backend".into());
+            }
+            let documents = std::iter::once(&site.index_document).chain(&site.not_found_document);
+            if let Some(bad) = documents.into_iter().find(|d| d.is_empty() || d.starts_with('/')) {
+                return Err(format!("spec.site: {bad:?} must be an object key, without a leading '/'"));
+            }
+            if !site.cache_control.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+                return Err("spec.site.cacheControl must be printable ASCII".into());
+            }
+        }
+        if self.tls.is_some() && (host == ANY_HOST || !host.contains('.')) {
+            return Err("spec.tls needs a fully qualified spec.host to obtain a certificate for".into());
+        }
+        match (self.load_balancer.policy, &self.load_balancer.hash_on) {
+            (LbPolicy::ConsistentHash, None) => return Err("spec.loadBalancer.hashOn is required for the consistent-hash policy".into()),
+            (LbPolicy::ConsistentHash, Some(HashOn::Header(name) | HashOn::Cookie(name))) if name.is_empty() => return Err("spec.loadBalancer.hashOn needs a header or cookie name".into()),
+            (LbPolicy::RoundRobin | LbPolicy::LeastConnections, Some(_)) => return Err("spec.loadBalancer.hashOn only applies to the consistent-hash policy".into()),
+            _ => {}
+        }
+        if let Some(check) = &self.health_check {
+            if !check.path.starts_with('/') {
+                return Err("spec.healthCheck.path must start with '/'".into());
+            }
+            if check.interval_secs == 0 || check.timeout_ms == 0 || check.unhealthy_threshold == 0 || check.healthy_threshold == 0 {
+                return Err("spec.healthCheck intervals, timeouts and thresholds must be positive".into());
+            }
+        }
+        if let Some(outliers) = &self.outlier_detection {
+            if outliers.consecutive_5xx == 0 || outliers.ejection_secs == 0 || outliers.max_ejection_percent > 100 {
+                return Err("spec.outlierDetection needs positive consecutive5xx and ejectionSecs and a maxEjectionPercent of at most 100".into());
+            }
+        }
+        if let Some(split) = &self.split {
+            split.validate()?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            if !limit.requests_per_second.is_finite() || limit.requests_per_second <= 0.0 || limit.burst == Some(0) {
+                return Err("spec.rateLimit needs a positive requestsPerSecond and burst".into());
+            }
+            if matches!(&limit.key, RateLimitKey::Header(name) if name.is_empty()) {
+                return Err("spec.rateLimit.key needs a header name".into());
+            }
+        }
+        if let Some(filter) = &self.ip_filter {
+            if let Some(bad) = filter.allow.iter().chain(&filter.deny).find(|n| parse_network(n).is_none()) {
+                return Err(format!("spec.ipFilter: {bad:?} is not a CIDR or IP address"));
+            }
+        }
+        if self.max_body_bytes == Some(0) {
+            return Err("spec.maxBodyBytes must be positive".into());
+        }
+        if let Some(resilience) = &self.resilience {
+            let timeouts = &resilience.timeouts;
+            if [timeouts.connect_ms, timeouts.read_ms, timeouts.request_ms].contains(&Some(0)) {
+                return Err("spec.resilience.timeouts must be positive".into());
+            }
+            if let Some(retries) = &resilience.retries {
+                if !(0.0..=1.0).contains(&retries.budget_ratio) {
+                    return Err("spec.resilience.retries.budgetRatio must be between 0 and 1".into());
+                }
+                if retries.attempts > 10 {
+                    return Err("spec.resilience.retries.attempts must be at most 10".into());
+                }
+            }
+            if let Some(breaker) = &resilience.circuit_breaker {
+                if breaker.consecutive_failures == 0 || breaker.open_secs == 0 || breaker.half_open_requests == 0 {
+                    return Err("spec.resilience.circuitBreaker needs positive consecutiveFailures, openSecs and halfOpenRequests".into());
+                }
+            }
+        }
+        if let Some(l4) = &self.l4 {
+            self.validate_l4(l4, host)?;
+        }
+        if self.rules.len() > RouteRule::MAX_RULES {
+            return Err(format!("spec.rules allows at most {} rules", RouteRule::MAX_RULES));
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            rule.validate(i)?;
+        }
+        match &self.auth {
+            None => {}
+            Some(RouteAuth::Basic(basic)) => {
+                if basic.secret_ref.is_empty() {
+                    return Err("spec.auth.basic.secretRef must name a secret in the route's namespace".into());
+                }
+                if basic.realm.contains('"') {
+                    return Err("spec.auth.basic.realm must not contain quotes".into());
+                }
+            }
+            Some(RouteAuth::Jwt(jwt)) => {
+                if !(jwt.jwks_url.starts_with("https://") || jwt.jwks_url.starts_with("http://")) {
+                    return Err("spec.auth.jwt.jwksUrl must be an http(s) URL".into());
+                }
+                if let Some(bad) = jwt.cookie.iter().chain(jwt.forward_claims.values()).find(|n| !valid_header_name(n)) {
+                    return Err(format!("spec.auth.jwt: {bad:?} is not a valid header or cookie name"));
+                }
+            }
+            Some(RouteAuth::ForwardAuth(forward)) => {
+                if !valid_app_name(&forward.backend_ref) {
+                    return Err("spec.auth.forwardAuth.backendRef must name an app in the route's namespace".into());
+                }
+                if !forward.path.starts_with('/') {
+                    return Err("spec.auth.forwardAuth.path must start with '/'".into());
+                }
+                if let Some(bad) = forward.response_headers.iter().find(|n| !valid_header_name(n)) {
+                    return Err(format!("spec.auth.forwardAuth: {bad:?} is not a valid header name"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_l4(&self, l4: &L4Route, host: &str) -> Result<(), String> {
+        if l4.listen_port == 0 || l4.target_port == Some(0) {
+            return Err("spec.l4 ports must be positive".into());
+        }
+        if l4.tls_passthrough && l4.protocol != L4Protocol::Tcp {
+            return Err("spec.l4.tlsPassthrough needs the tcp protocol".into());
+        }
+        if !l4.tls_passthrough && host != ANY_HOST {
+            return Err("spec.host must be \"*\" for spec.l4 routes without tlsPassthrough".into());
+        }
+        let http_only = self.tls.is_some()
+            || self.rate_limit.is_some()
+            || self.max_body_bytes.is_some()
+            || self.resilience.is_some()
+            || self.auth.is_some()
+            || self.site.is_some()
+            || !self.rules.is_empty()
+            || self.load_balancer.hash_on.is_some()
+            || !self.protocol.is_default()
+            || self.path_prefix != "/"
+            || self.backend_ref.starts_with(BUCKET_BACKEND_PREFIX);
+        if http_only || self.split.as_ref().is_some_and(|s| s.sticky_on.is_some()) {
+            return Err("spec.l4 routes forward an app's raw traffic and cannot use HTTP settings (tls, rateLimit, maxBodyBytes, resilience, auth, site, rules, loadBalancer.hashOn, split.stickyOn, protocol, pathPrefix or a bucket backend)".into());
+        }
+        Ok(())
+    }
+
+    /// Hostnames are case-insensitive; store them lowercased.
+    pub fn normalized(mut self) -> Self {
+        self.host = self.host.trim().to_ascii_lowercase();
+        self
+    }
+}
+
+/// A route as served by the gateway, with the endpoints of its backend.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GatewayRoute {
+    pub namespace: String,
+    pub name: String,
+    pub host: String,
+    pub path_prefix: String,
+    pub backend_ref: String,
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+    #[serde(default)]
+    pub load_balancer: LoadBalancer,
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+    #[serde(default)]
+    pub outlier_detection: Option<OutlierDetection>,
+    #[serde(default)]
+    pub protocol: BackendProtocol,
+    #[serde(default)]
+    pub split: Option<TrafficSplit>,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub ip_filter: Option<IpFilter>,
+    #[serde(default)]
+    pub max_body_bytes: Option<u64>,
+    #[serde(default)]
+    pub resilience: Option<Resilience>,
+    #[serde(default)]
+    pub auth: Option<RouteAuth>,
+    #[serde(default)]
+    pub site: Option<StaticSite>,
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+    #[serde(default)]
+    pub l4: Option<L4Route>,
+    /// Users of a basic auth route, resolved from its secret. Only sent to
+    /// gateways with an admin token.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub basic_auth_users: Vec<BasicCredential>,
+    /// Endpoints of a forward auth route's auth app.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth_endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Endpoint {
+    /// `host:port` the backend listens on.
+    pub address: String,
+    pub healthy: bool,
+    /// Release of the backend the endpoint runs, for routes that split
+    /// traffic between releases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+}
+
+impl Endpoint {
+    /// Whether `release` is usable as an endpoint's release label.
+    pub fn valid_release(release: &str) -> bool {
+        valid_release(release)
+    }
+}
+
+/// The routing table as of `version`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatewaySnapshot {
+    pub version: i64,
+    pub routes: Vec<GatewayRoute>,
+}
+
+/// A certificate and its private key, as served by the gateway for `host`.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatewayCertificate {
+    pub host: String,
+    pub cert_pem: String,
+    pub key_pem: String,
+    pub not_after: chrono::DateTime<chrono::Utc>,
+}
+
+impl std::fmt::Debug for GatewayCertificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GatewayCertificate").field("host", &self.host).field("not_after", &self.not_after).finish_non_exhaustive()
+    }
+}
+
+/// Record a route or endpoint change, returning the new config version.
+pub async fn bump_version(db: &PgPool) -> anyhow::Result<i64> {
+    Ok(sqlx::query_scalar("SELECT nextval('gateway_config_version')").fetch_one(db).await?)
+}
+
+/// Routes and endpoints with the config version they reflect. The version
+/// is read first, so a change racing with the read only makes the routes
+/// newer than their version and the change's own event reloads them again.
+pub async fn gateway_snapshot(db: &PgPool) -> anyhow::Result<GatewaySnapshot> {
+    let version: i64 = sqlx::query_scalar("SELECT last_value FROM gateway_config_version").fetch_one(db).await?;
+    Ok(GatewaySnapshot { version, routes: gateway_routes(db).await? })
+}
+
+/// Namespace and backend of every endpoint on `node_id`.
+pub async fn node_backends(node_id: uuid::Uuid, db: &PgPool) -> anyhow::Result<Vec<(String, String)>> {
+    Ok(sqlx::query_as("SELECT DISTINCT n.name, e.backend FROM service_endpoints e JOIN namespaces n ON n.id = e.namespace_id WHERE e.node_id = $1 AND e.backend IS NOT NULL")
+        .bind(node_id)
+        .fetch_all(db)
+        .await?)
+}
+
+/// Every route joined with the endpoints of its backend.
+pub async fn gateway_routes(db: &PgPool) -> anyhow::Result<Vec<GatewayRoute>> {
+    let rows = sqlx::query("SELECT n.name AS namespace, r.name, r.host, r.path_prefix, r.backend_ref, r.spec, COALESCE(json_agg(json_build_object('address', e.address, 'healthy', e.healthy, 'release', e.release) ORDER BY e.address) FILTER (WHERE e.address IS NOT NULL), '[]') AS endpoints FROM routes r JOIN namespaces n ON n.id = r.namespace_id LEFT JOIN service_endpoints e ON e.namespace_id = r.namespace_id AND e.backend = r.backend_ref GROUP BY n.name, r.id ORDER BY n.name, r.name")
+        .fetch_all(db)
+        .await?;
+    let mut routes = rows.into_iter()
+        .map(|row| {
+            let endpoints: serde_json::Value = row.get("endpoints");
+            // Routes applied before specs were stored have no policies
+            let spec: Option<serde_json::Value> = row.get("spec");
+            let spec: Option<RouteSpec> = spec.map(serde_json::from_value).transpose()?;
+            Ok(GatewayRoute {
+                namespace: row.get("namespace"),
+                name: row.get("name"),
+                host: row.get("host"),
+                path_prefix: row.get("path_prefix"),
+                backend_ref: row.get("backend_ref"),
+                endpoints: serde_json::from_value(endpoints)?,
+                load_balancer: spec.as_ref().map(|s| s.load_balancer.clone()).unwrap_or_default(),
+                health_check: spec.as_ref().and_then(|s| s.health_check.clone()),
+                outlier_detection: spec.as_ref().and_then(|s| s.outlier_detection.clone()),
+                protocol: spec.as_ref().map(|s| s.protocol).unwrap_or_default(),
+                split: spec.as_ref().and_then(|s| s.split.clone()),
+                rate_limit: spec.as_ref().and_then(|s| s.rate_limit.clone()),
+                ip_filter: spec.as_ref().and_then(|s| s.ip_filter.clone()),
+                max_body_bytes: spec.as_ref().and_then(|s| s.max_body_bytes),
+                resilience: spec.as_ref().and_then(|s| s.resilience.clone()),
+                auth: spec.as_ref().and_then(|s| s.auth.clone()),
+                site: spec.as_ref().and_then(|s| s.site.clone()),
+                rules: spec.as_ref().map(|s| s.rules.clone()).unwrap_or_default(),
+                l4: spec.and_then(|s| s.l4),
+                basic_auth_users: Vec::new(),
+                auth_endpoints: Vec::new(),
+            })
+        })
+        .collect::<anyhow::Result<Vec<GatewayRoute>>>()?;
+    for route in &mut routes {
+        if let Some(RouteAuth::ForwardAuth(forward)) = &route.auth {
+            route.auth_endpoints = sqlx::query_as("SELECT e.address, e.healthy, e.release FROM service_endpoints e JOIN namespaces n ON n.id = e.namespace_id WHERE n.name = $1 AND e.backend = $2 ORDER BY e.address")
+                .bind(&route.namespace)
+                .bind(&forward.backend_ref)
+                .fetch_all(db)
+                .await?
+                .into_iter()
+                .map(|(address, healthy, release)| Endpoint { address, healthy, release })
+                .collect();
+        }
+    }
+    Ok(routes)
+}
+
+/// Names of the routes in `namespace` whose basic auth users come from
+/// secret `secret`.
+pub async fn routes_using_secret(namespace: &str, secret: &str, db: &PgPool) -> anyhow::Result<Vec<String>> {
+    Ok(sqlx::query_scalar("SELECT r.name FROM routes r JOIN namespaces n ON n.id = r.namespace_id WHERE n.name = $1 AND r.spec->'auth'->'basic'->>'secretRef' = $2 ORDER BY r.name")
+        .bind(namespace)
+        .bind(secret)
+        .fetch_all(db)
+        .await?)
+}
+
+/// Another namespace already serving HTTP on `host`, if one does. A host
+/// belongs to the first namespace that routes it, so no other namespace can
+/// take over or shadow its traffic.
+pub async fn host_owner(namespace: &str, host: &str, db: &mut sqlx::PgConnection) -> anyhow::Result<Option<String>> {
+    Ok(sqlx::query_scalar("SELECT n.name FROM routes r JOIN namespaces n ON n.id = r.namespace_id WHERE r.host = $1 AND n.name <> $2 AND r.spec->'l4' IS NULL ORDER BY n.name LIMIT 1")
+        .bind(host)
+        .bind(namespace)
+        .fetch_optional(db)
+        .await?)
+}
+
+/// The route (`namespace/name`) already listening where `l4` would, if
+/// another one does.
+pub async fn conflicting_l4_route(namespace: &str, name: &str, host: &str, l4: &L4Route, db: &mut sqlx::PgConnection) -> anyhow::Result<Option<String>> {
+    let rows = sqlx::query("SELECT n.name AS namespace, r.name, r.host, r.spec->'l4' AS l4 FROM routes r JOIN namespaces n ON n.id = r.namespace_id WHERE r.spec->'l4'->>'listenPort' = $1 AND NOT (n.name = $2 AND r.name = $3) ORDER BY n.name, r.name")
+        .bind(l4.listen_port.to_string())
+        .bind(namespace)
+        .bind(name)
+        .fetch_all(db)
+        .await?;
+    for row in rows {
+        let other: L4Route = serde_json::from_value(row.get("l4"))?;
+        if l4.conflicts(host, &other, row.get("host")) {
+            return Ok(Some(format!("{}/{}", row.get::<String, _>("namespace"), row.get::<String, _>("name"))));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(host: &str, prefix: &str, backend: &str) -> RouteSpec {
+        RouteSpec { host: host.into(), path_prefix: prefix.into(), backend_ref: backend.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn validates_route_specs() {
+        assert!(spec("app.example.com", "/", "web").validate().is_ok());
+        assert!(spec("*", "/api", "api").validate().is_ok());
+        assert!(spec("https://app.example.com", "/", "web").validate().is_err());
+        assert!(spec("app.example.com:8080", "/", "web").validate().is_err());
+        assert!(spec("app.example.com", "api", "web").validate().is_err());
+        assert!(spec("app.example.com", "/", "other/web").validate().is_err());
+        assert_eq!(spec(" App.Example.COM ", "/", "web").normalized().host, "app.example.com");
+
+        let tls = Some(TlsPolicy::default());
+        assert!(RouteSpec { tls: tls.clone(), ..spec("app.example.com", "/", "web") }.validate().is_ok());
+        assert!(RouteSpec { tls: tls.clone(), ..spec("*", "/", "web") }.validate().is_err());
+        assert!(RouteSpec { tls, ..spec("localhost", "/", "web") }.validate().is_err());
+    }
+
+    #[test]
+    fn validates_upstream_policies() {
+        let base = spec("app.example.com", "/", "web");
+        let hash = |policy, hash_on| RouteSpec { load_balancer: LoadBalancer { policy, hash_on }, ..base.clone() };
+        assert!(hash(LbPolicy::ConsistentHash, Some(HashOn::Cookie("session".into()))).validate().is_ok());
+        assert!(hash(LbPolicy::ConsistentHash, None).validate().is_err());
+        assert!(hash(LbPolicy::ConsistentHash, Some(HashOn::Header(String::new()))).validate().is_err());
+        assert!(hash(LbPolicy::LeastConnections, Some(HashOn::Header("x-user".into()))).validate().is_err());
+
+        let check: HealthCheck = serde_json::from_value(serde_json::json!({ "path": "/healthz" })).unwrap();
+        assert_eq!((check.interval_secs, check.unhealthy_threshold, check.healthy_threshold), (10, 3, 2));
+        assert!(RouteSpec { health_check: Some(check.clone()), ..base.clone() }.validate().is_ok());
+        assert!(RouteSpec { health_check: Some(HealthCheck { path: "healthz".into(), ..check }), ..base.clone() }.validate().is_err());
+        let outliers = OutlierDetection { max_ejection_percent: 150, ..OutlierDetection::default() };
+        assert!(RouteSpec { outlier_detection: Some(outliers), ..base }.validate().is_err());
+    }
+
+    #[test]
+    fn parses_load_balancer_policies() {
+        let spec: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "a.example.com",
+            "backendRef": "web",
+            "loadBalancer": { "policy": "consistent-hash", "hashOn": { "header": "x-user-id" } },
+            "outlierDetection": {},
+        })).unwrap();
+        assert_eq!(spec.load_balancer.policy, LbPolicy::ConsistentHash);
+        assert_eq!(spec.load_balancer.hash_on, Some(HashOn::Header("x-user-id".into())));
+        assert_eq!(spec.outlier_detection, Some(OutlierDetection::default()));
+        assert_eq!(spec.protocol, BackendProtocol::Http1);
+        // Defaults are left out when a spec is written back
+        let plain = serde_json::to_value(RouteSpec { host: "a.example.com".into(), backend_ref: "web".into(), ..Default::default() }).unwrap();
+        assert!(plain.get("loadBalancer").is_none());
+        assert!(plain.get("protocol").is_none());
+        let grpc: RouteSpec = serde_json::from_value(serde_json::json!({ "host": "a.example.com", "backendRef": "api", "protocol": "grpc" })).unwrap();
+        assert_eq!(grpc.protocol, BackendProtocol::Grpc);
+    }
+
+    fn split(weights: &[(&str, u32)]) -> TrafficSplit {
+        TrafficSplit { releases: weights.iter().map(|(r, w)| ReleaseWeight { release: r.to_string(), weight: *w }).collect(), sticky_on: None }
+    }
+
+    fn weights(split: &TrafficSplit) -> Vec<u32> {
+        split.releases.iter().map(|e| e.weight).collect()
+    }
+
+    #[test]
+    fn validates_traffic_splits() {
+        assert!(split(&[("v12", 90), ("v13", 10)]).validate().is_ok());
+        assert!(split(&[("v12", 90), ("v13", 20)]).validate().is_err());
+        assert!(split(&[("v12", u32::MAX), ("v13", 101)]).validate().is_err());
+        assert!(split(&[("v12", 50), ("v12", 50)]).validate().is_err());
+        assert!(split(&[("v 12", 100)]).validate().is_err());
+        assert!(split(&[]).validate().is_err());
+        let parsed: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "a.example.com",
+            "backendRef": "web",
+            "split": { "releases": [{ "release": "v12", "weight": 90 }, { "release": "v13", "weight": 10 }], "stickyOn": { "cookie": "session" } },
+        })).unwrap();
+        assert_eq!(parsed.split.as_ref().unwrap().sticky_on, Some(HashOn::Cookie("session".into())));
+        assert!(parsed.validate().is_ok());
+    }
+
+    #[test]
+    fn shifting_weights_keeps_the_total() {
+        let mut canary = split(&[("v12", 90), ("v13", 10)]);
+        canary.set_weight("v13", 30).unwrap();
+        assert_eq!(weights(&canary), [70, 30]);
+        canary.set_weight("v13", 100).unwrap();
+        assert_eq!(weights(&canary), [0, 100]);
+        // Weight given up goes back to the largest other release
+        canary.set_weight("v13", 0).unwrap();
+        assert_eq!(weights(&canary), [100, 0]);
+        assert!(canary.set_weight("v14", 10).is_err());
+        assert!(canary.set_weight("v13", 101).is_err());
+
+        let mut three = split(&[("a", 50), ("b", 30), ("c", 20)]);
+        three.set_weight("c", 100).unwrap();
+        assert_eq!(weights(&three), [0, 0, 100]);
+        three.set_weight("c", 40).unwrap();
+        assert_eq!(weights(&three), [60, 0, 40]);
+        assert!(split(&[("only", 100)]).set_weight("only", 50).is_err());
+    }
+
+    #[test]
+    fn validates_access_policies() {
+        let parsed: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "a.example.com",
+            "backendRef": "web",
+            "rateLimit": { "requestsPerSecond": 2.5, "key": { "header": "x-api-key" }, "shared": true },
+            "ipFilter": { "allow": ["10.0.0.0/8", "2001:db8::/32", "192.0.2.7"], "deny": ["10.1.0.0/16"] },
+            "maxBodyBytes": 1048576,
+        })).unwrap();
+        assert!(parsed.validate().is_ok());
+        let limit = parsed.rate_limit.clone().unwrap();
+        assert_eq!((limit.key.clone(), limit.burst()), (RateLimitKey::Header("x-api-key".into()), 3));
+        assert_eq!(RateLimit { burst: Some(10), ..limit.clone() }.burst(), 10);
+        let by_ip: RateLimit = serde_json::from_value(serde_json::json!({ "requestsPerSecond": 0.5, "key": "clientIp" })).unwrap();
+        assert_eq!((by_ip.burst(), by_ip.key), (1, RateLimitKey::ClientIp));
+        assert!(parse_network("192.0.2.7").unwrap().contains(&"192.0.2.7".parse::<std::net::IpAddr>().unwrap()));
+
+        let with = |f: fn(&mut RouteSpec)| {
+            let mut spec = parsed.clone();
+            f(&mut spec);
+            spec.validate()
+        };
+        assert!(with(|s| s.rate_limit.as_mut().unwrap().requests_per_second = 0.0).is_err());
+        assert!(with(|s| s.rate_limit.as_mut().unwrap().burst = Some(0)).is_err());
+        assert!(with(|s| s.rate_limit.as_mut().unwrap().key = RateLimitKey::Header(String::new())).is_err());
+        assert!(with(|s| s.ip_filter.as_mut().unwrap().deny.push("10.0.0.0/33".into())).is_err());
+        assert!(with(|s| s.max_body_bytes = Some(0)).is_err());
+    }
+
+    #[test]
+    fn validates_resilience_policies() {
+        let parsed: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "a.example.com",
+            "backendRef": "web",
+            "resilience": { "timeouts": { "readMs": 500, "requestMs": 2000 }, "retries": {}, "circuitBreaker": { "openSecs": 10 } },
+        })).unwrap();
+        assert!(parsed.validate().is_ok());
+        let resilience = parsed.resilience.clone().unwrap();
+        assert_eq!(resilience.timeouts, Timeouts { connect_ms: None, read_ms: Some(500), request_ms: Some(2000) });
+        assert_eq!(resilience.retries, Some(Retries::default()));
+        assert_eq!(resilience.circuit_breaker, Some(CircuitBreaker { open_secs: 10, ..Default::default() }));
+
+        let with = |f: fn(&mut Resilience)| {
+            let mut spec = parsed.clone();
+            f(spec.resilience.as_mut().unwrap());
+            spec.validate()
+        };
+        assert!(with(|r| r.timeouts.connect_ms = Some(0)).is_err());
+        assert!(with(|r| r.retries.as_mut().unwrap().budget_ratio = 1.5).is_err());
+        assert!(with(|r| r.retries.as_mut().unwrap().attempts = 11).is_err());
+        assert!(with(|r| r.circuit_breaker.as_mut().unwrap().consecutive_failures = 0).is_err());
+    }
+
+    #[test]
+    fn validates_auth_policies() {
+        let parsed = |auth: serde_json::Value| {
+            serde_json::from_value::<RouteSpec>(serde_json::json!({ "host": "a.example.com", "backendRef": "grafana", "auth": auth })).unwrap()
+        };
+        let basic = parsed(serde_json::json!({ "basic": { "secretRef": "grafana-users" } }));
+        assert_eq!(basic.auth, Some(RouteAuth::Basic(BasicAuth { secret_ref: "grafana-users".into(), realm: "Restricted".into() })));
+        assert!(basic.validate().is_ok());
+        assert!(parsed(serde_json::json!({ "basic": { "secretRef": "" } })).validate().is_err());
+
+        let jwt = parsed(serde_json::json!({ "jwt": { "jwksUrl": "https://idp.example.com/jwks", "audiences": ["grafana"], "forwardClaims": { "email": "X-Email" } } }));
+        assert!(jwt.validate().is_ok());
+        assert!(parsed(serde_json::json!({ "jwt": { "jwksUrl": "idp.example.com/jwks" } })).validate().is_err());
+        assert!(parsed(serde_json::json!({ "jwt": { "jwksUrl": "https://idp.example.com/jwks", "forwardClaims": { "email": "X Email" } } })).validate().is_err());
+
+        let forward = parsed(serde_json::json!({ "forwardAuth": { "backendRef": "oauth2-proxy", "path": "/oauth2/auth", "responseHeaders": ["X-Auth-Request-User"] } }));
+        assert!(forward.validate().is_ok());
+        assert!(parsed(serde_json::json!({ "forwardAuth": { "backendRef": "oauth2-proxy", "path": "auth" } })).validate().is_err());
+        assert!(parsed(serde_json::json!({ "forwardAuth": { "backendRef": "other/app" } })).validate().is_err());
+    }
+
+    #[test]
+    fn validates_bucket_backends() {
+        let site = spec("www.example.com", "/", "bucket:web/site");
+        assert!(site.validate().is_ok());
+        assert_eq!(bucket_backend(&site.backend_ref), Some(("web", "site")));
+        assert_eq!(bucket_backend("site"), None);
+        assert!(spec("www.example.com", "/", "bucket:site").validate().is_err());
+        assert!(spec("www.example.com", "/", "bucket:web/").validate().is_err());
+
+        let parsed: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "www.example.com",
+            "backendRef": "bucket:web/site",
+            "site": { "spaFallback": true },
+        })).unwrap();
+        assert!(parsed.validate().is_ok());
+        assert_eq!(parsed.site, Some(StaticSite { spa_fallback: true, ..Default::default() }));
+
+        let with = |f: fn(&mut RouteSpec)| {
+            let mut spec = parsed.clone();
+            f(&mut spec);
+            spec.validate()
+        };
+        assert!(with(|s| s.site.as_mut().unwrap().index_document = "/index.html".into()).is_err());
+        assert!(with(|s| s.site.as_mut().unwrap().cache_control = "max-age=60\n".into()).is_err());
+        assert!(with(|s| s.backend_ref = "web".into()).is_err(), "sites need a bucket backend");
+        assert!(with(|s| s.split = Some(TrafficSplit { releases: Vec::new(), sticky_on: None })).is_err());
+    }
+
+    #[test]
+    fn validates_route_rules() {
+        let parsed: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "www.example.com",
+            "backendRef": "web",
+            "rules": [
+                { "when": { "scheme": "http" }, "redirect": { "scheme": "https", "status": 308 } },
+                { "when": { "pathPrefix": "/api" }, "rewrite": { "stripPrefix": "/api", "host": "api.internal" } },
+                { "requestHeaders": { "remove": ["X-Debug"] } },
+                { "responseHeaders": { "set": { "Strict-Transport-Security": "max-age=31536000" } } },
+            ],
+        })).unwrap();
+        assert!(parsed.validate().is_ok());
+        assert_eq!(parsed.rules[0].when.scheme.as_deref(), Some("http"));
+        assert_eq!(parsed.rules[2].when, RuleMatch::default());
+        assert!(matches!(&parsed.rules[1].action, RuleAction::Rewrite(r) if r.strip_prefix.as_deref() == Some("/api")));
+        let round_trip = serde_json::to_value(&parsed.rules[3]).unwrap();
+        assert_eq!(round_trip, serde_json::json!({ "responseHeaders": { "set": { "Strict-Transport-Security": "max-age=31536000" } } }));
+
+        let with = |rule: serde_json::Value| {
+            let mut spec = parsed.clone();
+            spec.rules.push(serde_json::from_value(rule).unwrap());
+            spec.validate()
+        };
+        assert!(with(serde_json::json!({ "redirect": { "scheme": "https", "status": 200 } })).is_err());
+        assert!(with(serde_json::json!({ "redirect": {} })).is_err(), "redirects must go somewhere");
+        assert!(with(serde_json::json!({ "redirect": { "path": "/new?x=1" } })).is_err());
+        assert!(with(serde_json::json!({ "when": { "scheme": "ftp" }, "redirect": { "host": "example.org" } })).is_err());
+        assert!(with(serde_json::json!({ "rewrite": { "stripPrefix": "api" } })).is_err());
+        assert!(with(serde_json::json!({ "rewrite": {} })).is_err());
+        assert!(with(serde_json::json!({ "requestHeaders": { "set": { "Host": "other" } } })).is_err());
+        assert!(with(serde_json::json!({ "responseHeaders": { "add": { "X-A": "line\nbreak" } } })).is_err());
+        assert!(with(serde_json::json!({ "when": { "methods": ["get"] }, "requestHeaders": { "remove": ["X-A"] } })).is_err());
+        assert!(serde_json::from_value::<RouteRule>(serde_json::json!({ "when": {} })).is_err(), "a rule needs an action");
+
+        let mut many = parsed.clone();
+        many.rules = vec![parsed.rules[2].clone(); RouteRule::MAX_RULES + 1];
+        assert!(many.validate().is_err());
+    }
+
+    #[test]
+    fn validates_l4_routes() {
+        let parsed: RouteSpec = serde_json::from_value(serde_json::json!({
+            "host": "*",
+            "backendRef": "minecraft",
+            "l4": { "listenPort": 25565 },
+            "ipFilter": { "allow": ["10.0.0.0/8"] },
+        })).unwrap();
+        assert!(parsed.validate().is_ok());
+        let l4 = parsed.l4.clone().unwrap();
+        assert_eq!((l4.protocol, l4.target_port, l4.tls_passthrough), (L4Protocol::Tcp, None, false));
+        assert_eq!(l4.target("10.0.0.5:8080"), "10.0.0.5:8080");
+        let postgres = L4Route { listen_port: 5432, target_port: Some(5432), tls_passthrough: true, ..l4.clone() };
+        assert_eq!(postgres.target("10.0.0.5:8080"), "10.0.0.5:5432");
+        assert_eq!(postgres.target("[fd00::5]:8080"), "[fd00::5]:5432");
+
+        let with = |f: fn(&mut RouteSpec)| {
+            let mut spec = parsed.clone();
+            f(&mut spec);
+            spec.validate()
+        };
+        assert!(with(|s| s.host = "mc.example.com".into()).is_err(), "only passthrough routes have hosts");
+        assert!(with(|s| {
+            s.host = "db.example.com".into();
+            s.l4.as_mut().unwrap().tls_passthrough = true;
+        }).is_ok());
+        assert!(with(|s| {
+            let l4 = s.l4.as_mut().unwrap();
+            l4.protocol = L4Protocol::Udp;
+            l4.tls_passthrough = true;
+        }).is_err());
+        assert!(with(|s| s.l4.as_mut().unwrap().listen_port = 0).is_err());
+        assert!(with(|s| s.max_body_bytes = Some(1024)).is_err());
+        assert!(with(|s| s.path_prefix = "/api".into()).is_err());
+        assert!(with(|s| s.protocol = BackendProtocol::Grpc).is_err());
+
+        // Ports are shared by passthrough routes for different hosts only
+        let passthrough = L4Route { tls_passthrough: true, ..l4.clone() };
+        assert!(l4.conflicts("*", &l4, "*"));
+        assert!(l4.conflicts("*", &passthrough, "a.example.com"));
+        assert!(!passthrough.conflicts("a.example.com", &passthrough, "b.example.com"));
+        assert!(passthrough.conflicts("a.example.com", &passthrough, "A.example.com"));
+        assert!(!l4.conflicts("*", &L4Route { protocol: L4Protocol::Udp, ..l4.clone() }, "*"));
+        assert!(!l4.conflicts("*", &L4Route { listen_port: 25566, ..l4.clone() }, "*"));
+    }
+
+    #[test]
+    fn parses_l4_port_ranges() {
+        let range: L4PortRange = "20000-29999".parse().unwrap();
+        assert_eq!(range, L4PortRange { start: 20000, end: 29999 });
+        assert_eq!(serde_json::to_value(range).unwrap(), "20000-29999");
+        let l4 = L4Route { protocol: L4Protocol::Tcp, listen_port: 25565, target_port: None, tls_passthrough: false };
+        assert!(range.check(&l4).is_ok());
+        assert!(range.check(&L4Route { listen_port: 22, ..l4.clone() }).is_err());
+        assert!(range.check(&L4Route { listen_port: 30000, ..l4 }).is_err());
+        for bad in ["", "20000", "0-100", "300-200", "1-65536"] {
+            assert!(bad.parse::<L4PortRange>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn parses_tls_policies() {
+        let spec: RouteSpec = serde_json::from_value(serde_json::json!({ "host": "a.example.com", "backendRef": "web", "tls": {} })).unwrap();
+        assert_eq!(spec.tls.unwrap().challenge, AcmeChallenge::Http01);
+        let spec: RouteSpec = serde_json::from_value(serde_json::json!({ "host": "a.example.com", "backendRef": "web", "tls": { "challenge": "tls-alpn-01" } })).unwrap();
+        assert_eq!(spec.tls.unwrap().challenge, AcmeChallenge::TlsAlpn01);
+    }
+
+    #[test]
+    fn defaults_path_prefix() {
+        let spec: RouteSpec = serde_json::from_value(serde_json::json!({ "host": "a.example.com", "backendRef": "web" })).unwrap();
+        assert_eq!(spec.path_prefix, "/");
+    }
+}